@@ -0,0 +1,16 @@
+-- Migration: 004_verification_codes.sql
+-- Description: One-time codes for email and phone verification
+
+CREATE TABLE verification_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose VARCHAR(50) NOT NULL,
+    code_hash VARCHAR(64) NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    consumed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_verification_codes_user_purpose ON verification_codes(user_id, purpose);
+CREATE INDEX idx_verification_codes_created_at ON verification_codes(created_at);
@@ -0,0 +1,5 @@
+-- Migration: 005_password_reset.sql
+-- Description: Invalidate outstanding access tokens on password change/reset
+
+-- Access tokens issued before this instant are rejected
+ALTER TABLE users ADD COLUMN tokens_valid_after TIMESTAMP WITH TIME ZONE;
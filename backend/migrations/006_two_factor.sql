@@ -0,0 +1,19 @@
+-- Migration: 006_two_factor.sql
+-- Description: TOTP two-factor authentication and recovery codes
+
+-- totp_secret holds the encrypted base32 secret; it is set during enrollment
+-- and only takes effect once totp_enabled is switched on
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT;
+
+-- Single-use recovery codes (only hashes are stored)
+CREATE TABLE totp_recovery_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_totp_recovery_codes_user_id ON totp_recovery_codes(user_id);
@@ -0,0 +1,16 @@
+-- Migration: 007_siwe.sql
+-- Description: Sign-In with Ethereum nonces and wallet linking
+
+-- Linked wallets are stored lower-cased so lookups are exact matches
+ALTER TABLE users ADD COLUMN IF NOT EXISTS wallet_address VARCHAR(255);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_wallet_address_unique ON users(wallet_address);
+
+-- Server-issued nonces; each one can back exactly one signed message
+CREATE TABLE siwe_nonces (
+    nonce VARCHAR(64) PRIMARY KEY,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    consumed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_siwe_nonces_expires_at ON siwe_nonces(expires_at);
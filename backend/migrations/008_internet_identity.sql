@@ -0,0 +1,11 @@
+-- Migration: 008_internet_identity.sql
+-- Description: Single-use challenges signed by Internet Identity session keys
+
+CREATE TABLE ii_challenges (
+    challenge VARCHAR(64) PRIMARY KEY,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    consumed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_ii_challenges_expires_at ON ii_challenges(expires_at);
@@ -0,0 +1,25 @@
+-- Migration: 009_login_protection.sql
+-- Description: Failed credential attempt tracking, lockouts and auth audit log
+
+-- scope is 'account' or 'ip'; attempt_key is the user/identifier or address
+CREATE TABLE login_attempts (
+    scope VARCHAR(16) NOT NULL,
+    attempt_key VARCHAR(320) NOT NULL,
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    locked_until TIMESTAMP WITH TIME ZONE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (scope, attempt_key)
+);
+
+CREATE TABLE auth_audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    event_type VARCHAR(64) NOT NULL,
+    ip_address VARCHAR(64),
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_auth_audit_log_user_id ON auth_audit_log(user_id);
+CREATE INDEX idx_auth_audit_log_created_at ON auth_audit_log(created_at);
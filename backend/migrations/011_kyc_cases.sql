@@ -0,0 +1,52 @@
+-- Migration: 011_kyc_cases.sql
+-- Description: KYC submissions, encrypted documents and review history
+
+ALTER TABLE users ADD COLUMN kyc_expires_at TIMESTAMP WITH TIME ZONE;
+
+-- status: submitted, under_review, approved, rejected, resubmit
+CREATE TABLE kyc_cases (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    document_type VARCHAR(50) NOT NULL,
+    document_number_encrypted TEXT NOT NULL,
+    document_number_hash VARCHAR(64) NOT NULL,
+    personal_info_encrypted TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'submitted',
+    decision_reason TEXT,
+    reviewer_id UUID REFERENCES users(id),
+    submitted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    reviewed_at TIMESTAMP WITH TIME ZONE,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    reverify_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_kyc_cases_user_id ON kyc_cases(user_id);
+CREATE INDEX idx_kyc_cases_status ON kyc_cases(status, submitted_at);
+CREATE INDEX idx_kyc_cases_document_number_hash ON kyc_cases(document_number_hash);
+
+-- Document images are AES-256-GCM encrypted at rest
+CREATE TABLE kyc_documents (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    case_id UUID NOT NULL REFERENCES kyc_cases(id) ON DELETE CASCADE,
+    kind VARCHAR(20) NOT NULL,
+    content_type VARCHAR(50) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    encrypted_data BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_kyc_documents_case_id ON kyc_documents(case_id);
+
+CREATE TABLE kyc_case_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    case_id UUID NOT NULL REFERENCES kyc_cases(id) ON DELETE CASCADE,
+    actor_id UUID NOT NULL REFERENCES users(id),
+    from_status VARCHAR(20),
+    to_status VARCHAR(20) NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_kyc_case_events_case_id ON kyc_case_events(case_id);
@@ -0,0 +1,35 @@
+-- Migration: 012_shipment_events.sql
+-- Description: Append-only shipment event history
+
+CREATE TABLE shipment_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    -- Tie-breaker for events written within the same timestamp
+    sequence BIGSERIAL NOT NULL,
+    shipment_id UUID NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    event_type VARCHAR(50) NOT NULL,
+    from_status shipment_status,
+    to_status shipment_status,
+    -- Kept as plain history; no foreign key so deleting users never rewrites events
+    actor_id UUID,
+    actor_role VARCHAR(50),
+    notes TEXT,
+    metadata JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_shipment_events_shipment_id ON shipment_events(shipment_id, created_at);
+
+-- History is never rewritten; rows only go away with their shipment
+CREATE OR REPLACE FUNCTION prevent_shipment_event_changes()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' AND pg_trigger_depth() > 1 THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'shipment_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER shipment_events_append_only
+    BEFORE UPDATE OR DELETE ON shipment_events
+    FOR EACH ROW EXECUTE FUNCTION prevent_shipment_event_changes();
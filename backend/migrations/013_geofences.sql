@@ -0,0 +1,29 @@
+-- Migration: 013_geofences.sql
+-- Description: Hubs and per-shipment geofence state for location-driven events
+
+CREATE TABLE hubs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    -- NULL uses the configured default radius
+    radius_meters DOUBLE PRECISION,
+    address JSONB NOT NULL DEFAULT '{}',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Whether a shipment is currently inside each fence, and since when
+CREATE TABLE geofence_states (
+    shipment_id UUID NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    fence_key VARCHAR(100) NOT NULL,
+    inside BOOLEAN NOT NULL,
+    entered_at TIMESTAMP WITH TIME ZONE,
+    -- Set once the dwell time has elapsed and the arrival event was emitted
+    arrived BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (shipment_id, fence_key)
+);
+
+CREATE INDEX idx_hubs_is_active ON hubs(is_active);
@@ -0,0 +1,21 @@
+-- Migration: 014_shipment_eta.sql
+-- Description: Latest predicted arrival window per shipment
+
+CREATE TABLE shipment_etas (
+    shipment_id UUID PRIMARY KEY REFERENCES shipments(id) ON DELETE CASCADE,
+    estimated_arrival TIMESTAMP WITH TIME ZONE NOT NULL,
+    earliest_arrival TIMESTAMP WITH TIME ZONE NOT NULL,
+    latest_arrival TIMESTAMP WITH TIME ZONE NOT NULL,
+    remaining_distance_km DOUBLE PRECISION,
+    recent_speed_kmh DOUBLE PRECISION,
+    -- Number of completed shipments on the same city pair that informed the estimate
+    lane_samples INTEGER NOT NULL DEFAULT 0,
+    confidence VARCHAR(20) NOT NULL,
+    computed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Lane history is looked up by city pair on delivered shipments
+CREATE INDEX idx_shipments_lane ON shipments (
+    LOWER(pickup_address->>'city'),
+    LOWER(delivery_address->>'city')
+) WHERE status = 'delivered';
@@ -0,0 +1,18 @@
+-- Migration: 015_route_planning.sql
+-- Description: Stop time windows on shipments and driver vehicle capacity
+
+ALTER TABLE shipments
+    ADD COLUMN pickup_window_start TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN pickup_window_end TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN delivery_window_start TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN delivery_window_end TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE driver_vehicles (
+    driver_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    vehicle_type VARCHAR(50) NOT NULL,
+    -- NULL means no limit
+    max_weight_kg DOUBLE PRECISION,
+    max_volume_m3 DOUBLE PRECISION,
+    average_speed_kmh DOUBLE PRECISION,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,32 @@
+-- Migration: 016_dispatch.sql
+-- Description: Driver availability and shipment dispatch offers
+
+CREATE TABLE driver_availability (
+    driver_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'offline', -- offline, available, on_break
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    location_updated_at TIMESTAMP WITH TIME ZONE,
+    shift_started_at TIMESTAMP WITH TIME ZONE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE dispatch_offers (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    shipment_id UUID NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    driver_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending, accepted, declined, expired, cancelled
+    -- NULL when the assignment engine made the offer
+    offered_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    distance_km DOUBLE PRECISION,
+    decline_reason TEXT,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    responded_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- A shipment is offered to one driver at a time
+CREATE UNIQUE INDEX idx_dispatch_offers_one_pending ON dispatch_offers(shipment_id) WHERE status = 'pending';
+CREATE INDEX idx_dispatch_offers_driver_id ON dispatch_offers(driver_id, status);
+CREATE INDEX idx_dispatch_offers_expires_at ON dispatch_offers(expires_at) WHERE status = 'pending';
+CREATE INDEX idx_driver_availability_status ON driver_availability(status);
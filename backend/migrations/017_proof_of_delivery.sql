@@ -0,0 +1,48 @@
+-- Migration: 017_proof_of_delivery.sql
+-- Description: Stored uploads, one-time delivery PINs and proof-of-delivery records
+
+CREATE TABLE uploads (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    category VARCHAR(50) NOT NULL, -- pod_signature, pod_photo
+    content_type VARCHAR(50) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    -- Digest of the plaintext, so evidence can be checked after decryption
+    sha256 VARCHAR(64) NOT NULL,
+    encrypted_data BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE delivery_pins (
+    shipment_id UUID PRIMARY KEY REFERENCES shipments(id) ON DELETE CASCADE,
+    pin_hash VARCHAR(64) NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    issued_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE proof_of_delivery (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    shipment_id UUID NOT NULL UNIQUE REFERENCES shipments(id) ON DELETE CASCADE,
+    captured_by UUID NOT NULL REFERENCES users(id),
+    recipient_name VARCHAR(255),
+    signature_upload_id UUID REFERENCES uploads(id),
+    photo_upload_ids UUID[] NOT NULL DEFAULT '{}',
+    pin_verified BOOLEAN NOT NULL DEFAULT false,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    accuracy_meters DOUBLE PRECISION,
+    -- NULL when the delivery address has no coordinates
+    distance_from_address_meters DOUBLE PRECISION,
+    outside_delivery_area BOOLEAN NOT NULL DEFAULT false,
+    -- Canonical JSON of the evidence bundle and its SHA-256
+    evidence JSONB NOT NULL,
+    evidence_hash VARCHAR(64) NOT NULL,
+    confirmation_id UUID REFERENCES confirmations(id) ON DELETE SET NULL,
+    captured_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_uploads_owner_id ON uploads(owner_id);
+CREATE INDEX idx_proof_of_delivery_captured_by ON proof_of_delivery(captured_by);
@@ -0,0 +1,32 @@
+-- Migration: 018_shipment_imports.sql
+-- Description: Bulk shipment import jobs and their per-row results
+
+CREATE TABLE shipment_imports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    format VARCHAR(10) NOT NULL, -- csv, jsonl
+    mode VARCHAR(20) NOT NULL, -- atomic, partial
+    status VARCHAR(20) NOT NULL DEFAULT 'queued', -- queued, processing, completed, failed
+    total_rows INTEGER NOT NULL,
+    processed_rows INTEGER NOT NULL DEFAULT 0,
+    created_rows INTEGER NOT NULL DEFAULT 0,
+    failed_rows INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMP WITH TIME ZONE,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE shipment_import_rows (
+    import_id UUID NOT NULL REFERENCES shipment_imports(id) ON DELETE CASCADE,
+    -- Line in the uploaded file
+    row_number INTEGER NOT NULL,
+    reference VARCHAR(255),
+    status VARCHAR(20) NOT NULL, -- created, failed, skipped
+    shipment_id UUID REFERENCES shipments(id) ON DELETE SET NULL,
+    tracking_number VARCHAR(100),
+    errors JSONB NOT NULL DEFAULT '[]',
+    PRIMARY KEY (import_id, row_number)
+);
+
+CREATE INDEX idx_shipment_imports_created_by ON shipment_imports(created_by, created_at);
@@ -0,0 +1,85 @@
+-- Migration: 019_rate_quotes.sql
+-- Description: Rate cards, price-locked quotes and the quoted cost on shipments
+
+CREATE TABLE rate_cards (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    -- Cubic centimetres per chargeable kilogram
+    volumetric_divisor DOUBLE PRECISION NOT NULL DEFAULT 5000,
+    -- Percent added per priority, e.g. {"low": -10, "medium": 0, "high": 25, "urgent": 75}
+    priority_surcharges JSONB NOT NULL DEFAULT '{}',
+    fuel_surcharge_percent DOUBLE PRECISION NOT NULL DEFAULT 0,
+    insurance_percent DOUBLE PRECISION NOT NULL DEFAULT 0,
+    insurance_minimum DOUBLE PRECISION NOT NULL DEFAULT 0,
+    cod_fee_flat DOUBLE PRECISION NOT NULL DEFAULT 0,
+    cod_fee_percent DOUBLE PRECISION NOT NULL DEFAULT 0,
+    tax_percent DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- At most one active card per currency
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by UUID REFERENCES users(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Freight per zone: base + per_kg * chargeable weight, never below minimum_charge
+CREATE TABLE rate_card_zones (
+    rate_card_id UUID NOT NULL REFERENCES rate_cards(id) ON DELETE CASCADE,
+    zone VARCHAR(20) NOT NULL CHECK (zone IN ('local', 'regional', 'national', 'international')),
+    base_price DOUBLE PRECISION NOT NULL,
+    per_kg DOUBLE PRECISION NOT NULL,
+    minimum_charge DOUBLE PRECISION NOT NULL DEFAULT 0,
+    PRIMARY KEY (rate_card_id, zone)
+);
+
+CREATE TABLE quotes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    rate_card_id UUID NOT NULL REFERENCES rate_cards(id),
+    requested_by UUID NOT NULL REFERENCES users(id),
+    -- What was priced; a shipment must match these to use the quote
+    weight DOUBLE PRECISION NOT NULL,
+    dimensions JSONB NOT NULL,
+    priority shipment_priority NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    pickup_address JSONB NOT NULL,
+    delivery_address JSONB NOT NULL,
+    insured BOOLEAN NOT NULL DEFAULT FALSE,
+    cod_amount DOUBLE PRECISION,
+    zone VARCHAR(20) NOT NULL,
+    volumetric_weight DOUBLE PRECISION NOT NULL,
+    chargeable_weight DOUBLE PRECISION NOT NULL,
+    breakdown JSONB NOT NULL,
+    total DECIMAL(15,2) NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    shipment_id UUID REFERENCES shipments(id) ON DELETE SET NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE shipments
+    ADD COLUMN quote_id UUID REFERENCES quotes(id),
+    ADD COLUMN shipping_cost DECIMAL(15,2);
+
+CREATE UNIQUE INDEX idx_rate_cards_active_currency ON rate_cards(currency) WHERE is_active;
+CREATE INDEX idx_quotes_requested_by ON quotes(requested_by);
+CREATE INDEX idx_quotes_expires_at ON quotes(expires_at) WHERE used_at IS NULL;
+
+-- Starting card so quotes work before an admin configures one
+WITH card AS (
+    INSERT INTO rate_cards (
+        name, currency, priority_surcharges, fuel_surcharge_percent,
+        insurance_percent, insurance_minimum, cod_fee_flat, cod_fee_percent, tax_percent
+    ) VALUES (
+        'Standard', 'USD', '{"low": -10, "medium": 0, "high": 25, "urgent": 75}', 8,
+        1.5, 2, 3, 1, 0
+    )
+    RETURNING id
+)
+INSERT INTO rate_card_zones (rate_card_id, zone, base_price, per_kg, minimum_charge)
+SELECT card.id, zones.zone, zones.base_price, zones.per_kg, zones.minimum_charge
+FROM card, (VALUES
+    ('local', 4.0, 0.8, 5.0),
+    ('regional', 6.0, 1.2, 8.0),
+    ('national', 9.0, 1.8, 12.0),
+    ('international', 25.0, 4.5, 35.0)
+) AS zones(zone, base_price, per_kg, minimum_charge);
@@ -0,0 +1,43 @@
+-- Migration: 020_returns.sql
+-- Description: Return merchandise authorizations, their reverse shipments and payment refunds
+
+CREATE TABLE return_requests (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    rma_number VARCHAR(70) UNIQUE NOT NULL,
+    -- The delivered shipment being sent back
+    shipment_id UUID NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    requested_by UUID NOT NULL REFERENCES users(id),
+    status VARCHAR(20) NOT NULL DEFAULT 'requested'
+        CHECK (status IN ('requested', 'approved', 'rejected', 'received', 'refunded')),
+    reason_code VARCHAR(30) NOT NULL, -- damaged, wrong_item, not_as_described, no_longer_needed, other
+    reason TEXT,
+    photo_upload_ids UUID[] NOT NULL DEFAULT '{}',
+    decided_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    decision_notes TEXT,
+    decided_at TIMESTAMP WITH TIME ZONE,
+    -- Created on approval, from the receiver back to the sender
+    return_shipment_id UUID REFERENCES shipments(id) ON DELETE SET NULL,
+    received_at TIMESTAMP WITH TIME ZONE,
+    refund_amount DECIMAL(15,2),
+    refunded_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE payment_refunds (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    payment_id UUID NOT NULL REFERENCES payments(id) ON DELETE CASCADE,
+    return_request_id UUID REFERENCES return_requests(id) ON DELETE SET NULL,
+    amount DECIMAL(15,2) NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    status VARCHAR(20) NOT NULL CHECK (status IN ('completed', 'failed')),
+    blockchain_tx_hash VARCHAR(66),
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- One open return per shipment; rejected ones may be requested again
+CREATE UNIQUE INDEX idx_return_requests_open ON return_requests(shipment_id) WHERE status <> 'rejected';
+CREATE UNIQUE INDEX idx_return_requests_return_shipment ON return_requests(return_shipment_id);
+CREATE INDEX idx_return_requests_requested_by ON return_requests(requested_by);
+CREATE INDEX idx_payment_refunds_payment_id ON payment_refunds(payment_id);
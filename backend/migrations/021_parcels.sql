@@ -0,0 +1,36 @@
+-- Migration: 021_parcels.sql
+-- Description: Parcels within a shipment and consolidated hub-to-hub master shipments
+
+CREATE TABLE parcels (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    shipment_id UUID NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    sequence INTEGER NOT NULL,
+    -- The shipment's tracking number plus a two-digit suffix; printed as the parcel barcode
+    tracking_number VARCHAR(60) UNIQUE NOT NULL,
+    weight DECIMAL(10,3) NOT NULL,
+    dimensions JSONB NOT NULL DEFAULT '{}',
+    description TEXT,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN (
+        'pending', 'picked_up', 'in_transit', 'out_for_delivery', 'delivered',
+        'missing', 'damaged', 'returned', 'cancelled'
+    )),
+    delivered_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (shipment_id, sequence)
+);
+
+ALTER TABLE shipments
+    -- A master shipment carrying other shipments between two hubs
+    ADD COLUMN is_consolidation BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN master_shipment_id UUID REFERENCES shipments(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_parcels_shipment_id ON parcels(shipment_id);
+CREATE INDEX idx_shipments_master_shipment_id ON shipments(master_shipment_id);
+
+-- Existing shipments become a single parcel each
+INSERT INTO parcels (
+    shipment_id, sequence, tracking_number, weight, dimensions, status, delivered_at, created_at, updated_at
+)
+SELECT id, 1, tracking_number || '-01', weight, dimensions, status::text, actual_delivery, created_at, updated_at
+FROM shipments;
@@ -0,0 +1,31 @@
+-- Migration: 022_delivery_slots.sql
+-- Description: Bookable delivery slots per zone and receiver delivery preferences
+
+CREATE TABLE delivery_slots (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    -- Normalized delivery city the slot serves
+    zone VARCHAR(100) NOT NULL,
+    -- Local calendar date of the window, as offered to receivers
+    slot_date DATE NOT NULL,
+    starts_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    ends_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    capacity INTEGER NOT NULL CHECK (capacity >= 0),
+    booked INTEGER NOT NULL DEFAULT 0 CHECK (booked >= 0),
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CHECK (ends_at > starts_at),
+    UNIQUE (zone, starts_at, ends_at)
+);
+
+ALTER TABLE shipments
+    ADD COLUMN delivery_slot_id UUID REFERENCES delivery_slots(id) ON DELETE SET NULL,
+    ADD COLUMN delivery_instructions TEXT,
+    -- Receiver allows the parcel to be left without anyone to sign for it
+    ADD COLUMN leave_authorized BOOLEAN NOT NULL DEFAULT FALSE,
+    -- The address before the first redirect to a neighbour or pickup point
+    ADD COLUMN original_delivery_address JSONB;
+
+CREATE INDEX idx_delivery_slots_zone_date ON delivery_slots(zone, slot_date);
+CREATE INDEX idx_shipments_delivery_slot_id ON shipments(delivery_slot_id);
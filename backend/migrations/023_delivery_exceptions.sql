@@ -0,0 +1,32 @@
+-- Migration: 023_delivery_exceptions.sql
+-- Description: Delivery exceptions (failed attempts, address problems, damage, customs holds) and attempt counts
+
+CREATE TABLE shipment_exceptions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    shipment_id UUID NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    exception_type VARCHAR(30) NOT NULL CHECK (exception_type IN (
+        'failed_attempt', 'address_problem', 'damaged', 'customs_hold', 'refused', 'other'
+    )),
+    -- Set when the exception was raised at the door and counted as an attempt
+    attempt_number INTEGER,
+    reason TEXT,
+    evidence_upload_ids UUID[] NOT NULL DEFAULT '{}',
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    next_action VARCHAR(30) NOT NULL CHECK (next_action IN (
+        'reattempt', 'contact_receiver', 'hold', 'return_to_sender', 'none'
+    )),
+    next_attempt_at TIMESTAMP WITH TIME ZONE,
+    status VARCHAR(20) NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'resolved')),
+    reported_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    resolution_notes TEXT,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE shipments ADD COLUMN delivery_attempts INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_shipment_exceptions_shipment_id ON shipment_exceptions(shipment_id);
+CREATE INDEX idx_shipment_exceptions_open ON shipment_exceptions(created_at) WHERE status = 'open';
@@ -0,0 +1,10 @@
+-- Migration: 024_return_refund_states.sql
+-- Description: Track return refunds that are still running or failed so they can be retried
+
+-- Returns received before refunds were tracked and still waiting for one
+UPDATE return_requests SET status = 'refund_failed' WHERE status = 'received';
+
+ALTER TABLE return_requests DROP CONSTRAINT return_requests_status_check;
+ALTER TABLE return_requests ADD CONSTRAINT return_requests_status_check
+    CHECK (status IN ('requested', 'approved', 'rejected', 'refund_pending', 'refund_failed', 'refunded'));
+
@@ -0,0 +1,7 @@
+-- Migration: 025_delivery_pin_salt.sql
+-- Description: Per-PIN random salt for delivery PIN hashes
+
+ALTER TABLE delivery_pins ADD COLUMN pin_salt VARCHAR(64) NOT NULL DEFAULT '';
+
+-- Outstanding PINs were hashed without a salt and can no longer be checked
+UPDATE delivery_pins SET expires_at = NOW() WHERE used_at IS NULL AND expires_at > NOW();
@@ -0,0 +1,11 @@
+-- Migration: 026_shipment_charges.sql
+-- Description: Currency of the quoted shipping cost and cash on delivery on shipments
+
+ALTER TABLE shipments
+    ADD COLUMN shipping_cost_currency VARCHAR(10),
+    ADD COLUMN cod_amount DOUBLE PRECISION;
+
+UPDATE shipments s
+SET shipping_cost_currency = q.currency, cod_amount = q.cod_amount
+FROM quotes q
+WHERE q.id = s.quote_id AND s.shipping_cost IS NOT NULL;
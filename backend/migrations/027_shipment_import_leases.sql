@@ -0,0 +1,7 @@
+-- Migration: 027_shipment_import_leases.sql
+-- Description: Heartbeat lease on shipment imports so abandoned jobs can be told apart from running ones
+
+ALTER TABLE shipment_imports ADD COLUMN lease_expires_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX idx_shipment_imports_active ON shipment_imports(lease_expires_at)
+    WHERE status IN ('queued', 'processing');
@@ -6,6 +6,7 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +15,10 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
+
+// Platform-wide predictions and insights are business data
+const INSIGHT_ROLES: &[UserRole] = &[UserRole::StoreOwner, UserRole::ShippingCompany, UserRole::Admin];
 
 #[derive(Debug, Clone)]
 pub struct AIService {
@@ -95,44 +100,46 @@ pub struct InsightResponse {
 
 pub async fn get_suggestions(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<SuggestionResponse>>, StatusCode> {
     info!("Fetching AI suggestions");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str());
-    let shipment_id = params.get("shipment_id").and_then(|v| v.as_str());
+    let parse_id = |key: &str| {
+        params
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|id| Uuid::parse_str(id).map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()
+    };
+
+    // Users see the suggestions made for them; admins may look at anyone's
+    let user_id = if auth_user.role == UserRole::Admin {
+        parse_id("user_id")?
+    } else {
+        Some(auth_user.user_id)
+    };
+    let shipment_id = parse_id("shipment_id")?;
     let status = params.get("status").and_then(|v| v.as_str());
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(20);
 
-    let mut query = "SELECT * FROM ai_suggestions WHERE 1=1".to_string();
-    let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(uid) = user_id {
-        query.push_str(&format!(" AND user_id = ${}", param_count));
-        bind_values.push(Box::new(Uuid::parse_str(uid).map_err(|_| StatusCode::BAD_REQUEST)?));
-        param_count += 1;
-    }
-
-    if let Some(sid) = shipment_id {
-        query.push_str(&format!(" AND shipment_id = ${}", param_count));
-        bind_values.push(Box::new(Uuid::parse_str(sid).map_err(|_| StatusCode::BAD_REQUEST)?));
-        param_count += 1;
-    }
-
-    if let Some(st) = status {
-        query.push_str(&format!(" AND status = ${}", param_count));
-        bind_values.push(Box::new(st.to_string()));
-        param_count += 1;
-    }
-
-    query.push_str(&format!(" ORDER BY priority DESC, confidence DESC LIMIT ${}", param_count));
-    bind_values.push(Box::new(limit as i64));
-
-    let rows = sqlx::query(&query)
-        .fetch_all(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM ai_suggestions
+        WHERE ($1::uuid IS NULL OR user_id = $1)
+          AND ($2::uuid IS NULL OR shipment_id = $2)
+          AND ($3::text IS NULL OR status::text = $3)
+        ORDER BY priority DESC, confidence DESC
+        LIMIT $4
+        "#,
+    )
+    .bind(user_id)
+    .bind(shipment_id)
+    .bind(status)
+    .bind(limit as i64)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let suggestions = rows
         .into_iter()
@@ -159,10 +166,13 @@ pub async fn get_suggestions(
 
 pub async fn get_predictions(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<PredictionResponse>>, StatusCode> {
     info!("Fetching AI predictions");
 
+    auth_user.require_role(INSIGHT_ROLES)?;
+
     // In a real implementation, this would fetch predictions from the AI service
     // For now, we'll return mock data
 
@@ -222,10 +232,13 @@ pub async fn get_predictions(
 
 pub async fn get_risk_assessment(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<RiskAssessmentResponse>>, StatusCode> {
     info!("Fetching risk assessments");
 
+    auth_user.require_role(INSIGHT_ROLES)?;
+
     // In a real implementation, this would fetch risk assessments from the AI service
     // For now, we'll return mock data
 
@@ -297,10 +310,13 @@ pub async fn get_risk_assessment(
 
 pub async fn get_insights(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<InsightResponse>>, StatusCode> {
     info!("Fetching AI insights");
 
+    auth_user.require_role(INSIGHT_ROLES)?;
+
     // In a real implementation, this would fetch insights from the AI service
     // For now, we'll return mock data
 
@@ -354,6 +370,7 @@ pub async fn get_insights(
 
 pub async fn apply_suggestion(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<ApplySuggestionRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     info!("Applying suggestion: {}", payload.suggestion_id);
@@ -373,6 +390,14 @@ pub async fn apply_suggestion(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    // Only the user a suggestion was made for may act on it; suggestions
+    // without a user are platform-wide and left to admins
+    if auth_user.role != UserRole::Admin
+        && suggestion_row.get::<Option<Uuid>, _>("user_id") != Some(auth_user.user_id)
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     // Apply the suggestion based on type
     let application_result = apply_suggestion_logic(
         &suggestion_row,
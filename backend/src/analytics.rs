@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct AnalyticsService {
@@ -138,10 +139,13 @@ pub struct ROISummary {
 
 pub async fn get_kpis(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<KPIsResponse>, StatusCode> {
     info!("Fetching KPIs");
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     let period = params.get("period").and_then(|v| v.as_str()).unwrap_or("30d");
 
     // Get total revenue
@@ -261,10 +265,13 @@ pub async fn get_kpis(
 
 pub async fn get_revenue_analytics(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<RevenueAnalyticsResponse>, StatusCode> {
     info!("Fetching revenue analytics");
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     let period = params.get("period").and_then(|v| v.as_str()).unwrap_or("30d");
     let start_date = params.get("start_date").and_then(|v| v.as_str());
     let end_date = params.get("end_date").and_then(|v| v.as_str());
@@ -322,10 +329,13 @@ pub async fn get_revenue_analytics(
 
 pub async fn get_customer_analytics(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<CustomerAnalyticsResponse>, StatusCode> {
     info!("Fetching customer analytics");
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     // Get total customers
     let total_customers_row = sqlx::query("SELECT COUNT(*) as count FROM users WHERE role = 'customer'")
         .fetch_one(&state.db.pool)
@@ -405,10 +415,13 @@ pub async fn get_customer_analytics(
 
 pub async fn get_product_analytics(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<ProductAnalyticsResponse>, StatusCode> {
     info!("Fetching product analytics");
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     // Get total products (mock data for now)
     let total_products = 150;
 
@@ -505,10 +518,13 @@ pub async fn get_product_analytics(
 
 pub async fn get_campaign_analytics(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<CampaignAnalyticsResponse>, StatusCode> {
     info!("Fetching campaign analytics");
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     // Mock data for campaigns
     let total_campaigns = 8;
     let active_campaigns = 3;
@@ -565,10 +581,13 @@ pub async fn get_campaign_analytics(
 
 pub async fn export_data(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     info!("Exporting analytics data");
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     let export_type = params.get("type").and_then(|v| v.as_str()).unwrap_or("all");
     let format = params.get("format").and_then(|v| v.as_str()).unwrap_or("json");
 
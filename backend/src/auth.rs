@@ -1,6 +1,7 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, HeaderMap, StatusCode},
     response::Json,
     routing::{get, post, put},
     Router,
@@ -18,6 +19,16 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::utils::{generate_random_string, hash_data, validate_password};
+use crate::services::utils::send_email;
+use crate::verification::{consume_code, issue_code, PASSWORD_RESET};
+use crate::login_protection::{ClientIp, LoginGuard};
+use crate::sessions::{
+    create_session, ensure_session_active, resume_session, revoke_user_sessions, update_device_info, SessionContext,
+};
+use crate::internet_identity::{
+    verify_session_signature, DelegationChain, VerifiedDelegation, VerifierSettings, CHALLENGE_TTL_MINUTES,
+};
 
 #[derive(Debug, Clone)]
 pub struct AuthService {
@@ -43,6 +54,74 @@ pub struct Claims {
     pub role: String,
     pub exp: usize,
     pub iat: usize,
+    // `iat` in microseconds; whole seconds can't tell apart tokens minted
+    // just before and just after a revocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_micros: Option<i64>,
+    pub jti: String,
+    pub token_type: String, // access, refresh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>, // Session ID
+}
+
+const ACCESS_TOKEN: &str = "access";
+const REFRESH_TOKEN: &str = "refresh";
+pub(crate) const MFA_CHALLENGE_TOKEN: &str = "mfa_challenge";
+pub(crate) const MFA_CHALLENGE_TTL: u64 = 300;
+
+/// The authenticated caller, resolved from the `Authorization: Bearer` header.
+///
+/// Add this as a handler argument to require a valid access token; the
+/// request is rejected with `401` before the handler runs otherwise.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: UserRole,
+    pub session_id: Option<Uuid>,
+}
+
+impl AuthUser {
+    /// Returns `403` unless the caller holds one of the `allowed` roles.
+    pub fn require_role(&self, allowed: &[UserRole]) -> Result<(), StatusCode> {
+        if allowed.contains(&self.role) {
+            Ok(())
+        } else {
+            warn!(
+                "User {} with role {} denied access (requires one of {:?})",
+                self.user_id,
+                self.role.as_str(),
+                allowed
+            );
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<crate::AppState> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &crate::AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = extract_token_from_headers(&parts.headers)?;
+        let claims = authenticate_access_token(state, &token).await?;
+
+        auth_user_from_claims(claims)
+    }
+}
+
+/// Builds the caller from already-authenticated access token claims, for
+/// handlers that can't use the extractor (e.g. tokens passed in a query).
+pub(crate) fn auth_user_from_claims(claims: Claims) -> Result<AuthUser, StatusCode> {
+    Ok(AuthUser {
+        user_id: Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?,
+        email: claims.email,
+        role: UserRole::parse(&claims.role).ok_or(StatusCode::UNAUTHORIZED)?,
+        session_id: claims.sid.as_deref().and_then(|sid| Uuid::parse_str(sid).ok()),
+    })
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,6 +152,23 @@ pub struct AuthResponse {
     pub expires_in: u64,
 }
 
+/// Returned by `login` when the account has two-factor authentication
+/// enabled; exchange `challenge_token` plus a TOTP or recovery code at
+/// `/api/auth/login/2fa` for an `AuthResponse`.
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallengeResponse {
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    TwoFactorRequired(TwoFactorChallengeResponse),
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: String,
@@ -89,18 +185,37 @@ pub struct UserResponse {
     pub kyc_verified: bool,
     pub biometric_enabled: bool,
     pub world_id_verified: bool,
+    pub two_factor_enabled: bool,
     pub internet_identity_principal: Option<String>,
     pub created_at: String,
     pub last_login: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct KYCRequest {
-    pub user_id: String,
-    pub document_type: String,
-    pub document_number: String,
-    pub document_image: String,
-    pub personal_info: serde_json::Value,
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -119,15 +234,25 @@ pub struct WorldIDVerificationRequest {
     pub nullifier_hash: String,
 }
 
+/// `delegation` is the JSON-serialized delegation chain from Internet
+/// Identity; `signature` is the session key's hex signature over `challenge`.
 #[derive(Debug, Deserialize)]
 pub struct InternetIdentityAuthRequest {
     pub principal: String,
     pub signature: String,
     pub delegation: String,
+    pub challenge: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InternetIdentityChallengeResponse {
+    pub challenge: String,
+    pub expires_at: String,
 }
 
 pub async fn register(
     State(state): State<crate::AppState>,
+    session_context: SessionContext,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<Json<AuthResponse>, StatusCode> {
     info!("Registration attempt for email: {}", payload.email);
@@ -158,14 +283,12 @@ pub async fn register(
         .to_string();
 
     // Parse role
-    let role = match payload.role.as_str() {
-        "customer" => UserRole::Customer,
-        "store_owner" => UserRole::StoreOwner,
-        "driver" => UserRole::Driver,
-        "shipping_company" => UserRole::ShippingCompany,
-        "admin" => UserRole::Admin,
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+    let role = UserRole::parse(&payload.role).ok_or(StatusCode::BAD_REQUEST)?;
+
+    // Admin accounts are provisioned out of band, never self-registered
+    if role == UserRole::Admin {
+        return Err(StatusCode::FORBIDDEN);
+    }
 
     // Create user
     let user_id = Uuid::new_v4();
@@ -205,37 +328,9 @@ pub async fn register(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    // Generate JWT token
-    let claims = Claims {
-        sub: user_id.to_string(),
-        email: payload.email.clone(),
-        role: payload.role.clone(),
-        exp: (Utc::now().timestamp() + state.config.jwt_expiry as i64) as usize,
-        iat: Utc::now().timestamp() as usize,
-    };
-
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Generate refresh token
-    let refresh_claims = Claims {
-        sub: user_id.to_string(),
-        email: payload.email.clone(),
-        role: payload.role.clone(),
-        exp: (Utc::now().timestamp() + 7 * 24 * 60 * 60) as usize, // 7 days
-        iat: Utc::now().timestamp() as usize,
-    };
-
-    let refresh_token = encode(
-        &Header::default(),
-        &refresh_claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Generate access and refresh tokens
+    let session_id = create_session(&state, user_id, &session_context).await?;
+    let tokens = issue_token_pair(&state, user_id, &payload.email, &role, session_id).await?;
 
     let user_response = UserResponse {
         id: user_id.to_string(),
@@ -245,13 +340,14 @@ pub async fn register(
         last_name: payload.last_name,
         phone: payload.phone,
         avatar_url: None,
-        role: payload.role,
+        role: role.as_str().to_string(),
         status: "active".to_string(),
         email_verified: false,
         phone_verified: false,
         kyc_verified: false,
         biometric_enabled: false,
         world_id_verified: false,
+        two_factor_enabled: false,
         internet_identity_principal: None,
         created_at: now.to_rfc3339(),
         last_login: None,
@@ -260,8 +356,8 @@ pub async fn register(
     info!("User registered successfully: {}", user_id);
 
     Ok(Json(AuthResponse {
-        token,
-        refresh_token,
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
         user: user_response,
         expires_in: state.config.jwt_expiry,
     }))
@@ -269,8 +365,9 @@ pub async fn register(
 
 pub async fn login(
     State(state): State<crate::AppState>,
+    session_context: SessionContext,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, StatusCode> {
+) -> Result<Json<LoginResponse>, StatusCode> {
     info!("Login attempt for email: {}", payload.email);
 
     // Get user from database
@@ -285,9 +382,18 @@ pub async fn login(
 
     let user_row = match user_row {
         Some(row) => row,
-        None => return Err(StatusCode::UNAUTHORIZED),
+        None => {
+            let guard = LoginGuard::for_identifier(&payload.email, session_context.client_ip);
+            guard.check(&state).await?;
+            guard.record_failure(&state, None, "login_failed").await?;
+            return Err(StatusCode::UNAUTHORIZED);
+        }
     };
 
+    let user_id = user_row.get::<Uuid, _>("id");
+    let guard = LoginGuard::for_user(user_id, session_context.client_ip);
+    guard.check(&state).await?;
+
     // Verify password
     let password_hash = user_row.get::<String, _>("password_hash");
     let parsed_hash = PasswordHash::new(&password_hash)
@@ -298,12 +404,14 @@ pub async fn login(
         .verify_password(payload.password.as_bytes(), &parsed_hash)
         .is_err()
     {
+        guard.record_failure(&state, Some(user_id), "login_failed").await?;
         return Err(StatusCode::UNAUTHORIZED);
     }
 
     // Check biometric authentication if provided
     if let Some(biometric_data) = payload.biometric_data {
         if !verify_biometric_auth(&user_row, &biometric_data).await {
+            guard.record_failure(&state, Some(user_id), "biometric_failed").await?;
             return Err(StatusCode::UNAUTHORIZED);
         }
     }
@@ -311,102 +419,142 @@ pub async fn login(
     // Check World ID verification if provided
     if let Some(world_id_proof) = payload.world_id_proof {
         if !verify_world_id_proof(&world_id_proof).await {
+            guard.record_failure(&state, Some(user_id), "world_id_failed").await?;
             return Err(StatusCode::UNAUTHORIZED);
         }
     }
 
-    // Check Internet Identity if provided
+    // A bare principal proves nothing on its own; it must match the one
+    // linked through a verified Internet Identity sign-in
     if let Some(principal) = payload.internet_identity_principal {
-        if !verify_internet_identity(&principal).await {
+        let linked = user_row.get::<Option<String>, _>("internet_identity_principal");
+        if linked.as_deref() != Some(principal.as_str()) {
+            guard.record_failure(&state, Some(user_id), "login_failed").await?;
             return Err(StatusCode::UNAUTHORIZED);
         }
     }
 
-    // Update last login
+    let response = begin_login(&state, &user_row, &session_context).await?;
+
+    // With two-factor enabled the counter is only cleared once the second
+    // factor checks out
+    if matches!(response, LoginResponse::Authenticated(_)) {
+        guard.record_success(&state, Some(user_id), "login_succeeded").await?;
+    }
+
+    Ok(Json(response))
+}
+
+/// Finishes a first-factor login: accounts with two-factor authentication
+/// only get a short-lived challenge, everyone else gets tokens straight away.
+pub(crate) async fn begin_login(
+    state: &crate::AppState,
+    user_row: &sqlx::postgres::PgRow,
+    session_context: &SessionContext,
+) -> Result<LoginResponse, StatusCode> {
+    if user_row.get::<bool, _>("totp_enabled") {
+        let user_id = user_row.get::<Uuid, _>("id");
+        let (_, challenge_token) = encode_token(
+            state,
+            user_id,
+            &user_row.get::<String, _>("email"),
+            &user_row.get::<UserRole, _>("role"),
+            MFA_CHALLENGE_TOKEN,
+            MFA_CHALLENGE_TTL,
+            None,
+        )?;
+
+        info!("Two-factor challenge issued for user: {}", user_id);
+
+        return Ok(LoginResponse::TwoFactorRequired(TwoFactorChallengeResponse {
+            two_factor_required: true,
+            challenge_token,
+            expires_in: MFA_CHALLENGE_TTL,
+        }));
+    }
+
+    let response = complete_login(state, user_row, session_context).await?;
+
+    Ok(LoginResponse::Authenticated(response))
+}
+
+/// Records the login, starts a session and issues a fresh token pair for an
+/// already authenticated user row.
+pub(crate) async fn complete_login(
+    state: &crate::AppState,
+    user_row: &sqlx::postgres::PgRow,
+    session_context: &SessionContext,
+) -> Result<AuthResponse, StatusCode> {
+    let user_id = user_row.get::<Uuid, _>("id");
     let now = Utc::now();
+
     sqlx::query("UPDATE users SET last_login = $1, updated_at = $2 WHERE id = $3")
         .bind(now)
         .bind(now)
-        .bind(user_row.get::<Uuid, _>("id"))
+        .bind(user_id)
         .execute(&state.db.pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    // Generate JWT token
-    let user_id = user_row.get::<Uuid, _>("id");
+    // Generate access and refresh tokens
     let email = user_row.get::<String, _>("email");
     let role = user_row.get::<UserRole, _>("role");
+    let session_id = create_session(state, user_id, session_context).await?;
+    let tokens = issue_token_pair(state, user_id, &email, &role, session_id).await?;
 
-    let claims = Claims {
-        sub: user_id.to_string(),
-        email: email.clone(),
-        role: format!("{:?}", role).to_lowercase(),
-        exp: (Utc::now().timestamp() + state.config.jwt_expiry as i64) as usize,
-        iat: Utc::now().timestamp() as usize,
-    };
-
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Generate refresh token
-    let refresh_claims = Claims {
-        sub: user_id.to_string(),
-        email: email.clone(),
-        role: format!("{:?}", role).to_lowercase(),
-        exp: (Utc::now().timestamp() + 7 * 24 * 60 * 60) as usize,
-        iat: Utc::now().timestamp() as usize,
-    };
-
-    let refresh_token = encode(
-        &Header::default(),
-        &refresh_claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let user_response = UserResponse {
-        id: user_id.to_string(),
-        email,
-        username: user_row.get::<String, _>("username"),
-        first_name: user_row.get::<String, _>("first_name"),
-        last_name: user_row.get::<String, _>("last_name"),
-        phone: user_row.get::<Option<String>, _>("phone"),
-        avatar_url: user_row.get::<Option<String>, _>("avatar_url"),
-        role: format!("{:?}", role).to_lowercase(),
-        status: format!("{:?}", user_row.get::<UserStatus, _>("status")).to_lowercase(),
-        email_verified: user_row.get::<bool, _>("email_verified"),
-        phone_verified: user_row.get::<bool, _>("phone_verified"),
-        kyc_verified: user_row.get::<bool, _>("kyc_verified"),
-        biometric_enabled: user_row.get::<bool, _>("biometric_enabled"),
-        world_id_verified: user_row.get::<bool, _>("world_id_verified"),
-        internet_identity_principal: user_row.get::<Option<String>, _>("internet_identity_principal"),
-        created_at: user_row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
-        last_login: Some(now.to_rfc3339()),
-    };
+    let mut user_response = user_response_from_row(user_row);
+    user_response.last_login = Some(now.to_rfc3339());
 
     info!("User logged in successfully: {}", user_id);
 
-    Ok(Json(AuthResponse {
-        token,
-        refresh_token,
+    Ok(AuthResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
         user: user_response,
         expires_in: state.config.jwt_expiry,
-    }))
+    })
 }
 
 pub async fn logout(
     State(state): State<crate::AppState>,
     headers: HeaderMap,
+    payload: Option<Json<LogoutRequest>>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    // In a real implementation, you would invalidate the token
-    // by adding it to a blacklist or using Redis
-    
-    info!("User logged out");
-    
+    let token = extract_token_from_headers(&headers)?;
+    let claims = authenticate_access_token(&state, &token).await?;
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+    // Revoke the presented access token until it would have expired anyway
+    revoke_token(&state, user_id, &claims).await?;
+
+    if let Some(session_id) = claims.sid.as_deref().and_then(|sid| Uuid::parse_str(sid).ok()) {
+        sqlx::query("UPDATE sessions SET revoked_at = $1 WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL")
+            .bind(Utc::now())
+            .bind(session_id)
+            .bind(user_id)
+            .execute(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        revoke_token_family(&state, session_id).await?;
+    }
+
+    // Revoke the refresh token family so the session cannot be resumed
+    if let Some(Json(LogoutRequest { refresh_token: Some(refresh_token) })) = payload {
+        let family_id = sqlx::query("SELECT family_id FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2")
+            .bind(hash_data(&refresh_token))
+            .bind(user_id)
+            .fetch_optional(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map(|row| row.get::<Uuid, _>("family_id"));
+
+        if let Some(family_id) = family_id {
+            revoke_token_family(&state, family_id).await?;
+        }
+    }
+
+    info!("User logged out: {}", user_id);
+
     Ok(Json(serde_json::json!({
         "message": "Logged out successfully"
     })))
@@ -417,7 +565,7 @@ pub async fn verify(
     headers: HeaderMap,
 ) -> Result<Json<UserResponse>, StatusCode> {
     let token = extract_token_from_headers(&headers)?;
-    let claims = verify_jwt_token(&token, &state.config.jwt_secret)?;
+    let claims = authenticate_access_token(&state, &token).await?;
 
     let user_row = sqlx::query("SELECT * FROM users WHERE id = $1")
         .bind(Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?)
@@ -430,94 +578,123 @@ pub async fn verify(
         None => return Err(StatusCode::UNAUTHORIZED),
     };
 
-    let user_response = UserResponse {
-        id: user_row.get::<Uuid, _>("id").to_string(),
-        email: user_row.get::<String, _>("email"),
-        username: user_row.get::<String, _>("username"),
-        first_name: user_row.get::<String, _>("first_name"),
-        last_name: user_row.get::<String, _>("last_name"),
-        phone: user_row.get::<Option<String>, _>("phone"),
-        avatar_url: user_row.get::<Option<String>, _>("avatar_url"),
-        role: format!("{:?}", user_row.get::<UserRole, _>("role")).to_lowercase(),
-        status: format!("{:?}", user_row.get::<UserStatus, _>("status")).to_lowercase(),
-        email_verified: user_row.get::<bool, _>("email_verified"),
-        phone_verified: user_row.get::<bool, _>("phone_verified"),
-        kyc_verified: user_row.get::<bool, _>("kyc_verified"),
-        biometric_enabled: user_row.get::<bool, _>("biometric_enabled"),
-        world_id_verified: user_row.get::<bool, _>("world_id_verified"),
-        internet_identity_principal: user_row.get::<Option<String>, _>("internet_identity_principal"),
-        created_at: user_row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
-        last_login: user_row.get::<Option<chrono::DateTime<Utc>>, _>("last_login")
-            .map(|dt| dt.to_rfc3339()),
-    };
-
-    Ok(Json(user_response))
+    Ok(Json(user_response_from_row(&user_row)))
 }
 
 pub async fn refresh_token(
     State(state): State<crate::AppState>,
-    Json(payload): Json<serde_json::Value>,
+    session_context: SessionContext,
+    Json(payload): Json<RefreshTokenRequest>,
 ) -> Result<Json<AuthResponse>, StatusCode> {
-    let refresh_token = payload.get("refresh_token")
-        .and_then(|v| v.as_str())
-        .ok_or(StatusCode::BAD_REQUEST)?;
-
-    let claims = verify_jwt_token(refresh_token, &state.config.jwt_secret)?;
-
-    // Generate new access token
-    let new_claims = Claims {
-        sub: claims.sub.clone(),
-        email: claims.email.clone(),
-        role: claims.role.clone(),
-        exp: (Utc::now().timestamp() + state.config.jwt_expiry as i64) as usize,
-        iat: Utc::now().timestamp() as usize,
+    // Unparseable tokens have no trustworthy subject, so they only count
+    // against the caller's address
+    let claims = match verify_jwt_token(&payload.refresh_token, &state.config.jwt_secret) {
+        Ok(claims) if claims.token_type == REFRESH_TOKEN => claims,
+        _ => {
+            let guard = LoginGuard::for_identifier("refresh:invalid", session_context.client_ip);
+            guard.check(&state).await?;
+            guard.record_failure(&state, None, "refresh_failed").await?;
+            return Err(StatusCode::UNAUTHORIZED);
+        }
     };
 
-    let token = encode(
-        &Header::default(),
-        &new_claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let subject = Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let guard = LoginGuard::for_user(subject, session_context.client_ip);
+    guard.check(&state).await?;
 
-    // Generate new refresh token
-    let new_refresh_claims = Claims {
-        sub: claims.sub.clone(),
-        email: claims.email.clone(),
-        role: claims.role.clone(),
-        exp: (Utc::now().timestamp() + 7 * 24 * 60 * 60) as usize,
-        iat: Utc::now().timestamp() as usize,
+    let stored = sqlx::query("SELECT * FROM refresh_tokens WHERE token_hash = $1")
+        .bind(hash_data(&payload.refresh_token))
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let stored = match stored {
+        Some(row) if row.get::<Uuid, _>("user_id") == subject => row,
+        _ => {
+            guard.record_failure(&state, Some(subject), "refresh_failed").await?;
+            return Err(StatusCode::UNAUTHORIZED);
+        }
     };
 
-    let new_refresh_token = encode(
-        &Header::default(),
-        &new_refresh_claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
+    let token_id = stored.get::<Uuid, _>("id");
+    let family_id = stored.get::<Uuid, _>("family_id");
+    let user_id = subject;
+    let now = Utc::now();
+
+    // A revoked refresh token is being replayed: assume it was stolen and kill
+    // every token descended from the same login. This runs before the session
+    // is touched so a replay never counts as session activity. Tokens revoked
+    // because the user signed the session out are simply rejected.
+    if stored.get::<Option<chrono::DateTime<Utc>>, _>("revoked_at").is_some() {
+        let signed_out = sqlx::query("SELECT revoked_at FROM sessions WHERE id = $1")
+            .bind(family_id)
+            .fetch_optional(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .is_some_and(|row| row.get::<Option<chrono::DateTime<Utc>>, _>("revoked_at").is_some());
+
+        if !signed_out {
+            warn!("Refresh token reuse detected for user {}, revoking family {}", user_id, family_id);
+            revoke_token_family(&state, family_id).await?;
+            guard.record_failure(&state, Some(user_id), "refresh_token_reused").await?;
+        }
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    resume_session(&state, family_id, user_id, &session_context).await?;
+
+    if stored.get::<chrono::DateTime<Utc>, _>("expires_at") <= now {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_row = sqlx::query("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !matches!(user_row.get::<UserStatus, _>("status"), UserStatus::Active) {
+        revoke_token_family(&state, family_id).await?;
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // Consume the presented token; losing this race means a concurrent
+    // request already used it, which is treated as reuse as well
+    let consumed = sqlx::query("UPDATE refresh_tokens SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL")
+        .bind(now)
+        .bind(token_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .rows_affected();
+
+    if consumed == 0 {
+        warn!("Concurrent refresh token reuse for user {}, revoking family {}", user_id, family_id);
+        revoke_token_family(&state, family_id).await?;
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let tokens = issue_token_pair(
+        &state,
+        user_id,
+        &user_row.get::<String, _>("email"),
+        &user_row.get::<UserRole, _>("role"),
+        family_id,
     )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .await?;
+
+    sqlx::query("UPDATE refresh_tokens SET replaced_by = $1 WHERE id = $2")
+        .bind(tokens.refresh_token_id)
+        .bind(token_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     Ok(Json(AuthResponse {
-        token,
-        refresh_token: new_refresh_token,
-        user: UserResponse {
-            id: claims.sub,
-            email: claims.email,
-            username: "".to_string(), // Will be filled from database if needed
-            first_name: "".to_string(),
-            last_name: "".to_string(),
-            phone: None,
-            avatar_url: None,
-            role: claims.role,
-            status: "active".to_string(),
-            email_verified: false,
-            phone_verified: false,
-            kyc_verified: false,
-            biometric_enabled: false,
-            world_id_verified: false,
-            internet_identity_principal: None,
-            created_at: Utc::now().to_rfc3339(),
-            last_login: None,
-        },
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        user: user_response_from_row(&user_row),
         expires_in: state.config.jwt_expiry,
     }))
 }
@@ -535,7 +712,7 @@ pub async fn update_profile(
     Json(payload): Json<serde_json::Value>,
 ) -> Result<Json<UserResponse>, StatusCode> {
     let token = extract_token_from_headers(&headers)?;
-    let claims = verify_jwt_token(&token, &state.config.jwt_secret)?;
+    let claims = authenticate_access_token(&state, &token).await?;
     let user_id = Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?;
 
     // Update user profile based on provided fields
@@ -590,59 +767,161 @@ pub async fn update_profile(
     get_profile(state, headers).await
 }
 
-// Advanced Authentication Methods
+// Password management
+
+const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
 
-pub async fn kyc_verification(
+pub async fn forgot_password(
     State(state): State<crate::AppState>,
-    Json(payload): Json<KYCRequest>,
+    Json(payload): Json<ForgotPasswordRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    info!("KYC verification request for user: {}", payload.user_id);
+    info!("Password reset requested for email: {}", payload.email);
 
-    let user_id = Uuid::parse_str(&payload.user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    // The response is identical whether or not the account exists so the
+    // endpoint cannot be used to enumerate registered emails
+    let response = Json(serde_json::json!({
+        "message": "If an account exists for this email, a reset link has been sent"
+    }));
 
-    // In a real implementation, you would:
-    // 1. Validate the document image
-    // 2. Extract information using OCR
-    // 3. Verify against government databases
-    // 4. Store the verification result
+    let user_row = sqlx::query("SELECT id, email FROM users WHERE email = $1")
+        .bind(&payload.email)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    // For now, we'll simulate the verification process
-    let verification_result = verify_kyc_document(&payload).await;
+    let user_row = match user_row {
+        Some(row) => row,
+        None => return Ok(response),
+    };
 
-    if verification_result {
-        // Update user KYC status
-        sqlx::query("UPDATE users SET kyc_verified = true, updated_at = $1 WHERE id = $2")
-            .bind(Utc::now())
-            .bind(user_id)
-            .execute(&state.db.pool)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user_id = user_row.get::<Uuid, _>("id");
+    let secret = generate_random_string(48);
 
-        info!("KYC verification successful for user: {}", payload.user_id);
+    match issue_code(&state, user_id, PASSWORD_RESET, &secret, chrono::Duration::minutes(PASSWORD_RESET_TTL_MINUTES)).await {
+        Ok(_) => {}
+        Err(StatusCode::TOO_MANY_REQUESTS) => return Ok(response),
+        Err(status) => return Err(status),
+    }
 
-        Ok(Json(serde_json::json!({
-            "status": "verified",
-            "message": "KYC verification completed successfully",
-            "verified_at": Utc::now().to_rfc3339()
-        })))
-    } else {
-        warn!("KYC verification failed for user: {}", payload.user_id);
-        Err(StatusCode::BAD_REQUEST)
+    // The user id travels with the secret so the reset needs no other input
+    let token = format!("{}.{}", user_id.simple(), secret);
+    let reset_link = format!("{}/reset-password?token={}", state.config.app_url, token);
+
+    send_email(
+        &user_row.get::<String, _>("email"),
+        "Reset your password",
+        &format!(
+            "Use the following link to reset your password: {}\nThe link expires in {} minutes and can be used once.",
+            reset_link, PASSWORD_RESET_TTL_MINUTES
+        ),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to send password reset email: {}", e);
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    Ok(response)
+}
+
+pub async fn reset_password(
+    State(state): State<crate::AppState>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let (user_id, secret) = payload.token.split_once('.').ok_or(StatusCode::BAD_REQUEST)?;
+    let user_id = Uuid::parse_str(user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    info!("Password reset attempt for user: {}", user_id);
+
+    validate_password(&payload.new_password).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+    consume_code(&state, user_id, PASSWORD_RESET, secret).await?;
+
+    set_password(&state, user_id, &payload.new_password).await?;
+    revoke_all_sessions(&state, user_id).await?;
+
+    info!("Password reset completed for user: {}", user_id);
+
+    Ok(Json(serde_json::json!({
+        "message": "Password has been reset, please sign in again"
+    })))
+}
+
+pub async fn change_password(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    session_context: SessionContext,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<AuthResponse>, StatusCode> {
+    info!("Password change for user: {}", auth_user.user_id);
+
+    let user_row = sqlx::query("SELECT * FROM users WHERE id = $1")
+        .bind(auth_user.user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let password_hash = user_row.get::<String, _>("password_hash");
+    let parsed_hash = PasswordHash::new(&password_hash)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if Argon2::default()
+        .verify_password(payload.current_password.as_bytes(), &parsed_hash)
+        .is_err()
+    {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if payload.new_password == payload.current_password {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
     }
+    validate_password(&payload.new_password).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    set_password(&state, auth_user.user_id, &payload.new_password).await?;
+    revoke_all_sessions(&state, auth_user.user_id).await?;
+
+    // Every other session is signed out; hand the caller a fresh pair
+    let session_id = create_session(&state, auth_user.user_id, &session_context).await?;
+    let tokens = issue_token_pair(&state, auth_user.user_id, &auth_user.email, &auth_user.role, session_id).await?;
+
+    info!("Password changed for user: {}", auth_user.user_id);
+
+    Ok(Json(AuthResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        user: user_response_from_row(&user_row),
+        expires_in: state.config.jwt_expiry,
+    }))
 }
 
+// Advanced Authentication Methods
+
 pub async fn biometric_auth(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    client_ip: ClientIp,
     Json(payload): Json<BiometricAuthRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     info!("Biometric authentication request for user: {}", payload.user_id);
 
     let user_id = Uuid::parse_str(&payload.user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if user_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::Admin])?;
+    }
+
+    let guard = LoginGuard::for_user(user_id, client_ip);
+    guard.check(&state).await?;
 
     // Verify biometric data
     let verification_result = verify_biometric_data(&payload).await;
 
     if verification_result {
+        guard.record_success(&state, Some(user_id), "biometric_verified").await?;
+
+        if let Some(session_id) = auth_user.session_id.filter(|_| user_id == auth_user.user_id) {
+            update_device_info(&state, session_id, &payload.device_info).await?;
+        }
+
         // Update user biometric status
         sqlx::query("UPDATE users SET biometric_enabled = true, updated_at = $1 WHERE id = $2")
             .bind(Utc::now())
@@ -660,22 +939,33 @@ pub async fn biometric_auth(
         })))
     } else {
         warn!("Biometric authentication failed for user: {}", payload.user_id);
+        guard.record_failure(&state, Some(user_id), "biometric_failed").await?;
         Err(StatusCode::UNAUTHORIZED)
     }
 }
 
 pub async fn world_id_verify(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    client_ip: ClientIp,
     Json(payload): Json<WorldIDVerificationRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     info!("World ID verification request for user: {}", payload.user_id);
 
     let user_id = Uuid::parse_str(&payload.user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if user_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::Admin])?;
+    }
+
+    let guard = LoginGuard::for_user(user_id, client_ip);
+    guard.check(&state).await?;
 
     // Verify World ID proof
     let verification_result = verify_world_id_proof(&payload.proof).await;
 
     if verification_result {
+        guard.record_success(&state, Some(user_id), "world_id_verified").await?;
+
         // Update user World ID status
         sqlx::query("UPDATE users SET world_id_verified = true, updated_at = $1 WHERE id = $2")
             .bind(Utc::now())
@@ -693,73 +983,112 @@ pub async fn world_id_verify(
         })))
     } else {
         warn!("World ID verification failed for user: {}", payload.user_id);
+        guard.record_failure(&state, Some(user_id), "world_id_failed").await?;
         Err(StatusCode::UNAUTHORIZED)
     }
 }
 
+pub async fn internet_identity_challenge(
+    State(state): State<crate::AppState>,
+) -> Result<Json<InternetIdentityChallengeResponse>, StatusCode> {
+    let challenge = generate_random_string(32);
+    let expires_at = Utc::now() + chrono::Duration::minutes(CHALLENGE_TTL_MINUTES);
+
+    sqlx::query("INSERT INTO ii_challenges (challenge, expires_at) VALUES ($1, $2)")
+        .bind(&challenge)
+        .bind(expires_at)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to store Internet Identity challenge: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(InternetIdentityChallengeResponse {
+        challenge,
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
 pub async fn internet_identity_auth(
     State(state): State<crate::AppState>,
+    session_context: SessionContext,
     Json(payload): Json<InternetIdentityAuthRequest>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<Json<LoginResponse>, StatusCode> {
     info!("Internet Identity authentication request for principal: {}", payload.principal);
 
-    // Verify Internet Identity signature
-    let verification_result = verify_internet_identity(&payload.principal).await;
+    // The principal is derived from the verified chain, never taken from the client
+    let verified = match verify_internet_identity(&state, &payload).await {
+        Ok(verified) => verified,
+        Err(status) => {
+            warn!("Internet Identity authentication failed for principal: {}", payload.principal);
+            return Err(status);
+        }
+    };
+    let principal = verified.principal.to_text();
 
-    if verification_result {
-        // Find or create user with this principal
-        let user_id = Uuid::new_v4();
-        let now = Utc::now();
-
-        sqlx::query(
-            r#"
-            INSERT INTO users (
-                id, email, username, password_hash, first_name, last_name,
-                role, status, email_verified, phone_verified, kyc_verified,
-                biometric_enabled, world_id_verified, internet_identity_principal,
-                created_at, updated_at
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
-            ON CONFLICT (internet_identity_principal) DO UPDATE SET
-                updated_at = $16, last_login = $16
-            "#,
-        )
-        .bind(user_id)
-        .bind(format!("{}@internet-identity.local", payload.principal))
-        .bind(format!("user_{}", &payload.principal[..8]))
-        .bind("") // No password for Internet Identity
-        .bind("Internet")
-        .bind("Identity")
-        .bind(&UserRole::Customer)
-        .bind(&UserStatus::Active)
-        .bind(true) // email_verified
-        .bind(false) // phone_verified
-        .bind(false) // kyc_verified
-        .bind(false) // biometric_enabled
-        .bind(false) // world_id_verified
-        .bind(Some(payload.principal.clone()))
-        .bind(now)
-        .bind(now)
-        .execute(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !payload.principal.is_empty() && payload.principal != principal {
+        warn!("Client principal {} does not match delegation principal {}", payload.principal, principal);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
 
-        info!("Internet Identity authentication successful for principal: {}", payload.principal);
+    // Find or create user with this principal
+    let user_id = Uuid::new_v4();
+    let now = Utc::now();
 
-        Ok(Json(serde_json::json!({
-            "status": "authenticated",
-            "message": "Internet Identity authentication completed successfully",
-            "principal": payload.principal,
-            "authenticated_at": now.to_rfc3339()
-        })))
-    } else {
-        warn!("Internet Identity authentication failed for principal: {}", payload.principal);
-        Err(StatusCode::UNAUTHORIZED)
+    let user_row = sqlx::query(
+        r#"
+        INSERT INTO users (
+            id, email, username, password_hash, first_name, last_name,
+            role, status, email_verified, phone_verified, kyc_verified,
+            biometric_enabled, world_id_verified, internet_identity_principal,
+            created_at, updated_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        ON CONFLICT (internet_identity_principal) DO UPDATE SET
+            updated_at = $16
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(format!("{}@internet-identity.local", principal))
+    // The full principal (at most 63 characters) keeps usernames unique
+    .bind(format!("user_{}", principal))
+    .bind("") // No password for Internet Identity
+    .bind("Internet")
+    .bind("Identity")
+    .bind(&UserRole::Customer)
+    .bind(&UserStatus::Active)
+    .bind(true) // email_verified
+    .bind(false) // phone_verified
+    .bind(false) // kyc_verified
+    .bind(false) // biometric_enabled
+    .bind(false) // world_id_verified
+    .bind(Some(principal.clone()))
+    .bind(now)
+    .bind(now)
+    .fetch_one(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !matches!(user_row.get::<UserStatus, _>("status"), UserStatus::Active) {
+        return Err(StatusCode::UNAUTHORIZED);
     }
+
+    info!(
+        "Internet Identity authentication successful for principal: {} (delegation expires {})",
+        principal,
+        verified.expires_at.to_rfc3339()
+    );
+
+    // Same tokens (or two-factor challenge) as a password or SIWE login
+    let response = begin_login(&state, &user_row, &session_context).await?;
+
+    Ok(Json(response))
 }
 
 // Helper functions
 
-fn extract_token_from_headers(headers: &HeaderMap) -> Result<String, StatusCode> {
+pub(crate) fn extract_token_from_headers(headers: &HeaderMap) -> Result<String, StatusCode> {
     let auth_header = headers
         .get("authorization")
         .and_then(|header| header.to_str().ok())
@@ -772,7 +1101,7 @@ fn extract_token_from_headers(headers: &HeaderMap) -> Result<String, StatusCode>
     Ok(auth_header[7..].to_string())
 }
 
-fn verify_jwt_token(token: &str, secret: &str) -> Result<Claims, StatusCode> {
+pub(crate) fn verify_jwt_token(token: &str, secret: &str) -> Result<Claims, StatusCode> {
     let validation = Validation::new(Algorithm::HS256);
     let token_data = decode::<Claims>(
         token,
@@ -784,6 +1113,231 @@ fn verify_jwt_token(token: &str, secret: &str) -> Result<Claims, StatusCode> {
     Ok(token_data.claims)
 }
 
+/// Validates an access token and consults the revocation list. Refresh
+/// tokens are rejected so they cannot be replayed as bearer tokens.
+pub(crate) async fn authenticate_access_token(state: &crate::AppState, token: &str) -> Result<Claims, StatusCode> {
+    let claims = verify_jwt_token(token, &state.config.jwt_secret)?;
+    if claims.token_type != ACCESS_TOKEN {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let revoked = sqlx::query("SELECT jti FROM revoked_tokens WHERE jti = $1")
+        .bind(&claims.jti)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .is_some();
+
+    if revoked {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // Tokens minted before the last password change/reset are no longer valid
+    let tokens_valid_after = sqlx::query("SELECT tokens_valid_after FROM users WHERE id = $1")
+        .bind(Uuid::parse_str(&claims.sub).map_err(|_| StatusCode::UNAUTHORIZED)?)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .get::<Option<chrono::DateTime<Utc>>, _>("tokens_valid_after");
+
+    // Tokens without `iat_micros` count from the start of their second, so one
+    // minted in the same second as the cutoff is rejected rather than let through
+    let issued_micros = claims.iat_micros.unwrap_or(claims.iat as i64 * 1_000_000);
+    if tokens_valid_after.is_some_and(|after| issued_micros < after.timestamp_micros()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // Tokens bound to a session die with it
+    if let Some(sid) = &claims.sid {
+        let session_id = Uuid::parse_str(sid).map_err(|_| StatusCode::UNAUTHORIZED)?;
+        ensure_session_active(state, session_id).await?;
+    }
+
+    Ok(claims)
+}
+
+pub(crate) fn encode_token(
+    state: &crate::AppState,
+    user_id: Uuid,
+    email: &str,
+    role: &UserRole,
+    token_type: &str,
+    ttl: u64,
+    session_id: Option<Uuid>,
+) -> Result<(Claims, String), StatusCode> {
+    let issued_at = Utc::now();
+    let now = issued_at.timestamp();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        email: email.to_string(),
+        role: role.as_str().to_string(),
+        exp: (now + ttl as i64) as usize,
+        iat: now as usize,
+        iat_micros: Some(issued_at.timestamp_micros()),
+        jti: Uuid::new_v4().to_string(),
+        token_type: token_type.to_string(),
+        sid: session_id.map(|id| id.to_string()),
+    };
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(state.config.jwt_secret.as_ref()),
+    )
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((claims, token))
+}
+
+struct IssuedTokens {
+    access_token: String,
+    refresh_token: String,
+    refresh_token_id: Uuid,
+}
+
+/// Issues an access token plus a refresh token bound to `session_id`; the
+/// session id doubles as the refresh token family.
+async fn issue_token_pair(
+    state: &crate::AppState,
+    user_id: Uuid,
+    email: &str,
+    role: &UserRole,
+    session_id: Uuid,
+) -> Result<IssuedTokens, StatusCode> {
+    let (_, access_token) =
+        encode_token(state, user_id, email, role, ACCESS_TOKEN, state.config.jwt_expiry, Some(session_id))?;
+    let (refresh_claims, refresh_token) =
+        encode_token(state, user_id, email, role, REFRESH_TOKEN, state.config.jwt_refresh_expiry, Some(session_id))?;
+
+    let refresh_token_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(refresh_token_id)
+    .bind(user_id)
+    .bind(session_id)
+    .bind(hash_data(&refresh_token))
+    .bind(chrono::DateTime::from_timestamp(refresh_claims.exp as i64, 0).unwrap_or_else(Utc::now))
+    .bind(Utc::now())
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error storing refresh token: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(IssuedTokens {
+        access_token,
+        refresh_token,
+        refresh_token_id,
+    })
+}
+
+/// Adds a token's `jti` to the revocation list. Returns `false` when it was
+/// already revoked, which lets single-use tokens detect replays.
+pub(crate) async fn revoke_token(state: &crate::AppState, user_id: Uuid, claims: &Claims) -> Result<bool, StatusCode> {
+    let inserted = sqlx::query(
+        "INSERT INTO revoked_tokens (jti, user_id, expires_at, revoked_at) VALUES ($1, $2, $3, $4) ON CONFLICT (jti) DO NOTHING"
+    )
+    .bind(&claims.jti)
+    .bind(user_id)
+    .bind(chrono::DateTime::from_timestamp(claims.exp as i64, 0).unwrap_or_else(Utc::now))
+    .bind(Utc::now())
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error revoking token: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .rows_affected();
+
+    Ok(inserted == 1)
+}
+
+async fn revoke_token_family(state: &crate::AppState, family_id: Uuid) -> Result<(), StatusCode> {
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = $1 WHERE family_id = $2 AND revoked_at IS NULL")
+        .bind(Utc::now())
+        .bind(family_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(())
+}
+
+async fn set_password(state: &crate::AppState, user_id: Uuid, password: &str) -> Result<(), StatusCode> {
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .to_string();
+
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = $2 WHERE id = $3")
+        .bind(&password_hash)
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|e| {
+            error!("Database error updating password: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(())
+}
+
+/// Signs the user out everywhere: every refresh token is revoked and every
+/// access token issued up to now stops being accepted.
+pub(crate) async fn revoke_all_sessions(state: &crate::AppState, user_id: Uuid) -> Result<(), StatusCode> {
+    let now = Utc::now();
+
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL")
+        .bind(now)
+        .bind(user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    sqlx::query("UPDATE users SET tokens_valid_after = $1 WHERE id = $2")
+        .bind(now)
+        .bind(user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    revoke_user_sessions(state, user_id).await?;
+
+    Ok(())
+}
+
+pub(crate) fn user_response_from_row(user_row: &sqlx::postgres::PgRow) -> UserResponse {
+    UserResponse {
+        id: user_row.get::<Uuid, _>("id").to_string(),
+        email: user_row.get::<String, _>("email"),
+        username: user_row.get::<String, _>("username"),
+        first_name: user_row.get::<String, _>("first_name"),
+        last_name: user_row.get::<String, _>("last_name"),
+        phone: user_row.get::<Option<String>, _>("phone"),
+        avatar_url: user_row.get::<Option<String>, _>("avatar_url"),
+        role: user_row.get::<UserRole, _>("role").as_str().to_string(),
+        status: format!("{:?}", user_row.get::<UserStatus, _>("status")).to_lowercase(),
+        email_verified: user_row.get::<bool, _>("email_verified"),
+        phone_verified: user_row.get::<bool, _>("phone_verified"),
+        kyc_verified: user_row.get::<bool, _>("kyc_verified"),
+        biometric_enabled: user_row.get::<bool, _>("biometric_enabled"),
+        world_id_verified: user_row.get::<bool, _>("world_id_verified"),
+        two_factor_enabled: user_row.get::<bool, _>("totp_enabled"),
+        internet_identity_principal: user_row.get::<Option<String>, _>("internet_identity_principal"),
+        created_at: user_row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
+        last_login: user_row.get::<Option<chrono::DateTime<Utc>>, _>("last_login")
+            .map(|dt| dt.to_rfc3339()),
+    }
+}
+
 async fn verify_biometric_auth(user_row: &sqlx::postgres::PgRow, biometric_data: &str) -> bool {
     // In a real implementation, you would:
     // 1. Decode the biometric data
@@ -804,25 +1358,41 @@ async fn verify_world_id_proof(proof: &str) -> bool {
     !proof.is_empty() && proof.len() > 10
 }
 
-async fn verify_internet_identity(principal: &str) -> bool {
-    // In a real implementation, you would:
-    // 1. Verify the signature using ICP's verification methods
-    // 2. Check the delegation chain
-    // 3. Validate the principal format
-    
-    // For now, we'll simulate verification
-    !principal.is_empty() && principal.len() > 20
-}
+/// Verifies the delegation chain against the configured IC root key, then
+/// checks the session key signed a fresh, unused challenge.
+async fn verify_internet_identity(
+    state: &crate::AppState,
+    request: &InternetIdentityAuthRequest,
+) -> Result<VerifiedDelegation, StatusCode> {
+    let chain = DelegationChain::parse(&request.delegation).map_err(StatusCode::from)?;
+
+    let settings = VerifierSettings {
+        root_key_der_hex: &state.config.icp_root_key,
+        identity_canister_id: &state.config.ii_canister_id,
+        target_canister_id: Some(state.config.icp_canister_id.as_str()).filter(|id| !id.is_empty()),
+    };
+    let verified = chain.verify(&settings, Utc::now()).map_err(|e| {
+        warn!("Delegation chain rejected: {}", e);
+        StatusCode::from(e)
+    })?;
 
-async fn verify_kyc_document(request: &KYCRequest) -> bool {
-    // In a real implementation, you would:
-    // 1. Process the document image
-    // 2. Extract text using OCR
-    // 3. Validate against government databases
-    // 4. Check for document authenticity
-    
-    // For now, we'll simulate verification
-    !request.document_number.is_empty() && !request.document_image.is_empty()
+    verify_session_signature(&verified.session_public_key, request.challenge.as_bytes(), &request.signature)
+        .map_err(StatusCode::from)?;
+
+    let consumed = sqlx::query(
+        "UPDATE ii_challenges SET consumed_at = NOW()
+         WHERE challenge = $1 AND consumed_at IS NULL AND expires_at > NOW()",
+    )
+    .bind(&request.challenge)
+    .execute(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if consumed.rows_affected() != 1 {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(verified)
 }
 
 async fn verify_biometric_data(request: &BiometricAuthRequest) -> bool {
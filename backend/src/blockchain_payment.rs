@@ -14,6 +14,8 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
+use crate::kyc::{fiat_value, require_kyc_for_amount};
 
 #[derive(Debug, Clone)]
 pub struct BlockchainPaymentService {
@@ -177,6 +179,7 @@ pub enum BlockchainNetwork {
 
 pub async fn create_crypto_payment(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateCryptoPaymentRequest>,
 ) -> Result<Json<CryptoPaymentResponse>, StatusCode> {
     info!("Creating crypto payment: {} {}", payload.amount, format!("{:?}", payload.currency));
@@ -186,13 +189,17 @@ pub async fn create_crypto_payment(
         return Err(StatusCode::BAD_REQUEST);
     }
 
+    // The KYC threshold is a fiat amount
+    let fiat_amount = fiat_value(crypto_symbol(&payload.currency), payload.amount).await;
+    require_kyc_for_amount(&state, auth_user.user_id, fiat_amount).await?;
+
     // Validate recipient address
     if !validate_crypto_address(&payload.recipient_address, &payload.currency).await {
         return Err(StatusCode::BAD_REQUEST);
     }
 
     let payment_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Parse shipment_id if provided
     let shipment_id = if let Some(sid) = payload.shipment_id {
@@ -270,6 +277,7 @@ pub async fn create_crypto_payment(
 
 pub async fn create_icp_payment(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateICPPaymentRequest>,
 ) -> Result<Json<ICPPaymentResponse>, StatusCode> {
     info!("Creating ICP payment: {} ICP to {}", payload.amount, payload.recipient_principal);
@@ -285,7 +293,7 @@ pub async fn create_icp_payment(
     }
 
     let payment_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Parse shipment_id if provided
     let shipment_id = if let Some(sid) = payload.shipment_id {
@@ -354,12 +362,13 @@ pub async fn create_icp_payment(
 
 pub async fn create_nft_payment(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateNFTPaymentRequest>,
 ) -> Result<Json<NFTPaymentResponse>, StatusCode> {
     info!("Creating NFT payment for shipment: {:?}", payload.shipment_id);
 
     let payment_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
     let nft_id = Uuid::new_v4();
 
     // Parse shipment_id if provided
@@ -558,6 +567,23 @@ async fn validate_crypto_address(address: &str, currency: &CryptoCurrency) -> bo
     }
 }
 
+/// USD value of `amount`. Currencies without a price count as unbounded so
+/// they can't slip under the KYC threshold.
+fn crypto_symbol(currency: &CryptoCurrency) -> &'static str {
+    match currency {
+        CryptoCurrency::Bitcoin => "BTC",
+        CryptoCurrency::Ethereum => "ETH",
+        CryptoCurrency::Usdt => "USDT",
+        CryptoCurrency::Usdc => "USDC",
+        CryptoCurrency::Bnb => "BNB",
+        CryptoCurrency::Ada => "ADA",
+        CryptoCurrency::Sol => "SOL",
+        CryptoCurrency::Matic => "MATIC",
+        CryptoCurrency::ICP => "ICP",
+        CryptoCurrency::Worldcoin => "WLD",
+    }
+}
+
 async fn validate_icp_principal(principal: &str) -> bool {
     principal.len() > 20 && principal.chars().all(|c| c.is_alphanumeric())
 }
@@ -625,14 +651,19 @@ async fn get_blockchain_balance(address: &str, currency: &str) -> f64 {
     }
 }
 
-async fn get_crypto_price(currency: &str) -> f64 {
+pub(crate) async fn get_crypto_price(currency: &str) -> f64 {
     // In a real implementation, this would fetch from price API
     match currency {
         "ETH" => 2000.0,
         "BTC" => 45000.0,
         "USDT" => 1.0,
         "USDC" => 1.0,
+        "BNB" => 300.0,
+        "ADA" => 0.5,
+        "SOL" => 100.0,
+        "MATIC" => 0.8,
         "ICP" => 5.0,
+        "WLD" => 2.0,
         _ => 0.0,
     }
 }
@@ -8,11 +8,15 @@ pub struct Config {
     pub redis_url: String,
     pub jwt_secret: String,
     pub jwt_expiry: u64,
+    pub jwt_refresh_expiry: u64,
     pub cors_origins: Vec<String>,
+    pub app_url: String,
+    pub trust_proxy_headers: bool,
     
     // Web3 Configuration
     pub ethereum_rpc_url: String,
     pub ethereum_chain_id: u64,
+    pub siwe_domain: String,
     pub ethereum_private_key: String,
     pub ethereum_contract_address: String,
     
@@ -20,6 +24,8 @@ pub struct Config {
     pub icp_canister_id: String,
     pub icp_network_url: String,
     pub icp_identity_provider: String,
+    pub ii_canister_id: String,
+    pub icp_root_key: String,
     
     // External Services
     pub twilio_account_sid: String,
@@ -40,6 +46,8 @@ pub struct Config {
     
     // Security
     pub encryption_key: String,
+    pub kyc_validity_days: i64,
+    pub kyc_high_value_threshold: f64,
     pub rate_limit_requests: u32,
     pub rate_limit_window: u64,
     
@@ -49,6 +57,26 @@ pub struct Config {
     pub aws_region: String,
     pub aws_bucket_name: String,
     
+    // Tracking
+    pub geofence_radius_meters: f64,
+    pub geofence_dwell_seconds: i64,
+    pub delivery_zone_radius_meters: f64,
+    pub pod_max_distance_meters: f64,
+    
+    // Dispatch
+    pub dispatch_offer_timeout_seconds: i64,
+    pub dispatch_max_radius_km: f64,
+    
+    // Pricing
+    pub quote_ttl_minutes: i64,
+    
+    // Returns
+    pub return_window_days: i64,
+    
+    // Delivery exceptions
+    pub max_delivery_attempts: i32,
+    pub reattempt_delay_hours: i64,
+    
     // Monitoring
     pub prometheus_port: u16,
     pub log_level: String,
@@ -71,11 +99,20 @@ impl Config {
                 .unwrap_or_else(|_| "3600".to_string())
                 .parse()
                 .unwrap_or(3600),
+            jwt_refresh_expiry: env::var("JWT_REFRESH_EXPIRY")
+                .unwrap_or_else(|_| "604800".to_string())
+                .parse()
+                .unwrap_or(604800),
             cors_origins: env::var("CORS_ORIGINS")
                 .unwrap_or_else(|_| "http://localhost:3000,http://localhost:5173".to_string())
                 .split(',')
                 .map(|s| s.trim().to_string())
                 .collect(),
+            app_url: env::var("APP_URL")
+                .unwrap_or_else(|_| "http://localhost:5000".to_string()),
+            trust_proxy_headers: env::var("TRUST_PROXY_HEADERS")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
             
             // Web3 Configuration
             ethereum_rpc_url: env::var("ETHEREUM_RPC_URL")
@@ -84,6 +121,8 @@ impl Config {
                 .unwrap_or_else(|_| "1".to_string())
                 .parse()
                 .unwrap_or(1),
+            siwe_domain: env::var("SIWE_DOMAIN")
+                .unwrap_or_else(|_| "localhost:5000".to_string()),
             ethereum_private_key: env::var("ETHEREUM_PRIVATE_KEY")
                 .unwrap_or_else(|_| "".to_string()),
             ethereum_contract_address: env::var("ETHEREUM_CONTRACT_ADDRESS")
@@ -96,6 +135,11 @@ impl Config {
                 .unwrap_or_else(|_| "https://ic0.app".to_string()),
             icp_identity_provider: env::var("ICP_IDENTITY_PROVIDER")
                 .unwrap_or_else(|_| "https://identity.ic0.app".to_string()),
+            ii_canister_id: env::var("II_CANISTER_ID")
+                .unwrap_or_else(|_| "rdmx6-jaaaa-aaaaa-aaadq-cai".to_string()),
+            // DER-encoded, hex; override with a local replica's key for development
+            icp_root_key: env::var("ICP_ROOT_KEY")
+                .unwrap_or_else(|_| "308182301d060d2b0601040182dc7c0503010201060c2b0601040182dc7c05030201036100814c0e6ec71fab583b08bd81373c255c3c371b2e84863c98a4f1e08b74235d14fb5d9c0cd546d9685f913a0c0b2cc5341583bf4b4392e467db96d65b9bb4cb717112f8472e0d5a4d14505ffd7484b01291091c5f87b98883463f98091a0baaae".to_string()),
             
             // External Services
             twilio_account_sid: env::var("TWILIO_ACCOUNT_SID")
@@ -128,6 +172,15 @@ impl Config {
             // Security
             encryption_key: env::var("ENCRYPTION_KEY")
                 .unwrap_or_else(|_| "your-encryption-key".to_string()),
+            kyc_validity_days: env::var("KYC_VALIDITY_DAYS")
+                .unwrap_or_else(|_| "365".to_string())
+                .parse()
+                .unwrap_or(365),
+            // Shipment values and payment amounts at or above this need KYC
+            kyc_high_value_threshold: env::var("KYC_HIGH_VALUE_THRESHOLD")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()
+                .unwrap_or(1000.0),
             rate_limit_requests: env::var("RATE_LIMIT_REQUESTS")
                 .unwrap_or_else(|_| "100".to_string())
                 .parse()
@@ -147,6 +200,59 @@ impl Config {
             aws_bucket_name: env::var("AWS_BUCKET_NAME")
                 .unwrap_or_else(|_| "".to_string()),
             
+            // Tracking
+            geofence_radius_meters: env::var("GEOFENCE_RADIUS_METERS")
+                .unwrap_or_else(|_| "150".to_string())
+                .parse()
+                .unwrap_or(150.0),
+            // How long a vehicle must stay inside a fence to count as arrived
+            geofence_dwell_seconds: env::var("GEOFENCE_DWELL_SECONDS")
+                .unwrap_or_else(|_| "120".to_string())
+                .parse()
+                .unwrap_or(120),
+            delivery_zone_radius_meters: env::var("DELIVERY_ZONE_RADIUS_METERS")
+                .unwrap_or_else(|_| "5000".to_string())
+                .parse()
+                .unwrap_or(5000.0),
+            // Deliveries captured further than this from the address are flagged
+            pod_max_distance_meters: env::var("POD_MAX_DISTANCE_METERS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .unwrap_or(300.0),
+            
+            // Dispatch
+            // How long a driver has to accept an offer before it moves on
+            dispatch_offer_timeout_seconds: env::var("DISPATCH_OFFER_TIMEOUT_SECONDS")
+                .unwrap_or_else(|_| "120".to_string())
+                .parse()
+                .unwrap_or(120),
+            dispatch_max_radius_km: env::var("DISPATCH_MAX_RADIUS_KM")
+                .unwrap_or_else(|_| "50".to_string())
+                .parse()
+                .unwrap_or(50.0),
+            
+            // Pricing
+            quote_ttl_minutes: env::var("QUOTE_TTL_MINUTES")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            
+            // Returns
+            return_window_days: env::var("RETURN_WINDOW_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            
+            // Delivery exceptions
+            max_delivery_attempts: env::var("MAX_DELIVERY_ATTEMPTS")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .unwrap_or(3),
+            reattempt_delay_hours: env::var("REATTEMPT_DELAY_HOURS")
+                .unwrap_or_else(|_| "24".to_string())
+                .parse()
+                .unwrap_or(24),
+            
             // Monitoring
             prometheus_port: env::var("PROMETHEUS_PORT")
                 .unwrap_or_else(|_| "9090".to_string())
@@ -6,6 +6,7 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +15,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct ConfirmationService {
@@ -68,10 +70,16 @@ pub struct ConfirmationResponse {
 
 pub async fn create_confirmation(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateConfirmationRequest>,
 ) -> Result<Json<ConfirmationResponse>, StatusCode> {
     info!("Creating confirmation: {}", payload.title);
 
+    // Users can only open confirmations they take part in themselves
+    if !is_staff(&auth_user) && !is_participant(&payload.participants, auth_user.user_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     // Parse confirmation type and priority
     let confirmation_type = match payload.confirmation_type.as_str() {
         "delivery_confirmation" => ConfirmationType::DeliveryConfirmation,
@@ -154,6 +162,7 @@ pub async fn create_confirmation(
 
 pub async fn get_confirmation(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(confirmation_id): Path<String>,
 ) -> Result<Json<ConfirmationResponse>, StatusCode> {
     info!("Fetching confirmation: {}", confirmation_id);
@@ -171,6 +180,10 @@ pub async fn get_confirmation(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    if !is_staff(&auth_user) && !is_participant(&row.get::<serde_json::Value, _>("participants"), auth_user.user_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     let response = ConfirmationResponse {
         id: confirmation_id,
         confirmation_type: format!("{:?}", row.get::<ConfirmationType, _>("confirmation_type")).to_lowercase(),
@@ -195,6 +208,7 @@ pub async fn get_confirmation(
 
 pub async fn confirm(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(confirmation_id): Path<String>,
     Json(payload): Json<ConfirmRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
@@ -202,6 +216,11 @@ pub async fn confirm(
 
     let id = Uuid::parse_str(&confirmation_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
+    // Participants can only sign off for themselves
+    if Uuid::parse_str(&payload.participant_id).ok() != Some(auth_user.user_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     // Get confirmation
     let confirmation_row = sqlx::query("SELECT * FROM confirmations WHERE id = $1")
         .bind(id)
@@ -283,15 +302,23 @@ pub async fn confirm(
         let now = Utc::now();
         let blockchain_tx_hash = generate_blockchain_transaction(&confirmation_row, &now).await;
 
-        sqlx::query(
-            "UPDATE confirmations SET status = 'completed', completed_at = $1, blockchain_tx_hash = $2 WHERE id = $3"
+        // Delivery confirmations are created already anchored to their
+        // proof-of-delivery evidence hash; keep that anchor
+        let blockchain_tx_hash = sqlx::query(
+            r#"
+            UPDATE confirmations
+            SET status = 'completed', completed_at = $1, blockchain_tx_hash = COALESCE(blockchain_tx_hash, $2)
+            WHERE id = $3
+            RETURNING blockchain_tx_hash
+            "#,
         )
         .bind(now)
         .bind(blockchain_tx_hash)
         .bind(id)
-        .execute(&state.db.pool)
+        .fetch_one(&state.db.pool)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .get::<Option<String>, _>("blockchain_tx_hash");
 
         info!("Confirmation completed: {}", confirmation_id);
 
@@ -314,12 +341,25 @@ pub async fn confirm(
 
 pub async fn cancel(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(confirmation_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     info!("Cancelling confirmation: {}", confirmation_id);
 
     let id = Uuid::parse_str(&confirmation_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
+    let participants = sqlx::query("SELECT participants FROM confirmations WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?
+        .get::<serde_json::Value, _>("participants");
+
+    if !is_staff(&auth_user) && !is_participant(&participants, auth_user.user_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     // Update confirmation status to cancelled
     sqlx::query("UPDATE confirmations SET status = 'cancelled' WHERE id = $1")
         .bind(id)
@@ -338,29 +378,28 @@ pub async fn cancel(
 
 pub async fn get_pending(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<ConfirmationResponse>>, StatusCode> {
     info!("Fetching pending confirmations");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str());
+    let user_id = participant_filter(&auth_user, &params)?;
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(50);
 
-    let mut query = "SELECT * FROM confirmations WHERE status = 'pending'".to_string();
-    let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(uid) = user_id {
-        // This would need to check if the user is a participant in the confirmation
-        // For now, we'll fetch all pending confirmations
-    }
-
-    query.push_str(&format!(" ORDER BY created_at DESC LIMIT ${}", param_count));
-    bind_values.push(Box::new(limit as i64));
-
-    let rows = sqlx::query(&query)
-        .fetch_all(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM confirmations
+        WHERE status = 'pending'
+          AND ($1::text IS NULL OR participants @> jsonb_build_array(jsonb_build_object('id', $1::text)))
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(user_id.map(|id| id.to_string()))
+    .bind(limit as i64)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let confirmations = rows
         .into_iter()
@@ -389,29 +428,28 @@ pub async fn get_pending(
 
 pub async fn get_completed(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<ConfirmationResponse>>, StatusCode> {
     info!("Fetching completed confirmations");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str());
+    let user_id = participant_filter(&auth_user, &params)?;
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(50);
 
-    let mut query = "SELECT * FROM confirmations WHERE status = 'completed'".to_string();
-    let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(uid) = user_id {
-        // This would need to check if the user is a participant in the confirmation
-        // For now, we'll fetch all completed confirmations
-    }
-
-    query.push_str(&format!(" ORDER BY completed_at DESC LIMIT ${}", param_count));
-    bind_values.push(Box::new(limit as i64));
-
-    let rows = sqlx::query(&query)
-        .fetch_all(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM confirmations
+        WHERE status = 'completed'
+          AND ($1::text IS NULL OR participants @> jsonb_build_array(jsonb_build_object('id', $1::text)))
+        ORDER BY completed_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(user_id.map(|id| id.to_string()))
+    .bind(limit as i64)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let confirmations = rows
         .into_iter()
@@ -440,6 +478,31 @@ pub async fn get_completed(
 
 // Helper functions
 
+fn is_staff(auth_user: &AuthUser) -> bool {
+    matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin)
+}
+
+fn is_participant(participants: &serde_json::Value, user_id: Uuid) -> bool {
+    let user_id = user_id.to_string();
+    participants
+        .as_array()
+        .is_some_and(|list| list.iter().any(|p| p.get("id").and_then(|v| v.as_str()) == Some(user_id.as_str())))
+}
+
+/// Whose confirmations a listing covers: callers only see their own, staff
+/// may ask for a given `user_id` or see everyone's.
+fn participant_filter(auth_user: &AuthUser, params: &serde_json::Value) -> Result<Option<Uuid>, StatusCode> {
+    if !is_staff(auth_user) {
+        return Ok(Some(auth_user.user_id));
+    }
+
+    params
+        .get("user_id")
+        .and_then(|v| v.as_str())
+        .map(|id| Uuid::parse_str(id).map_err(|_| StatusCode::BAD_REQUEST))
+        .transpose()
+}
+
 async fn generate_blockchain_transaction(
     confirmation_row: &sqlx::postgres::PgRow,
     completed_at: &chrono::DateTime<Utc>,
@@ -0,0 +1,370 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashSet;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::dispatch::dispatch_shipment;
+use crate::eta::recalculate as recalculate_eta;
+use crate::geofencing::hub_address;
+use crate::models::*;
+use crate::tracking::{
+    insert_shipment, record_shipment_event, transition_shipment_status, validate_shipment_request,
+    CreateShipmentRequest, ShipmentEventRecord,
+};
+use crate::utils::AppError;
+
+// Consolidation: several shipments loaded onto one master shipment for a
+// hub-to-hub leg. While the master is open its shipments move with it and
+// can't change status on their own; once it arrives (or is cancelled) they
+// are released at the destination hub.
+
+const MAX_CONSOLIDATED_SHIPMENTS: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateConsolidationRequest {
+    pub shipment_ids: Vec<Uuid>,
+    pub origin_hub_id: Uuid,
+    pub destination_hub_id: Uuid,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsolidatedShipment {
+    pub id: String,
+    pub tracking_number: String,
+    pub status: String,
+    pub weight: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsolidationResponse {
+    pub id: String,
+    pub tracking_number: String,
+    pub status: String,
+    pub driver_id: Option<String>,
+    pub origin: serde_json::Value,
+    pub destination: serde_json::Value,
+    pub total_weight: f64,
+    /// Whether the leg is over and its shipments move on their own again.
+    pub released: bool,
+    pub shipments: Vec<ConsolidatedShipment>,
+    pub created_at: String,
+}
+
+pub async fn create_consolidation(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateConsolidationRequest>,
+) -> Result<Json<ConsolidationResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to consolidate shipments".to_string()))?;
+
+    let shipment_ids: Vec<Uuid> = payload
+        .shipment_ids
+        .iter()
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if shipment_ids.len() < 2 || shipment_ids.len() > MAX_CONSOLIDATED_SHIPMENTS {
+        return Err(AppError::Validation(format!(
+            "A consolidation needs between 2 and {} shipments",
+            MAX_CONSOLIDATED_SHIPMENTS
+        )));
+    }
+    if payload.origin_hub_id == payload.destination_hub_id {
+        return Err(AppError::Validation("Origin and destination hubs must differ".to_string()));
+    }
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+
+    let origin = hub_address(&state, payload.origin_hub_id).await?;
+    let destination = hub_address(&state, payload.destination_hub_id).await?;
+
+    let now = Utc::now();
+    let mut tx = state.db.pool.begin().await?;
+
+    let shipments = sqlx::query(
+        r#"
+        SELECT s.id, s.tracking_number, s.status, s.priority, s.is_consolidation,
+               s.weight::float8 AS weight_kg, master.status AS master_status
+        FROM shipments s
+        LEFT JOIN shipments master ON master.id = s.master_shipment_id
+        WHERE s.id = ANY($1)
+        FOR UPDATE OF s
+        "#,
+    )
+    .bind(&shipment_ids)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if shipments.len() != shipment_ids.len() {
+        return Err(AppError::NotFound("One or more shipments were not found".to_string()));
+    }
+
+    let mut problems = Vec::new();
+    for shipment in &shipments {
+        let tracking_number = shipment.get::<String, _>("tracking_number");
+        let status = shipment.get::<ShipmentStatus, _>("status");
+
+        if shipment.get::<bool, _>("is_consolidation") {
+            problems.push(format!("{} is itself a consolidation", tracking_number));
+        } else if shipment.get::<Option<ShipmentStatus>, _>("master_status").is_some_and(|master| !master.is_terminal()) {
+            problems.push(format!("{} is already consolidated", tracking_number));
+        } else if !matches!(status, ShipmentStatus::PickedUp | ShipmentStatus::InTransit) {
+            problems.push(format!("{} is {} and not in the network", tracking_number, status.as_str()));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(AppError::Conflict(problems.join("; ")));
+    }
+
+    let total_weight: f64 = shipments.iter().map(|shipment| shipment.get::<f64, _>("weight_kg")).sum();
+    let priority = shipments
+        .iter()
+        .map(|shipment| shipment.get::<ShipmentPriority, _>("priority"))
+        .max_by_key(|priority| priority_rank(*priority))
+        .unwrap_or(ShipmentPriority::Medium);
+
+    let master = CreateShipmentRequest {
+        sender_id: auth_user.user_id.to_string(),
+        receiver_id: auth_user.user_id.to_string(),
+        weight: total_weight,
+        dimensions: serde_json::json!({}),
+        description: notes
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("Consolidation of {} shipments", shipments.len())),
+        // The shipments carry their own declared values
+        value: 0.0,
+        currency: "USD".to_string(),
+        pickup_address: origin,
+        delivery_address: destination,
+        priority: priority.as_str().to_string(),
+        estimated_delivery: None,
+        pickup_window: None,
+        delivery_window: None,
+        quote_id: None,
+        cod_amount: None,
+        parcels: Vec::new(),
+        delivery_slot_id: None,
+        delivery_instructions: None,
+        leave_authorized: false,
+    };
+    let validated = validate_shipment_request(&master)
+        .map_err(|errors| AppError::Validation(format!("Consolidation is invalid: {}", errors.join("; "))))?;
+    let (master_id, master_tracking_number) =
+        insert_shipment(&mut tx, &state, &master, &validated, &auth_user, now).await?;
+
+    sqlx::query("UPDATE shipments SET is_consolidation = TRUE WHERE id = $1")
+        .bind(master_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE shipments SET master_shipment_id = $1, updated_at = $2 WHERE id = ANY($3)")
+        .bind(master_id)
+        .bind(now)
+        .bind(&shipment_ids)
+        .execute(&mut *tx)
+        .await?;
+
+    for shipment_id in &shipment_ids {
+        record_shipment_event(&mut tx, ShipmentEventRecord {
+            shipment_id: *shipment_id,
+            event_type: "consolidated",
+            from_status: None,
+            to_status: None,
+            actor: Some(&auth_user),
+            notes,
+            metadata: serde_json::json!({
+                "master_shipment_id": master_id,
+                "master_tracking_number": master_tracking_number,
+                "origin_hub_id": payload.origin_hub_id,
+                "destination_hub_id": payload.destination_hub_id
+            }),
+        })
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "Consolidation {} created by {} with {} shipments ({:.1} kg)",
+        master_tracking_number,
+        auth_user.user_id,
+        shipment_ids.len(),
+        total_weight
+    );
+
+    // Same follow-up as a newly created shipment
+    if let Err(e) = recalculate_eta(&state, master_id).await {
+        warn!("Could not estimate arrival for shipment {}: {}", master_id, e);
+    }
+    if let Err(e) = dispatch_shipment(&state, master_id, &[]).await {
+        warn!("Automatic dispatch failed for shipment {}: {}", master_id, e);
+    }
+
+    Ok(Json(load_consolidation(&state, master_id).await?))
+}
+
+pub async fn get_consolidation(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(master_id): Path<Uuid>,
+) -> Result<Json<ConsolidationResponse>, AppError> {
+    let consolidation = load_consolidation(&state, master_id).await?;
+
+    let is_driver = consolidation.driver_id.as_deref() == Some(auth_user.user_id.to_string().as_str());
+    if !is_driver && !matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin) {
+        return Err(AppError::Authorization("Not allowed to view this consolidation".to_string()));
+    }
+
+    Ok(Json(consolidation))
+}
+
+/// Rejects status changes for a shipment that is riding an open
+/// consolidation; it moves with its master until released.
+pub(crate) async fn ensure_not_consolidated(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+) -> Result<(), AppError> {
+    let master = sqlx::query(
+        r#"
+        SELECT master.tracking_number, master.status
+        FROM shipments s
+        JOIN shipments master ON master.id = s.master_shipment_id
+        WHERE s.id = $1
+        "#,
+    )
+    .bind(shipment_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match master {
+        Some(master) if !master.get::<ShipmentStatus, _>("status").is_terminal() => Err(AppError::Conflict(format!(
+            "Shipment moves with consolidation {} until it is released",
+            master.get::<String, _>("tracking_number")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Carries a master shipment's status change over to the shipments on it.
+/// Once the master is picked up they are in transit; when it arrives or is
+/// called off they are released where it stands.
+pub(crate) async fn follow_master(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    master_id: Uuid,
+    to: ShipmentStatus,
+    actor: &AuthUser,
+) -> Result<(), AppError> {
+    let Some(master) = sqlx::query("SELECT tracking_number FROM shipments WHERE id = $1 AND is_consolidation")
+        .bind(master_id)
+        .fetch_optional(&mut **tx)
+        .await?
+    else {
+        return Ok(());
+    };
+    let master_tracking_number = master.get::<String, _>("tracking_number");
+
+    let children = sqlx::query("SELECT id, status FROM shipments WHERE master_shipment_id = $1 ORDER BY id FOR UPDATE")
+        .bind(master_id)
+        .fetch_all(&mut **tx)
+        .await?;
+    let metadata = serde_json::json!({
+        "master_shipment_id": master_id,
+        "master_tracking_number": master_tracking_number
+    });
+
+    match to {
+        ShipmentStatus::PickedUp | ShipmentStatus::InTransit => {
+            let notes = format!("Moving with consolidation {}", master_tracking_number);
+            for child in &children {
+                if child.get::<ShipmentStatus, _>("status") == ShipmentStatus::PickedUp {
+                    transition_shipment_status(
+                        tx,
+                        child.get::<Uuid, _>("id"),
+                        ShipmentStatus::InTransit,
+                        Some(actor),
+                        Some(&notes),
+                        metadata.clone(),
+                    )
+                    .await?;
+                }
+            }
+        }
+        ShipmentStatus::Delivered | ShipmentStatus::Cancelled | ShipmentStatus::Returned => {
+            for child in &children {
+                record_shipment_event(tx, ShipmentEventRecord {
+                    shipment_id: child.get::<Uuid, _>("id"),
+                    event_type: "deconsolidated",
+                    from_status: None,
+                    to_status: None,
+                    actor: Some(actor),
+                    notes: None,
+                    metadata: metadata.clone(),
+                })
+                .await?;
+            }
+            info!("Consolidation {} released {} shipments", master_tracking_number, children.len());
+        }
+        ShipmentStatus::Pending | ShipmentStatus::OutForDelivery => {}
+    }
+
+    Ok(())
+}
+
+// Helper functions
+
+async fn load_consolidation(state: &crate::AppState, master_id: Uuid) -> Result<ConsolidationResponse, AppError> {
+    let master = sqlx::query("SELECT * FROM shipments WHERE id = $1 AND is_consolidation")
+        .bind(master_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Consolidation not found".to_string()))?;
+
+    let children = sqlx::query(
+        "SELECT id, tracking_number, status, weight::float8 AS weight_kg FROM shipments WHERE master_shipment_id = $1 ORDER BY tracking_number",
+    )
+    .bind(master_id)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    let status = master.get::<ShipmentStatus, _>("status");
+    let shipments: Vec<ConsolidatedShipment> = children
+        .iter()
+        .map(|child| ConsolidatedShipment {
+            id: child.get::<Uuid, _>("id").to_string(),
+            tracking_number: child.get::<String, _>("tracking_number"),
+            status: child.get::<ShipmentStatus, _>("status").as_str().to_string(),
+            weight: child.get::<f64, _>("weight_kg"),
+        })
+        .collect();
+
+    Ok(ConsolidationResponse {
+        id: master_id.to_string(),
+        tracking_number: master.get::<String, _>("tracking_number"),
+        status: status.as_str().to_string(),
+        driver_id: master.get::<Option<Uuid>, _>("driver_id").map(|id| id.to_string()),
+        origin: master.get::<serde_json::Value, _>("pickup_address"),
+        destination: master.get::<serde_json::Value, _>("delivery_address"),
+        total_weight: shipments.iter().map(|shipment| shipment.weight).sum(),
+        released: status.is_terminal(),
+        shipments,
+        created_at: master.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    })
+}
+
+fn priority_rank(priority: ShipmentPriority) -> u8 {
+    match priority {
+        ShipmentPriority::Low => 0,
+        ShipmentPriority::Medium => 1,
+        ShipmentPriority::High => 2,
+        ShipmentPriority::Urgent => 3,
+    }
+}
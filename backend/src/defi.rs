@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct DeFiService {
@@ -204,12 +205,13 @@ pub enum DeFiStatus {
 
 pub async fn create_staking(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateStakingRequest>,
 ) -> Result<Json<StakingResponse>, StatusCode> {
     info!("Creating staking position: {} {}", payload.amount, format!("{:?}", payload.currency));
 
     let staking_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Calculate APY based on protocol and currency
     let apy = calculate_staking_apy(&payload.protocol, &payload.currency, payload.staking_period).await;
@@ -283,12 +285,13 @@ pub async fn create_staking(
 
 pub async fn create_liquidity_pool(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateLiquidityPoolRequest>,
 ) -> Result<Json<LiquidityPoolResponse>, StatusCode> {
     info!("Creating liquidity pool: {} {} + {} {}", payload.amount_a, format!("{:?}", payload.token_a), payload.amount_b, format!("{:?}", payload.token_b));
 
     let pool_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Calculate pool share and total value
     let total_value = calculate_pool_value(&payload.token_a, payload.amount_a, &payload.token_b, payload.amount_b).await;
@@ -361,12 +364,13 @@ pub async fn create_liquidity_pool(
 
 pub async fn create_lending(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateLendingRequest>,
 ) -> Result<Json<LendingResponse>, StatusCode> {
     info!("Creating lending position: {} {}", payload.amount, format!("{:?}", payload.currency));
 
     let lending_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Calculate total interest
     let total_interest = calculate_total_interest(payload.amount, payload.interest_rate, payload.lending_period).await;
@@ -437,12 +441,13 @@ pub async fn create_lending(
 
 pub async fn create_yield_farming(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateYieldFarmingRequest>,
 ) -> Result<Json<YieldFarmingResponse>, StatusCode> {
     info!("Creating yield farming position: {} {}", payload.amount, format!("{:?}", payload.currency));
 
     let farming_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Calculate APY for the pool
     let apy = calculate_farming_apy(&payload.pool_id, &payload.currency).await;
@@ -513,17 +518,18 @@ pub async fn create_yield_farming(
 
 pub async fn get_defi_portfolio(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<DeFiPortfolioResponse>, StatusCode> {
     info!("Fetching DeFi portfolio");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str()).unwrap_or("default");
+    let user_id = auth_user.user_id.to_string();
 
     // Get all DeFi positions
-    let staking_positions = get_staking_positions(user_id).await;
-    let liquidity_positions = get_liquidity_positions(user_id).await;
-    let lending_positions = get_lending_positions(user_id).await;
-    let farming_positions = get_farming_positions(user_id).await;
+    let staking_positions = get_staking_positions(&user_id).await;
+    let liquidity_positions = get_liquidity_positions(&user_id).await;
+    let lending_positions = get_lending_positions(&user_id).await;
+    let farming_positions = get_farming_positions(&user_id).await;
 
     // Calculate totals
     let staking_value = staking_positions.iter().map(|p| p.value_usd).sum();
@@ -606,12 +612,13 @@ pub async fn get_defi_protocols(
 
 pub async fn claim_rewards(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(position_id): Path<String>,
 ) -> Result<Json<DeFiRewardResponse>, StatusCode> {
     info!("Claiming rewards for position: {}", position_id);
 
     let reward_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Claim rewards on blockchain
     let claim_result = claim_rewards_on_blockchain(&position_id).await;
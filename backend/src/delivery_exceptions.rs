@@ -0,0 +1,809 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::consolidation::ensure_not_consolidated;
+use crate::delivery_slots::release_slot;
+use crate::eta::recalculate as recalculate_eta;
+use crate::models::*;
+use crate::notifications::notify_user;
+use crate::parcels;
+use crate::proof_of_delivery::EvidenceFileContentResponse;
+use crate::realtime::ShipmentUpdate;
+use crate::tracking::{can_access_shipment, record_shipment_event, transition_shipment_status, ShipmentEventRecord};
+use crate::upload::{decode_image, load_upload, store_upload};
+use crate::utils::AppError;
+
+// Delivery exceptions: anything that stops a shipment from being delivered
+// as planned. Failed attempts at the door are counted; below the limit the
+// shipment goes back into the network with the next attempt scheduled, at
+// the limit it is returned to the sender. Open exceptions form the queue
+// operations staff work through, and close on their own once the shipment
+// moves on.
+
+const MAX_PHOTOS: usize = 5;
+const MAX_REASON_LENGTH: usize = 2000;
+const DEFAULT_QUEUE_LIMIT: i64 = 50;
+const MAX_QUEUE_LIMIT: i64 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionType {
+    FailedAttempt,
+    AddressProblem,
+    Damaged,
+    CustomsHold,
+    Refused,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextAction {
+    Reattempt,
+    ContactReceiver,
+    Hold,
+    ReturnToSender,
+    None,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportExceptionRequest {
+    pub exception_type: String,
+    /// Required for `other`
+    pub reason: Option<String>,
+    /// Base64 JPEG or PNG images, optionally as data URLs.
+    #[serde(default)]
+    pub photos: Vec<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Overrides the default follow-up for the type; staff only
+    pub next_action: Option<String>,
+    /// The parcels found damaged, for a damage report
+    pub parcel_ids: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveExceptionRequest {
+    pub notes: Option<String>,
+    /// `return_to_sender` to give up on delivering the shipment
+    pub next_action: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExceptionQueueQuery {
+    /// `open` (default) or `resolved`
+    pub status: Option<String>,
+    pub exception_type: Option<String>,
+    /// Only re-attempts whose scheduled time has passed
+    pub overdue: Option<bool>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExceptionResponse {
+    pub id: String,
+    pub shipment_id: String,
+    pub tracking_number: String,
+    pub shipment_status: String,
+    pub delivery_attempts: i32,
+    pub exception_type: String,
+    pub attempt_number: Option<i32>,
+    pub reason: Option<String>,
+    pub evidence_ids: Vec<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub next_action: String,
+    pub next_attempt_at: Option<String>,
+    /// A scheduled re-attempt that hasn't started on time
+    pub overdue: bool,
+    pub status: String,
+    pub reported_by: Option<String>,
+    pub resolved_by: Option<String>,
+    pub resolution_notes: Option<String>,
+    pub resolved_at: Option<String>,
+    pub created_at: String,
+}
+
+pub async fn report_exception(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<ReportExceptionRequest>,
+) -> Result<Json<ExceptionResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Driver, UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to report delivery exceptions".to_string()))?;
+    let is_staff = matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin);
+
+    let kind = ExceptionType::parse(&payload.exception_type).ok_or_else(|| {
+        AppError::Validation(format!("exception_type must be one of {}", ExceptionType::ALL_NAMES.join(", ")))
+    })?;
+    let reason = payload.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+    if kind == ExceptionType::Other && reason.is_none() {
+        return Err(AppError::Validation("A reason is required for other exceptions".to_string()));
+    }
+    if reason.is_some_and(|reason| reason.chars().count() > MAX_REASON_LENGTH) {
+        return Err(AppError::Validation(format!("reason must be at most {} characters", MAX_REASON_LENGTH)));
+    }
+    if payload.photos.len() > MAX_PHOTOS {
+        return Err(AppError::Validation(format!("At most {} photos can be attached", MAX_PHOTOS)));
+    }
+    let location = match (payload.latitude, payload.longitude) {
+        (Some(latitude), Some(longitude))
+            if (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude) =>
+        {
+            Some((latitude, longitude))
+        }
+        (None, None) => None,
+        _ => return Err(AppError::Validation("latitude and longitude must be valid and given together".to_string())),
+    };
+    let requested_action = match payload.next_action.as_deref() {
+        Some(_) if !is_staff => {
+            return Err(AppError::Authorization("Only operations staff can choose the next action".to_string()))
+        }
+        Some(action) => Some(NextAction::parse(action).ok_or_else(|| {
+            AppError::Validation(format!("next_action must be one of {}", NextAction::ALL_NAMES.join(", ")))
+        })?),
+        None => None,
+    };
+    if payload.parcel_ids.is_some() && kind != ExceptionType::Damaged {
+        return Err(AppError::InvalidInput("parcel_ids only apply to damage reports".to_string()));
+    }
+
+    let photos = payload
+        .photos
+        .iter()
+        .map(|photo| decode_image(photo))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let now = Utc::now();
+    let mut tx = state.db.pool.begin().await?;
+
+    let shipment = sqlx::query("SELECT * FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+    if !is_staff && shipment.get::<Option<Uuid>, _>("driver_id") != Some(auth_user.user_id) {
+        return Err(AppError::Authorization("Only the assigned driver can report on this shipment".to_string()));
+    }
+
+    let status = shipment.get::<ShipmentStatus, _>("status");
+    if status.is_terminal() {
+        return Err(AppError::Conflict(format!("Shipment is {}", status.as_str())));
+    }
+    let at_door = status == ShipmentStatus::OutForDelivery;
+    if matches!(kind, ExceptionType::FailedAttempt | ExceptionType::Refused) && !at_door {
+        return Err(AppError::Conflict(format!(
+            "A {} needs the shipment to be out for delivery",
+            kind.as_str().replace('_', " ")
+        )));
+    }
+
+    // Exceptions raised at the door count as a delivery attempt
+    let counts_attempt = at_door
+        && matches!(kind, ExceptionType::FailedAttempt | ExceptionType::Refused | ExceptionType::AddressProblem);
+    let attempt_number = counts_attempt.then(|| shipment.get::<i32, _>("delivery_attempts") + 1);
+
+    let mut next_action = requested_action.unwrap_or_else(|| kind.default_action());
+    let escalated = attempt_number.is_some_and(|attempt| attempt >= state.config.max_delivery_attempts)
+        && matches!(next_action, NextAction::Reattempt | NextAction::ContactReceiver);
+    if escalated {
+        next_action = NextAction::ReturnToSender;
+    }
+
+    let tracking_number = shipment.get::<String, _>("tracking_number");
+    let transition_notes = match attempt_number {
+        Some(attempt) => format!("Delivery attempt {} failed: {}", attempt, kind.as_str()),
+        None => format!("Delivery exception: {}", kind.as_str()),
+    };
+
+    // A shipment that isn't handed over goes back into the network, unless
+    // it is being sent back
+    let target = match next_action {
+        NextAction::ReturnToSender => {
+            if !status.can_transition_to(ShipmentStatus::Returned) {
+                return Err(AppError::Conflict("Shipment hasn't been picked up and can't be returned".to_string()));
+            }
+            Some(ShipmentStatus::Returned)
+        }
+        _ if at_door => Some(ShipmentStatus::InTransit),
+        _ => None,
+    };
+    if let Some(target) = target {
+        ensure_not_consolidated(&mut tx, shipment_id).await?;
+        transition_shipment_status(
+            &mut tx,
+            shipment_id,
+            target,
+            Some(&auth_user),
+            Some(&transition_notes),
+            serde_json::json!({ "exception_type": kind.as_str(), "attempt_number": attempt_number }),
+        )
+        .await?;
+    }
+    if target == Some(ShipmentStatus::Returned) {
+        release_slot(&mut tx, shipment_id).await?;
+    }
+
+    if let Some(attempt) = attempt_number {
+        sqlx::query("UPDATE shipments SET delivery_attempts = $2 WHERE id = $1")
+            .bind(shipment_id)
+            .bind(attempt)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    // The missed window is gone; the next attempt takes the same hours on
+    // a later day, which the route planner picks up
+    let next_attempt_at = if at_door && matches!(next_action, NextAction::Reattempt | NextAction::ContactReceiver) {
+        let window = shipment
+            .get::<Option<DateTime<Utc>>, _>("delivery_window_start")
+            .zip(shipment.get::<Option<DateTime<Utc>>, _>("delivery_window_end"));
+        let (at, next_window) = schedule_next_attempt(now, window, state.config.reattempt_delay_hours);
+
+        release_slot(&mut tx, shipment_id).await?;
+        sqlx::query("UPDATE shipments SET delivery_window_start = $2, delivery_window_end = $3 WHERE id = $1")
+            .bind(shipment_id)
+            .bind(next_window.map(|(start, _)| start))
+            .bind(next_window.map(|(_, end)| end))
+            .execute(&mut *tx)
+            .await?;
+        Some(at)
+    } else {
+        None
+    };
+
+    if let Some(parcel_ids) = &payload.parcel_ids {
+        let marked = parcels::mark_damaged(&mut tx, shipment_id, parcel_ids).await?;
+        if marked != parcel_ids.len() as u64 {
+            return Err(AppError::Validation("Some parcels aren't open parcels of this shipment".to_string()));
+        }
+    }
+
+    let mut evidence_ids = Vec::with_capacity(photos.len());
+    for (content_type, bytes) in &photos {
+        let upload = store_upload(
+            &mut tx,
+            &state.config.encryption_key,
+            auth_user.user_id,
+            "exception_evidence",
+            content_type,
+            bytes,
+        )
+        .await?;
+        evidence_ids.push(upload.id);
+    }
+
+    let exception_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO shipment_exceptions (
+            id, shipment_id, exception_type, attempt_number, reason, evidence_upload_ids,
+            latitude, longitude, next_action, next_attempt_at, reported_by, created_at, updated_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $12)
+        "#,
+    )
+    .bind(exception_id)
+    .bind(shipment_id)
+    .bind(kind.as_str())
+    .bind(attempt_number)
+    .bind(reason)
+    .bind(&evidence_ids)
+    .bind(location.map(|(latitude, _)| latitude))
+    .bind(location.map(|(_, longitude)| longitude))
+    .bind(next_action.as_str())
+    .bind(next_attempt_at)
+    .bind(auth_user.user_id)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "delivery_exception",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: reason,
+        metadata: serde_json::json!({
+            "exception_id": exception_id,
+            "exception_type": kind.as_str(),
+            "attempt_number": attempt_number,
+            "next_action": next_action.as_str(),
+            "next_attempt_at": next_attempt_at,
+            "escalated": escalated
+        }),
+    })
+    .await?;
+
+    let exception = load_exception(&mut *tx, exception_id).await?;
+    tx.commit().await?;
+
+    info!(
+        "Delivery exception {} ({}) on shipment {} reported by {}; next action {}",
+        exception_id,
+        kind.as_str(),
+        tracking_number,
+        auth_user.user_id,
+        next_action.as_str()
+    );
+
+    if let Some(target) = target {
+        state
+            .tracking_hub
+            .publish(ShipmentUpdate::new(shipment_id, "status", serde_json::json!({
+                "previous_status": status.as_str(),
+                "status": target.as_str(),
+                "notes": transition_notes
+            })))
+            .await;
+    }
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(
+            shipment_id,
+            "exception",
+            serde_json::to_value(&exception).unwrap_or_default(),
+        ))
+        .await;
+
+    if let Err(e) = recalculate_eta(&state, shipment_id).await {
+        warn!("ETA recalculation failed for shipment {}: {}", shipment_id, e);
+    }
+
+    notify_parties(&state, &shipment, &exception, escalated).await;
+
+    Ok(Json(exception))
+}
+
+pub async fn list_shipment_exceptions(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+) -> Result<Json<Vec<ExceptionResponse>>, AppError> {
+    let shipment = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    if !can_access_shipment(&auth_user, &shipment) {
+        return Err(AppError::Authorization("Not allowed to view this shipment".to_string()));
+    }
+
+    let rows = sqlx::query(&format!("{} WHERE e.shipment_id = $1 ORDER BY e.created_at", EXCEPTION_SELECT))
+        .bind(shipment_id)
+        .fetch_all(&state.db.pool)
+        .await?;
+
+    Ok(Json(rows.iter().map(exception_response).collect()))
+}
+
+/// The operations queue: open exceptions by default, overdue re-attempts
+/// first, then oldest first.
+pub async fn list_exceptions(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Query(query): Query<ExceptionQueueQuery>,
+) -> Result<Json<Vec<ExceptionResponse>>, AppError> {
+    require_staff(&auth_user)?;
+
+    let status = query.status.as_deref().unwrap_or("open");
+    if !["open", "resolved"].contains(&status) {
+        return Err(AppError::InvalidInput("status must be open or resolved".to_string()));
+    }
+    let exception_type = match query.exception_type.as_deref() {
+        Some(value) => Some(
+            ExceptionType::parse(value)
+                .ok_or_else(|| AppError::InvalidInput(format!("Unknown exception type '{}'", value)))?,
+        ),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_QUEUE_LIMIT).clamp(1, MAX_QUEUE_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let rows = sqlx::query(&format!(
+        r#"
+        {}
+        WHERE e.status = $1
+          AND ($2::text IS NULL OR e.exception_type = $2)
+          AND (NOT $3 OR ({}))
+        ORDER BY ({}) DESC, e.created_at ASC
+        LIMIT $4 OFFSET $5
+        "#,
+        EXCEPTION_SELECT, OVERDUE_CONDITION, OVERDUE_CONDITION
+    ))
+    .bind(status)
+    .bind(exception_type.map(|kind| kind.as_str()))
+    .bind(query.overdue.unwrap_or(false))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    Ok(Json(rows.iter().map(exception_response).collect()))
+}
+
+pub async fn resolve_exception(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(exception_id): Path<Uuid>,
+    Json(payload): Json<ResolveExceptionRequest>,
+) -> Result<Json<ExceptionResponse>, AppError> {
+    require_staff(&auth_user)?;
+
+    let notes = payload
+        .notes
+        .as_deref()
+        .map(str::trim)
+        .filter(|notes| !notes.is_empty())
+        .ok_or_else(|| AppError::Validation("Resolution notes are required".to_string()))?;
+    let return_to_sender = match payload.next_action.as_deref().map(NextAction::parse) {
+        None => false,
+        Some(Some(NextAction::ReturnToSender)) => true,
+        Some(Some(NextAction::None)) => false,
+        Some(_) => {
+            return Err(AppError::Validation("next_action must be return_to_sender or none".to_string()))
+        }
+    };
+
+    let mut tx = state.db.pool.begin().await?;
+    let exception = sqlx::query("SELECT * FROM shipment_exceptions WHERE id = $1 FOR UPDATE")
+        .bind(exception_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Exception not found".to_string()))?;
+    if exception.get::<String, _>("status") != "open" {
+        return Err(AppError::Conflict("Exception is already resolved".to_string()));
+    }
+    let shipment_id = exception.get::<Uuid, _>("shipment_id");
+
+    let previous = if return_to_sender {
+        ensure_not_consolidated(&mut tx, shipment_id).await?;
+        let previous = transition_shipment_status(
+            &mut tx,
+            shipment_id,
+            ShipmentStatus::Returned,
+            Some(&auth_user),
+            Some(notes),
+            serde_json::json!({ "exception_id": exception_id }),
+        )
+        .await?;
+        release_slot(&mut tx, shipment_id).await?;
+        Some(previous)
+    } else {
+        None
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE shipment_exceptions
+        SET status = 'resolved', resolved_by = $2, resolution_notes = $3, resolved_at = NOW(), updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(exception_id)
+    .bind(auth_user.user_id)
+    .bind(notes)
+    .execute(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "delivery_exception_resolved",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: Some(notes),
+        metadata: serde_json::json!({ "exception_id": exception_id, "returned": return_to_sender }),
+    })
+    .await?;
+
+    let response = load_exception(&mut *tx, exception_id).await?;
+    tx.commit().await?;
+
+    info!("Delivery exception {} resolved by {}", exception_id, auth_user.user_id);
+
+    if let Some(previous) = previous {
+        state
+            .tracking_hub
+            .publish(ShipmentUpdate::new(shipment_id, "status", serde_json::json!({
+                "previous_status": previous.as_str(),
+                "status": ShipmentStatus::Returned.as_str(),
+                "notes": notes
+            })))
+            .await;
+        if let Err(e) = recalculate_eta(&state, shipment_id).await {
+            warn!("ETA recalculation failed for shipment {}: {}", shipment_id, e);
+        }
+    }
+
+    Ok(Json(response))
+}
+
+pub async fn get_exception_evidence(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path((exception_id, upload_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<EvidenceFileContentResponse>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT e.evidence_upload_ids, s.sender_id, s.receiver_id, s.driver_id
+        FROM shipment_exceptions e
+        JOIN shipments s ON s.id = e.shipment_id
+        WHERE e.id = $1
+        "#,
+    )
+    .bind(exception_id)
+    .fetch_optional(&state.db.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Exception not found".to_string()))?;
+
+    if !can_access_shipment(&auth_user, &row) {
+        return Err(AppError::Authorization("Not allowed to view this exception".to_string()));
+    }
+    // Only files referenced by this exception are reachable here
+    if !row.get::<Vec<Uuid>, _>("evidence_upload_ids").contains(&upload_id) {
+        return Err(AppError::NotFound("Evidence not found".to_string()));
+    }
+
+    let (content_type, bytes) = load_upload(&state, upload_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Evidence not found".to_string()))?;
+
+    Ok(Json(EvidenceFileContentResponse {
+        id: upload_id.to_string(),
+        content_type,
+        data: BASE64.encode(bytes),
+    }))
+}
+
+/// Closes open exceptions the shipment has moved past: a new run out for
+/// delivery settles pending re-attempts, and a finished shipment settles
+/// everything.
+pub(crate) async fn follow_shipment_status(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    to: ShipmentStatus,
+    actor: Option<&AuthUser>,
+) -> Result<(), sqlx::Error> {
+    let (actions, notes): (&[&str], String) = match to {
+        ShipmentStatus::OutForDelivery => (&["reattempt", "contact_receiver"], "Delivery re-attempted".to_string()),
+        ShipmentStatus::Delivered | ShipmentStatus::Cancelled => {
+            (NextAction::ALL_NAMES, format!("Shipment {}", to.as_str()))
+        }
+        // Returns stay open: someone still has to get the shipment back
+        _ => return Ok(()),
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE shipment_exceptions
+        SET status = 'resolved', resolved_by = $2, resolution_notes = $3, resolved_at = NOW(), updated_at = NOW()
+        WHERE shipment_id = $1 AND status = 'open' AND next_action = ANY($4)
+        "#,
+    )
+    .bind(shipment_id)
+    .bind(actor.map(|actor| actor.user_id))
+    .bind(notes)
+    .bind(actions)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+impl ExceptionType {
+    const ALL_NAMES: &'static [&'static str] =
+        &["failed_attempt", "address_problem", "damaged", "customs_hold", "refused", "other"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExceptionType::FailedAttempt => "failed_attempt",
+            ExceptionType::AddressProblem => "address_problem",
+            ExceptionType::Damaged => "damaged",
+            ExceptionType::CustomsHold => "customs_hold",
+            ExceptionType::Refused => "refused",
+            ExceptionType::Other => "other",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "failed_attempt" => Some(ExceptionType::FailedAttempt),
+            "address_problem" => Some(ExceptionType::AddressProblem),
+            "damaged" => Some(ExceptionType::Damaged),
+            "customs_hold" => Some(ExceptionType::CustomsHold),
+            "refused" => Some(ExceptionType::Refused),
+            "other" => Some(ExceptionType::Other),
+            _ => None,
+        }
+    }
+
+    /// What happens next unless staff decide otherwise.
+    pub fn default_action(&self) -> NextAction {
+        match self {
+            ExceptionType::FailedAttempt => NextAction::Reattempt,
+            ExceptionType::AddressProblem => NextAction::ContactReceiver,
+            ExceptionType::Refused => NextAction::ReturnToSender,
+            ExceptionType::Damaged | ExceptionType::CustomsHold | ExceptionType::Other => NextAction::Hold,
+        }
+    }
+}
+
+impl NextAction {
+    const ALL_NAMES: &'static [&'static str] = &["reattempt", "contact_receiver", "hold", "return_to_sender", "none"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NextAction::Reattempt => "reattempt",
+            NextAction::ContactReceiver => "contact_receiver",
+            NextAction::Hold => "hold",
+            NextAction::ReturnToSender => "return_to_sender",
+            NextAction::None => "none",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reattempt" => Some(NextAction::Reattempt),
+            "contact_receiver" => Some(NextAction::ContactReceiver),
+            "hold" => Some(NextAction::Hold),
+            "return_to_sender" => Some(NextAction::ReturnToSender),
+            "none" => Some(NextAction::None),
+            _ => None,
+        }
+    }
+}
+
+// Helper functions
+
+const EXCEPTION_SELECT: &str = r#"
+    SELECT e.*, s.tracking_number, s.status AS shipment_status, s.delivery_attempts
+    FROM shipment_exceptions e
+    JOIN shipments s ON s.id = e.shipment_id
+"#;
+
+const OVERDUE_CONDITION: &str =
+    "e.status = 'open' AND e.next_action IN ('reattempt', 'contact_receiver') AND e.next_attempt_at < NOW()";
+
+fn require_staff(auth_user: &AuthUser) -> Result<(), AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage delivery exceptions".to_string()))
+}
+
+/// When to try again and in which window. A missed window moves to the
+/// same hours on the first day that leaves at least the re-attempt delay;
+/// without one the attempt is simply due after the delay.
+fn schedule_next_attempt(
+    now: DateTime<Utc>,
+    window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    delay_hours: i64,
+) -> (DateTime<Utc>, Option<(DateTime<Utc>, DateTime<Utc>)>) {
+    let earliest = now + Duration::hours(delay_hours.max(0));
+
+    match window {
+        Some((start, end)) => {
+            let mut days = 1;
+            while end + Duration::days(days) <= earliest {
+                days += 1;
+            }
+            let next = (start + Duration::days(days), end + Duration::days(days));
+            (next.0.max(earliest), Some(next))
+        }
+        None => (earliest, None),
+    }
+}
+
+async fn notify_parties(
+    state: &crate::AppState,
+    shipment: &sqlx::postgres::PgRow,
+    exception: &ExceptionResponse,
+    escalated: bool,
+) {
+    let tracking_number = &exception.tracking_number;
+    let (recipients, title, message) = match exception.next_action.as_str() {
+        "return_to_sender" if escalated => (
+            vec![shipment.get::<Uuid, _>("sender_id"), shipment.get::<Uuid, _>("receiver_id")],
+            "Shipment being returned",
+            format!(
+                "Shipment {} couldn't be delivered after {} attempts and is being returned to the sender",
+                tracking_number, exception.delivery_attempts
+            ),
+        ),
+        "return_to_sender" => (
+            vec![shipment.get::<Uuid, _>("sender_id")],
+            "Shipment being returned",
+            format!("Shipment {} is being returned to the sender ({})", tracking_number, exception.exception_type),
+        ),
+        "reattempt" | "contact_receiver" => (
+            vec![shipment.get::<Uuid, _>("receiver_id")],
+            "Delivery attempt failed",
+            match &exception.next_attempt_at {
+                Some(at) => format!(
+                    "We couldn't deliver shipment {}. We'll try again from {}; you can reschedule or redirect it",
+                    tracking_number, at
+                ),
+                None => format!("There is a problem delivering shipment {}", tracking_number),
+            },
+        ),
+        _ => (
+            vec![shipment.get::<Uuid, _>("sender_id")],
+            "Shipment on hold",
+            format!("Shipment {} is on hold: {}", tracking_number, exception.exception_type.replace('_', " ")),
+        ),
+    };
+
+    for user_id in recipients {
+        if let Err(status) = notify_user(
+            state,
+            user_id,
+            title,
+            &message,
+            "delivery_exception",
+            serde_json::json!({ "shipment_id": exception.shipment_id, "exception_id": exception.id }),
+        )
+        .await
+        {
+            warn!("Could not notify user {} about exception {}: {}", user_id, exception.id, status);
+        }
+    }
+}
+
+async fn load_exception<'e, E>(executor: E, exception_id: Uuid) -> Result<ExceptionResponse, AppError>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query(&format!("{} WHERE e.id = $1", EXCEPTION_SELECT))
+        .bind(exception_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Exception not found".to_string()))?;
+
+    Ok(exception_response(&row))
+}
+
+fn exception_response(row: &sqlx::postgres::PgRow) -> ExceptionResponse {
+    let status = row.get::<String, _>("status");
+    let next_action = row.get::<String, _>("next_action");
+    let next_attempt_at = row.get::<Option<DateTime<Utc>>, _>("next_attempt_at");
+
+    ExceptionResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        shipment_id: row.get::<Uuid, _>("shipment_id").to_string(),
+        tracking_number: row.get::<String, _>("tracking_number"),
+        shipment_status: row.get::<ShipmentStatus, _>("shipment_status").as_str().to_string(),
+        delivery_attempts: row.get::<i32, _>("delivery_attempts"),
+        exception_type: row.get::<String, _>("exception_type"),
+        attempt_number: row.get::<Option<i32>, _>("attempt_number"),
+        reason: row.get::<Option<String>, _>("reason"),
+        evidence_ids: row
+            .get::<Vec<Uuid>, _>("evidence_upload_ids")
+            .iter()
+            .map(|id| id.to_string())
+            .collect(),
+        latitude: row.get::<Option<f64>, _>("latitude"),
+        longitude: row.get::<Option<f64>, _>("longitude"),
+        overdue: status == "open"
+            && matches!(next_action.as_str(), "reattempt" | "contact_receiver")
+            && next_attempt_at.is_some_and(|at| at < Utc::now()),
+        next_action,
+        next_attempt_at: next_attempt_at.map(|at| at.to_rfc3339()),
+        status,
+        reported_by: row.get::<Option<Uuid>, _>("reported_by").map(|id| id.to_string()),
+        resolved_by: row.get::<Option<Uuid>, _>("resolved_by").map(|id| id.to_string()),
+        resolution_notes: row.get::<Option<String>, _>("resolution_notes"),
+        resolved_at: row.get::<Option<DateTime<Utc>>, _>("resolved_at").map(|at| at.to_rfc3339()),
+        created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    }
+}
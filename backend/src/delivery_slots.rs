@@ -0,0 +1,892 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::eta::{predict, recalculate as recalculate_eta, EtaInputs, ROAD_FACTOR};
+use crate::geofencing::{address_coordinates, hub_address};
+use crate::models::*;
+use crate::notifications::notify_user;
+use crate::realtime::ShipmentUpdate;
+use crate::tracking::{
+    can_access_shipment, record_shipment_event, validate_address, CreateShipmentRequest, ShipmentEventRecord,
+    TimeWindowRequest,
+};
+use crate::utils::{calculate_distance, AppError};
+
+// Delivery slots: dispatchers publish windows per delivery zone (the
+// receiver's city) with a capacity, senders book one when creating a
+// shipment, and receivers can move to another slot, redirect the delivery
+// to a neighbour or pickup point, or leave instructions for the driver.
+// A booked slot becomes the shipment's delivery window, which the route
+// planner already honours.
+
+const SLOT_HORIZON_DAYS: i64 = 14;
+const MAX_SLOT_HOURS: i64 = 12;
+const MAX_SLOT_CAPACITY: i32 = 10_000;
+const MAX_WINDOWS_PER_REQUEST: usize = 100;
+const MAX_LISTED_SLOTS: i64 = 500;
+pub(crate) const MAX_INSTRUCTIONS_LENGTH: usize = 500;
+const MAX_NEIGHBOUR_DISTANCE_KM: f64 = 0.5;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSlotsRequest {
+    /// The delivery city the slots serve.
+    pub zone: String,
+    pub capacity: i32,
+    /// RFC 3339 bounds; the offset decides which local date a slot is on.
+    pub windows: Vec<TimeWindowRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateSlotRequest {
+    pub capacity: Option<i32>,
+    pub is_active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SlotQuery {
+    pub zone: Option<String>,
+    /// `YYYY-MM-DD`, defaults to today
+    pub from: Option<String>,
+    /// `YYYY-MM-DD`, defaults to two weeks ahead
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RescheduleRequest {
+    pub slot_id: Uuid,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectTarget {
+    Neighbour,
+    PickupPoint,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedirectRequest {
+    pub target: RedirectTarget,
+    /// Required for a neighbour
+    pub neighbour_name: Option<String>,
+    /// The neighbour's address; must be close to the current one
+    pub address: Option<serde_json::Value>,
+    /// The hub to collect from, for a pickup point
+    pub hub_id: Option<Uuid>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryInstructionsRequest {
+    /// Whether the driver may leave the shipment without a signature
+    pub leave_authorized: bool,
+    pub instructions: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliverySlotResponse {
+    pub id: String,
+    pub zone: String,
+    pub date: String,
+    pub start: String,
+    pub end: String,
+    pub capacity: i32,
+    pub booked: i32,
+    pub available: i32,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryPreferencesResponse {
+    pub shipment_id: String,
+    pub status: String,
+    pub delivery_address: serde_json::Value,
+    pub original_delivery_address: Option<serde_json::Value>,
+    pub slot: Option<DeliverySlotResponse>,
+    pub window_start: Option<String>,
+    pub window_end: Option<String>,
+    pub leave_authorized: bool,
+    pub delivery_instructions: Option<String>,
+    pub updated_at: String,
+}
+
+pub async fn list_slots(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Query(query): Query<SlotQuery>,
+) -> Result<Json<Vec<DeliverySlotResponse>>, AppError> {
+    let is_staff = matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin);
+    let zone = query.zone.as_deref().map(normalize_zone).filter(|zone| !zone.is_empty());
+    if zone.is_none() && !is_staff {
+        return Err(AppError::InvalidInput("zone is required".to_string()));
+    }
+
+    let today = Utc::now().date_naive();
+    let from = parse_date("from", query.from.as_deref())?.unwrap_or(today);
+    let to = parse_date("to", query.to.as_deref())?.unwrap_or(today + Duration::days(SLOT_HORIZON_DAYS));
+
+    // Receivers only see what they could still book
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM delivery_slots
+        WHERE ($1::text IS NULL OR zone = $1)
+          AND slot_date BETWEEN $2 AND $3
+          AND ($4 OR (is_active AND starts_at > NOW() AND booked < capacity))
+        ORDER BY starts_at, zone
+        LIMIT $5
+        "#,
+    )
+    .bind(zone)
+    .bind(from)
+    .bind(to)
+    .bind(is_staff)
+    .bind(MAX_LISTED_SLOTS)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    Ok(Json(rows.iter().map(slot_response).collect()))
+}
+
+pub async fn create_slots(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateSlotsRequest>,
+) -> Result<Json<Vec<DeliverySlotResponse>>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage delivery slots".to_string()))?;
+
+    let zone = normalize_zone(&payload.zone);
+    if zone.is_empty() {
+        return Err(AppError::Validation("zone must not be empty".to_string()));
+    }
+    if !(0..=MAX_SLOT_CAPACITY).contains(&payload.capacity) {
+        return Err(AppError::Validation(format!("capacity must be between 0 and {}", MAX_SLOT_CAPACITY)));
+    }
+    if payload.windows.is_empty() || payload.windows.len() > MAX_WINDOWS_PER_REQUEST {
+        return Err(AppError::Validation(format!(
+            "Between 1 and {} windows can be created at once",
+            MAX_WINDOWS_PER_REQUEST
+        )));
+    }
+
+    let now = Utc::now();
+    let mut windows = Vec::with_capacity(payload.windows.len());
+    for (index, window) in payload.windows.iter().enumerate() {
+        let (date, start, end) = parse_slot_window(window)
+            .map_err(|message| AppError::Validation(format!("windows[{}] {}", index, message)))?;
+        if start <= now {
+            return Err(AppError::Validation(format!("windows[{}] must start in the future", index)));
+        }
+        windows.push((date, start, end));
+    }
+
+    // Publishing the same window again updates its capacity and reopens it
+    let mut tx = state.db.pool.begin().await?;
+    let mut slots = Vec::with_capacity(windows.len());
+    for (date, start, end) in windows {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO delivery_slots (zone, slot_date, starts_at, ends_at, capacity, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (zone, starts_at, ends_at)
+            DO UPDATE SET capacity = EXCLUDED.capacity, is_active = TRUE, updated_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(&zone)
+        .bind(date)
+        .bind(start)
+        .bind(end)
+        .bind(payload.capacity)
+        .bind(auth_user.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        slots.push(slot_response(&row));
+    }
+    tx.commit().await?;
+
+    info!("{} delivery slots published for {} by {}", slots.len(), zone, auth_user.user_id);
+
+    Ok(Json(slots))
+}
+
+/// Changes a slot's capacity or withdraws it. Shipments already booked on a
+/// withdrawn slot keep their window.
+pub async fn update_slot(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(slot_id): Path<Uuid>,
+    Json(payload): Json<UpdateSlotRequest>,
+) -> Result<Json<DeliverySlotResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage delivery slots".to_string()))?;
+
+    if payload.capacity.is_some_and(|capacity| !(0..=MAX_SLOT_CAPACITY).contains(&capacity)) {
+        return Err(AppError::Validation(format!("capacity must be between 0 and {}", MAX_SLOT_CAPACITY)));
+    }
+
+    let row = sqlx::query(
+        r#"
+        UPDATE delivery_slots
+        SET capacity = COALESCE($2, capacity), is_active = COALESCE($3, is_active), updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(slot_id)
+    .bind(payload.capacity)
+    .bind(payload.is_active)
+    .fetch_optional(&state.db.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Delivery slot not found".to_string()))?;
+
+    info!("Delivery slot {} updated by {}", slot_id, auth_user.user_id);
+
+    Ok(Json(slot_response(&row)))
+}
+
+pub async fn get_delivery(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+) -> Result<Json<DeliveryPreferencesResponse>, AppError> {
+    let shipment = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    if !can_access_shipment(&auth_user, &shipment) {
+        return Err(AppError::Authorization("Not allowed to view this shipment".to_string()));
+    }
+
+    Ok(Json(load_preferences(&state.db.pool, shipment_id).await?))
+}
+
+/// Slots the receiver can move the delivery to: same zone, still open and
+/// not ending before the shipment can arrive.
+pub async fn list_shipment_slots(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+) -> Result<Json<Vec<DeliverySlotResponse>>, AppError> {
+    let shipment = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+    require_receiver(&auth_user, &shipment)?;
+
+    let Some(zone) = delivery_zone(&shipment.get::<serde_json::Value, _>("delivery_address")) else {
+        return Ok(Json(Vec::new()));
+    };
+    let earliest = earliest_arrival(&state.db.pool, shipment_id).await?.unwrap_or_else(Utc::now);
+
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM delivery_slots
+        WHERE zone = $1 AND is_active AND booked < capacity
+          AND starts_at > NOW() AND ends_at >= $2 AND starts_at < $3
+        ORDER BY starts_at
+        LIMIT $4
+        "#,
+    )
+    .bind(&zone)
+    .bind(earliest)
+    .bind(Utc::now() + Duration::days(SLOT_HORIZON_DAYS))
+    .bind(MAX_LISTED_SLOTS)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    Ok(Json(rows.iter().map(slot_response).collect()))
+}
+
+pub async fn reschedule_delivery(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<RescheduleRequest>,
+) -> Result<Json<DeliveryPreferencesResponse>, AppError> {
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+
+    let mut tx = state.db.pool.begin().await?;
+    let shipment = lock_shipment(&mut tx, shipment_id).await?;
+    require_receiver(&auth_user, &shipment)?;
+
+    match shipment.get::<ShipmentStatus, _>("status") {
+        ShipmentStatus::Pending | ShipmentStatus::PickedUp | ShipmentStatus::InTransit => {}
+        ShipmentStatus::OutForDelivery => {
+            return Err(AppError::Conflict("Shipment is already out for delivery".to_string()))
+        }
+        status => return Err(AppError::Conflict(format!("Shipment is {}", status.as_str()))),
+    }
+    let delivery_address = shipment.get::<serde_json::Value, _>("delivery_address");
+    if is_pickup_point(&delivery_address) {
+        return Err(AppError::Conflict("Shipment is collected from a pickup point".to_string()));
+    }
+    let previous_slot = shipment.get::<Option<Uuid>, _>("delivery_slot_id");
+    if previous_slot == Some(payload.slot_id) {
+        return Err(AppError::Conflict("Shipment is already booked on this slot".to_string()));
+    }
+
+    let zone = delivery_zone(&delivery_address)
+        .ok_or_else(|| AppError::Validation("The delivery address has no city to book a slot in".to_string()))?;
+    let earliest = earliest_arrival(&mut *tx, shipment_id).await?;
+
+    release_slot(&mut tx, shipment_id).await?;
+    let (start, end) = book_slot(&mut tx, payload.slot_id, &zone, earliest, Utc::now()).await?;
+    sqlx::query(
+        r#"
+        UPDATE shipments
+        SET delivery_slot_id = $2, delivery_window_start = $3, delivery_window_end = $4, updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(shipment_id)
+    .bind(payload.slot_id)
+    .bind(start)
+    .bind(end)
+    .execute(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "delivery_rescheduled",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes,
+        metadata: serde_json::json!({
+            "previous_slot_id": previous_slot,
+            "previous_window_start": shipment.get::<Option<DateTime<Utc>>, _>("delivery_window_start"),
+            "previous_window_end": shipment.get::<Option<DateTime<Utc>>, _>("delivery_window_end"),
+            "slot_id": payload.slot_id,
+            "window_start": start,
+            "window_end": end
+        }),
+    })
+    .await?;
+
+    let preferences = load_preferences(&mut *tx, shipment_id).await?;
+    tx.commit().await?;
+
+    info!("Delivery of shipment {} rescheduled to {} by {}", shipment_id, start.to_rfc3339(), auth_user.user_id);
+
+    announce_change(
+        &state,
+        &shipment,
+        &preferences,
+        "Delivery rescheduled",
+        &format!(
+            "Shipment {} is now due between {} and {}",
+            shipment.get::<String, _>("tracking_number"),
+            start.format("%Y-%m-%d %H:%M"),
+            end.format("%H:%M UTC")
+        ),
+    )
+    .await;
+
+    Ok(Json(preferences))
+}
+
+pub async fn redirect_delivery(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<RedirectRequest>,
+) -> Result<Json<DeliveryPreferencesResponse>, AppError> {
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+
+    // Resolved before locking the shipment; a hub lookup needs no lock
+    let pickup_point = match (payload.target, payload.hub_id) {
+        (RedirectTarget::PickupPoint, Some(hub_id)) => {
+            let mut address = hub_address(&state, hub_id).await?;
+            address["pickup_point"] = serde_json::json!(true);
+            Some(address)
+        }
+        (RedirectTarget::PickupPoint, None) => {
+            return Err(AppError::Validation("hub_id is required for a pickup point".to_string()))
+        }
+        (RedirectTarget::Neighbour, _) => None,
+    };
+
+    let mut tx = state.db.pool.begin().await?;
+    let shipment = lock_shipment(&mut tx, shipment_id).await?;
+    require_receiver(&auth_user, &shipment)?;
+
+    let status = shipment.get::<ShipmentStatus, _>("status");
+    if status.is_terminal() {
+        return Err(AppError::Conflict(format!("Shipment is {}", status.as_str())));
+    }
+    if shipment.get::<bool, _>("is_consolidation") {
+        return Err(AppError::Conflict("Consolidations go hub to hub and can't be redirected".to_string()));
+    }
+
+    let current_address = shipment.get::<serde_json::Value, _>("delivery_address");
+    let new_address = match pickup_point {
+        Some(address) => address,
+        None => neighbour_address(&payload, &current_address)?,
+    };
+
+    // A pickup point is collected from, so any booked slot is given back
+    if payload.target == RedirectTarget::PickupPoint {
+        release_slot(&mut tx, shipment_id).await?;
+        sqlx::query("UPDATE shipments SET delivery_window_start = NULL, delivery_window_end = NULL WHERE id = $1")
+            .bind(shipment_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(
+        r#"
+        UPDATE shipments
+        SET original_delivery_address = COALESCE(original_delivery_address, delivery_address),
+            delivery_address = $2, updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(shipment_id)
+    .bind(&new_address)
+    .execute(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "delivery_redirected",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes,
+        metadata: serde_json::json!({
+            "target": payload.target.as_str(),
+            "previous_address": current_address,
+            "address": new_address
+        }),
+    })
+    .await?;
+
+    let preferences = load_preferences(&mut *tx, shipment_id).await?;
+    tx.commit().await?;
+
+    info!(
+        "Delivery of shipment {} redirected to a {} by {}",
+        shipment_id,
+        payload.target.as_str(),
+        auth_user.user_id
+    );
+
+    // The destination moved, so the prediction did too
+    if let Err(e) = recalculate_eta(&state, shipment_id).await {
+        warn!("ETA recalculation failed for shipment {}: {}", shipment_id, e);
+    }
+
+    let tracking_number = shipment.get::<String, _>("tracking_number");
+    let message = match payload.target {
+        RedirectTarget::Neighbour => format!("Shipment {} will be left with a neighbour", tracking_number),
+        RedirectTarget::PickupPoint => format!("Shipment {} will wait at a pickup point", tracking_number),
+    };
+    announce_change(&state, &shipment, &preferences, "Delivery redirected", &message).await;
+
+    Ok(Json(preferences))
+}
+
+pub async fn update_delivery_instructions(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<DeliveryInstructionsRequest>,
+) -> Result<Json<DeliveryPreferencesResponse>, AppError> {
+    let instructions = payload.instructions.as_deref().map(str::trim).filter(|text| !text.is_empty());
+    if instructions.is_some_and(|text| text.chars().count() > MAX_INSTRUCTIONS_LENGTH) {
+        return Err(AppError::Validation(format!(
+            "instructions must be at most {} characters",
+            MAX_INSTRUCTIONS_LENGTH
+        )));
+    }
+
+    let mut tx = state.db.pool.begin().await?;
+    let shipment = lock_shipment(&mut tx, shipment_id).await?;
+    require_receiver(&auth_user, &shipment)?;
+
+    let status = shipment.get::<ShipmentStatus, _>("status");
+    if status.is_terminal() {
+        return Err(AppError::Conflict(format!("Shipment is {}", status.as_str())));
+    }
+
+    sqlx::query(
+        "UPDATE shipments SET leave_authorized = $2, delivery_instructions = $3, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(shipment_id)
+    .bind(payload.leave_authorized)
+    .bind(instructions)
+    .execute(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "delivery_instructions_updated",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: instructions,
+        metadata: serde_json::json!({ "leave_authorized": payload.leave_authorized }),
+    })
+    .await?;
+
+    let preferences = load_preferences(&mut *tx, shipment_id).await?;
+    tx.commit().await?;
+
+    info!("Delivery instructions for shipment {} updated by {}", shipment_id, auth_user.user_id);
+
+    let message = format!(
+        "The receiver of shipment {} updated the delivery instructions",
+        shipment.get::<String, _>("tracking_number")
+    );
+    announce_change(&state, &shipment, &preferences, "Delivery instructions", &message).await;
+
+    Ok(Json(preferences))
+}
+
+/// Books a slot for a shipment being created, checking that it serves the
+/// delivery address and that the shipment can make it in time.
+pub(crate) async fn reserve_for_new_shipment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    slot_id: Uuid,
+    payload: &CreateShipmentRequest,
+    priority: ShipmentPriority,
+    now: DateTime<Utc>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+    let zone = delivery_zone(&payload.delivery_address)
+        .ok_or_else(|| AppError::Validation("delivery_address needs a city to book a delivery slot".to_string()))?;
+
+    let earliest = match (
+        address_coordinates(&payload.pickup_address),
+        address_coordinates(&payload.delivery_address),
+    ) {
+        (Some(from), Some(to)) => {
+            let distance_km = calculate_distance(from.0, from.1, to.0, to.1) * ROAD_FACTOR;
+            predict(&EtaInputs {
+                status: ShipmentStatus::Pending,
+                priority,
+                now,
+                remaining_km: Some(distance_km),
+                total_km: Some(distance_km),
+                recent_speed_kmh: None,
+                hours_since_pickup: None,
+                lane: None,
+            })
+            .map(|prediction| prediction.earliest_arrival)
+        }
+        _ => None,
+    };
+
+    book_slot(tx, slot_id, &zone, earliest, now).await
+}
+
+/// Gives back the shipment's booked slot, if any.
+pub(crate) async fn release_slot(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE delivery_slots
+        SET booked = GREATEST(booked - 1, 0), updated_at = NOW()
+        WHERE id = (SELECT delivery_slot_id FROM shipments WHERE id = $1)
+        "#,
+    )
+    .bind(shipment_id)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query("UPDATE shipments SET delivery_slot_id = NULL WHERE id = $1")
+        .bind(shipment_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// The city a delivery address falls under for slot capacity.
+pub(crate) fn delivery_zone(address: &serde_json::Value) -> Option<String> {
+    address
+        .get("city")
+        .and_then(|city| city.as_str())
+        .map(normalize_zone)
+        .filter(|zone| !zone.is_empty())
+}
+
+impl RedirectTarget {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RedirectTarget::Neighbour => "neighbour",
+            RedirectTarget::PickupPoint => "pickup_point",
+        }
+    }
+}
+
+// Helper functions
+
+/// Takes one place in the slot. `earliest` is when the shipment could arrive
+/// at the soonest; slots ending before then are refused.
+async fn book_slot(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    slot_id: Uuid,
+    zone: &str,
+    earliest: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+    let slot = sqlx::query("SELECT * FROM delivery_slots WHERE id = $1 FOR UPDATE")
+        .bind(slot_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Delivery slot not found".to_string()))?;
+
+    let start = slot.get::<DateTime<Utc>, _>("starts_at");
+    let end = slot.get::<DateTime<Utc>, _>("ends_at");
+
+    if slot.get::<String, _>("zone") != zone {
+        return Err(AppError::Validation("The delivery slot doesn't serve the delivery address".to_string()));
+    }
+    if !slot.get::<bool, _>("is_active") || start <= now {
+        return Err(AppError::Conflict("The delivery slot is no longer offered".to_string()));
+    }
+    if slot.get::<i32, _>("booked") >= slot.get::<i32, _>("capacity") {
+        return Err(AppError::Conflict("The delivery slot is fully booked".to_string()));
+    }
+    if let Some(earliest) = earliest.filter(|earliest| end < *earliest) {
+        return Err(AppError::Validation(format!(
+            "The shipment can't arrive before {}, after the slot ends",
+            earliest.to_rfc3339()
+        )));
+    }
+
+    sqlx::query("UPDATE delivery_slots SET booked = booked + 1, updated_at = NOW() WHERE id = $1")
+        .bind(slot_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok((start, end))
+}
+
+async fn lock_shipment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+) -> Result<sqlx::postgres::PgRow, AppError> {
+    sqlx::query("SELECT * FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))
+}
+
+/// Delivery preferences belong to the receiver; staff may act for them.
+fn require_receiver(auth_user: &AuthUser, shipment: &sqlx::postgres::PgRow) -> Result<(), AppError> {
+    if matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin)
+        || shipment.get::<Uuid, _>("receiver_id") == auth_user.user_id
+    {
+        Ok(())
+    } else {
+        Err(AppError::Authorization("Only the receiver can change the delivery".to_string()))
+    }
+}
+
+async fn earliest_arrival<'e, E>(executor: E, shipment_id: Uuid) -> Result<Option<DateTime<Utc>>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    Ok(sqlx::query("SELECT earliest_arrival FROM shipment_etas WHERE shipment_id = $1")
+        .bind(shipment_id)
+        .fetch_optional(executor)
+        .await?
+        .map(|row| row.get::<DateTime<Utc>, _>("earliest_arrival")))
+}
+
+/// The neighbour's address, which must be a valid address close to the
+/// current one (or in the same zone when either lacks coordinates).
+fn neighbour_address(
+    payload: &RedirectRequest,
+    current_address: &serde_json::Value,
+) -> Result<serde_json::Value, AppError> {
+    let name = payload
+        .neighbour_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| AppError::Validation("neighbour_name is required for a neighbour".to_string()))?;
+    let address = payload
+        .address
+        .as_ref()
+        .ok_or_else(|| AppError::Validation("address is required for a neighbour".to_string()))?;
+    validate_address(address).map_err(|message| AppError::Validation(format!("address {}", message)))?;
+
+    match (address_coordinates(address), address_coordinates(current_address)) {
+        (Some(to), Some(from)) => {
+            if calculate_distance(from.0, from.1, to.0, to.1) > MAX_NEIGHBOUR_DISTANCE_KM {
+                return Err(AppError::Validation(format!(
+                    "A neighbour must be within {} m of the delivery address",
+                    (MAX_NEIGHBOUR_DISTANCE_KM * 1000.0) as i64
+                )));
+            }
+        }
+        _ => {
+            if delivery_zone(address).is_none() || delivery_zone(address) != delivery_zone(current_address) {
+                return Err(AppError::Validation(
+                    "A neighbour must be in the same city as the delivery address".to_string(),
+                ));
+            }
+        }
+    }
+
+    let mut address = address.clone();
+    address["recipient_name"] = serde_json::json!(name);
+    address["redirected_to"] = serde_json::json!(RedirectTarget::Neighbour.as_str());
+    Ok(address)
+}
+
+fn is_pickup_point(address: &serde_json::Value) -> bool {
+    address.get("pickup_point").and_then(|flag| flag.as_bool()).unwrap_or(false)
+}
+
+/// Tells subscribers and the assigned driver about a delivery change.
+async fn announce_change(
+    state: &crate::AppState,
+    shipment: &sqlx::postgres::PgRow,
+    preferences: &DeliveryPreferencesResponse,
+    title: &str,
+    message: &str,
+) {
+    let shipment_id = shipment.get::<Uuid, _>("id");
+
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(
+            shipment_id,
+            "delivery",
+            serde_json::to_value(preferences).unwrap_or_default(),
+        ))
+        .await;
+
+    if let Some(driver_id) = shipment.get::<Option<Uuid>, _>("driver_id") {
+        if let Err(status) = notify_user(
+            state,
+            driver_id,
+            title,
+            message,
+            "delivery_changed",
+            serde_json::json!({ "shipment_id": shipment_id }),
+        )
+        .await
+        {
+            warn!("Could not notify driver {} about shipment {}: {}", driver_id, shipment_id, status);
+        }
+    }
+}
+
+async fn load_preferences<'e, E>(executor: E, shipment_id: Uuid) -> Result<DeliveryPreferencesResponse, AppError>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query(
+        r#"
+        SELECT s.id, s.status, s.delivery_address, s.original_delivery_address,
+               s.delivery_window_start, s.delivery_window_end, s.leave_authorized,
+               s.delivery_instructions, s.updated_at,
+               ds.id AS slot_id, ds.zone, ds.slot_date, ds.starts_at, ds.ends_at,
+               ds.capacity, ds.booked, ds.is_active
+        FROM shipments s
+        LEFT JOIN delivery_slots ds ON ds.id = s.delivery_slot_id
+        WHERE s.id = $1
+        "#,
+    )
+    .bind(shipment_id)
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    let slot = row.get::<Option<Uuid>, _>("slot_id").map(|slot_id| {
+        let capacity = row.get::<i32, _>("capacity");
+        let booked = row.get::<i32, _>("booked");
+        DeliverySlotResponse {
+            id: slot_id.to_string(),
+            zone: row.get::<String, _>("zone"),
+            date: row.get::<NaiveDate, _>("slot_date").to_string(),
+            start: row.get::<DateTime<Utc>, _>("starts_at").to_rfc3339(),
+            end: row.get::<DateTime<Utc>, _>("ends_at").to_rfc3339(),
+            capacity,
+            booked,
+            available: (capacity - booked).max(0),
+            is_active: row.get::<bool, _>("is_active"),
+        }
+    });
+
+    Ok(DeliveryPreferencesResponse {
+        shipment_id: shipment_id.to_string(),
+        status: row.get::<ShipmentStatus, _>("status").as_str().to_string(),
+        delivery_address: row.get::<serde_json::Value, _>("delivery_address"),
+        original_delivery_address: row.get::<Option<serde_json::Value>, _>("original_delivery_address"),
+        slot,
+        window_start: row.get::<Option<DateTime<Utc>>, _>("delivery_window_start").map(|at| at.to_rfc3339()),
+        window_end: row.get::<Option<DateTime<Utc>>, _>("delivery_window_end").map(|at| at.to_rfc3339()),
+        leave_authorized: row.get::<bool, _>("leave_authorized"),
+        delivery_instructions: row.get::<Option<String>, _>("delivery_instructions"),
+        updated_at: row.get::<DateTime<Utc>, _>("updated_at").to_rfc3339(),
+    })
+}
+
+fn slot_response(row: &sqlx::postgres::PgRow) -> DeliverySlotResponse {
+    let capacity = row.get::<i32, _>("capacity");
+    let booked = row.get::<i32, _>("booked");
+
+    DeliverySlotResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        zone: row.get::<String, _>("zone"),
+        date: row.get::<NaiveDate, _>("slot_date").to_string(),
+        start: row.get::<DateTime<Utc>, _>("starts_at").to_rfc3339(),
+        end: row.get::<DateTime<Utc>, _>("ends_at").to_rfc3339(),
+        capacity,
+        booked,
+        available: (capacity - booked).max(0),
+        is_active: row.get::<bool, _>("is_active"),
+    }
+}
+
+/// A window's local date and its bounds in UTC.
+fn parse_slot_window(window: &TimeWindowRequest) -> Result<(NaiveDate, DateTime<Utc>, DateTime<Utc>), String> {
+    let parse = |value: &str| {
+        DateTime::parse_from_rfc3339(value).map_err(|_| "bounds must be RFC 3339 timestamps".to_string())
+    };
+    let start = parse(&window.start)?;
+    let end = parse(&window.end)?;
+
+    if end <= start {
+        return Err("must end after it starts".to_string());
+    }
+    if end - start > Duration::hours(MAX_SLOT_HOURS) {
+        return Err(format!("must be at most {} hours long", MAX_SLOT_HOURS));
+    }
+
+    Ok((start.date_naive(), start.with_timezone(&Utc), end.with_timezone(&Utc)))
+}
+
+fn parse_date(field: &str, value: Option<&str>) -> Result<Option<NaiveDate>, AppError> {
+    value
+        .map(|value| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map_err(|_| AppError::InvalidInput(format!("{} must be a YYYY-MM-DD date", field)))
+        })
+        .transpose()
+}
+
+fn normalize_zone(city: &str) -> String {
+    city.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
@@ -0,0 +1,908 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::geofencing::address_coordinates;
+use crate::models::*;
+use crate::notifications::notify_user;
+use crate::realtime::ShipmentUpdate;
+use crate::routing::dimensions_volume_m3;
+use crate::tracking::{record_shipment_event, ShipmentEventRecord};
+use crate::utils::{calculate_distance, AppError};
+
+// Driver availability and shipment dispatch. A shipment is offered to one
+// driver at a time; the driver has a limited time to accept, and a decline
+// or timeout moves the offer on to the next best driver.
+
+const AVAILABILITY_STATUSES: &[&str] = &["offline", "available", "on_break"];
+// Positions older than this don't count when picking the nearest driver
+const LOCATION_FRESHNESS_MINUTES: i64 = 30;
+// Each open shipment a driver already has counts as this much extra distance
+const LOAD_BALANCE_KM_PER_SHIPMENT: f64 = 5.0;
+const EXPIRY_SWEEP_SECONDS: u64 = 15;
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAvailabilityRequest {
+    pub status: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssignDriverRequest {
+    pub driver_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReassignDriverRequest {
+    /// Omit to let the assignment engine pick the next driver.
+    pub driver_id: Option<Uuid>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnassignDriverRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeclineOfferRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DriverAvailabilityResponse {
+    pub driver_id: String,
+    pub status: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_updated_at: Option<String>,
+    pub shift_started_at: Option<String>,
+    pub active_shipments: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OfferResponse {
+    pub id: String,
+    pub shipment_id: String,
+    pub tracking_number: String,
+    pub driver_id: String,
+    pub status: String,
+    pub distance_km: Option<f64>,
+    pub pickup_address: serde_json::Value,
+    pub delivery_address: serde_json::Value,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+pub async fn update_availability(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<UpdateAvailabilityRequest>,
+) -> Result<Json<DriverAvailabilityResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Driver])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    if !AVAILABILITY_STATUSES.contains(&payload.status.as_str()) {
+        return Err(AppError::InvalidInput(format!("Unknown availability status '{}'", payload.status)));
+    }
+
+    let location = match (payload.latitude, payload.longitude) {
+        (Some(latitude), Some(longitude)) => Some((latitude, longitude)),
+        (None, None) => None,
+        _ => return Err(AppError::InvalidInput("Latitude and longitude go together".to_string())),
+    };
+
+    let now = Utc::now();
+
+    // Shift start is kept while the driver toggles between available and
+    // on break, and cleared when they go offline
+    sqlx::query(
+        r#"
+        INSERT INTO driver_availability (driver_id, status, latitude, longitude, location_updated_at, shift_started_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, CASE WHEN $2 = 'offline' THEN NULL ELSE $6 END, $6)
+        ON CONFLICT (driver_id) DO UPDATE SET
+            status = EXCLUDED.status,
+            latitude = COALESCE(EXCLUDED.latitude, driver_availability.latitude),
+            longitude = COALESCE(EXCLUDED.longitude, driver_availability.longitude),
+            location_updated_at = COALESCE(EXCLUDED.location_updated_at, driver_availability.location_updated_at),
+            shift_started_at = CASE
+                WHEN EXCLUDED.status = 'offline' THEN NULL
+                ELSE COALESCE(driver_availability.shift_started_at, EXCLUDED.shift_started_at)
+            END,
+            updated_at = EXCLUDED.updated_at
+        "#,
+    )
+    .bind(auth_user.user_id)
+    .bind(&payload.status)
+    .bind(location.map(|(latitude, _)| latitude))
+    .bind(location.map(|(_, longitude)| longitude))
+    .bind(location.map(|_| now))
+    .bind(now)
+    .execute(&state.db.pool)
+    .await?;
+
+    info!("Driver {} is now {}", auth_user.user_id, payload.status);
+
+    let driver = fetch_driver_availability(&state, Some(auth_user.user_id))
+        .await?
+        .pop()
+        .ok_or_else(|| AppError::Internal("Availability row missing after update".to_string()))?;
+
+    Ok(Json(driver))
+}
+
+pub async fn list_drivers(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<DriverAvailabilityResponse>>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    Ok(Json(fetch_driver_availability(&state, None).await?))
+}
+
+pub async fn list_my_offers(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<OfferResponse>>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Driver])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT o.*, s.tracking_number, s.pickup_address, s.delivery_address
+        FROM dispatch_offers o
+        JOIN shipments s ON s.id = o.shipment_id
+        WHERE o.driver_id = $1 AND o.status = 'pending' AND o.expires_at > $2
+        ORDER BY o.created_at ASC
+        "#,
+    )
+    .bind(auth_user.user_id)
+    .bind(Utc::now())
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    Ok(Json(rows.iter().map(offer_response).collect()))
+}
+
+pub async fn accept_offer(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(offer_id): Path<Uuid>,
+) -> Result<Json<OfferResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Driver])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    let now = Utc::now();
+    let mut tx = state.db.pool.begin().await?;
+
+    let offer = lock_own_pending_offer(&mut tx, offer_id, &auth_user).await?;
+    let shipment_id = offer.get::<Uuid, _>("shipment_id");
+
+    if offer.get::<DateTime<Utc>, _>("expires_at") <= now {
+        expire_offer(&mut tx, offer_id, shipment_id, auth_user.user_id).await?;
+        tx.commit().await?;
+        redispatch(&state, shipment_id).await;
+        return Err(AppError::Conflict("Offer has expired".to_string()));
+    }
+
+    let shipment = sqlx::query("SELECT driver_id, status FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if shipment.get::<ShipmentStatus, _>("status").is_terminal()
+        || shipment.get::<Option<Uuid>, _>("driver_id").is_some()
+    {
+        set_offer_status(&mut tx, offer_id, "cancelled", None).await?;
+        tx.commit().await?;
+        return Err(AppError::Conflict("Shipment is no longer available".to_string()));
+    }
+
+    sqlx::query("UPDATE shipments SET driver_id = $1, updated_at = $2 WHERE id = $3")
+        .bind(auth_user.user_id)
+        .bind(now)
+        .bind(shipment_id)
+        .execute(&mut *tx)
+        .await?;
+
+    set_offer_status(&mut tx, offer_id, "accepted", None).await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "driver_assigned",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: None,
+        metadata: serde_json::json!({ "offer_id": offer_id, "driver_id": auth_user.user_id }),
+    })
+    .await?;
+
+    tx.commit().await?;
+
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(shipment_id, "assignment", serde_json::json!({
+            "driver_id": auth_user.user_id,
+            "assigned": true
+        })))
+        .await;
+
+    info!("Driver {} accepted shipment {}", auth_user.user_id, shipment_id);
+
+    Ok(Json(fetch_offer(&state, offer_id).await?))
+}
+
+pub async fn decline_offer(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(offer_id): Path<Uuid>,
+    Json(payload): Json<DeclineOfferRequest>,
+) -> Result<Json<OfferResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Driver])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    let reason = payload.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+
+    let mut tx = state.db.pool.begin().await?;
+    let offer = lock_own_pending_offer(&mut tx, offer_id, &auth_user).await?;
+    let shipment_id = offer.get::<Uuid, _>("shipment_id");
+
+    set_offer_status(&mut tx, offer_id, "declined", reason).await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "driver_declined",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: reason,
+        metadata: serde_json::json!({ "offer_id": offer_id, "driver_id": auth_user.user_id }),
+    })
+    .await?;
+
+    tx.commit().await?;
+
+    info!("Driver {} declined shipment {}", auth_user.user_id, shipment_id);
+
+    redispatch(&state, shipment_id).await;
+
+    Ok(Json(fetch_offer(&state, offer_id).await?))
+}
+
+/// Runs the assignment engine for a shipment that has no driver yet.
+pub async fn auto_dispatch(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+) -> Result<Json<OfferResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    let offer_id = dispatch_shipment(&state, shipment_id, &[])
+        .await?
+        .ok_or_else(|| AppError::NotFound("No available driver can take this shipment".to_string()))?;
+
+    Ok(Json(fetch_offer(&state, offer_id).await?))
+}
+
+/// Offers the shipment to a specific driver, replacing any pending offer.
+pub async fn assign_driver(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<AssignDriverRequest>,
+) -> Result<Json<OfferResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+    ensure_driver(&state, payload.driver_id).await?;
+
+    let mut tx = state.db.pool.begin().await?;
+    let shipment = lock_open_shipment(&mut tx, shipment_id).await?;
+
+    if shipment.get::<Option<Uuid>, _>("driver_id").is_some() {
+        return Err(AppError::Conflict("Shipment already has a driver; reassign it instead".to_string()));
+    }
+
+    cancel_pending_offers(&mut tx, shipment_id).await?;
+    let offer_id = create_offer(&state, &mut tx, shipment_id, payload.driver_id, Some(&auth_user), None).await?;
+    tx.commit().await?;
+
+    notify_offer(&state, payload.driver_id, shipment_id).await;
+
+    Ok(Json(fetch_offer(&state, offer_id).await?))
+}
+
+pub async fn unassign_driver(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<UnassignDriverRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    let reason = payload.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+
+    let mut tx = state.db.pool.begin().await?;
+    let previous = release_shipment(&mut tx, shipment_id, &auth_user, reason).await?;
+    tx.commit().await?;
+
+    publish_unassigned(&state, shipment_id, previous).await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Driver unassigned",
+        "shipment_id": shipment_id,
+        "previous_driver_id": previous
+    })))
+}
+
+/// Takes the shipment away from its current driver and offers it to the
+/// given driver, or to the best remaining one.
+pub async fn reassign_driver(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+    Json(payload): Json<ReassignDriverRequest>,
+) -> Result<Json<OfferResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to manage dispatch".to_string()))?;
+
+    if let Some(driver_id) = payload.driver_id {
+        ensure_driver(&state, driver_id).await?;
+    }
+
+    let reason = payload.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+
+    let mut tx = state.db.pool.begin().await?;
+    let previous = release_shipment(&mut tx, shipment_id, &auth_user, reason).await?;
+
+    let offer_id = match payload.driver_id {
+        Some(driver_id) => {
+            let offer_id = create_offer(&state, &mut tx, shipment_id, driver_id, Some(&auth_user), None).await?;
+            tx.commit().await?;
+            notify_offer(&state, driver_id, shipment_id).await;
+            offer_id
+        }
+        None => {
+            tx.commit().await?;
+            let exclude: Vec<Uuid> = previous.into_iter().collect();
+            dispatch_shipment(&state, shipment_id, &exclude)
+                .await?
+                .ok_or_else(|| AppError::NotFound("No available driver can take this shipment".to_string()))?
+        }
+    };
+
+    publish_unassigned(&state, shipment_id, previous).await;
+
+    Ok(Json(fetch_offer(&state, offer_id).await?))
+}
+
+// Assignment engine
+
+/// Offers the shipment to the best available driver: the nearest one with
+/// room in their vehicle, with drivers who already carry more shipments
+/// counted as further away. Drivers who declined or let an offer for this
+/// shipment lapse are skipped. Returns the offer id, or `None` if nobody
+/// qualifies.
+pub(crate) async fn dispatch_shipment(
+    state: &crate::AppState,
+    shipment_id: Uuid,
+    exclude: &[Uuid],
+) -> Result<Option<Uuid>, AppError> {
+    let now = Utc::now();
+
+    let shipment = sqlx::query(
+        "SELECT status, driver_id, weight::float8 AS weight, dimensions, pickup_address FROM shipments WHERE id = $1",
+    )
+    .bind(shipment_id)
+    .fetch_optional(&state.db.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    let status = shipment.get::<ShipmentStatus, _>("status");
+    if status.is_terminal() || shipment.get::<Option<Uuid>, _>("driver_id").is_some() {
+        return Ok(None);
+    }
+
+    // Unpicked shipments are collected from the pickup address; anything
+    // else is handed over wherever it was last seen
+    let origin = if status == ShipmentStatus::Pending {
+        None
+    } else {
+        sqlx::query(
+            "SELECT latitude::float8 AS latitude, longitude::float8 AS longitude FROM location_updates WHERE shipment_id = $1 ORDER BY timestamp DESC LIMIT 1",
+        )
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .map(|row| (row.get::<f64, _>("latitude"), row.get::<f64, _>("longitude")))
+    }
+    .or_else(|| address_coordinates(&shipment.get::<serde_json::Value, _>("pickup_address")));
+
+    let Some((origin_latitude, origin_longitude)) = origin else {
+        warn!("Shipment {} has no coordinates to dispatch from", shipment_id);
+        return Ok(None);
+    };
+
+    let weight = shipment.get::<f64, _>("weight");
+    let volume = dimensions_volume_m3(&shipment.get::<serde_json::Value, _>("dimensions"));
+
+    let candidates = sqlx::query(
+        r#"
+        SELECT
+            da.driver_id, da.latitude, da.longitude,
+            dv.max_weight_kg, dv.max_volume_m3,
+            COUNT(s.id) AS active_shipments,
+            COALESCE(SUM(s.weight), 0)::float8 AS active_weight
+        FROM driver_availability da
+        JOIN users u ON u.id = da.driver_id
+        LEFT JOIN driver_vehicles dv ON dv.driver_id = da.driver_id
+        LEFT JOIN shipments s ON s.driver_id = da.driver_id
+            AND s.status IN ('pending', 'picked_up', 'in_transit', 'out_for_delivery')
+        WHERE da.status = 'available'
+          AND u.role = 'driver'
+          AND u.status = 'active'
+          AND da.latitude IS NOT NULL
+          AND da.location_updated_at >= $1
+          AND da.driver_id <> ALL($2)
+          AND NOT EXISTS (
+              SELECT 1 FROM dispatch_offers o
+              WHERE o.shipment_id = $3 AND o.driver_id = da.driver_id AND o.status IN ('declined', 'expired')
+          )
+        GROUP BY da.driver_id, da.latitude, da.longitude, dv.max_weight_kg, dv.max_volume_m3
+        "#,
+    )
+    .bind(now - Duration::minutes(LOCATION_FRESHNESS_MINUTES))
+    .bind(exclude)
+    .bind(shipment_id)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    let best = candidates
+        .iter()
+        .filter(|row| {
+            let weight_fits = row
+                .get::<Option<f64>, _>("max_weight_kg")
+                .is_none_or(|max| row.get::<f64, _>("active_weight") + weight <= max);
+            let volume_fits = row.get::<Option<f64>, _>("max_volume_m3").is_none_or(|max| volume <= max);
+            weight_fits && volume_fits
+        })
+        .map(|row| {
+            let distance_km = calculate_distance(
+                origin_latitude,
+                origin_longitude,
+                row.get::<f64, _>("latitude"),
+                row.get::<f64, _>("longitude"),
+            );
+            let score = distance_km + LOAD_BALANCE_KM_PER_SHIPMENT * row.get::<i64, _>("active_shipments") as f64;
+            (row.get::<Uuid, _>("driver_id"), distance_km, score)
+        })
+        .filter(|&(_, distance_km, _)| distance_km <= state.config.dispatch_max_radius_km)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+
+    let Some((driver_id, distance_km, _)) = best else {
+        info!("No available driver for shipment {}", shipment_id);
+        return Ok(None);
+    };
+
+    let mut tx = state.db.pool.begin().await?;
+    // A driver may have accepted another offer since the check above
+    let locked = lock_open_shipment(&mut tx, shipment_id).await?;
+    if locked.get::<Option<Uuid>, _>("driver_id").is_some() {
+        info!("Shipment {} was assigned while dispatching", shipment_id);
+        return Ok(None);
+    }
+    cancel_pending_offers(&mut tx, shipment_id).await?;
+    let offer_id = create_offer(state, &mut tx, shipment_id, driver_id, None, Some(distance_km)).await?;
+    tx.commit().await?;
+
+    notify_offer(state, driver_id, shipment_id).await;
+
+    info!(
+        "Offered shipment {} to driver {} ({:.1} km away)",
+        shipment_id, driver_id, distance_km
+    );
+
+    Ok(Some(offer_id))
+}
+
+/// Background loop that expires unanswered offers and moves each shipment
+/// on to the next driver.
+pub async fn run_offer_expiry(state: crate::AppState) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(EXPIRY_SWEEP_SECONDS));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = expire_offers(&state).await {
+            error!("Dispatch offer expiry sweep failed: {}", e);
+        }
+    }
+}
+
+/// Keeps the dispatcher's view of where a driver is current.
+pub(crate) async fn record_driver_location(
+    state: &crate::AppState,
+    driver_id: Uuid,
+    latitude: f64,
+    longitude: f64,
+) -> Result<(), AppError> {
+    let now = Utc::now();
+
+    sqlx::query(
+        r#"
+        INSERT INTO driver_availability (driver_id, latitude, longitude, location_updated_at, updated_at)
+        VALUES ($1, $2, $3, $4, $4)
+        ON CONFLICT (driver_id) DO UPDATE SET
+            latitude = EXCLUDED.latitude,
+            longitude = EXCLUDED.longitude,
+            location_updated_at = EXCLUDED.location_updated_at,
+            updated_at = EXCLUDED.updated_at
+        "#,
+    )
+    .bind(driver_id)
+    .bind(latitude)
+    .bind(longitude)
+    .bind(now)
+    .execute(&state.db.pool)
+    .await?;
+
+    Ok(())
+}
+
+// Helper functions
+
+async fn expire_offers(state: &crate::AppState) -> Result<(), AppError> {
+    let mut tx = state.db.pool.begin().await?;
+
+    // Claiming rows with the update makes concurrent sweeps on other
+    // instances skip them
+    let expired = sqlx::query(
+        r#"
+        UPDATE dispatch_offers SET status = 'expired', responded_at = $1
+        WHERE status = 'pending' AND expires_at <= $1
+        RETURNING id, shipment_id, driver_id
+        "#,
+    )
+    .bind(Utc::now())
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for row in &expired {
+        record_shipment_event(&mut tx, ShipmentEventRecord {
+            shipment_id: row.get::<Uuid, _>("shipment_id"),
+            event_type: "driver_offer_expired",
+            from_status: None,
+            to_status: None,
+            actor: None,
+            notes: None,
+            metadata: serde_json::json!({
+                "offer_id": row.get::<Uuid, _>("id"),
+                "driver_id": row.get::<Uuid, _>("driver_id")
+            }),
+        })
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    for row in &expired {
+        redispatch(state, row.get::<Uuid, _>("shipment_id")).await;
+    }
+
+    Ok(())
+}
+
+async fn redispatch(state: &crate::AppState, shipment_id: Uuid) {
+    match dispatch_shipment(state, shipment_id, &[]).await {
+        Ok(Some(_)) => {}
+        Ok(None) => warn!("Shipment {} is waiting for a driver; none available", shipment_id),
+        Err(e) => error!("Re-dispatch failed for shipment {}: {}", shipment_id, e),
+    }
+}
+
+async fn expire_offer(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    offer_id: Uuid,
+    shipment_id: Uuid,
+    driver_id: Uuid,
+) -> Result<(), AppError> {
+    set_offer_status(tx, offer_id, "expired", None).await?;
+
+    record_shipment_event(tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "driver_offer_expired",
+        from_status: None,
+        to_status: None,
+        actor: None,
+        notes: None,
+        metadata: serde_json::json!({ "offer_id": offer_id, "driver_id": driver_id }),
+    })
+    .await?;
+
+    Ok(())
+}
+
+async fn create_offer(
+    state: &crate::AppState,
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    driver_id: Uuid,
+    offered_by: Option<&AuthUser>,
+    distance_km: Option<f64>,
+) -> Result<Uuid, AppError> {
+    let offer_id = Uuid::new_v4();
+    let now = Utc::now();
+
+    sqlx::query(
+        r#"
+        INSERT INTO dispatch_offers (id, shipment_id, driver_id, status, offered_by, distance_km, expires_at, created_at)
+        VALUES ($1, $2, $3, 'pending', $4, $5, $6, $7)
+        "#,
+    )
+    .bind(offer_id)
+    .bind(shipment_id)
+    .bind(driver_id)
+    .bind(offered_by.map(|user| user.user_id))
+    .bind(distance_km)
+    .bind(now + Duration::seconds(state.config.dispatch_offer_timeout_seconds))
+    .bind(now)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => {
+            AppError::Conflict("Shipment already has a pending offer".to_string())
+        }
+        _ => AppError::Database(e),
+    })?;
+
+    record_shipment_event(tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "driver_offered",
+        from_status: None,
+        to_status: None,
+        actor: offered_by,
+        notes: None,
+        metadata: serde_json::json!({
+            "offer_id": offer_id,
+            "driver_id": driver_id,
+            "automatic": offered_by.is_none(),
+            "distance_km": distance_km
+        }),
+    })
+    .await?;
+
+    Ok(offer_id)
+}
+
+/// Clears the driver and any pending offer, returning the previous driver.
+async fn release_shipment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    actor: &AuthUser,
+    reason: Option<&str>,
+) -> Result<Option<Uuid>, AppError> {
+    let shipment = lock_open_shipment(tx, shipment_id).await?;
+    let previous = shipment.get::<Option<Uuid>, _>("driver_id");
+    let cancelled = cancel_pending_offers(tx, shipment_id).await?;
+
+    if previous.is_none() && cancelled == 0 {
+        return Err(AppError::Conflict("Shipment has no driver or pending offer".to_string()));
+    }
+
+    sqlx::query("UPDATE shipments SET driver_id = NULL, updated_at = $1 WHERE id = $2")
+        .bind(Utc::now())
+        .bind(shipment_id)
+        .execute(&mut **tx)
+        .await?;
+
+    record_shipment_event(tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "driver_unassigned",
+        from_status: None,
+        to_status: None,
+        actor: Some(actor),
+        notes: reason,
+        metadata: serde_json::json!({ "previous_driver_id": previous }),
+    })
+    .await?;
+
+    Ok(previous)
+}
+
+async fn lock_open_shipment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+) -> Result<sqlx::postgres::PgRow, AppError> {
+    let shipment = sqlx::query("SELECT driver_id, status FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    if shipment.get::<ShipmentStatus, _>("status").is_terminal() {
+        return Err(AppError::Conflict("Shipment is already finished".to_string()));
+    }
+
+    Ok(shipment)
+}
+
+async fn lock_own_pending_offer(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    offer_id: Uuid,
+    driver: &AuthUser,
+) -> Result<sqlx::postgres::PgRow, AppError> {
+    let offer = sqlx::query("SELECT * FROM dispatch_offers WHERE id = $1 FOR UPDATE")
+        .bind(offer_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .filter(|offer| offer.get::<Uuid, _>("driver_id") == driver.user_id)
+        .ok_or_else(|| AppError::NotFound("Offer not found".to_string()))?;
+
+    let status = offer.get::<String, _>("status");
+    if status != "pending" {
+        return Err(AppError::Conflict(format!("Offer is already {}", status)));
+    }
+
+    Ok(offer)
+}
+
+async fn set_offer_status(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    offer_id: Uuid,
+    status: &str,
+    decline_reason: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query("UPDATE dispatch_offers SET status = $1, decline_reason = $2, responded_at = $3 WHERE id = $4")
+        .bind(status)
+        .bind(decline_reason)
+        .bind(Utc::now())
+        .bind(offer_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn cancel_pending_offers(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+) -> Result<u64, AppError> {
+    let cancelled = sqlx::query(
+        "UPDATE dispatch_offers SET status = 'cancelled', responded_at = $1 WHERE shipment_id = $2 AND status = 'pending'",
+    )
+    .bind(Utc::now())
+    .bind(shipment_id)
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+
+    Ok(cancelled)
+}
+
+async fn ensure_driver(state: &crate::AppState, driver_id: Uuid) -> Result<(), AppError> {
+    let is_driver = sqlx::query("SELECT 1 FROM users WHERE id = $1 AND role = 'driver' AND status = 'active'")
+        .bind(driver_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .is_some();
+
+    if !is_driver {
+        return Err(AppError::InvalidInput("Not an active driver".to_string()));
+    }
+
+    Ok(())
+}
+
+async fn notify_offer(state: &crate::AppState, driver_id: Uuid, shipment_id: Uuid) {
+    if let Err(status) = notify_user(
+        state,
+        driver_id,
+        "New delivery offer",
+        "A shipment is waiting for you to accept or decline",
+        "dispatch_offer",
+        serde_json::json!({ "shipment_id": shipment_id }),
+    )
+    .await
+    {
+        warn!("Failed to notify driver {} of offer: {}", driver_id, status);
+    }
+}
+
+async fn publish_unassigned(state: &crate::AppState, shipment_id: Uuid, previous: Option<Uuid>) {
+    if previous.is_some() {
+        state
+            .tracking_hub
+            .publish(ShipmentUpdate::new(shipment_id, "assignment", serde_json::json!({
+                "driver_id": previous,
+                "assigned": false
+            })))
+            .await;
+    }
+}
+
+async fn fetch_offer(state: &crate::AppState, offer_id: Uuid) -> Result<OfferResponse, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT o.*, s.tracking_number, s.pickup_address, s.delivery_address
+        FROM dispatch_offers o
+        JOIN shipments s ON s.id = o.shipment_id
+        WHERE o.id = $1
+        "#,
+    )
+    .bind(offer_id)
+    .fetch_one(&state.db.pool)
+    .await?;
+
+    Ok(offer_response(&row))
+}
+
+async fn fetch_driver_availability(
+    state: &crate::AppState,
+    driver_id: Option<Uuid>,
+) -> Result<Vec<DriverAvailabilityResponse>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT da.*, COUNT(s.id) AS active_shipments
+        FROM driver_availability da
+        LEFT JOIN shipments s ON s.driver_id = da.driver_id
+            AND s.status IN ('pending', 'picked_up', 'in_transit', 'out_for_delivery')
+        WHERE $1::uuid IS NULL OR da.driver_id = $1
+        GROUP BY da.driver_id
+        ORDER BY da.status, da.updated_at DESC
+        "#,
+    )
+    .bind(driver_id)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| DriverAvailabilityResponse {
+            driver_id: row.get::<Uuid, _>("driver_id").to_string(),
+            status: row.get::<String, _>("status"),
+            latitude: row.get::<Option<f64>, _>("latitude"),
+            longitude: row.get::<Option<f64>, _>("longitude"),
+            location_updated_at: row
+                .get::<Option<DateTime<Utc>>, _>("location_updated_at")
+                .map(|at| at.to_rfc3339()),
+            shift_started_at: row
+                .get::<Option<DateTime<Utc>>, _>("shift_started_at")
+                .map(|at| at.to_rfc3339()),
+            active_shipments: row.get::<i64, _>("active_shipments"),
+        })
+        .collect())
+}
+
+fn offer_response(row: &sqlx::postgres::PgRow) -> OfferResponse {
+    OfferResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        shipment_id: row.get::<Uuid, _>("shipment_id").to_string(),
+        tracking_number: row.get::<String, _>("tracking_number"),
+        driver_id: row.get::<Uuid, _>("driver_id").to_string(),
+        status: row.get::<String, _>("status"),
+        distance_km: row.get::<Option<f64>, _>("distance_km"),
+        pickup_address: row.get::<serde_json::Value, _>("pickup_address"),
+        delivery_address: row.get::<serde_json::Value, _>("delivery_address"),
+        expires_at: row.get::<DateTime<Utc>, _>("expires_at").to_rfc3339(),
+        created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    }
+}
@@ -0,0 +1,480 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::geofencing::{address_city, address_coordinates};
+use crate::models::*;
+use crate::realtime::ShipmentUpdate;
+use crate::utils::{calculate_distance, AppError};
+
+// Arrival predictions from remaining distance, recent driving speed and how
+// long the same city pair has taken before
+
+// Straight-line distance understates the road distance
+pub(crate) const ROAD_FACTOR: f64 = 1.3;
+const SPEED_WINDOW_HOURS: i64 = 2;
+const MIN_SPEED_SAMPLES: usize = 3;
+const MIN_PLAUSIBLE_SPEED_KMH: f64 = 5.0;
+const MAX_PLAUSIBLE_SPEED_KMH: f64 = 130.0;
+const MIN_LANE_SAMPLES: i64 = 5;
+const LANE_HISTORY_LIMIT: i64 = 500;
+const MIN_SPREAD_HOURS: f64 = 0.25;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EtaResponse {
+    pub estimated_arrival: String,
+    pub earliest_arrival: String,
+    pub latest_arrival: String,
+    pub confidence: String, // high, medium, low
+    pub remaining_distance_km: Option<f64>,
+    pub computed_at: String,
+}
+
+/// Delivery durations (pickup to delivery, in hours) seen on one city pair.
+#[derive(Debug, Clone, Copy)]
+pub struct LaneStats {
+    pub samples: i64,
+    pub p10_hours: f64,
+    pub median_hours: f64,
+    pub p90_hours: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct EtaInputs {
+    pub status: ShipmentStatus,
+    pub priority: ShipmentPriority,
+    pub now: DateTime<Utc>,
+    pub remaining_km: Option<f64>,
+    pub total_km: Option<f64>,
+    pub recent_speed_kmh: Option<f64>,
+    pub hours_since_pickup: Option<f64>,
+    pub lane: Option<LaneStats>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EtaPrediction {
+    pub estimated_arrival: DateTime<Utc>,
+    pub earliest_arrival: DateTime<Utc>,
+    pub latest_arrival: DateTime<Utc>,
+    pub confidence: &'static str,
+    pub remaining_km: Option<f64>,
+    pub recent_speed_kmh: Option<f64>,
+    pub lane_samples: i64,
+}
+
+/// Blends a distance/speed estimate with the lane's history. Early in the
+/// trip the lane history dominates; as the shipment nears its destination
+/// the live estimate takes over. Returns `None` when there's nothing to go
+/// on or the shipment is finished.
+pub fn predict(inputs: &EtaInputs) -> Option<EtaPrediction> {
+    if inputs.status.is_terminal() {
+        return None;
+    }
+
+    let handling = if inputs.status == ShipmentStatus::Pending {
+        handling_hours(&inputs.priority)
+    } else {
+        0.0
+    };
+
+    // (estimate, hours early, hours late)
+    let movement = inputs.remaining_km.map(|remaining_km| {
+        let speed = inputs.recent_speed_kmh.unwrap_or_else(|| default_speed_kmh(&inputs.priority));
+        let hours = remaining_km / speed;
+        let uncertainty = if inputs.recent_speed_kmh.is_some() { 0.15 } else { 0.35 };
+        let spread = (hours * uncertainty).max(MIN_SPREAD_HOURS);
+        (hours + handling, spread, spread)
+    });
+
+    let lane = inputs.lane.filter(|lane| lane.samples >= MIN_LANE_SAMPLES).map(|lane| {
+        let elapsed = inputs.hours_since_pickup.unwrap_or(0.0);
+        let remaining = (lane.median_hours - elapsed).max(0.0) + handling;
+        let early = (remaining - ((lane.p10_hours - elapsed).max(0.0) + handling)).max(0.0);
+        let late = ((lane.p90_hours - elapsed).max(0.0) + handling - remaining).max(MIN_SPREAD_HOURS);
+        (remaining, early, late)
+    });
+
+    let (hours, early, late) = match (movement, lane) {
+        (Some(movement), Some(lane)) => {
+            let progress = match (inputs.remaining_km, inputs.total_km) {
+                (Some(remaining), Some(total)) if total > 0.0 => (1.0 - remaining / total).clamp(0.0, 1.0),
+                _ => 0.0,
+            };
+            let weight = 0.3 + 0.7 * progress;
+            (
+                weight * movement.0 + (1.0 - weight) * lane.0,
+                weight * movement.1 + (1.0 - weight) * lane.1,
+                weight * movement.2 + (1.0 - weight) * lane.2,
+            )
+        }
+        (Some(estimate), None) | (None, Some(estimate)) => estimate,
+        (None, None) => return None,
+    };
+
+    let confidence = match (inputs.recent_speed_kmh.is_some(), lane.is_some()) {
+        (true, true) => "high",
+        (true, false) | (false, true) => "medium",
+        (false, false) => "low",
+    };
+
+    let at = |hours: f64| inputs.now + Duration::seconds((hours.max(0.0) * 3600.0).round() as i64);
+
+    Some(EtaPrediction {
+        estimated_arrival: at(hours),
+        earliest_arrival: at(hours - early),
+        latest_arrival: at(hours + late),
+        confidence,
+        remaining_km: inputs.remaining_km.map(|km| (km * 10.0).round() / 10.0),
+        recent_speed_kmh: inputs.recent_speed_kmh,
+        lane_samples: inputs.lane.map_or(0, |lane| lane.samples),
+    })
+}
+
+/// Recomputes and stores the shipment's ETA, telling subscribers about the
+/// new window. Finished shipments have their prediction removed.
+pub(crate) async fn recalculate(state: &crate::AppState, shipment_id: Uuid) -> Result<Option<EtaResponse>, AppError> {
+    let now = Utc::now();
+
+    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    let pickup_address = shipment_row.get::<serde_json::Value, _>("pickup_address");
+    let delivery_address = shipment_row.get::<serde_json::Value, _>("delivery_address");
+    let origin = address_coordinates(&pickup_address);
+    let destination = address_coordinates(&delivery_address);
+
+    let recent: Vec<(f64, f64, DateTime<Utc>)> = sqlx::query(
+        r#"
+        SELECT latitude::float8 AS latitude, longitude::float8 AS longitude, timestamp
+        FROM location_updates
+        WHERE shipment_id = $1 AND timestamp >= $2
+        ORDER BY timestamp ASC
+        "#,
+    )
+    .bind(shipment_id)
+    .bind(now - Duration::hours(SPEED_WINDOW_HOURS))
+    .fetch_all(&state.db.pool)
+    .await?
+    .iter()
+    .map(|row| {
+        (
+            row.get::<f64, _>("latitude"),
+            row.get::<f64, _>("longitude"),
+            row.get::<DateTime<Utc>, _>("timestamp"),
+        )
+    })
+    .collect();
+
+    let current = match recent.last() {
+        Some(&(latitude, longitude, _)) => Some((latitude, longitude)),
+        None => sqlx::query(
+            "SELECT latitude::float8 AS latitude, longitude::float8 AS longitude FROM location_updates WHERE shipment_id = $1 ORDER BY timestamp DESC LIMIT 1",
+        )
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .map(|row| (row.get::<f64, _>("latitude"), row.get::<f64, _>("longitude")))
+        .or(origin),
+    };
+
+    let road_distance = |from: (f64, f64), to: (f64, f64)| calculate_distance(from.0, from.1, to.0, to.1) * ROAD_FACTOR;
+
+    let picked_up_at = sqlx::query(
+        "SELECT MIN(created_at) AS picked_up_at FROM shipment_events WHERE shipment_id = $1 AND to_status = 'picked_up'",
+    )
+    .bind(shipment_id)
+    .fetch_one(&state.db.pool)
+    .await?
+    .get::<Option<DateTime<Utc>>, _>("picked_up_at");
+
+    let lane = match (address_city(&pickup_address), address_city(&delivery_address)) {
+        (Some(from), Some(to)) => lane_stats(state, &from, &to).await?,
+        _ => None,
+    };
+
+    let prediction = predict(&EtaInputs {
+        status: shipment_row.get::<ShipmentStatus, _>("status"),
+        priority: shipment_row.get::<ShipmentPriority, _>("priority"),
+        now,
+        remaining_km: current.zip(destination).map(|(from, to)| road_distance(from, to)),
+        total_km: origin.zip(destination).map(|(from, to)| road_distance(from, to)),
+        recent_speed_kmh: recent_speed(&recent),
+        hours_since_pickup: picked_up_at.map(|at| (now - at).num_seconds() as f64 / 3600.0),
+        lane,
+    });
+
+    let Some(prediction) = prediction else {
+        sqlx::query("DELETE FROM shipment_etas WHERE shipment_id = $1")
+            .bind(shipment_id)
+            .execute(&state.db.pool)
+            .await?;
+        return Ok(None);
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO shipment_etas (
+            shipment_id, estimated_arrival, earliest_arrival, latest_arrival,
+            remaining_distance_km, recent_speed_kmh, lane_samples, confidence, computed_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (shipment_id) DO UPDATE SET
+            estimated_arrival = EXCLUDED.estimated_arrival,
+            earliest_arrival = EXCLUDED.earliest_arrival,
+            latest_arrival = EXCLUDED.latest_arrival,
+            remaining_distance_km = EXCLUDED.remaining_distance_km,
+            recent_speed_kmh = EXCLUDED.recent_speed_kmh,
+            lane_samples = EXCLUDED.lane_samples,
+            confidence = EXCLUDED.confidence,
+            computed_at = EXCLUDED.computed_at
+        "#,
+    )
+    .bind(shipment_id)
+    .bind(prediction.estimated_arrival)
+    .bind(prediction.earliest_arrival)
+    .bind(prediction.latest_arrival)
+    .bind(prediction.remaining_km)
+    .bind(prediction.recent_speed_kmh)
+    .bind(prediction.lane_samples as i32)
+    .bind(prediction.confidence)
+    .bind(now)
+    .execute(&state.db.pool)
+    .await?;
+
+    let response = EtaResponse {
+        estimated_arrival: prediction.estimated_arrival.to_rfc3339(),
+        earliest_arrival: prediction.earliest_arrival.to_rfc3339(),
+        latest_arrival: prediction.latest_arrival.to_rfc3339(),
+        confidence: prediction.confidence.to_string(),
+        remaining_distance_km: prediction.remaining_km,
+        computed_at: now.to_rfc3339(),
+    };
+
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(shipment_id, "eta", serde_json::to_value(&response).unwrap_or_default()))
+        .await;
+
+    info!(
+        "ETA for shipment {}: {} ({} confidence)",
+        shipment_id, response.estimated_arrival, response.confidence
+    );
+
+    Ok(Some(response))
+}
+
+pub(crate) async fn load_eta(state: &crate::AppState, shipment_id: Uuid) -> Result<Option<EtaResponse>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM shipment_etas WHERE shipment_id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?;
+
+    Ok(row.map(|row| EtaResponse {
+        estimated_arrival: row.get::<DateTime<Utc>, _>("estimated_arrival").to_rfc3339(),
+        earliest_arrival: row.get::<DateTime<Utc>, _>("earliest_arrival").to_rfc3339(),
+        latest_arrival: row.get::<DateTime<Utc>, _>("latest_arrival").to_rfc3339(),
+        confidence: row.get::<String, _>("confidence"),
+        remaining_distance_km: row.get::<Option<f64>, _>("remaining_distance_km"),
+        computed_at: row.get::<DateTime<Utc>, _>("computed_at").to_rfc3339(),
+    }))
+}
+
+// Helper functions
+
+fn default_speed_kmh(priority: &ShipmentPriority) -> f64 {
+    match priority {
+        ShipmentPriority::Urgent => 60.0,
+        ShipmentPriority::High => 55.0,
+        ShipmentPriority::Medium => 45.0,
+        ShipmentPriority::Low => 40.0,
+    }
+}
+
+/// Typical wait before a pending shipment is collected.
+fn handling_hours(priority: &ShipmentPriority) -> f64 {
+    match priority {
+        ShipmentPriority::Urgent => 1.0,
+        ShipmentPriority::High => 4.0,
+        ShipmentPriority::Medium => 12.0,
+        ShipmentPriority::Low => 24.0,
+    }
+}
+
+/// Average speed over the recent track. GPS jitter while parked or a
+/// teleporting fix makes the figure meaningless, so implausible values are
+/// discarded rather than clamped.
+fn recent_speed(points: &[(f64, f64, DateTime<Utc>)]) -> Option<f64> {
+    if points.len() < MIN_SPEED_SAMPLES {
+        return None;
+    }
+
+    let distance_km: f64 = points
+        .windows(2)
+        .map(|pair| calculate_distance(pair[0].0, pair[0].1, pair[1].0, pair[1].1))
+        .sum();
+    let hours = (points[points.len() - 1].2 - points[0].2).num_seconds() as f64 / 3600.0;
+    if hours <= 0.0 {
+        return None;
+    }
+
+    let speed = distance_km * ROAD_FACTOR / hours;
+    (MIN_PLAUSIBLE_SPEED_KMH..=MAX_PLAUSIBLE_SPEED_KMH)
+        .contains(&speed)
+        .then_some(speed)
+}
+
+async fn lane_stats(state: &crate::AppState, from_city: &str, to_city: &str) -> Result<Option<LaneStats>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COUNT(*) AS samples,
+            percentile_cont(0.1) WITHIN GROUP (ORDER BY hours) AS p10,
+            percentile_cont(0.5) WITHIN GROUP (ORDER BY hours) AS median,
+            percentile_cont(0.9) WITHIN GROUP (ORDER BY hours) AS p90
+        FROM (
+            SELECT EXTRACT(EPOCH FROM (s.actual_delivery - picked_up.at))::float8 / 3600.0 AS hours
+            FROM shipments s
+            JOIN LATERAL (
+                SELECT MIN(e.created_at) AS at
+                FROM shipment_events e
+                WHERE e.shipment_id = s.id AND e.to_status = 'picked_up'
+            ) picked_up ON picked_up.at IS NOT NULL
+            WHERE s.status = 'delivered'
+              AND s.actual_delivery IS NOT NULL
+              AND LOWER(s.pickup_address->>'city') = LOWER($1)
+              AND LOWER(s.delivery_address->>'city') = LOWER($2)
+            ORDER BY s.actual_delivery DESC
+            LIMIT $3
+        ) lane
+        WHERE hours > 0
+        "#,
+    )
+    .bind(from_city)
+    .bind(to_city)
+    .bind(LANE_HISTORY_LIMIT)
+    .fetch_one(&state.db.pool)
+    .await?;
+
+    let samples = row.get::<i64, _>("samples");
+    match (
+        row.get::<Option<f64>, _>("p10"),
+        row.get::<Option<f64>, _>("median"),
+        row.get::<Option<f64>, _>("p90"),
+    ) {
+        (Some(p10_hours), Some(median_hours), Some(p90_hours)) => Ok(Some(LaneStats {
+            samples,
+            p10_hours,
+            median_hours,
+            p90_hours,
+        })),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T08:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn lane(samples: i64) -> LaneStats {
+        LaneStats {
+            samples,
+            p10_hours: 4.0,
+            median_hours: 5.0,
+            p90_hours: 8.0,
+        }
+    }
+
+    // Halfway along a 200 km trip at 50 km/h, one hour after pickup
+    fn inputs() -> EtaInputs {
+        EtaInputs {
+            status: ShipmentStatus::InTransit,
+            priority: ShipmentPriority::Medium,
+            now: now(),
+            remaining_km: Some(100.0),
+            total_km: Some(200.0),
+            recent_speed_kmh: Some(50.0),
+            hours_since_pickup: Some(1.0),
+            lane: Some(lane(10)),
+        }
+    }
+
+    fn hours_from_now(at: DateTime<Utc>) -> f64 {
+        (at - now()).num_seconds() as f64 / 3600.0
+    }
+
+    #[test]
+    fn blends_live_estimate_with_lane_by_progress() {
+        // Live: 2 h ± 0.3 h. Lane: 4 h, 1 h early, 3 h late. Halfway the
+        // live estimate weighs 0.3 + 0.7 * 0.5 = 0.65.
+        let prediction = predict(&inputs()).unwrap();
+
+        assert!((hours_from_now(prediction.estimated_arrival) - 2.7).abs() < 0.001);
+        assert!((hours_from_now(prediction.earliest_arrival) - 2.155).abs() < 0.001);
+        assert!((hours_from_now(prediction.latest_arrival) - 3.945).abs() < 0.001);
+        assert_eq!(prediction.lane_samples, 10);
+    }
+
+    #[test]
+    fn live_estimate_takes_over_at_the_destination() {
+        let prediction = predict(&EtaInputs { remaining_km: Some(0.0), ..inputs() }).unwrap();
+
+        assert_eq!(prediction.estimated_arrival, now());
+        assert!((hours_from_now(prediction.latest_arrival) - MIN_SPREAD_HOURS).abs() < 0.001);
+    }
+
+    #[test]
+    fn confidence_follows_the_available_signals() {
+        let confidence = |recent_speed_kmh, lane| {
+            predict(&EtaInputs { recent_speed_kmh, lane, ..inputs() }).unwrap().confidence
+        };
+
+        assert_eq!(confidence(Some(50.0), Some(lane(10))), "high");
+        assert_eq!(confidence(None, Some(lane(10))), "medium");
+        assert_eq!(confidence(Some(50.0), None), "medium");
+        assert_eq!(confidence(None, None), "low");
+        // Too little lane history counts as none
+        assert_eq!(confidence(None, Some(lane(MIN_LANE_SAMPLES - 1))), "low");
+    }
+
+    #[test]
+    fn finished_shipments_have_no_prediction() {
+        for status in [ShipmentStatus::Delivered, ShipmentStatus::Returned, ShipmentStatus::Cancelled] {
+            assert!(predict(&EtaInputs { status, ..inputs() }).is_none());
+        }
+    }
+
+    #[test]
+    fn falls_back_to_lane_without_a_distance() {
+        let prediction = predict(&EtaInputs { remaining_km: None, ..inputs() }).unwrap();
+
+        assert!((hours_from_now(prediction.estimated_arrival) - 4.0).abs() < 0.001);
+        assert!((hours_from_now(prediction.earliest_arrival) - 3.0).abs() < 0.001);
+        assert!((hours_from_now(prediction.latest_arrival) - 7.0).abs() < 0.001);
+        assert_eq!(prediction.remaining_km, None);
+
+        assert!(predict(&EtaInputs { remaining_km: None, lane: None, ..inputs() }).is_none());
+    }
+
+    #[test]
+    fn pending_shipments_wait_for_collection() {
+        let prediction = predict(&EtaInputs {
+            status: ShipmentStatus::Pending,
+            remaining_km: Some(200.0),
+            hours_since_pickup: None,
+            lane: None,
+            ..inputs()
+        })
+        .unwrap();
+
+        // 12 h handling for medium priority, then 4 h of driving
+        assert!((hours_from_now(prediction.estimated_arrival) - 16.0).abs() < 0.001);
+    }
+}
@@ -0,0 +1,621 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashMap;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::models::*;
+use crate::notifications::notify_user;
+use crate::realtime::ShipmentUpdate;
+use crate::tracking::{record_shipment_event, transition_shipment_status, ShipmentEventRecord};
+use crate::utils::{calculate_distance, AppError};
+
+// Geofences around a shipment's pickup and delivery points and around hubs.
+// Each location update is checked against them; entering a fence and staying
+// for the dwell time counts as arriving, leaving after that counts as leaving.
+
+// Fixes less accurate than this, or implying a faster trip from the previous
+// fix, are ignored rather than allowed to move the shipment along
+const MAX_FIX_ACCURACY_METERS: f64 = 150.0;
+const MAX_PLAUSIBLE_SPEED_KMH: f64 = 250.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenceKind {
+    Pickup,
+    Delivery,
+    DeliveryZone,
+    Hub,
+}
+
+impl FenceKind {
+    fn arrived_event(self) -> &'static str {
+        match self {
+            FenceKind::Pickup => "arrived_at_pickup",
+            FenceKind::Delivery => "arrived_at_delivery",
+            FenceKind::DeliveryZone => "entered_delivery_zone",
+            FenceKind::Hub => "arrived_at_hub",
+        }
+    }
+
+    fn left_event(self) -> &'static str {
+        match self {
+            FenceKind::Pickup => "left_pickup",
+            FenceKind::Delivery => "left_delivery",
+            FenceKind::DeliveryZone => "left_delivery_zone",
+            FenceKind::Hub => "left_hub",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Geofence {
+    pub key: String,
+    pub kind: FenceKind,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_meters: f64,
+    pub hub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeofenceEvent {
+    pub event_type: String,
+    pub fence_key: String,
+    pub fence_name: String,
+    pub hub_id: Option<String>,
+    pub distance_meters: f64,
+    pub status_changed_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateHubRequest {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_meters: Option<f64>,
+    pub address: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HubResponse {
+    pub id: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_meters: Option<f64>,
+    pub address: serde_json::Value,
+    pub created_at: String,
+}
+
+pub async fn list_hubs(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<HubResponse>>, StatusCode> {
+    auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin, UserRole::Driver])?;
+
+    let rows = sqlx::query("SELECT * FROM hubs WHERE is_active = TRUE ORDER BY name")
+        .fetch_all(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(rows.iter().map(hub_response).collect()))
+}
+
+pub async fn create_hub(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateHubRequest>,
+) -> Result<Json<HubResponse>, StatusCode> {
+    auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
+
+    if payload.name.trim().is_empty()
+        || !valid_coordinates(payload.latitude, payload.longitude)
+        || payload.radius_meters.is_some_and(|radius| radius <= 0.0)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO hubs (id, name, latitude, longitude, radius_meters, address, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(payload.name.trim())
+    .bind(payload.latitude)
+    .bind(payload.longitude)
+    .bind(payload.radius_meters)
+    .bind(payload.address.unwrap_or_else(|| serde_json::json!({})))
+    .bind(Utc::now())
+    .fetch_one(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error creating hub: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    info!("Hub {} created by {}", row.get::<Uuid, _>("id"), auth_user.user_id);
+
+    Ok(Json(hub_response(&row)))
+}
+
+/// Hubs are deactivated rather than deleted so shipment events that mention
+/// them still resolve.
+pub async fn deactivate_hub(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(hub_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
+
+    let updated = sqlx::query("UPDATE hubs SET is_active = FALSE, updated_at = $1 WHERE id = $2 AND is_active = TRUE")
+        .bind(Utc::now())
+        .bind(hub_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .rows_affected();
+
+    if updated == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("Hub {} deactivated by {}", hub_id, auth_user.user_id);
+
+    Ok(Json(serde_json::json!({
+        "message": "Hub deactivated",
+        "hub_id": hub_id
+    })))
+}
+
+/// Checks a new position against the shipment's fences, records the
+/// resulting events and applies any automatic status change. Subscribers
+/// and the sender/receiver are told about what happened after commit.
+pub(crate) async fn process_location(
+    state: &crate::AppState,
+    shipment_id: Uuid,
+    latitude: f64,
+    longitude: f64,
+    accuracy_meters: f64,
+    driver: &AuthUser,
+) -> Result<Vec<GeofenceEvent>, AppError> {
+    let now = Utc::now();
+
+    if !plausible_fix(state, shipment_id, latitude, longitude, accuracy_meters, now).await? {
+        return Ok(Vec::new());
+    }
+
+    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    if shipment_row.get::<ShipmentStatus, _>("status").is_terminal() {
+        return Ok(Vec::new());
+    }
+
+    let fences = fences_for_shipment(state, &shipment_row).await?;
+    if fences.is_empty() {
+        return Ok(Vec::new());
+    }
+    let local_delivery = pickup_in_delivery_zone(&fences);
+
+    let mut tx = state.db.pool.begin().await?;
+
+    let mut previous: HashMap<String, sqlx::postgres::PgRow> = sqlx::query(
+        "SELECT * FROM geofence_states WHERE shipment_id = $1 FOR UPDATE",
+    )
+    .bind(shipment_id)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.get::<String, _>("fence_key"), row))
+    .collect();
+
+    let dwell = Duration::seconds(state.config.geofence_dwell_seconds.max(0));
+    let mut events = Vec::new();
+    let mut status_updates = Vec::new();
+
+    for fence in &fences {
+        let distance_meters = calculate_distance(latitude, longitude, fence.latitude, fence.longitude) * 1000.0;
+        let inside = distance_meters <= fence.radius_meters;
+
+        let state_row = previous.remove(&fence.key);
+        let was_inside = state_row.as_ref().is_some_and(|row| row.get::<bool, _>("inside"));
+        let had_arrived = state_row.as_ref().is_some_and(|row| row.get::<bool, _>("arrived"));
+        let entered_at = state_row
+            .as_ref()
+            .filter(|_| was_inside)
+            .and_then(|row| row.get::<Option<DateTime<Utc>>, _>("entered_at"))
+            .unwrap_or(now);
+
+        let (event_type, arrived) = match (inside, had_arrived) {
+            (true, false) if now - entered_at >= dwell => (Some(fence.kind.arrived_event()), true),
+            (true, arrived) => (None, arrived),
+            // Passing through without dwelling never counted as arriving,
+            // so leaving again isn't an event either
+            (false, true) => (Some(fence.kind.left_event()), false),
+            (false, false) => (None, false),
+        };
+
+        if state_row.is_some() || inside {
+            sqlx::query(
+                r#"
+                INSERT INTO geofence_states (shipment_id, fence_key, inside, entered_at, arrived, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (shipment_id, fence_key) DO UPDATE SET
+                    inside = EXCLUDED.inside,
+                    entered_at = EXCLUDED.entered_at,
+                    arrived = EXCLUDED.arrived,
+                    updated_at = EXCLUDED.updated_at
+                "#,
+            )
+            .bind(shipment_id)
+            .bind(&fence.key)
+            .bind(inside)
+            .bind(inside.then_some(entered_at))
+            .bind(arrived)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let Some(event_type) = event_type else { continue };
+
+        let metadata = serde_json::json!({
+            "source": "geofence",
+            "fence": fence.key,
+            "hub_id": fence.hub_id,
+            "driver_id": driver.user_id,
+            "latitude": latitude,
+            "longitude": longitude,
+            "distance_meters": distance_meters.round(),
+        });
+
+        record_shipment_event(&mut tx, ShipmentEventRecord {
+            shipment_id,
+            event_type,
+            from_status: None,
+            to_status: None,
+            actor: Some(driver),
+            notes: Some(&fence.name),
+            metadata: metadata.clone(),
+        })
+        .await?;
+
+        let mut status_changed_to = None;
+        for &(expected, to) in automatic_transitions(event_type, local_delivery) {
+            let current = sqlx::query("SELECT status FROM shipments WHERE id = $1 FOR UPDATE")
+                .bind(shipment_id)
+                .fetch_one(&mut *tx)
+                .await?
+                .get::<ShipmentStatus, _>("status");
+
+            if current == expected {
+                transition_shipment_status(&mut tx, shipment_id, to, None, Some(&fence.name), metadata.clone()).await?;
+                status_updates.push((current, to));
+                status_changed_to = Some(to.as_str().to_string());
+            }
+        }
+
+        events.push(GeofenceEvent {
+            event_type: event_type.to_string(),
+            fence_key: fence.key.clone(),
+            fence_name: fence.name.clone(),
+            hub_id: fence.hub_id.map(|id| id.to_string()),
+            distance_meters: distance_meters.round(),
+            status_changed_to,
+        });
+    }
+
+    tx.commit().await?;
+
+    for event in &events {
+        info!("Shipment {} geofence event {} ({})", shipment_id, event.event_type, event.fence_name);
+        state
+            .tracking_hub
+            .publish(ShipmentUpdate::new(shipment_id, "geofence", serde_json::to_value(event).unwrap_or_default()))
+            .await;
+    }
+
+    for (previous_status, status) in &status_updates {
+        state
+            .tracking_hub
+            .publish(ShipmentUpdate::new(shipment_id, "status", serde_json::json!({
+                "previous_status": previous_status.as_str(),
+                "status": status.as_str(),
+                "source": "geofence"
+            })))
+            .await;
+    }
+
+    notify_parties(state, &shipment_row, &events).await;
+
+    Ok(events)
+}
+
+// Helper functions
+
+/// Automatic status changes: leaving the pickup point or a hub with the
+/// parcel on board puts it in transit, and entering the delivery zone puts
+/// it out for delivery. When the pickup point already lies in the delivery
+/// zone the zone is never entered, so leaving the pickup does both.
+/// Delivery itself always needs an explicit update.
+fn automatic_transitions(event_type: &str, local_delivery: bool) -> &'static [(ShipmentStatus, ShipmentStatus)] {
+    const IN_TRANSIT: (ShipmentStatus, ShipmentStatus) = (ShipmentStatus::PickedUp, ShipmentStatus::InTransit);
+    const OUT_FOR_DELIVERY: (ShipmentStatus, ShipmentStatus) = (ShipmentStatus::InTransit, ShipmentStatus::OutForDelivery);
+
+    match event_type {
+        "left_pickup" if local_delivery => &[IN_TRANSIT, OUT_FOR_DELIVERY],
+        "left_pickup" | "left_hub" => &[IN_TRANSIT],
+        "entered_delivery_zone" => &[OUT_FOR_DELIVERY],
+        _ => &[],
+    }
+}
+
+fn pickup_in_delivery_zone(fences: &[Geofence]) -> bool {
+    let fence = |kind: FenceKind| fences.iter().find(|fence| fence.kind == kind);
+
+    match (fence(FenceKind::Pickup), fence(FenceKind::DeliveryZone)) {
+        (Some(pickup), Some(zone)) => {
+            calculate_distance(pickup.latitude, pickup.longitude, zone.latitude, zone.longitude) * 1000.0
+                <= zone.radius_meters
+        }
+        _ => false,
+    }
+}
+
+/// Rejects fixes that are too coarse, or that would mean driving
+/// implausibly fast since the shipment's previous fix.
+async fn plausible_fix(
+    state: &crate::AppState,
+    shipment_id: Uuid,
+    latitude: f64,
+    longitude: f64,
+    accuracy_meters: f64,
+    now: DateTime<Utc>,
+) -> Result<bool, AppError> {
+    if !accuracy_meters.is_finite() || accuracy_meters > MAX_FIX_ACCURACY_METERS {
+        warn!("Ignoring fix for shipment {} with accuracy {} m", shipment_id, accuracy_meters);
+        return Ok(false);
+    }
+
+    // The newest update is the fix being checked
+    let previous = sqlx::query(
+        r#"
+        SELECT latitude::float8 AS latitude, longitude::float8 AS longitude, timestamp
+        FROM location_updates WHERE shipment_id = $1
+        ORDER BY timestamp DESC OFFSET 1 LIMIT 1
+        "#,
+    )
+    .bind(shipment_id)
+    .fetch_optional(&state.db.pool)
+    .await?;
+
+    let Some(previous) = previous else {
+        return Ok(true);
+    };
+
+    let distance_km = calculate_distance(
+        previous.get::<f64, _>("latitude"),
+        previous.get::<f64, _>("longitude"),
+        latitude,
+        longitude,
+    );
+    let elapsed_seconds = (now - previous.get::<DateTime<Utc>, _>("timestamp")).num_seconds().max(1);
+    let speed_kmh = distance_km / (elapsed_seconds as f64 / 3600.0);
+
+    if speed_kmh > MAX_PLAUSIBLE_SPEED_KMH {
+        warn!("Ignoring fix for shipment {} implying {:.0} km/h", shipment_id, speed_kmh);
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+async fn fences_for_shipment(
+    state: &crate::AppState,
+    shipment_row: &sqlx::postgres::PgRow,
+) -> Result<Vec<Geofence>, AppError> {
+    let radius = state.config.geofence_radius_meters;
+    let mut fences = Vec::new();
+
+    if let Some((latitude, longitude)) = address_coordinates(&shipment_row.get::<serde_json::Value, _>("pickup_address")) {
+        fences.push(Geofence {
+            key: "pickup".to_string(),
+            kind: FenceKind::Pickup,
+            name: "Pickup address".to_string(),
+            latitude,
+            longitude,
+            radius_meters: radius,
+            hub_id: None,
+        });
+    }
+
+    if let Some((latitude, longitude)) = address_coordinates(&shipment_row.get::<serde_json::Value, _>("delivery_address")) {
+        fences.push(Geofence {
+            key: "delivery".to_string(),
+            kind: FenceKind::Delivery,
+            name: "Delivery address".to_string(),
+            latitude,
+            longitude,
+            radius_meters: radius,
+            hub_id: None,
+        });
+        fences.push(Geofence {
+            key: "delivery_zone".to_string(),
+            kind: FenceKind::DeliveryZone,
+            name: "Delivery zone".to_string(),
+            latitude,
+            longitude,
+            radius_meters: state.config.delivery_zone_radius_meters,
+            hub_id: None,
+        });
+    }
+
+    let hub_ids = route_hub_ids(state, shipment_row).await?;
+    if hub_ids.is_empty() {
+        return Ok(fences);
+    }
+
+    let hubs = sqlx::query(
+        "SELECT id, name, latitude, longitude, radius_meters FROM hubs WHERE is_active = TRUE AND id = ANY($1)",
+    )
+    .bind(&hub_ids)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    fences.extend(hubs.iter().map(|row| {
+        let hub_id = row.get::<Uuid, _>("id");
+        Geofence {
+            key: format!("hub:{}", hub_id),
+            kind: FenceKind::Hub,
+            name: row.get::<String, _>("name"),
+            latitude: row.get::<f64, _>("latitude"),
+            longitude: row.get::<f64, _>("longitude"),
+            radius_meters: row.get::<Option<f64>, _>("radius_meters").unwrap_or(radius),
+            hub_id: Some(hub_id),
+        }
+    }));
+
+    Ok(fences)
+}
+
+/// Hubs the shipment passes through: its own hub addresses (a pickup point,
+/// a consolidation's ends) and those of the consolidation carrying it.
+async fn route_hub_ids(state: &crate::AppState, shipment_row: &sqlx::postgres::PgRow) -> Result<Vec<Uuid>, AppError> {
+    let mut addresses = vec![
+        shipment_row.get::<serde_json::Value, _>("pickup_address"),
+        shipment_row.get::<serde_json::Value, _>("delivery_address"),
+    ];
+
+    if let Some(master_id) = shipment_row.get::<Option<Uuid>, _>("master_shipment_id") {
+        if let Some(master) = sqlx::query("SELECT pickup_address, delivery_address FROM shipments WHERE id = $1")
+            .bind(master_id)
+            .fetch_optional(&state.db.pool)
+            .await?
+        {
+            addresses.push(master.get::<serde_json::Value, _>("pickup_address"));
+            addresses.push(master.get::<serde_json::Value, _>("delivery_address"));
+        }
+    }
+
+    let mut hub_ids: Vec<Uuid> = addresses
+        .iter()
+        .filter_map(|address| address.get("hub_id")?.as_str()?.parse().ok())
+        .collect();
+    hub_ids.sort();
+    hub_ids.dedup();
+
+    Ok(hub_ids)
+}
+
+/// Addresses are free-form JSON; accept `latitude`/`longitude` or `lat`/`lng`.
+pub(crate) fn address_coordinates(address: &serde_json::Value) -> Option<(f64, f64)> {
+    let coordinate = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| address.get(*key))
+            .and_then(|value| value.as_f64().or_else(|| value.as_str()?.parse().ok()))
+    };
+
+    let latitude = coordinate(&["latitude", "lat"])?;
+    let longitude = coordinate(&["longitude", "lng", "lon"])?;
+
+    valid_coordinates(latitude, longitude).then_some((latitude, longitude))
+}
+
+/// The address's city, if it names one.
+pub(crate) fn address_city(address: &serde_json::Value) -> Option<String> {
+    address
+        .get("city")
+        .and_then(|city| city.as_str())
+        .map(str::trim)
+        .filter(|city| !city.is_empty())
+        .map(str::to_string)
+}
+
+/// A hub's address with its coordinates, as a shipment address.
+pub(crate) async fn hub_address(state: &crate::AppState, hub_id: Uuid) -> Result<serde_json::Value, AppError> {
+    let hub = sqlx::query("SELECT * FROM hubs WHERE id = $1 AND is_active")
+        .bind(hub_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Hub {} not found", hub_id)))?;
+
+    let mut address = match hub.get::<serde_json::Value, _>("address") {
+        serde_json::Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    };
+    address.insert("hub_id".to_string(), serde_json::json!(hub_id));
+    address.insert("name".to_string(), serde_json::json!(hub.get::<String, _>("name")));
+    address.insert("latitude".to_string(), serde_json::json!(hub.get::<f64, _>("latitude")));
+    address.insert("longitude".to_string(), serde_json::json!(hub.get::<f64, _>("longitude")));
+
+    Ok(serde_json::Value::Object(address))
+}
+
+fn valid_coordinates(latitude: f64, longitude: f64) -> bool {
+    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+}
+
+async fn notify_parties(state: &crate::AppState, shipment_row: &sqlx::postgres::PgRow, events: &[GeofenceEvent]) {
+    let tracking_number = shipment_row.get::<String, _>("tracking_number");
+    let recipients = [
+        shipment_row.get::<Uuid, _>("sender_id"),
+        shipment_row.get::<Uuid, _>("receiver_id"),
+    ];
+
+    for event in events {
+        let message = match event.event_type.as_str() {
+            "arrived_at_pickup" => "The driver has arrived at the pickup address",
+            "left_pickup" => "Your shipment has left the pickup address",
+            "entered_delivery_zone" => "Your shipment is nearby and out for delivery",
+            "arrived_at_delivery" => "The driver has arrived at the delivery address",
+            "left_hub" => "Your shipment has left a sorting hub",
+            "arrived_at_hub" => "Your shipment has arrived at a sorting hub",
+            _ => continue,
+        };
+
+        for user_id in recipients {
+            if let Err(status) = notify_user(
+                state,
+                user_id,
+                &format!("Shipment {}", tracking_number),
+                message,
+                "shipment_geofence",
+                serde_json::json!({
+                    "shipment_id": shipment_row.get::<Uuid, _>("id"),
+                    "tracking_number": tracking_number,
+                    "event_type": event.event_type,
+                }),
+            )
+            .await
+            {
+                warn!("Failed to notify user {} of geofence event: {}", user_id, status);
+            }
+        }
+    }
+}
+
+fn hub_response(row: &sqlx::postgres::PgRow) -> HubResponse {
+    HubResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        name: row.get::<String, _>("name"),
+        latitude: row.get::<f64, _>("latitude"),
+        longitude: row.get::<f64, _>("longitude"),
+        radius_meters: row.get::<Option<f64>, _>("radius_meters"),
+        address: row.get::<serde_json::Value, _>("address"),
+        created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    }
+}
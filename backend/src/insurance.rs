@@ -6,6 +6,7 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +15,8 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
+use crate::tracking::can_access_shipment;
 
 #[derive(Debug, Clone)]
 pub struct InsuranceService {
@@ -82,37 +85,39 @@ pub struct ClaimResponse {
 
 pub async fn get_policies(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<PolicyResponse>>, StatusCode> {
     info!("Fetching insurance policies");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str());
+    // Policy holders see their own policies; admins may look up anyone's
+    let user_id = if auth_user.role == UserRole::Admin {
+        params
+            .get("user_id")
+            .and_then(|v| v.as_str())
+            .map(|id| Uuid::parse_str(id).map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()?
+    } else {
+        Some(auth_user.user_id)
+    };
     let status = params.get("status").and_then(|v| v.as_str());
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(50);
 
-    let mut query = "SELECT * FROM insurance_policies WHERE 1=1".to_string();
-    let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(uid) = user_id {
-        query.push_str(&format!(" AND user_id = ${}", param_count));
-        bind_values.push(Box::new(Uuid::parse_str(uid).map_err(|_| StatusCode::BAD_REQUEST)?));
-        param_count += 1;
-    }
-
-    if let Some(st) = status {
-        query.push_str(&format!(" AND status = ${}", param_count));
-        bind_values.push(Box::new(st.to_string()));
-        param_count += 1;
-    }
-
-    query.push_str(&format!(" ORDER BY created_at DESC LIMIT ${}", param_count));
-    bind_values.push(Box::new(limit as i64));
-
-    let rows = sqlx::query(&query)
-        .fetch_all(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM insurance_policies
+        WHERE ($1::uuid IS NULL OR user_id = $1)
+          AND ($2::text IS NULL OR status::text = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(status)
+    .bind(limit as i64)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let policies = rows
         .into_iter()
@@ -137,12 +142,13 @@ pub async fn get_policies(
 
 pub async fn create_policy(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreatePolicyRequest>,
 ) -> Result<Json<PolicyResponse>, StatusCode> {
     info!("Creating insurance policy for shipment: {}", payload.shipment_id);
 
     let shipment_id = Uuid::parse_str(&payload.shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
     let policy_id = Uuid::new_v4();
     let policy_number = generate_policy_number();
 
@@ -154,16 +160,16 @@ pub async fn create_policy(
         .map_err(|_| StatusCode::BAD_REQUEST)?
         .with_timezone(&Utc);
 
-    // Verify shipment exists
-    let shipment_exists = sqlx::query("SELECT id FROM shipments WHERE id = $1")
+    // Only parties to the shipment may insure it
+    let shipment = sqlx::query("SELECT * FROM shipments WHERE id = $1")
         .bind(shipment_id)
         .fetch_optional(&state.db.pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .is_some();
+        .ok_or(StatusCode::NOT_FOUND)?;
 
-    if !shipment_exists {
-        return Err(StatusCode::NOT_FOUND);
+    if !can_access_shipment(&auth_user, &shipment) {
+        return Err(StatusCode::FORBIDDEN);
     }
 
     // Create policy
@@ -226,6 +232,7 @@ pub async fn create_policy(
 
 pub async fn create_claim(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateClaimRequest>,
 ) -> Result<Json<ClaimResponse>, StatusCode> {
     info!("Creating insurance claim for policy: {}", payload.policy_id);
@@ -251,6 +258,11 @@ pub async fn create_claim(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    // Claims are filed by the policy holder
+    if policy_row.get::<Uuid, _>("user_id") != auth_user.user_id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     let policy_status: PolicyStatus = policy_row.get("status");
     if !matches!(policy_status, PolicyStatus::Active) {
         return Err(StatusCode::BAD_REQUEST);
@@ -304,23 +316,35 @@ pub async fn create_claim(
 
 pub async fn get_claim(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(claim_id): Path<String>,
 ) -> Result<Json<ClaimResponse>, StatusCode> {
     info!("Fetching insurance claim: {}", claim_id);
 
     let id = Uuid::parse_str(&claim_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    let row = sqlx::query("SELECT * FROM insurance_claims WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let row = sqlx::query(
+        r#"
+        SELECT c.*, p.user_id AS policy_holder_id
+        FROM insurance_claims c
+        JOIN insurance_policies p ON p.id = c.policy_id
+        WHERE c.id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let row = match row {
         Some(row) => row,
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    if auth_user.role != UserRole::Admin && row.get::<Uuid, _>("policy_holder_id") != auth_user.user_id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     let response = ClaimResponse {
         id: claim_id,
         policy_id: row.get::<Uuid, _>("policy_id").to_string(),
@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct IntegrationsService {
@@ -70,11 +71,13 @@ pub struct IntegrationResponse {
 
 pub async fn shopify_connect(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<ShopifyConnectRequest>,
 ) -> Result<Json<IntegrationResponse>, StatusCode> {
     info!("Connecting Shopify store: {}", payload.shop_domain);
 
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    auth_user.require_role(&[UserRole::StoreOwner])?;
+    let user_id = auth_user.user_id;
     let integration_id = Uuid::new_v4();
 
     // Verify Shopify connection
@@ -124,11 +127,13 @@ pub async fn shopify_connect(
 
 pub async fn woocommerce_connect(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<WooCommerceConnectRequest>,
 ) -> Result<Json<IntegrationResponse>, StatusCode> {
     info!("Connecting WooCommerce store: {}", payload.store_url);
 
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    auth_user.require_role(&[UserRole::StoreOwner])?;
+    let user_id = auth_user.user_id;
     let integration_id = Uuid::new_v4();
 
     // Verify WooCommerce connection
@@ -178,11 +183,13 @@ pub async fn woocommerce_connect(
 
 pub async fn wix_connect(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<WixConnectRequest>,
 ) -> Result<Json<IntegrationResponse>, StatusCode> {
     info!("Connecting Wix site: {}", payload.site_id);
 
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    auth_user.require_role(&[UserRole::StoreOwner])?;
+    let user_id = auth_user.user_id;
     let integration_id = Uuid::new_v4();
 
     // Verify Wix connection
@@ -232,11 +239,13 @@ pub async fn wix_connect(
 
 pub async fn easyorder_connect(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<EasyOrderConnectRequest>,
 ) -> Result<Json<IntegrationResponse>, StatusCode> {
     info!("Connecting EasyOrder store: {}", payload.store_id);
 
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    auth_user.require_role(&[UserRole::StoreOwner])?;
+    let user_id = auth_user.user_id;
     let integration_id = Uuid::new_v4();
 
     // Verify EasyOrder connection
@@ -0,0 +1,179 @@
+use candid::Principal;
+use chrono::{DateTime, Utc};
+use ic_canister_sig_creation::{delegation_signature_msg, extract_raw_root_pk_from_der, CanisterSigPublicKey};
+use ic_signature_verification::verify_canister_sig;
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, ED25519};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::utils::AppError;
+
+// Internet Identity delegation-chain verification
+
+pub const CHALLENGE_TTL_MINUTES: i64 = 5;
+
+const ED25519_DER_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+const P256_DER_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// JSON form produced by `DelegationChain.toJSON()` in agent-js: binary
+/// fields are hex strings and expirations are hex-encoded nanoseconds.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DelegationChain {
+    pub delegations: Vec<SignedDelegation>,
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignedDelegation {
+    pub delegation: Delegation,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Delegation {
+    pub pubkey: String,
+    pub expiration: String,
+    pub targets: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifiedDelegation {
+    pub principal: Principal,
+    pub session_public_key: Vec<u8>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Settings a chain is checked against; the root key is configurable so a
+/// local replica's key can be used in development.
+pub struct VerifierSettings<'a> {
+    pub root_key_der_hex: &'a str,
+    pub identity_canister_id: &'a str,
+    pub target_canister_id: Option<&'a str>,
+}
+
+impl DelegationChain {
+    pub fn parse(delegation: &str) -> Result<Self, AppError> {
+        serde_json::from_str(delegation)
+            .map_err(|_| AppError::InvalidInput("Malformed delegation chain".to_string()))
+    }
+
+    /// Verifies every link of the chain and derives the principal from the
+    /// chain's root public key.
+    pub fn verify(&self, settings: &VerifierSettings<'_>, now: DateTime<Utc>) -> Result<VerifiedDelegation, AppError> {
+        if self.delegations.is_empty() || self.delegations.len() > 20 {
+            return Err(AppError::Authentication("Invalid delegation chain length".to_string()));
+        }
+
+        let root_public_key = decode_hex(&self.public_key)?;
+        let ic_root_key = decode_hex(settings.root_key_der_hex)
+            .and_then(|der| {
+                extract_raw_root_pk_from_der(&der)
+                    .map_err(|_| AppError::Internal("Invalid IC root key".to_string()))
+            })?;
+
+        // The chain must start at a canister signature issued by Internet Identity
+        let canister_key = CanisterSigPublicKey::try_from(root_public_key.as_slice())
+            .map_err(|_| AppError::Authentication("Delegation is not signed by a canister".to_string()))?;
+        let identity_canister = Principal::from_text(settings.identity_canister_id)
+            .map_err(|_| AppError::Internal("Invalid Internet Identity canister id".to_string()))?;
+        if canister_key.canister_id != identity_canister {
+            warn!("Delegation signed by unexpected canister {}", canister_key.canister_id);
+            return Err(AppError::Authentication("Delegation not issued by Internet Identity".to_string()));
+        }
+
+        let target = settings
+            .target_canister_id
+            .map(|id| {
+                Principal::from_text(id)
+                    .map_err(|_| AppError::Internal("Invalid target canister id".to_string()))
+            })
+            .transpose()?;
+
+        let now_nanos = now
+            .timestamp_nanos_opt()
+            .ok_or_else(|| AppError::Internal("Clock out of range".to_string()))? as u64;
+
+        let mut signing_key = root_public_key.clone();
+        let mut earliest_expiry = u64::MAX;
+
+        for (index, signed) in self.delegations.iter().enumerate() {
+            let pubkey = decode_hex(&signed.delegation.pubkey)?;
+            let signature = decode_hex(&signed.signature)?;
+            let expiration = u64::from_str_radix(&signed.delegation.expiration, 16)
+                .map_err(|_| AppError::InvalidInput("Malformed delegation expiration".to_string()))?;
+
+            if expiration <= now_nanos {
+                return Err(AppError::Authentication("Delegation expired".to_string()));
+            }
+            earliest_expiry = earliest_expiry.min(expiration);
+
+            let targets = signed
+                .delegation
+                .targets
+                .as_ref()
+                .map(|targets| targets.iter().map(|t| decode_hex(t)).collect::<Result<Vec<_>, _>>())
+                .transpose()?;
+
+            // A delegation scoped to specific canisters is only usable here if
+            // it covers ours
+            if let Some(targets) = &targets {
+                let allowed = target.is_some_and(|target| targets.iter().any(|t| t.as_slice() == target.as_slice()));
+                if !allowed {
+                    return Err(AppError::Authentication("Delegation targets do not include this service".to_string()));
+                }
+            }
+
+            let message = delegation_signature_msg(&pubkey, expiration, targets.as_ref());
+
+            if index == 0 {
+                verify_canister_sig(&message, &signature, &root_public_key, &ic_root_key)
+                    .map_err(|e| {
+                        warn!("Canister signature verification failed: {}", e);
+                        AppError::Authentication("Invalid canister signature".to_string())
+                    })?;
+            } else {
+                verify_basic_signature(&signing_key, &message, &signature)?;
+            }
+
+            signing_key = pubkey;
+        }
+
+        let expires_at = DateTime::from_timestamp_nanos(earliest_expiry as i64);
+
+        Ok(VerifiedDelegation {
+            principal: Principal::self_authenticating(&root_public_key),
+            session_public_key: signing_key,
+            expires_at,
+        })
+    }
+}
+
+/// Checks a signature made by the session key at the end of the chain.
+pub fn verify_session_signature(session_public_key: &[u8], message: &[u8], signature: &str) -> Result<(), AppError> {
+    let signature = decode_hex(signature)?;
+    verify_basic_signature(session_public_key, message, &signature)
+}
+
+/// Verifies an Ed25519 or ECDSA P-256 signature for a DER-encoded key.
+fn verify_basic_signature(public_key_der: &[u8], message: &[u8], signature: &[u8]) -> Result<(), AppError> {
+    let result = if let Some(raw) = public_key_der.strip_prefix(ED25519_DER_PREFIX.as_slice()) {
+        UnparsedPublicKey::new(&ED25519, raw).verify(message, signature)
+    } else if let Some(raw) = public_key_der.strip_prefix(P256_DER_PREFIX.as_slice()) {
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, raw).verify(message, signature)
+    } else {
+        return Err(AppError::Authentication("Unsupported session key type".to_string()));
+    };
+
+    result.map_err(|_| AppError::Authentication("Invalid delegation signature".to_string()))
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, AppError> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|_| AppError::InvalidInput("Malformed hex value".to_string()))
+}
@@ -0,0 +1,621 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::blockchain_payment::get_crypto_price;
+use crate::models::*;
+use crate::utils::{decrypt_bytes, decrypt_data, encrypt_bytes, encrypt_data, hash_data};
+
+// KYC cases: document submission, manual review and expiry
+
+const MAX_DOCUMENT_BYTES: usize = 10 * 1024 * 1024;
+// Users are asked to re-verify this long before their approval lapses
+const REVERIFY_LEAD_DAYS: i64 = 30;
+// USD value of one unit of each fiat currency; the KYC threshold is in USD
+const FIAT_USD_RATES: &[(&str, f64)] = &[
+    ("USD", 1.0),
+    ("EUR", 1.08),
+    ("GBP", 1.27),
+    ("CHF", 1.13),
+    ("CAD", 0.73),
+    ("AUD", 0.66),
+    ("SAR", 0.2667),
+    ("AED", 0.2723),
+    ("QAR", 0.2747),
+    ("KWD", 3.25),
+    ("EGP", 0.0206),
+    ("TRY", 0.031),
+    ("INR", 0.012),
+    ("CNY", 0.138),
+    ("JPY", 0.0067),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KycStatus {
+    Submitted,
+    UnderReview,
+    Approved,
+    Rejected,
+    Resubmit,
+}
+
+impl KycStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KycStatus::Submitted => "submitted",
+            KycStatus::UnderReview => "under_review",
+            KycStatus::Approved => "approved",
+            KycStatus::Rejected => "rejected",
+            KycStatus::Resubmit => "resubmit",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "submitted" => Some(KycStatus::Submitted),
+            "under_review" => Some(KycStatus::UnderReview),
+            "approved" => Some(KycStatus::Approved),
+            "rejected" => Some(KycStatus::Rejected),
+            "resubmit" => Some(KycStatus::Resubmit),
+            _ => None,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        matches!(self, KycStatus::Submitted | KycStatus::UnderReview)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KYCRequest {
+    pub user_id: String,
+    pub document_type: String,
+    pub document_number: String,
+    pub document_image: String, // base64
+    pub selfie_image: Option<String>, // base64
+    pub personal_info: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KycCaseQuery {
+    pub status: Option<String>,
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KycDecisionRequest {
+    pub decision: String, // approve, reject, resubmit
+    pub reason: Option<String>,
+    pub valid_for_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KycCaseResponse {
+    pub id: String,
+    pub user_id: String,
+    pub document_type: String,
+    pub status: String,
+    pub decision_reason: Option<String>,
+    pub reviewer_id: Option<String>,
+    pub submitted_at: String,
+    pub reviewed_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub reverify_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KycDocumentResponse {
+    pub id: String,
+    pub kind: String,
+    pub content_type: String,
+    pub data: String, // base64
+}
+
+#[derive(Debug, Serialize)]
+pub struct KycCaseDetailResponse {
+    #[serde(flatten)]
+    pub case: KycCaseResponse,
+    pub document_number: String,
+    pub personal_info: serde_json::Value,
+    pub documents: Vec<KycDocumentResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KycStatusResponse {
+    pub kyc_verified: bool,
+    pub kyc_expires_at: Option<String>,
+    pub latest_case: Option<KycCaseResponse>,
+}
+
+pub async fn submit_case(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<KYCRequest>,
+) -> Result<Json<KycCaseResponse>, StatusCode> {
+    info!("KYC submission for user: {}", payload.user_id);
+
+    let user_id = Uuid::parse_str(&payload.user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if user_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::Admin])?;
+    }
+
+    if payload.document_type.trim().is_empty() || payload.document_number.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Only one case may be in flight per user
+    let open_case = sqlx::query("SELECT id FROM kyc_cases WHERE user_id = $1 AND status = ANY($2)")
+        .bind(user_id)
+        .bind(vec![KycStatus::Submitted.as_str(), KycStatus::UnderReview.as_str()])
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if open_case.is_some() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut documents = vec![("document", decode_document(&payload.document_image)?)];
+    if let Some(selfie) = &payload.selfie_image {
+        documents.push(("selfie", decode_document(selfie)?));
+    }
+
+    let key = &state.config.encryption_key;
+    let document_number = payload.document_number.trim();
+    let encrypted_number = encrypt_data(document_number, key).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let encrypted_info = encrypt_data(&payload.personal_info.to_string(), key)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let case_id = Uuid::new_v4();
+    let now = Utc::now();
+
+    let mut tx = state.db.pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO kyc_cases (
+            id, user_id, document_type, document_number_encrypted, document_number_hash,
+            personal_info_encrypted, status, submitted_at, created_at, updated_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8, $8)
+        "#,
+    )
+    .bind(case_id)
+    .bind(user_id)
+    .bind(payload.document_type.trim())
+    .bind(&encrypted_number)
+    .bind(hash_data(&format!("{}:{}", payload.document_type.trim(), document_number)))
+    .bind(&encrypted_info)
+    .bind(KycStatus::Submitted.as_str())
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Failed to create KYC case: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    for (kind, (content_type, bytes)) in &documents {
+        let sealed = encrypt_bytes(bytes, key).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO kyc_documents (id, case_id, kind, content_type, size_bytes, encrypted_data, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(case_id)
+        .bind(*kind)
+        .bind(*content_type)
+        .bind(bytes.len() as i64)
+        .bind(sealed)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Failed to store KYC document: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    record_case_event(&mut tx, case_id, auth_user.user_id, None, KycStatus::Submitted, None).await?;
+
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    info!("KYC case {} submitted for user {}", case_id, user_id);
+
+    let row = fetch_case(&state, case_id).await?;
+    Ok(Json(case_response_from_row(&row)))
+}
+
+pub async fn get_status(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<KycStatusResponse>, StatusCode> {
+    let user_row = sqlx::query("SELECT kyc_verified, kyc_expires_at FROM users WHERE id = $1")
+        .bind(auth_user.user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let latest_case = sqlx::query("SELECT * FROM kyc_cases WHERE user_id = $1 ORDER BY submitted_at DESC LIMIT 1")
+        .bind(auth_user.user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let expires_at = user_row.get::<Option<DateTime<Utc>>, _>("kyc_expires_at");
+
+    Ok(Json(KycStatusResponse {
+        kyc_verified: user_row.get::<bool, _>("kyc_verified") && expires_at.map_or(true, |at| at > Utc::now()),
+        kyc_expires_at: expires_at.map(|at| at.to_rfc3339()),
+        latest_case: latest_case.as_ref().map(case_response_from_row),
+    }))
+}
+
+pub async fn list_cases(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<KycCaseQuery>,
+) -> Result<Json<Vec<KycCaseResponse>>, StatusCode> {
+    auth_user.require_role(&[UserRole::Admin])?;
+
+    let status = match params.status.as_deref() {
+        Some(status) => KycStatus::parse(status).ok_or(StatusCode::BAD_REQUEST)?,
+        None => KycStatus::Submitted,
+    };
+    let limit = params.limit.unwrap_or(20).min(100) as i64;
+    let offset = (params.page.unwrap_or(1).max(1) as i64 - 1) * limit;
+
+    // Oldest first so the queue is worked in order
+    let rows = sqlx::query(
+        "SELECT * FROM kyc_cases WHERE status = $1 ORDER BY submitted_at ASC LIMIT $2 OFFSET $3",
+    )
+    .bind(status.as_str())
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(rows.iter().map(case_response_from_row).collect()))
+}
+
+pub async fn get_case(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(case_id): Path<Uuid>,
+) -> Result<Json<KycCaseDetailResponse>, StatusCode> {
+    auth_user.require_role(&[UserRole::Admin])?;
+
+    let row = fetch_case(&state, case_id).await?;
+    let key = &state.config.encryption_key;
+
+    let document_number = decrypt_data(&row.get::<String, _>("document_number_encrypted"), key)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let personal_info = decrypt_data(&row.get::<String, _>("personal_info_encrypted"), key)
+        .ok()
+        .and_then(|info| serde_json::from_str(&info).ok())
+        .unwrap_or(serde_json::Value::Null);
+
+    let document_rows = sqlx::query("SELECT * FROM kyc_documents WHERE case_id = $1 ORDER BY created_at")
+        .bind(case_id)
+        .fetch_all(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut documents = Vec::with_capacity(document_rows.len());
+    for document in &document_rows {
+        let bytes = decrypt_bytes(&document.get::<Vec<u8>, _>("encrypted_data"), key)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        documents.push(KycDocumentResponse {
+            id: document.get::<Uuid, _>("id").to_string(),
+            kind: document.get::<String, _>("kind"),
+            content_type: document.get::<String, _>("content_type"),
+            data: BASE64.encode(bytes),
+        });
+    }
+
+    info!("KYC case {} opened by reviewer {}", case_id, auth_user.user_id);
+
+    Ok(Json(KycCaseDetailResponse {
+        case: case_response_from_row(&row),
+        document_number,
+        personal_info,
+        documents,
+    }))
+}
+
+/// Moves a submitted case into the reviewer's hands.
+pub async fn claim_case(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(case_id): Path<Uuid>,
+) -> Result<Json<KycCaseResponse>, StatusCode> {
+    auth_user.require_role(&[UserRole::Admin])?;
+
+    let mut tx = state.db.pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let claimed = sqlx::query(
+        "UPDATE kyc_cases SET status = $1, reviewer_id = $2, updated_at = $3 WHERE id = $4 AND status = $5",
+    )
+    .bind(KycStatus::UnderReview.as_str())
+    .bind(auth_user.user_id)
+    .bind(Utc::now())
+    .bind(case_id)
+    .bind(KycStatus::Submitted.as_str())
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .rows_affected();
+
+    if claimed == 0 {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    record_case_event(
+        &mut tx,
+        case_id,
+        auth_user.user_id,
+        Some(KycStatus::Submitted),
+        KycStatus::UnderReview,
+        None,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let row = fetch_case(&state, case_id).await?;
+    Ok(Json(case_response_from_row(&row)))
+}
+
+pub async fn decide_case(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(case_id): Path<Uuid>,
+    Json(payload): Json<KycDecisionRequest>,
+) -> Result<Json<KycCaseResponse>, StatusCode> {
+    auth_user.require_role(&[UserRole::Admin])?;
+
+    let decision = match payload.decision.as_str() {
+        "approve" => KycStatus::Approved,
+        "reject" => KycStatus::Rejected,
+        "resubmit" => KycStatus::Resubmit,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let reason = payload.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    if decision != KycStatus::Approved && reason.is_none() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let row = fetch_case(&state, case_id).await?;
+    let current = KycStatus::parse(&row.get::<String, _>("status")).ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !current.is_open() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let user_id = row.get::<Uuid, _>("user_id");
+    if user_id == auth_user.user_id {
+        warn!("Reviewer {} attempted to decide their own KYC case", auth_user.user_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let now = Utc::now();
+    let (expires_at, reverify_at) = if decision == KycStatus::Approved {
+        let valid_for = payload.valid_for_days.unwrap_or(state.config.kyc_validity_days);
+        if valid_for <= 0 {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let expires_at = now + Duration::days(valid_for);
+        let reverify_at = expires_at - Duration::days(REVERIFY_LEAD_DAYS.min(valid_for / 2));
+        (Some(expires_at), Some(reverify_at))
+    } else {
+        (None, None)
+    };
+
+    let mut tx = state.db.pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let updated = sqlx::query(
+        r#"
+        UPDATE kyc_cases SET
+            status = $1, decision_reason = $2, reviewer_id = $3, reviewed_at = $4,
+            expires_at = $5, reverify_at = $6, updated_at = $4
+        WHERE id = $7 AND status = $8
+        "#,
+    )
+    .bind(decision.as_str())
+    .bind(reason)
+    .bind(auth_user.user_id)
+    .bind(now)
+    .bind(expires_at)
+    .bind(reverify_at)
+    .bind(case_id)
+    .bind(current.as_str())
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    // Only an approval grants verification; other outcomes leave any earlier
+    // approval to run out on its own schedule
+    if decision == KycStatus::Approved {
+        sqlx::query("UPDATE users SET kyc_verified = true, kyc_expires_at = $1, updated_at = $2 WHERE id = $3")
+            .bind(expires_at)
+            .bind(now)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    record_case_event(&mut tx, case_id, auth_user.user_id, Some(current), decision, reason).await?;
+
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    info!("KYC case {} {} by reviewer {}", case_id, decision.as_str(), auth_user.user_id);
+
+    let row = fetch_case(&state, case_id).await?;
+    Ok(Json(case_response_from_row(&row)))
+}
+
+/// Returns `403` unless the user holds an unexpired KYC approval.
+pub(crate) async fn require_kyc(state: &crate::AppState, user_id: Uuid) -> Result<(), StatusCode> {
+    let row = sqlx::query("SELECT kyc_verified, kyc_expires_at FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::FORBIDDEN)?;
+
+    if !row.get::<bool, _>("kyc_verified") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let expires_at = row.get::<Option<DateTime<Utc>>, _>("kyc_expires_at");
+    if expires_at.is_some_and(|at| at <= Utc::now()) {
+        // Lapsed approvals are cleared lazily the first time they're checked
+        sqlx::query("UPDATE users SET kyc_verified = false, updated_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        info!("KYC approval expired for user {}", user_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(())
+}
+
+/// Converts `amount` of `currency` (an ISO code or a crypto symbol) into the
+/// USD the KYC threshold is set in. Currencies without a rate come out as
+/// infinite so they always need KYC.
+pub(crate) async fn fiat_value(currency: &str, amount: f64) -> f64 {
+    let code = currency.trim().to_uppercase();
+
+    if let Some((_, rate)) = FIAT_USD_RATES.iter().find(|(fiat, _)| *fiat == code) {
+        return amount * rate;
+    }
+
+    match get_crypto_price(&code).await {
+        price if price > 0.0 => amount * price,
+        _ => f64::INFINITY,
+    }
+}
+
+/// Requires KYC when `amount` reaches the configured high-value threshold.
+/// `amount` must already be in USD; see `fiat_value`.
+pub(crate) async fn require_kyc_for_amount(
+    state: &crate::AppState,
+    user_id: Uuid,
+    amount: f64,
+) -> Result<(), StatusCode> {
+    if amount < state.config.kyc_high_value_threshold {
+        return Ok(());
+    }
+
+    require_kyc(state, user_id).await.map_err(|status| {
+        warn!("User {} needs KYC for a {} transaction", user_id, amount);
+        status
+    })
+}
+
+// Helper functions
+
+async fn fetch_case(state: &crate::AppState, case_id: Uuid) -> Result<sqlx::postgres::PgRow, StatusCode> {
+    sqlx::query("SELECT * FROM kyc_cases WHERE id = $1")
+        .bind(case_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn record_case_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    case_id: Uuid,
+    actor_id: Uuid,
+    from_status: Option<KycStatus>,
+    to_status: KycStatus,
+    reason: Option<&str>,
+) -> Result<(), StatusCode> {
+    sqlx::query(
+        r#"
+        INSERT INTO kyc_case_events (id, case_id, actor_id, from_status, to_status, reason, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(case_id)
+    .bind(actor_id)
+    .bind(from_status.map(|status| status.as_str()))
+    .bind(to_status.as_str())
+    .bind(reason)
+    .bind(Utc::now())
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        error!("Failed to record KYC case event: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(())
+}
+
+/// Decodes a base64 upload (optionally a data URL) and checks it is an
+/// image or PDF within the size limit.
+fn decode_document(data: &str) -> Result<(&'static str, Vec<u8>), StatusCode> {
+    let encoded = data.split_once(";base64,").map_or(data, |(_, encoded)| encoded);
+    let bytes = BASE64.decode(encoded.trim()).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    if bytes.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if bytes.len() > MAX_DOCUMENT_BYTES {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let content_type = if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if bytes.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    };
+
+    Ok((content_type, bytes))
+}
+
+fn case_response_from_row(row: &sqlx::postgres::PgRow) -> KycCaseResponse {
+    KycCaseResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        user_id: row.get::<Uuid, _>("user_id").to_string(),
+        document_type: row.get::<String, _>("document_type"),
+        status: row.get::<String, _>("status"),
+        decision_reason: row.get::<Option<String>, _>("decision_reason"),
+        reviewer_id: row.get::<Option<Uuid>, _>("reviewer_id").map(|id| id.to_string()),
+        submitted_at: row.get::<DateTime<Utc>, _>("submitted_at").to_rfc3339(),
+        reviewed_at: row.get::<Option<DateTime<Utc>>, _>("reviewed_at").map(|at| at.to_rfc3339()),
+        expires_at: row.get::<Option<DateTime<Utc>>, _>("expires_at").map(|at| at.to_rfc3339()),
+        reverify_at: row.get::<Option<DateTime<Utc>>, _>("reverify_at").map(|at| at.to_rfc3339()),
+    }
+}
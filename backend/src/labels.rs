@@ -0,0 +1,834 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use qrcode::{Color, QrCode};
+use serde::Deserialize;
+use sqlx::Row;
+use std::collections::HashMap;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::models::*;
+use crate::routing::{build_driver_route, StopKind};
+use crate::parcels::load_parcels;
+use crate::tracking::can_access_shipment;
+
+// Printable shipping labels and driver manifests, drawn as vector PDF
+
+const MM: f32 = 72.0 / 25.4;
+const MARGIN: f32 = 10.0;
+// Horizontal room left around a barcode for scanners, in modules
+const BARCODE_QUIET_ZONE: usize = 10;
+const QR_QUIET_ZONE: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum LabelFormat {
+    #[default]
+    #[serde(rename = "a6")]
+    A6,
+    #[serde(rename = "4x6")]
+    FourBySix,
+}
+
+impl LabelFormat {
+    fn page_size(&self) -> (f32, f32) {
+        match self {
+            LabelFormat::A6 => (105.0 * MM, 148.0 * MM),
+            LabelFormat::FourBySix => (4.0 * 72.0, 6.0 * 72.0),
+        }
+    }
+}
+
+pub struct LabelData {
+    pub tracking_number: String,
+    pub tracking_url: String,
+    pub sender_name: String,
+    pub sender_address: Vec<String>,
+    pub receiver_name: String,
+    pub receiver_address: Vec<String>,
+    pub weight_kg: f64,
+    pub dimensions: Option<String>,
+    pub priority: String,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+    pub delivery_window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+pub struct ManifestEntry {
+    pub sequence: usize,
+    pub kind: &'static str,
+    pub tracking_number: String,
+    pub contact_name: String,
+    pub address: Vec<String>,
+    pub weight_kg: f64,
+    pub priority: String,
+    pub arrival: Option<DateTime<Utc>>,
+    pub window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+pub struct ManifestData {
+    pub driver_name: String,
+    pub vehicle: Option<String>,
+    pub date: NaiveDate,
+    pub total_distance_km: f64,
+    pub total_duration_minutes: f64,
+    pub entries: Vec<ManifestEntry>,
+    /// Shipments the route left out, with the reason.
+    pub unrouted: Vec<(String, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LabelQuery {
+    /// `a6` (default) or `4x6`
+    #[serde(default)]
+    pub format: LabelFormat,
+}
+
+pub async fn get_label(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<String>,
+    Query(query): Query<LabelQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let rows = fetch_shipment_details(&state, &[id]).await?;
+    let row = rows.first().ok_or(StatusCode::NOT_FOUND)?;
+
+    if !can_access_shipment(&auth_user, row) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if row.get::<ShipmentStatus, _>("status") == ShipmentStatus::Cancelled {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let tracking_number = row.get::<String, _>("tracking_number");
+    let label = LabelData {
+        tracking_url: format!("{}/track/{}", state.config.app_url.trim_end_matches('/'), tracking_number),
+        tracking_number: tracking_number.clone(),
+        sender_name: row.get::<String, _>("sender_name"),
+        sender_address: address_lines(&row.get::<serde_json::Value, _>("pickup_address")),
+        receiver_name: row.get::<String, _>("receiver_name"),
+        receiver_address: address_lines(&row.get::<serde_json::Value, _>("delivery_address")),
+        weight_kg: row.get::<f64, _>("weight_kg"),
+        dimensions: format_dimensions(&row.get::<serde_json::Value, _>("dimensions")),
+        priority: row.get::<ShipmentPriority, _>("priority").as_str().to_string(),
+        description: row.get::<String, _>("description"),
+        created_at: row.get::<DateTime<Utc>, _>("created_at"),
+        delivery_window: row
+            .get::<Option<DateTime<Utc>>, _>("delivery_window_start")
+            .zip(row.get::<Option<DateTime<Utc>>, _>("delivery_window_end")),
+    };
+
+    // One label per parcel, each with its own barcode; the QR code still
+    // points at the shipment's tracking page
+    let parcels = load_parcels(&state.db.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let labels: Vec<LabelData> = if parcels.is_empty() {
+        vec![label]
+    } else {
+        let count = parcels.len();
+        parcels
+            .iter()
+            .map(|parcel| LabelData {
+                tracking_number: parcel.tracking_number.clone(),
+                weight_kg: parcel.weight,
+                dimensions: format_dimensions(&parcel.dimensions),
+                description: match &parcel.description {
+                    Some(description) => format!("Parcel {} of {} - {}", parcel.sequence, count, description),
+                    None => format!("Parcel {} of {} - {}", parcel.sequence, count, label.description),
+                },
+                tracking_url: label.tracking_url.clone(),
+                sender_name: label.sender_name.clone(),
+                sender_address: label.sender_address.clone(),
+                receiver_name: label.receiver_name.clone(),
+                receiver_address: label.receiver_address.clone(),
+                priority: label.priority.clone(),
+                created_at: label.created_at,
+                delivery_window: label.delivery_window,
+            })
+            .collect()
+    };
+
+    info!("{} label(s) for shipment {} printed by {}", labels.len(), id, auth_user.user_id);
+
+    Ok(pdf_response(
+        render_labels(&labels, query.format),
+        &format!("label-{}.pdf", tracking_number),
+    ))
+}
+
+/// Today's stops for a driver in planned route order.
+pub async fn get_driver_manifest(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(driver_id): Path<Uuid>,
+    Query(query): Query<LabelQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if driver_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
+    }
+
+    let driver = sqlx::query(
+        r#"
+        SELECT u.first_name, u.last_name, v.vehicle_type
+        FROM users u
+        LEFT JOIN driver_vehicles v ON v.driver_id = u.id
+        WHERE u.id = $1 AND u.role = 'driver'
+        "#,
+    )
+    .bind(driver_id)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let route = build_driver_route(&state, driver_id, None).await?;
+
+    let parse_id = |id: &str| Uuid::parse_str(id).ok();
+    let shipment_ids: Vec<Uuid> = route
+        .stops
+        .iter()
+        .map(|stop| stop.shipment_id.as_str())
+        .chain(route.unassigned.iter().map(|stop| stop.shipment_id.as_str()))
+        .chain(route.skipped_shipments.iter().map(String::as_str))
+        .filter_map(parse_id)
+        .collect();
+    let details: HashMap<Uuid, sqlx::postgres::PgRow> = fetch_shipment_details(&state, &shipment_ids)
+        .await?
+        .into_iter()
+        .map(|row| (row.get::<Uuid, _>("id"), row))
+        .collect();
+
+    let parse_time = |value: &str| DateTime::parse_from_rfc3339(value).ok().map(|at| at.with_timezone(&Utc));
+
+    let entries = route
+        .stops
+        .iter()
+        .filter_map(|stop| {
+            let row = details.get(&parse_id(&stop.shipment_id)?)?;
+            let (kind, contact, address) = match stop.kind {
+                StopKind::Pickup => ("PICKUP", "sender_name", "pickup_address"),
+                StopKind::Delivery => ("DELIVERY", "receiver_name", "delivery_address"),
+            };
+
+            Some(ManifestEntry {
+                sequence: stop.sequence,
+                kind,
+                tracking_number: stop.tracking_number.clone(),
+                contact_name: row.get::<String, _>(contact),
+                address: address_lines(&row.get::<serde_json::Value, _>(address)),
+                weight_kg: row.get::<f64, _>("weight_kg"),
+                priority: row.get::<ShipmentPriority, _>("priority").as_str().to_string(),
+                arrival: parse_time(&stop.arrival),
+                window: stop
+                    .window_start
+                    .as_deref()
+                    .and_then(parse_time)
+                    .zip(stop.window_end.as_deref().and_then(parse_time)),
+            })
+        })
+        .collect();
+
+    let unrouted = route
+        .unassigned
+        .iter()
+        .map(|stop| (stop.tracking_number.clone(), stop.reason.replace('_', " ")))
+        .chain(route.skipped_shipments.iter().filter_map(|id| {
+            let row = details.get(&parse_id(id)?)?;
+            Some((row.get::<String, _>("tracking_number"), "no usable coordinates".to_string()))
+        }))
+        .collect();
+
+    let now = Utc::now();
+    let manifest = ManifestData {
+        driver_name: format!(
+            "{} {}",
+            driver.get::<String, _>("first_name"),
+            driver.get::<String, _>("last_name")
+        ),
+        vehicle: driver.get::<Option<String>, _>("vehicle_type"),
+        date: now.date_naive(),
+        total_distance_km: route.total_distance_km,
+        total_duration_minutes: route.total_duration_minutes,
+        entries,
+        unrouted,
+    };
+
+    info!("Manifest for driver {} printed by {}", driver_id, auth_user.user_id);
+
+    Ok(pdf_response(
+        render_manifest(&manifest, query.format),
+        &format!("manifest-{}-{}.pdf", driver_id, now.format("%Y-%m-%d")),
+    ))
+}
+
+// Helper functions
+
+async fn fetch_shipment_details(
+    state: &crate::AppState,
+    shipment_ids: &[Uuid],
+) -> Result<Vec<sqlx::postgres::PgRow>, StatusCode> {
+    sqlx::query(
+        r#"
+        SELECT s.*, s.weight::float8 AS weight_kg,
+               sender.first_name || ' ' || sender.last_name AS sender_name,
+               receiver.first_name || ' ' || receiver.last_name AS receiver_name
+        FROM shipments s
+        JOIN users sender ON sender.id = s.sender_id
+        JOIN users receiver ON receiver.id = s.receiver_id
+        WHERE s.id = ANY($1)
+        "#,
+    )
+    .bind(shipment_ids)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn pdf_response(pdf: Vec<u8>, filename: &str) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", filename)),
+        ],
+        pdf,
+    )
+}
+
+/// Free-form address JSON as printable lines.
+fn address_lines(address: &serde_json::Value) -> Vec<String> {
+    if let Some(address) = address.as_str() {
+        return vec![address.to_string()];
+    }
+
+    let field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| address.get(*key)?.as_str())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let join = |parts: [Option<&str>; 2], separator: &str| {
+        parts.into_iter().flatten().collect::<Vec<_>>().join(separator)
+    };
+
+    let lines: Vec<String> = [
+        field(&["street", "address_line1", "line1", "address"]).map(str::to_string),
+        field(&["address_line2", "line2"]).map(str::to_string),
+        Some(join([field(&["postal_code", "zip", "postcode"]), field(&["city"])], " ")),
+        Some(join([field(&["state", "region"]), field(&["country"])], ", ")),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|line| !line.is_empty())
+    .collect();
+
+    if !lines.is_empty() {
+        return lines;
+    }
+
+    // Unknown shape; print whatever text it carries
+    address
+        .as_object()
+        .map(|fields| fields.values().filter_map(|value| value.as_str()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn format_dimensions(dimensions: &serde_json::Value) -> Option<String> {
+    let side = |key: &str| dimensions.get(key).and_then(|value| value.as_f64()).filter(|side| *side > 0.0);
+    let (length, width, height) = (side("length")?, side("width")?, side("height")?);
+    Some(format!("{} x {} x {} cm", length, width, height))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn name(&self) -> Name<'static> {
+        match self {
+            Font::Regular => Name(b"F1"),
+            Font::Bold => Name(b"F2"),
+        }
+    }
+}
+
+/// Pages of one size, written out with the standard Helvetica faces so no
+/// font has to be embedded.
+struct Document {
+    width: f32,
+    height: f32,
+    pages: Vec<Content>,
+}
+
+impl Document {
+    fn new(format: LabelFormat) -> Self {
+        let (width, height) = format.page_size();
+        Self { width, height, pages: Vec::new() }
+    }
+
+    fn add_page(&mut self) -> usize {
+        self.pages.push(Content::new());
+        self.pages.len() - 1
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let catalog_id = Ref::new(1);
+        let tree_id = Ref::new(2);
+        let regular_id = Ref::new(3);
+        let bold_id = Ref::new(4);
+        let page_ids: Vec<Ref> = (0..self.pages.len()).map(|index| Ref::new(5 + 2 * index as i32)).collect();
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(tree_id);
+        pdf.pages(tree_id).kids(page_ids.iter().copied()).count(page_ids.len() as i32);
+
+        for (page_id, content) in page_ids.iter().zip(self.pages) {
+            let content_id = Ref::new(page_id.get() + 1);
+
+            let mut page = pdf.page(*page_id);
+            page.media_box(Rect::new(0.0, 0.0, self.width, self.height));
+            page.parent(tree_id);
+            page.contents(content_id);
+            page.resources()
+                .fonts()
+                .pair(Font::Regular.name(), regular_id)
+                .pair(Font::Bold.name(), bold_id);
+            page.finish();
+
+            pdf.stream(content_id, &content.finish());
+        }
+
+        for (font_id, base_font) in [(regular_id, &b"Helvetica"[..]), (bold_id, &b"Helvetica-Bold"[..])] {
+            pdf.type1_font(font_id)
+                .base_font(Name(base_font))
+                .encoding_predefined(Name(b"WinAnsiEncoding"));
+        }
+
+        pdf.finish()
+    }
+}
+
+/// Renders one label per shipment, one page each.
+pub fn render_labels(labels: &[LabelData], format: LabelFormat) -> Vec<u8> {
+    let mut document = Document::new(format);
+    for label in labels {
+        let page = document.add_page();
+        let (width, height) = (document.width, document.height);
+        draw_label(&mut document.pages[page], label, width, height);
+    }
+    document.finish()
+}
+
+fn draw_label(content: &mut Content, label: &LabelData, width: f32, height: f32) {
+    let inner = width - 2.0 * MARGIN;
+    let mut y = height - MARGIN;
+
+    // Priority band across the top, inverted for anything above standard
+    let band = 22.0;
+    let urgent = matches!(label.priority.as_str(), "high" | "urgent");
+    if urgent {
+        content.rect(MARGIN, y - band, inner, band).fill_nonzero();
+        content.set_fill_gray(1.0);
+    } else {
+        content.set_line_width(1.0).rect(MARGIN, y - band, inner, band).stroke();
+    }
+    draw_text(content, Font::Bold, 14.0, MARGIN + 6.0, y - 16.0, &label.priority.to_uppercase());
+    let weight = format!("{:.2} kg", label.weight_kg);
+    draw_text(content, Font::Bold, 14.0, width - MARGIN - 6.0 - text_width(Font::Bold, 14.0, &weight), y - 16.0, &weight);
+    content.set_fill_gray(0.0);
+    y -= band + 10.0;
+
+    // Sender
+    draw_text(content, Font::Bold, 7.0, MARGIN, y, "FROM");
+    y -= 10.0;
+    draw_text(content, Font::Regular, 9.0, MARGIN, y, &fit(Font::Regular, 9.0, &label.sender_name, inner));
+    for line in label.sender_address.iter().take(3) {
+        y -= 10.0;
+        draw_text(content, Font::Regular, 8.0, MARGIN, y, &fit(Font::Regular, 8.0, line, inner));
+    }
+    y -= 8.0;
+    rule(content, MARGIN, y, inner);
+    y -= 14.0;
+
+    // Receiver, the part the sorter reads
+    draw_text(content, Font::Bold, 8.0, MARGIN, y, "TO");
+    y -= 15.0;
+    draw_text(content, Font::Bold, 13.0, MARGIN, y, &fit(Font::Bold, 13.0, &label.receiver_name, inner));
+    for line in label.receiver_address.iter().take(4) {
+        y -= 14.0;
+        draw_text(content, Font::Regular, 11.0, MARGIN, y, &fit(Font::Regular, 11.0, line, inner));
+    }
+    y -= 10.0;
+    rule(content, MARGIN, y, inner);
+
+    // Details on the left, QR to the public tracking page on the right
+    let qr_size = (inner * 0.38).min(y - MARGIN - 96.0);
+    let details_width = inner - qr_size - 8.0;
+    draw_qr(content, &label.tracking_url, width - MARGIN - qr_size, y - 6.0 - qr_size, qr_size);
+
+    let mut details = vec![
+        format!("Shipped {}", label.created_at.format("%Y-%m-%d")),
+        format!("Weight {:.2} kg", label.weight_kg),
+    ];
+    if let Some(dimensions) = &label.dimensions {
+        details.push(format!("Size {}", dimensions));
+    }
+    if let Some((start, end)) = label.delivery_window {
+        details.push(format!("Deliver {} - {}", start.format("%m-%d %H:%M"), end.format("%H:%M")));
+    }
+    details.extend(wrap(Font::Regular, 8.0, &label.description, details_width, 2));
+
+    let mut detail_y = y - 16.0;
+    for line in &details {
+        draw_text(content, Font::Regular, 8.0, MARGIN, detail_y, &fit(Font::Regular, 8.0, line, details_width));
+        detail_y -= 11.0;
+    }
+
+    // Barcode and the number it encodes along the bottom
+    let barcode_height = 52.0;
+    let barcode_y = MARGIN + 18.0;
+    draw_barcode(content, &label.tracking_number, MARGIN, barcode_y, inner, barcode_height);
+    let number_width = text_width(Font::Bold, 12.0, &label.tracking_number);
+    draw_text(content, Font::Bold, 12.0, (width - number_width) / 2.0, MARGIN + 4.0, &label.tracking_number);
+}
+
+/// Renders the driver's stops in route order, paginated, with a barcode per
+/// stop for scanning at the door.
+pub fn render_manifest(manifest: &ManifestData, format: LabelFormat) -> Vec<u8> {
+    const ENTRY_HEIGHT: f32 = 84.0;
+    const FOOTER: f32 = 14.0;
+
+    let mut document = Document::new(format);
+    let (width, height) = (document.width, document.height);
+    let inner = width - 2.0 * MARGIN;
+
+    let mut page = document.add_page();
+    let mut y = height - MARGIN - 12.0;
+
+    {
+        let content = &mut document.pages[page];
+        draw_text(content, Font::Bold, 13.0, MARGIN, y, "DRIVER MANIFEST");
+        let date = manifest.date.format("%Y-%m-%d").to_string();
+        draw_text(content, Font::Bold, 11.0, width - MARGIN - text_width(Font::Bold, 11.0, &date), y, &date);
+        y -= 14.0;
+        draw_text(content, Font::Regular, 9.0, MARGIN, y, &fit(Font::Regular, 9.0, &manifest.driver_name, inner));
+        if let Some(vehicle) = &manifest.vehicle {
+            y -= 11.0;
+            draw_text(content, Font::Regular, 8.0, MARGIN, y, &fit(Font::Regular, 8.0, &format!("Vehicle: {}", vehicle), inner));
+        }
+        y -= 11.0;
+        let total_weight: f64 = manifest
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == "DELIVERY")
+            .map(|entry| entry.weight_kg)
+            .sum();
+        let summary = format!(
+            "{} stops  {:.1} km  {:.0} min  {:.1} kg",
+            manifest.entries.len(),
+            manifest.total_distance_km,
+            manifest.total_duration_minutes,
+            total_weight
+        );
+        draw_text(content, Font::Regular, 8.0, MARGIN, y, &fit(Font::Regular, 8.0, &summary, inner));
+        y -= 8.0;
+        content.set_line_width(1.5);
+        rule(content, MARGIN, y, inner);
+        content.set_line_width(1.0);
+        y -= 4.0;
+    }
+
+    for entry in &manifest.entries {
+        if y - ENTRY_HEIGHT < MARGIN + FOOTER {
+            page = document.add_page();
+            y = height - MARGIN;
+        }
+        let content = &mut document.pages[page];
+        let top = y;
+
+        y -= 12.0;
+        let heading = format!("{}. {}", entry.sequence, entry.kind);
+        draw_text(content, Font::Bold, 10.0, MARGIN, y, &heading);
+        let badge = format!("{}  {:.1} kg", entry.priority.to_uppercase(), entry.weight_kg);
+        draw_text(content, Font::Bold, 8.0, width - MARGIN - text_width(Font::Bold, 8.0, &badge), y, &badge);
+
+        y -= 11.0;
+        draw_text(content, Font::Regular, 9.0, MARGIN, y, &fit(Font::Regular, 9.0, &entry.contact_name, inner));
+        y -= 10.0;
+        let address = entry.address.join(", ");
+        draw_text(content, Font::Regular, 8.0, MARGIN, y, &fit(Font::Regular, 8.0, &address, inner));
+
+        y -= 10.0;
+        let mut timing = Vec::new();
+        if let Some(arrival) = entry.arrival {
+            timing.push(format!("ETA {}", arrival.format("%H:%M")));
+        }
+        if let Some((start, end)) = entry.window {
+            timing.push(format!("Window {}-{}", start.format("%H:%M"), end.format("%H:%M")));
+        }
+        draw_text(content, Font::Regular, 8.0, MARGIN, y, &timing.join("   "));
+
+        let barcode_width = inner * 0.62;
+        draw_barcode(content, &entry.tracking_number, MARGIN, top - ENTRY_HEIGHT + 14.0, barcode_width, 22.0);
+        draw_text(content, Font::Regular, 7.0, MARGIN + 4.0, top - ENTRY_HEIGHT + 6.0, &entry.tracking_number);
+
+        // Initials box for the driver
+        let box_size = 22.0;
+        content
+            .rect(width - MARGIN - box_size, top - ENTRY_HEIGHT + 10.0, box_size, box_size)
+            .stroke();
+
+        y = top - ENTRY_HEIGHT;
+        rule(content, MARGIN, y, inner);
+    }
+
+    if !manifest.unrouted.is_empty() {
+        if y - 30.0 < MARGIN + FOOTER {
+            page = document.add_page();
+            y = height - MARGIN;
+        }
+        y -= 14.0;
+        draw_text(&mut document.pages[page], Font::Bold, 9.0, MARGIN, y, "NOT ROUTED");
+
+        for (tracking_number, reason) in &manifest.unrouted {
+            if y - 11.0 < MARGIN + FOOTER {
+                page = document.add_page();
+                y = height - MARGIN;
+            }
+            y -= 11.0;
+            let line = format!("{}  {}", tracking_number, reason);
+            draw_text(&mut document.pages[page], Font::Regular, 8.0, MARGIN, y, &fit(Font::Regular, 8.0, &line, inner));
+        }
+    }
+
+    let page_count = document.pages.len();
+    for (index, content) in document.pages.iter_mut().enumerate() {
+        let footer = format!("{} - page {} of {}", manifest.driver_name, index + 1, page_count);
+        draw_text(content, Font::Regular, 7.0, MARGIN, MARGIN, &fit(Font::Regular, 7.0, &footer, inner));
+    }
+
+    document.finish()
+}
+
+fn draw_text(content: &mut Content, font: Font, size: f32, x: f32, y: f32, text: &str) {
+    content.begin_text();
+    content.set_font(font.name(), size);
+    content.next_line(x, y);
+    content.show(Str(&win_ansi(text)));
+    content.end_text();
+}
+
+fn rule(content: &mut Content, x: f32, y: f32, width: f32) {
+    content.move_to(x, y).line_to(x + width, y).stroke();
+}
+
+/// The standard fonts use WinAnsi, which matches Latin-1 above 0xA0.
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            0x20..=0x7E | 0xA0..=0xFF => c as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+// Helvetica advance widths for ASCII 0x20..=0x7E, in 1/1000 em
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556, 556, 556, 556,
+    556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667,
+    556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556,
+    556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722,
+    500, 500, 500, 334, 260, 334, 584,
+];
+
+fn text_width(font: Font, size: f32, text: &str) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c as u32 {
+            code @ 0x20..=0x7E => HELVETICA_WIDTHS[(code - 0x20) as usize] as u32,
+            _ => 556,
+        })
+        .sum();
+    // Bold runs roughly 6% wider than the regular metrics
+    let scale = match font {
+        Font::Regular => 1.0,
+        Font::Bold => 1.06,
+    };
+    units as f32 * size * scale / 1000.0
+}
+
+/// Cuts `text` down to `max_width`, marking the cut with an ellipsis.
+fn fit(font: Font, size: f32, text: &str, max_width: f32) -> String {
+    if text_width(font, size, text) <= max_width {
+        return text.to_string();
+    }
+
+    let mut fitted: String = text.to_string();
+    while !fitted.is_empty() && text_width(font, size, &format!("{}...", fitted)) > max_width {
+        fitted.pop();
+    }
+    format!("{}...", fitted.trim_end())
+}
+
+fn wrap(font: Font, size: f32, text: &str, max_width: f32, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let candidate = if current.is_empty() { word.to_string() } else { format!("{} {}", current, word) };
+        if text_width(font, size, &candidate) <= max_width || current.is_empty() {
+            current = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut current, word.to_string()));
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    if lines.len() > max_lines {
+        let rest = lines.split_off(max_lines - 1).join(" ");
+        lines.push(rest);
+    }
+    lines.into_iter().map(|line| fit(font, size, &line, max_width)).collect()
+}
+
+fn draw_qr(content: &mut Content, data: &str, x: f32, y: f32, size: f32) {
+    let code = match QrCode::new(data.as_bytes()) {
+        Ok(code) => code,
+        Err(_) => return,
+    };
+    let modules = code.width();
+    let colors = code.to_colors();
+    let module = size / (modules + 2 * QR_QUIET_ZONE) as f32;
+
+    // One rectangle per run of dark modules keeps the stream small
+    for (row, line) in colors.chunks(modules).enumerate() {
+        let top = y + size - (row + QR_QUIET_ZONE + 1) as f32 * module;
+        let mut column = 0;
+        while column < modules {
+            if line[column] != Color::Dark {
+                column += 1;
+                continue;
+            }
+            let start = column;
+            while column < modules && line[column] == Color::Dark {
+                column += 1;
+            }
+            content.rect(
+                x + (start + QR_QUIET_ZONE) as f32 * module,
+                top,
+                (column - start) as f32 * module,
+                module,
+            );
+        }
+    }
+    content.fill_nonzero();
+}
+
+fn draw_barcode(content: &mut Content, data: &str, x: f32, y: f32, width: f32, height: f32) {
+    let Some(bars) = code128_bars(data) else { return };
+    let modules: usize = bars.iter().map(|(_, width)| *width as usize).sum::<usize>() + 2 * BARCODE_QUIET_ZONE;
+    let module = width / modules as f32;
+
+    let mut cursor = x + BARCODE_QUIET_ZONE as f32 * module;
+    for (dark, bar_width) in bars {
+        let bar = bar_width as f32 * module;
+        if dark {
+            content.rect(cursor, y, bar, height);
+        }
+        cursor += bar;
+    }
+    content.fill_nonzero();
+}
+
+// Code 128 bar/space widths for symbol values 0..=106; 106 is the stop
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+/// Encodes printable ASCII with code set B. Returns alternating bar and
+/// space widths in modules, starting with a bar.
+fn code128_bars(data: &str) -> Option<Vec<(bool, u8)>> {
+    let mut values = vec![CODE128_START_B];
+    for byte in data.bytes() {
+        if !(0x20..=0x7E).contains(&byte) {
+            return None;
+        }
+        values.push((byte - 0x20) as usize);
+    }
+
+    let checksum = values
+        .iter()
+        .enumerate()
+        .map(|(position, value)| position.max(1) * value)
+        .sum::<usize>()
+        % 103;
+    values.push(checksum);
+    values.push(CODE128_STOP);
+
+    Some(
+        values
+            .iter()
+            .flat_map(|value| CODE128_PATTERNS[*value].bytes().enumerate())
+            .map(|(index, width)| (index % 2 == 0, width - b'0'))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn widths(bars: &[(bool, u8)]) -> String {
+        bars.iter().map(|(_, width)| char::from(b'0' + width)).collect()
+    }
+
+    #[test]
+    fn code128_encodes_known_value() {
+        // Start B, "A" (33), checksum (104 + 33) % 103 = 34, stop
+        let bars = code128_bars("A").unwrap();
+        assert_eq!(widths(&bars), "2112141113231311232331112");
+        assert!(bars.iter().step_by(2).all(|(dark, _)| *dark));
+        assert!(bars.iter().skip(1).step_by(2).all(|(dark, _)| !*dark));
+    }
+
+    #[test]
+    fn code128_weights_checksum_by_position() {
+        // 104 + 55*1 + 73*2 + 75*3 + 73*4 + 80*5 + 69*6 + 68*7 + 73*8 + 65*9 = 3281,
+        // and 3281 % 103 = 88
+        let bars = code128_bars("Wikipedia").unwrap();
+        let symbols = widths(&bars);
+        let checksum = &symbols[symbols.len() - 13..symbols.len() - 7];
+        assert_eq!(checksum, "421211");
+        // 11 modules per symbol (start, 9 characters, checksum) plus 13 for the stop
+        assert_eq!(bars.iter().map(|(_, width)| *width as usize).sum::<usize>(), 11 * 11 + 13);
+    }
+
+    #[test]
+    fn code128_rejects_characters_outside_code_set_b() {
+        assert!(code128_bars("SHP\u{e9}").is_none());
+        assert!(code128_bars("SHP\n1").is_none());
+    }
+}
@@ -0,0 +1,297 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::Row;
+use std::net::{IpAddr, SocketAddr};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::models::*;
+
+// Failed-attempt tracking, backoff and temporary lockout for credential checks
+
+const ACCOUNT_MAX_FAILURES: i32 = 5;
+const IP_MAX_FAILURES: i32 = 20;
+const LOCKOUT_MINUTES: i64 = 15;
+const BACKOFF_MAX_SECONDS: i64 = 60;
+// Failures older than this no longer count towards a lockout
+const FAILURE_WINDOW_HOURS: i64 = 1;
+
+const SCOPE_ACCOUNT: &str = "account";
+const SCOPE_IP: &str = "ip";
+
+/// Best-effort client address. Forwarded headers are only honoured when the
+/// deployment says it sits behind a trusted proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<crate::AppState> for ClientIp {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &crate::AppState) -> Result<Self, Self::Rejection> {
+        if state.config.trust_proxy_headers {
+            let forwarded = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .and_then(|value| value.trim().parse::<IpAddr>().ok());
+            if forwarded.is_some() {
+                return Ok(ClientIp(forwarded));
+            }
+        }
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        Ok(ClientIp(peer))
+    }
+}
+
+/// Tracks one credential check against both the targeted account and the
+/// caller's address.
+pub struct LoginGuard {
+    account_key: String,
+    ip: Option<IpAddr>,
+}
+
+impl LoginGuard {
+    pub fn for_user(user_id: Uuid, ip: ClientIp) -> Self {
+        Self {
+            account_key: format!("user:{}", user_id),
+            ip: ip.0,
+        }
+    }
+
+    /// Used when the identifier doesn't resolve to a user, so probing
+    /// unknown emails is throttled the same way as real accounts.
+    pub fn for_identifier(identifier: &str, ip: ClientIp) -> Self {
+        Self {
+            account_key: format!("id:{}", identifier.trim().to_lowercase()),
+            ip: ip.0,
+        }
+    }
+
+    /// Rejects the attempt outright while the account or address is locked
+    /// (423 / 429) or still inside its backoff window (429).
+    pub async fn check(&self, state: &crate::AppState) -> Result<(), StatusCode> {
+        let now = Utc::now();
+
+        if let Some(row) = fetch_attempts(state, SCOPE_ACCOUNT, &self.account_key).await? {
+            if is_locked(&row, now) {
+                return Err(StatusCode::LOCKED);
+            }
+            if in_backoff(&row, now) {
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+        }
+
+        if let Some(ip) = self.ip {
+            if let Some(row) = fetch_attempts(state, SCOPE_IP, &ip.to_string()).await? {
+                if is_locked(&row, now) || in_backoff(&row, now) {
+                    return Err(StatusCode::TOO_MANY_REQUESTS);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn record_failure(
+        &self,
+        state: &crate::AppState,
+        user_id: Option<Uuid>,
+        event: &str,
+    ) -> Result<(), StatusCode> {
+        let failures = bump_failures(state, SCOPE_ACCOUNT, &self.account_key, ACCOUNT_MAX_FAILURES).await?;
+        record_audit(state, user_id, event, self.ip, serde_json::json!({ "failed_attempts": failures })).await?;
+
+        if failures >= ACCOUNT_MAX_FAILURES {
+            warn!("Locking {} after {} failed attempts", self.account_key, failures);
+            record_audit(state, user_id, "account_locked", self.ip, serde_json::json!({
+                "failed_attempts": failures,
+                "lockout_minutes": LOCKOUT_MINUTES
+            }))
+            .await?;
+        }
+
+        if let Some(ip) = self.ip {
+            let ip_failures = bump_failures(state, SCOPE_IP, &ip.to_string(), IP_MAX_FAILURES).await?;
+            if ip_failures == IP_MAX_FAILURES {
+                warn!("Blocking address {} after {} failed attempts", ip, ip_failures);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clears the account's counter. The address counter is left alone so a
+    /// single valid account can't be used to reset an attacker's budget.
+    pub async fn record_success(
+        &self,
+        state: &crate::AppState,
+        user_id: Option<Uuid>,
+        event: &str,
+    ) -> Result<(), StatusCode> {
+        sqlx::query("DELETE FROM login_attempts WHERE scope = $1 AND attempt_key = $2")
+            .bind(SCOPE_ACCOUNT)
+            .bind(&self.account_key)
+            .execute(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        record_audit(state, user_id, event, self.ip, serde_json::json!({})).await
+    }
+}
+
+pub async fn unlock_account(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    auth_user.require_role(&[UserRole::Admin])?;
+
+    let email = sqlx::query("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?
+        .get::<String, _>("email");
+
+    let cleared = sqlx::query("DELETE FROM login_attempts WHERE scope = $1 AND attempt_key = ANY($2)")
+        .bind(SCOPE_ACCOUNT)
+        .bind(vec![
+            format!("user:{}", user_id),
+            format!("id:{}", email.to_lowercase()),
+        ])
+        .execute(&state.db.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to unlock account {}: {}", user_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .rows_affected();
+
+    record_audit(&state, Some(user_id), "account_unlocked", ip, serde_json::json!({
+        "unlocked_by": auth_user.user_id
+    }))
+    .await?;
+
+    info!("Account {} unlocked by admin {}", user_id, auth_user.user_id);
+
+    Ok(Json(serde_json::json!({
+        "user_id": user_id,
+        "was_locked": cleared > 0,
+        "unlocked_at": Utc::now().to_rfc3339()
+    })))
+}
+
+pub(crate) async fn record_audit(
+    state: &crate::AppState,
+    user_id: Option<Uuid>,
+    event: &str,
+    ip: Option<IpAddr>,
+    details: serde_json::Value,
+) -> Result<(), StatusCode> {
+    sqlx::query(
+        "INSERT INTO auth_audit_log (id, user_id, event_type, ip_address, details, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(event)
+    .bind(ip.map(|ip| ip.to_string()))
+    .bind(details)
+    .bind(Utc::now())
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to write audit entry {}: {}", event, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(())
+}
+
+async fn fetch_attempts(
+    state: &crate::AppState,
+    scope: &str,
+    key: &str,
+) -> Result<Option<sqlx::postgres::PgRow>, StatusCode> {
+    sqlx::query("SELECT * FROM login_attempts WHERE scope = $1 AND attempt_key = $2")
+        .bind(scope)
+        .bind(key)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Increments the failure counter (restarting it if the last failure is
+/// outside the window) and locks the key once it reaches `max_failures`.
+async fn bump_failures(
+    state: &crate::AppState,
+    scope: &str,
+    key: &str,
+    max_failures: i32,
+) -> Result<i32, StatusCode> {
+    let now = Utc::now();
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO login_attempts (scope, attempt_key, failed_count, last_failed_at, locked_until, updated_at)
+        VALUES ($1, $2, 1, $3, NULL, $3)
+        ON CONFLICT (scope, attempt_key) DO UPDATE SET
+            failed_count = CASE
+                WHEN login_attempts.last_failed_at < $4 THEN 1
+                ELSE login_attempts.failed_count + 1
+            END,
+            last_failed_at = $3,
+            locked_until = CASE
+                WHEN login_attempts.last_failed_at >= $4 AND login_attempts.failed_count + 1 >= $5 THEN $6
+                ELSE login_attempts.locked_until
+            END,
+            updated_at = $3
+        RETURNING failed_count
+        "#,
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(now)
+    .bind(now - Duration::hours(FAILURE_WINDOW_HOURS))
+    .bind(max_failures)
+    .bind(now + Duration::minutes(LOCKOUT_MINUTES))
+    .fetch_one(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to record failed attempt: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(row.get::<i32, _>("failed_count"))
+}
+
+fn is_locked(row: &sqlx::postgres::PgRow, now: DateTime<Utc>) -> bool {
+    row.get::<Option<DateTime<Utc>>, _>("locked_until")
+        .is_some_and(|until| until > now)
+}
+
+/// Each consecutive failure doubles the wait before the next attempt.
+fn in_backoff(row: &sqlx::postgres::PgRow, now: DateTime<Utc>) -> bool {
+    let failures = row.get::<i32, _>("failed_count");
+    if failures <= 0 {
+        return false;
+    }
+
+    let delay = (1i64 << (failures - 1).min(6)).min(BACKOFF_MAX_SECONDS);
+    row.get::<DateTime<Utc>, _>("last_failed_at") + Duration::seconds(delay) > now
+}
@@ -17,10 +17,32 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod auth;
 mod web3;
+mod siwe;
+mod internet_identity;
+mod login_protection;
+mod sessions;
+mod kyc;
+mod realtime;
+mod public_tracking;
+mod geofencing;
+mod eta;
+mod routing;
+mod dispatch;
+mod proof_of_delivery;
+mod shipment_import;
+mod labels;
+mod quotes;
+mod returns;
+mod parcels;
+mod consolidation;
+mod delivery_slots;
+mod delivery_exceptions;
 mod tracking;
 mod ai;
 mod support;
 mod confirmation;
+mod verification;
+mod two_factor;
 mod database;
 mod models;
 mod services;
@@ -38,6 +60,7 @@ use crate::database::Database;
 pub struct AppState {
     pub db: Database,
     pub config: Config,
+    pub tracking_hub: realtime::TrackingHub,
 }
 
 #[tokio::main]
@@ -73,11 +96,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let support_service = support::SupportService::new(&db);
     let confirmation_service = confirmation::ConfirmationService::new(&db);
 
+    let tracking_hub = realtime::TrackingHub::connect(&config.redis_url).await;
+
     let app_state = AppState {
         db,
         config: config.clone(),
+        tracking_hub,
     };
 
+    tokio::spawn(dispatch::run_offer_expiry(app_state.clone()));
+    tokio::spawn(shipment_import::run_abandoned_import_sweep(app_state.clone()));
+    tokio::spawn(public_tracking::run_limiter_cleanup());
+
     // Build application routes
     let app = Router::new()
         // Health check
@@ -91,15 +121,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/api/auth/refresh", post(auth::refresh_token))
         .route("/api/auth/profile", get(auth::get_profile))
         .route("/api/auth/profile", put(auth::update_profile))
+        .route("/api/auth/forgot-password", post(auth::forgot_password))
+        .route("/api/auth/reset-password", post(auth::reset_password))
+        .route("/api/auth/change-password", post(auth::change_password))
+        .route("/api/auth/sessions", get(sessions::list_sessions))
+        .route("/api/auth/sessions/revoke-others", post(sessions::revoke_other_sessions))
+        .route("/api/auth/sessions/:id", delete(sessions::revoke_session))
+        .route("/api/auth/login/2fa", post(two_factor::verify_login))
+        .route("/api/auth/2fa/enroll", post(two_factor::enroll))
+        .route("/api/auth/2fa/confirm", post(two_factor::confirm_enrollment))
+        .route("/api/auth/2fa/recovery-codes", post(two_factor::regenerate_recovery_codes))
+        .route("/api/auth/2fa/disable", post(two_factor::disable))
+        .route("/api/auth/verify-email/request", post(verification::request_email_verification))
+        .route("/api/auth/verify-email", post(verification::confirm_email))
+        .route("/api/auth/verify-phone/request", post(verification::request_phone_verification))
+        .route("/api/auth/verify-phone", post(verification::confirm_phone))
         
         // Advanced authentication
-        .route("/api/auth/kyc", post(auth::kyc_verification))
+        .route("/api/auth/kyc", post(kyc::submit_case))
+        .route("/api/auth/kyc/status", get(kyc::get_status))
         .route("/api/auth/biometric", post(auth::biometric_auth))
         .route("/api/auth/world-id", post(auth::world_id_verify))
+        .route("/api/auth/internet-identity/challenge", post(auth::internet_identity_challenge))
         .route("/api/auth/internet-identity", post(auth::internet_identity_auth))
         
         // Web3 & Blockchain routes
+        .route("/api/web3/nonce", get(web3::get_nonce))
         .route("/api/web3/connect", post(web3::connect_wallet))
+        .route("/api/web3/login", post(web3::wallet_login))
         .route("/api/web3/balance", get(web3::get_balance))
         .route("/api/web3/send", post(web3::send_transaction))
         .route("/api/web3/nft/mint", post(web3::mint_nft))
@@ -110,13 +159,79 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/api/icp/canister/:id", get(web3::get_canister))
         .route("/api/icp/call", post(web3::call_canister))
         
+        // Pricing routes
+        .route("/api/quotes", post(quotes::create_quote))
+        .route("/api/quotes/:id", get(quotes::get_quote))
+        .route("/api/rate-cards", get(quotes::list_rate_cards))
+        .route("/api/rate-cards", post(quotes::create_rate_card))
+        
+        // Return routes
+        .route("/api/returns", get(returns::list_returns))
+        .route("/api/returns", post(returns::create_return))
+        .route("/api/returns/:id", get(returns::get_return))
+        .route("/api/returns/:id/photos/:photo_id", get(returns::get_return_photo))
+        .route("/api/returns/:id/approve", post(returns::approve_return))
+        .route("/api/returns/:id/reject", post(returns::reject_return))
+        .route("/api/returns/:id/refund", post(returns::retry_refund))
+        
+        // Parcel and consolidation routes
+        .route("/api/parcels/:tracking_number", get(parcels::get_parcel_by_barcode))
+        .route("/api/consolidations", post(consolidation::create_consolidation))
+        .route("/api/consolidations/:id", get(consolidation::get_consolidation))
+        
+        // Delivery slot routes
+        .route("/api/delivery-slots", get(delivery_slots::list_slots))
+        .route("/api/delivery-slots", post(delivery_slots::create_slots))
+        .route("/api/delivery-slots/:id", put(delivery_slots::update_slot))
+        
+        // Delivery exception routes
+        .route("/api/exceptions", get(delivery_exceptions::list_exceptions))
+        .route("/api/exceptions/:id/resolve", post(delivery_exceptions::resolve_exception))
+        .route("/api/exceptions/:id/evidence/:upload_id", get(delivery_exceptions::get_exception_evidence))
+        
         // Tracking routes
         .route("/api/tracking/create", post(tracking::create_shipment))
         .route("/api/tracking/:id", get(tracking::get_shipment))
         .route("/api/tracking/:id/update", put(tracking::update_location))
         .route("/api/tracking/:id/status", put(tracking::update_status))
+        .route("/api/tracking/:id/timeline", get(tracking::get_timeline))
+        .route("/api/tracking/:id/delivery-pin", post(proof_of_delivery::issue_delivery_pin))
+        .route("/api/tracking/:id/proof-of-delivery", get(proof_of_delivery::get_proof_of_delivery))
+        .route("/api/tracking/:id/proof-of-delivery/files/:file_id", get(proof_of_delivery::get_evidence_file))
+        .route("/api/tracking/:id/label", get(labels::get_label))
+        .route("/api/tracking/:id/parcels", get(parcels::list_parcels))
+        .route("/api/tracking/:id/parcels/:parcel_id/status", put(parcels::update_parcel_status))
+        .route("/api/tracking/:id/delivery", get(delivery_slots::get_delivery))
+        .route("/api/tracking/:id/delivery/slots", get(delivery_slots::list_shipment_slots))
+        .route("/api/tracking/:id/delivery/reschedule", post(delivery_slots::reschedule_delivery))
+        .route("/api/tracking/:id/delivery/redirect", post(delivery_slots::redirect_delivery))
+        .route("/api/tracking/:id/delivery/instructions", put(delivery_slots::update_delivery_instructions))
+        .route("/api/tracking/:id/exceptions", get(delivery_exceptions::list_shipment_exceptions))
+        .route("/api/tracking/:id/exceptions", post(delivery_exceptions::report_exception))
+        .route("/api/tracking/:id/ws", get(realtime::shipment_ws))
+        .route("/api/tracking/:id/events", get(realtime::shipment_sse))
         .route("/api/tracking/:id/nft", post(tracking::convert_to_nft))
         .route("/api/tracking/search", get(tracking::search_shipments))
+        .route("/api/tracking/imports", post(shipment_import::create_import))
+        .route("/api/tracking/imports/:id", get(shipment_import::get_import))
+        .route("/api/tracking/imports/:id/rows", get(shipment_import::get_import_rows))
+        .route("/api/tracking/imports/:id/errors", get(shipment_import::get_error_report))
+        .route("/api/public/track/:tracking_number", get(public_tracking::track))
+        .route("/api/hubs", get(geofencing::list_hubs))
+        .route("/api/hubs", post(geofencing::create_hub))
+        .route("/api/hubs/:id", delete(geofencing::deactivate_hub))
+        .route("/api/drivers/:id/route", get(routing::get_driver_route))
+        .route("/api/drivers/:id/manifest", get(labels::get_driver_manifest))
+        .route("/api/drivers/:id/vehicle", put(routing::update_vehicle))
+        .route("/api/drivers/availability", put(dispatch::update_availability))
+        .route("/api/drivers/offers", get(dispatch::list_my_offers))
+        .route("/api/dispatch/drivers", get(dispatch::list_drivers))
+        .route("/api/dispatch/offers/:id/accept", post(dispatch::accept_offer))
+        .route("/api/dispatch/offers/:id/decline", post(dispatch::decline_offer))
+        .route("/api/dispatch/shipments/:id/auto", post(dispatch::auto_dispatch))
+        .route("/api/dispatch/shipments/:id/assign", post(dispatch::assign_driver))
+        .route("/api/dispatch/shipments/:id/unassign", post(dispatch::unassign_driver))
+        .route("/api/dispatch/shipments/:id/reassign", post(dispatch::reassign_driver))
         
         // AI Suggestions routes
         .route("/api/ai/suggestions", get(ai::get_suggestions))
@@ -210,6 +325,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/api/dashboard/store", get(dashboard::store_dashboard))
         .route("/api/dashboard/driver", get(dashboard::driver_dashboard))
         .route("/api/dashboard/admin", get(dashboard::admin_dashboard))
+        .route("/api/admin/users/:id/unlock", post(login_protection::unlock_account))
+        .route("/api/admin/kyc/cases", get(kyc::list_cases))
+        .route("/api/admin/kyc/cases/:id", get(kyc::get_case))
+        .route("/api/admin/kyc/cases/:id/claim", post(kyc::claim_case))
+        .route("/api/admin/kyc/cases/:id/decision", post(kyc::decide_case))
         
         // File upload routes
         .route("/api/upload/document", post(upload::upload_document))
@@ -233,7 +353,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let listener = tokio::net::TcpListener::bind(&config.server_address).await?;
     info!("Server listening on {}", config.server_address);
     
-    axum::serve(listener, app).await?;
+    // Peer addresses feed the per-IP login throttling
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await?;
     
     Ok(())
 }
@@ -45,7 +45,7 @@ pub struct User {
     pub last_login: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "user_role", rename_all = "snake_case")]
 pub enum UserRole {
     Customer,
@@ -55,6 +55,29 @@ pub enum UserRole {
     Admin,
 }
 
+impl UserRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Customer => "customer",
+            UserRole::StoreOwner => "store_owner",
+            UserRole::Driver => "driver",
+            UserRole::ShippingCompany => "shipping_company",
+            UserRole::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "customer" => Some(UserRole::Customer),
+            "store_owner" | "storeowner" => Some(UserRole::StoreOwner),
+            "driver" => Some(UserRole::Driver),
+            "shipping_company" | "shippingcompany" => Some(UserRole::ShippingCompany),
+            "admin" => Some(UserRole::Admin),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "user_status", rename_all = "snake_case")]
 pub enum UserStatus {
@@ -89,7 +112,7 @@ pub struct Shipment {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "shipment_status", rename_all = "snake_case")]
 pub enum ShipmentStatus {
     Pending,
@@ -101,7 +124,59 @@ pub enum ShipmentStatus {
     Cancelled,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
+impl ShipmentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShipmentStatus::Pending => "pending",
+            ShipmentStatus::PickedUp => "picked_up",
+            ShipmentStatus::InTransit => "in_transit",
+            ShipmentStatus::OutForDelivery => "out_for_delivery",
+            ShipmentStatus::Delivered => "delivered",
+            ShipmentStatus::Returned => "returned",
+            ShipmentStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(ShipmentStatus::Pending),
+            "picked_up" => Some(ShipmentStatus::PickedUp),
+            "in_transit" => Some(ShipmentStatus::InTransit),
+            "out_for_delivery" => Some(ShipmentStatus::OutForDelivery),
+            "delivered" => Some(ShipmentStatus::Delivered),
+            "returned" => Some(ShipmentStatus::Returned),
+            "cancelled" => Some(ShipmentStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    /// Statuses a shipment may move to from this one. Delivered, returned
+    /// and cancelled shipments are final.
+    pub fn allowed_transitions(&self) -> &'static [ShipmentStatus] {
+        match self {
+            ShipmentStatus::Pending => &[ShipmentStatus::PickedUp, ShipmentStatus::Cancelled],
+            ShipmentStatus::PickedUp => &[ShipmentStatus::InTransit, ShipmentStatus::Returned],
+            ShipmentStatus::InTransit => &[ShipmentStatus::OutForDelivery, ShipmentStatus::Returned],
+            // A failed delivery attempt goes back into the network
+            ShipmentStatus::OutForDelivery => &[
+                ShipmentStatus::Delivered,
+                ShipmentStatus::InTransit,
+                ShipmentStatus::Returned,
+            ],
+            ShipmentStatus::Delivered | ShipmentStatus::Returned | ShipmentStatus::Cancelled => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: ShipmentStatus) -> bool {
+        self.allowed_transitions().contains(&next)
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.allowed_transitions().is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "shipment_priority", rename_all = "snake_case")]
 pub enum ShipmentPriority {
     Low,
@@ -110,6 +185,27 @@ pub enum ShipmentPriority {
     Urgent,
 }
 
+impl ShipmentPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShipmentPriority::Low => "low",
+            ShipmentPriority::Medium => "medium",
+            ShipmentPriority::High => "high",
+            ShipmentPriority::Urgent => "urgent",
+        }
+    }
+
+    pub fn parse(priority: &str) -> Option<Self> {
+        match priority {
+            "low" => Some(ShipmentPriority::Low),
+            "medium" => Some(ShipmentPriority::Medium),
+            "high" => Some(ShipmentPriority::High),
+            "urgent" => Some(ShipmentPriority::Urgent),
+            _ => None,
+        }
+    }
+}
+
 // Location Tracking
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct LocationUpdate {
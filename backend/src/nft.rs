@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct NFTService {
@@ -250,13 +251,14 @@ pub enum RewardType {
 
 pub async fn create_shipment_nft(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateShipmentNFTRequest>,
 ) -> Result<Json<ShipmentNFTResponse>, StatusCode> {
     info!("Creating shipment NFT for shipment: {}", payload.shipment_id);
 
     let nft_id = Uuid::new_v4();
     let shipment_id = Uuid::parse_str(&payload.shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Generate token ID
     let token_id = generate_token_id().await;
@@ -322,13 +324,14 @@ pub async fn create_shipment_nft(
 
 pub async fn create_document_nft(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateDocumentNFTRequest>,
 ) -> Result<Json<DocumentNFTResponse>, StatusCode> {
     info!("Creating document NFT: {:?}", payload.document_type);
 
     let nft_id = Uuid::new_v4();
     let document_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Generate token ID
     let token_id = generate_token_id().await;
@@ -397,13 +400,14 @@ pub async fn create_document_nft(
 
 pub async fn create_certificate_nft(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateCertificateNFTRequest>,
 ) -> Result<Json<CertificateNFTResponse>, StatusCode> {
     info!("Creating certificate NFT: {:?}", payload.certificate_type);
 
     let nft_id = Uuid::new_v4();
     let certificate_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Generate token ID
     let token_id = generate_token_id().await;
@@ -472,13 +476,14 @@ pub async fn create_certificate_nft(
 
 pub async fn create_reward_nft(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateRewardNFTRequest>,
 ) -> Result<Json<RewardNFTResponse>, StatusCode> {
     info!("Creating reward NFT: {:?}", payload.reward_type);
 
     let nft_id = Uuid::new_v4();
     let reward_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Generate token ID
     let token_id = generate_token_id().await;
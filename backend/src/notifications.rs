@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct NotificationsService {
@@ -64,11 +65,12 @@ pub struct NotificationSettingsResponse {
 
 pub async fn get_notifications(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<NotificationResponse>>, StatusCode> {
     info!("Fetching notifications");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str()).unwrap_or("default");
+    let user_id = auth_user.user_id;
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(50);
     let unread_only = params.get("unread_only").and_then(|v| v.as_bool()).unwrap_or(false);
 
@@ -86,7 +88,7 @@ pub async fn get_notifications(
     bind_values.push(Box::new(limit as i64));
 
     let rows = sqlx::query(&query)
-        .bind(Uuid::parse_str(user_id).map_err(|_| StatusCode::BAD_REQUEST)?)
+        .bind(user_id)
         .fetch_all(&state.db.pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -110,14 +112,16 @@ pub async fn get_notifications(
 
 pub async fn mark_read(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(notification_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     info!("Marking notification as read: {}", notification_id);
 
     let id = Uuid::parse_str(&notification_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    sqlx::query("UPDATE notifications SET is_read = true WHERE id = $1")
+    sqlx::query("UPDATE notifications SET is_read = true WHERE id = $1 AND user_id = $2")
         .bind(id)
+        .bind(auth_user.user_id)
         .execute(&state.db.pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -130,11 +134,11 @@ pub async fn mark_read(
 
 pub async fn get_settings(
     State(state): State<crate::AppState>,
-    Query(params): Query<serde_json::Value>,
+    auth_user: AuthUser,
 ) -> Result<Json<NotificationSettingsResponse>, StatusCode> {
     info!("Fetching notification settings");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str()).unwrap_or("default");
+    let user_id = auth_user.user_id;
 
     // Mock notification settings
     let response = NotificationSettingsResponse {
@@ -153,11 +157,12 @@ pub async fn get_settings(
 
 pub async fn update_settings(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<UpdateNotificationSettingsRequest>,
 ) -> Result<Json<NotificationSettingsResponse>, StatusCode> {
     info!("Updating notification settings");
 
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // In a real implementation, this would update the user's notification settings in the database
     // For now, we'll return the updated settings
@@ -175,3 +180,34 @@ pub async fn update_settings(
 
     Ok(Json(response))
 }
+
+// Helper functions
+
+pub(crate) async fn notify_user(
+    state: &crate::AppState,
+    user_id: Uuid,
+    title: &str,
+    message: &str,
+    notification_type: &str,
+    data: serde_json::Value,
+) -> Result<(), StatusCode> {
+    sqlx::query(
+        "INSERT INTO notifications (id, user_id, title, message, notification_type, data, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(title)
+    .bind(message)
+    .bind(notification_type)
+    .bind(data)
+    .bind(Utc::now())
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to create notification for {}: {}", user_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,428 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::models::*;
+use crate::realtime::ShipmentUpdate;
+use crate::tracking::{
+    can_access_shipment, ensure_assigned_driver, record_shipment_event, CreateShipmentRequest, ShipmentEventRecord,
+};
+use crate::utils::AppError;
+
+// Parcels: the boxes a shipment is made of. Each has its own barcode (the
+// shipment's tracking number plus a suffix) and status. Parcels follow the
+// shipment through the network; scans can flag single parcels missing or
+// damaged, and a delivery can hand over only some of them.
+
+const MAX_PARCELS: usize = 99;
+// Parcels still moving with their shipment
+const OPEN_STATUSES: &[&str] = &["pending", "picked_up", "in_transit", "out_for_delivery"];
+// Tolerance when checking parcel weights add up to the shipment weight
+const WEIGHT_EPSILON_KG: f64 = 0.001;
+
+#[derive(Debug, Deserialize)]
+pub struct ParcelRequest {
+    pub weight: f64,
+    #[serde(default)]
+    pub dimensions: serde_json::Value,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateParcelStatusRequest {
+    pub status: String,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParcelResponse {
+    pub id: String,
+    pub shipment_id: String,
+    pub sequence: i32,
+    pub tracking_number: String,
+    pub weight: f64,
+    pub dimensions: serde_json::Value,
+    pub description: Option<String>,
+    pub status: String,
+    pub delivered_at: Option<String>,
+    pub updated_at: String,
+}
+
+/// Parcels handed over by a delivery and those left behind.
+#[derive(Debug, Serialize)]
+pub struct DeliveredParcels {
+    pub delivered: Vec<String>,
+    pub undelivered: Vec<String>,
+}
+
+pub async fn list_parcels(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<Uuid>,
+) -> Result<Json<Vec<ParcelResponse>>, AppError> {
+    let shipment = sqlx::query("SELECT sender_id, receiver_id, driver_id FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    if !can_access_shipment(&auth_user, &shipment) {
+        return Err(AppError::Authorization("Not allowed to view this shipment".to_string()));
+    }
+
+    Ok(Json(load_parcels(&state.db.pool, shipment_id).await?))
+}
+
+/// Looks a parcel up by the barcode printed on it.
+pub async fn get_parcel_by_barcode(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(tracking_number): Path<String>,
+) -> Result<Json<ParcelResponse>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT p.*, p.weight::float8 AS weight_kg, s.sender_id, s.receiver_id, s.driver_id
+        FROM parcels p
+        JOIN shipments s ON s.id = p.shipment_id
+        WHERE p.tracking_number = $1
+        "#,
+    )
+    .bind(tracking_number.trim().to_uppercase())
+    .fetch_optional(&state.db.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Parcel not found".to_string()))?;
+
+    if !can_access_shipment(&auth_user, &row) {
+        return Err(AppError::Authorization("Not allowed to view this shipment".to_string()));
+    }
+
+    Ok(Json(parcel_response(&row)))
+}
+
+/// Records a scan or exception for a single parcel. Delivery normally
+/// happens through the shipment status; a parcel missing from a delivered
+/// shipment can be delivered here once it turns up.
+pub async fn update_parcel_status(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path((shipment_id, parcel_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<UpdateParcelStatusRequest>,
+) -> Result<Json<ParcelResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Driver, UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to change parcel status".to_string()))?;
+
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+    let now = Utc::now();
+    let mut tx = state.db.pool.begin().await?;
+    ensure_assigned_driver(&mut *tx, shipment_id, &auth_user).await?;
+
+    let parcel = sqlx::query(
+        r#"
+        SELECT p.tracking_number, p.status, s.status AS shipment_status
+        FROM parcels p
+        JOIN shipments s ON s.id = p.shipment_id
+        WHERE p.id = $1 AND p.shipment_id = $2
+        FOR UPDATE OF p
+        "#,
+    )
+    .bind(parcel_id)
+    .bind(shipment_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Parcel not found".to_string()))?;
+
+    let from = parcel.get::<String, _>("status");
+    let to = payload.status.as_str();
+    let shipment_delivered = parcel.get::<ShipmentStatus, _>("shipment_status") == ShipmentStatus::Delivered;
+
+    let allowed: &[&str] = match from.as_str() {
+        "pending" => &["picked_up", "missing", "damaged"],
+        "picked_up" => &["in_transit", "missing", "damaged"],
+        "in_transit" => &["out_for_delivery", "missing", "damaged"],
+        "out_for_delivery" => &["in_transit", "missing", "damaged"],
+        "missing" if shipment_delivered => &["delivered", "damaged"],
+        "missing" => &["in_transit", "damaged"],
+        _ => &[],
+    };
+    if !allowed.contains(&to) {
+        warn!("Rejected parcel {} transition {} -> {}", parcel_id, from, to);
+        return Err(AppError::Conflict(if allowed.is_empty() {
+            format!("Parcel is {} and can no longer change status", from)
+        } else {
+            format!("Cannot move parcel from {} to {}; allowed: {}", from, to, allowed.join(", "))
+        }));
+    }
+
+    let row = sqlx::query(
+        r#"
+        UPDATE parcels
+        SET status = $2,
+            delivered_at = CASE WHEN $2 = 'delivered' THEN $3 ELSE delivered_at END,
+            updated_at = $3
+        WHERE id = $1
+        RETURNING *, weight::float8 AS weight_kg
+        "#,
+    )
+    .bind(parcel_id)
+    .bind(to)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "parcel_status_changed",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes,
+        metadata: serde_json::json!({
+            "parcel_id": parcel_id,
+            "tracking_number": parcel.get::<String, _>("tracking_number"),
+            "from": from,
+            "to": to
+        }),
+    })
+    .await?;
+
+    tx.commit().await?;
+
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(shipment_id, "parcel", serde_json::json!({
+            "parcel_id": parcel_id,
+            "previous_status": from,
+            "status": to
+        })))
+        .await;
+
+    info!("Parcel {} of shipment {}: {} -> {}", parcel_id, shipment_id, from, to);
+
+    Ok(Json(parcel_response(&row)))
+}
+
+// Helper functions
+
+/// Problems with the parcel list of a create request, in the same form as
+/// the other shipment validation messages.
+pub(crate) fn validate_parcels(payload: &CreateShipmentRequest) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if payload.parcels.len() > MAX_PARCELS {
+        errors.push(format!("a shipment can have at most {} parcels", MAX_PARCELS));
+    }
+    for (index, parcel) in payload.parcels.iter().enumerate() {
+        if !parcel.weight.is_finite() || parcel.weight <= 0.0 {
+            errors.push(format!("parcels[{}].weight must be greater than zero", index));
+        }
+    }
+
+    let total: f64 = payload.parcels.iter().map(|parcel| parcel.weight).sum();
+    if !payload.parcels.is_empty() && total.is_finite() && (total - payload.weight).abs() > WEIGHT_EPSILON_KG {
+        errors.push(format!("weight must equal the sum of parcel weights ({:.3})", total));
+    }
+
+    errors
+}
+
+/// Creates the parcels of a new shipment; without a parcel list the
+/// shipment is a single parcel.
+pub(crate) async fn insert_parcels(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    tracking_number: &str,
+    payload: &CreateShipmentRequest,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let single = [ParcelRequest {
+        weight: payload.weight,
+        dimensions: payload.dimensions.clone(),
+        description: None,
+    }];
+    let parcels = if payload.parcels.is_empty() { &single[..] } else { &payload.parcels[..] };
+
+    for (index, parcel) in parcels.iter().enumerate() {
+        let sequence = index as i32 + 1;
+        let description = parcel.description.as_deref().map(str::trim).filter(|text| !text.is_empty());
+
+        sqlx::query(
+            r#"
+            INSERT INTO parcels (
+                id, shipment_id, sequence, tracking_number, weight, dimensions, description, created_at, updated_at
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(shipment_id)
+        .bind(sequence)
+        .bind(format!("{}-{:02}", tracking_number, sequence))
+        .bind(parcel.weight)
+        .bind(&parcel.dimensions)
+        .bind(description)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Moves a shipment's open parcels along with a shipment status change.
+/// Deliveries go through `record_delivery` instead, since they may cover
+/// only some of the parcels.
+pub(crate) async fn follow_shipment_status(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    to: ShipmentStatus,
+) -> Result<(), sqlx::Error> {
+    let (status, from): (&str, &[&str]) = match to {
+        ShipmentStatus::PickedUp => ("picked_up", &["pending"]),
+        ShipmentStatus::InTransit => ("in_transit", &["pending", "picked_up", "out_for_delivery"]),
+        ShipmentStatus::OutForDelivery => ("out_for_delivery", &["pending", "picked_up", "in_transit"]),
+        ShipmentStatus::Returned => ("returned", OPEN_STATUSES),
+        ShipmentStatus::Cancelled => ("cancelled", OPEN_STATUSES),
+        ShipmentStatus::Pending | ShipmentStatus::Delivered => return Ok(()),
+    };
+
+    sqlx::query("UPDATE parcels SET status = $2, updated_at = NOW() WHERE shipment_id = $1 AND status = ANY($3)")
+        .bind(shipment_id)
+        .bind(status)
+        .bind(from)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Flags open parcels of a shipment as damaged. Returns how many were.
+pub(crate) async fn mark_damaged(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    parcel_ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE parcels SET status = 'damaged', updated_at = NOW() WHERE shipment_id = $1 AND id = ANY($2) AND status = ANY($3)",
+    )
+    .bind(shipment_id)
+    .bind(parcel_ids)
+    .bind(OPEN_STATUSES)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Delivers the given parcels, or every open one when none are named. Open
+/// parcels that weren't handed over are marked missing and the delivery is
+/// logged as partial.
+pub(crate) async fn record_delivery(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    parcel_ids: Option<&[Uuid]>,
+    actor: &AuthUser,
+) -> Result<DeliveredParcels, AppError> {
+    let parcels = sqlx::query("SELECT id, tracking_number, status FROM parcels WHERE shipment_id = $1 ORDER BY sequence FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_all(&mut **tx)
+        .await?;
+
+    let is_open = |row: &sqlx::postgres::PgRow| OPEN_STATUSES.contains(&row.get::<String, _>("status").as_str());
+
+    if let Some(ids) = parcel_ids {
+        for id in ids {
+            if !parcels.iter().any(|row| row.get::<Uuid, _>("id") == *id && is_open(row)) {
+                return Err(AppError::Validation(format!("Parcel {} is not awaiting delivery on this shipment", id)));
+            }
+        }
+    }
+    let handed_over = |row: &sqlx::postgres::PgRow| {
+        is_open(row) && parcel_ids.is_none_or(|ids| ids.contains(&row.get::<Uuid, _>("id")))
+    };
+
+    let delivered: Vec<&sqlx::postgres::PgRow> = parcels.iter().filter(|row| handed_over(row)).collect();
+    if delivered.is_empty() {
+        return Err(AppError::Validation("No parcels are awaiting delivery on this shipment".to_string()));
+    }
+    let left_behind: Vec<Uuid> = parcels
+        .iter()
+        .filter(|row| is_open(row) && !handed_over(row))
+        .map(|row| row.get::<Uuid, _>("id"))
+        .collect();
+
+    let now = Utc::now();
+    let delivered_ids: Vec<Uuid> = delivered.iter().map(|row| row.get::<Uuid, _>("id")).collect();
+    sqlx::query("UPDATE parcels SET status = 'delivered', delivered_at = $2, updated_at = $2 WHERE id = ANY($1)")
+        .bind(&delivered_ids)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE parcels SET status = 'missing', updated_at = $2 WHERE id = ANY($1)")
+        .bind(&left_behind)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+
+    let tracking_number = |row: &&sqlx::postgres::PgRow| row.get::<String, _>("tracking_number");
+    let result = DeliveredParcels {
+        delivered: delivered.iter().map(tracking_number).collect(),
+        // Includes parcels already flagged missing or damaged before this delivery
+        undelivered: parcels
+            .iter()
+            .filter(|row| !delivered_ids.contains(&row.get::<Uuid, _>("id")))
+            .map(|row| row.get::<String, _>("tracking_number"))
+            .collect(),
+    };
+
+    if !result.undelivered.is_empty() {
+        record_shipment_event(tx, ShipmentEventRecord {
+            shipment_id,
+            event_type: "partially_delivered",
+            from_status: None,
+            to_status: None,
+            actor: Some(actor),
+            notes: None,
+            metadata: serde_json::json!({
+                "delivered": result.delivered,
+                "undelivered": result.undelivered
+            }),
+        })
+        .await?;
+    }
+
+    Ok(result)
+}
+
+pub(crate) async fn load_parcels<'e, E>(executor: E, shipment_id: Uuid) -> Result<Vec<ParcelResponse>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let rows = sqlx::query("SELECT *, weight::float8 AS weight_kg FROM parcels WHERE shipment_id = $1 ORDER BY sequence")
+        .bind(shipment_id)
+        .fetch_all(executor)
+        .await?;
+
+    Ok(rows.iter().map(parcel_response).collect())
+}
+
+fn parcel_response(row: &sqlx::postgres::PgRow) -> ParcelResponse {
+    ParcelResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        shipment_id: row.get::<Uuid, _>("shipment_id").to_string(),
+        sequence: row.get::<i32, _>("sequence"),
+        tracking_number: row.get::<String, _>("tracking_number"),
+        weight: row.get::<f64, _>("weight_kg"),
+        dimensions: row.get::<serde_json::Value, _>("dimensions"),
+        description: row.get::<Option<String>, _>("description"),
+        status: row.get::<String, _>("status"),
+        delivered_at: row.get::<Option<DateTime<Utc>>, _>("delivered_at").map(|at| at.to_rfc3339()),
+        updated_at: row.get::<DateTime<Utc>, _>("updated_at").to_rfc3339(),
+    }
+}
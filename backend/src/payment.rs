@@ -6,6 +6,7 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +15,8 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
+use crate::kyc::{fiat_value, require_kyc_for_amount};
 
 #[derive(Debug, Clone)]
 pub struct PaymentService {
@@ -83,6 +86,17 @@ pub struct InvoiceResponse {
     pub paid_at: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct RefundResponse {
+    pub id: String,
+    pub payment_id: String,
+    pub amount: f64,
+    pub currency: String,
+    pub status: String,
+    pub blockchain_tx_hash: Option<String>,
+    pub created_at: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct CryptoBalanceResponse {
     pub address: String,
@@ -94,6 +108,7 @@ pub struct CryptoBalanceResponse {
 
 pub async fn create_payment(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreatePaymentRequest>,
 ) -> Result<Json<PaymentResponse>, StatusCode> {
     info!("Creating payment: {} {}", payload.amount, payload.currency);
@@ -103,6 +118,10 @@ pub async fn create_payment(
         return Err(StatusCode::BAD_REQUEST);
     }
 
+    // The KYC threshold is a fiat amount
+    let fiat_amount = fiat_value(&payload.currency, payload.amount).await;
+    require_kyc_for_amount(&state, auth_user.user_id, fiat_amount).await?;
+
     // Parse payment method
     let payment_method = match payload.payment_method.as_str() {
         "bitcoin" => PaymentMethod::Bitcoin,
@@ -119,7 +138,7 @@ pub async fn create_payment(
     };
 
     let payment_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Parse shipment_id if provided
     let shipment_id = if let Some(sid) = payload.shipment_id {
@@ -432,6 +451,96 @@ async fn process_payment(
     })
 }
 
+/// Refunds every completed payment on a shipment in full. The payment
+/// processor is called outside any transaction, so callers must make sure
+/// only one refund runs per shipment at a time. A payment whose refund fails
+/// stays completed, with the failed attempt on record, so it can be refunded
+/// later.
+pub(crate) async fn refund_shipment_payments(
+    state: &crate::AppState,
+    shipment_id: Uuid,
+    return_request_id: Option<Uuid>,
+    reason: &str,
+) -> Result<Vec<RefundResponse>, sqlx::Error> {
+    let payments = sqlx::query(
+        "SELECT id, amount::float8 AS amount, currency FROM payments WHERE shipment_id = $1 AND status = 'completed'",
+    )
+    .bind(shipment_id)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    let mut refunds = Vec::with_capacity(payments.len());
+    for payment in &payments {
+        let payment_id = payment.get::<Uuid, _>("id");
+        let amount = payment.get::<f64, _>("amount");
+        let currency = payment.get::<String, _>("currency");
+        let refund_id = Uuid::new_v4();
+
+        let tx_hash = match process_refund(&payment_id, amount).await {
+            Ok(result) => Some(result.tx_hash),
+            Err(e) => {
+                warn!("Refund of payment {} failed: {}", payment_id, e);
+                None
+            }
+        };
+        let status = if tx_hash.is_some() { "completed" } else { "failed" };
+        let now = Utc::now();
+
+        let mut tx = state.db.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO payment_refunds (
+                id, payment_id, return_request_id, amount, currency, status, blockchain_tx_hash, reason, created_at
+            ) VALUES ($1, $2, $3, $4::numeric, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(refund_id)
+        .bind(payment_id)
+        .bind(return_request_id)
+        .bind(amount)
+        .bind(&currency)
+        .bind(status)
+        .bind(&tx_hash)
+        .bind(reason)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        if tx_hash.is_some() {
+            sqlx::query("UPDATE payments SET status = 'refunded' WHERE id = $1 AND status = 'completed'")
+                .bind(payment_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        refunds.push(RefundResponse {
+            id: refund_id.to_string(),
+            payment_id: payment_id.to_string(),
+            amount,
+            currency,
+            status: status.to_string(),
+            blockchain_tx_hash: tx_hash,
+            created_at: now.to_rfc3339(),
+        });
+    }
+
+    info!("Refunded {} payments for shipment {}", refunds.len(), shipment_id);
+
+    Ok(refunds)
+}
+
+async fn process_refund(payment_id: &Uuid, amount: f64) -> Result<PaymentResult> {
+    // In a real implementation, this would send the funds back to the payer
+    // For now, we'll simulate the refund transaction
+    info!("Refunding {:.2} for payment {}", amount, payment_id);
+    Ok(PaymentResult {
+        tx_hash: format!("0x{:x}", rand::random::<u64>()),
+    })
+}
+
 async fn confirm_blockchain_transaction(payment_row: &sqlx::postgres::PgRow) -> bool {
     // In a real implementation, this would verify the blockchain transaction
     // For now, we'll simulate confirmation
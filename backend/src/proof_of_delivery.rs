@@ -0,0 +1,658 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::geofencing::address_coordinates;
+use crate::models::*;
+use crate::notifications::notify_user;
+use crate::services::utils::send_sms;
+use crate::tracking::{can_access_shipment, record_shipment_event, ShipmentEventRecord};
+use crate::upload::{decode_image, load_upload, store_upload, StoredUpload};
+use crate::utils::{calculate_distance, generate_random_number, generate_random_string, hash_data, AppError};
+
+// Proof of delivery: signature and photo evidence, one-time receiver PINs and
+// the GPS stamp captured when a shipment is marked delivered
+
+const MAX_PHOTOS: usize = 5;
+const PIN_TTL_HOURS: i64 = 48;
+const MAX_PIN_ATTEMPTS: i32 = 5;
+// Minimum time between PINs for one shipment, so reissuing can't reset the
+// attempt limit at will
+const PIN_REISSUE_MINUTES: i64 = 15;
+// How long the receiver has to countersign the delivery confirmation
+const CONFIRMATION_TTL_DAYS: i64 = 14;
+
+#[derive(Debug, Deserialize)]
+pub struct ProofOfDeliveryRequest {
+    pub recipient_name: Option<String>,
+    /// Base64 JPEG/PNG, optionally as a data URL
+    pub signature_image: Option<String>,
+    #[serde(default)]
+    pub photos: Vec<String>,
+    pub pin: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy_meters: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EvidenceFileResponse {
+    pub id: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProofOfDeliveryResponse {
+    pub id: String,
+    pub shipment_id: String,
+    pub captured_by: String,
+    pub recipient_name: Option<String>,
+    pub signature: Option<EvidenceFileResponse>,
+    pub photos: Vec<EvidenceFileResponse>,
+    pub pin_verified: bool,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy_meters: Option<f64>,
+    pub distance_from_address_meters: Option<f64>,
+    pub outside_delivery_area: bool,
+    pub evidence_hash: String,
+    pub confirmation_id: Option<String>,
+    pub confirmation_status: Option<String>,
+    pub captured_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EvidenceFileContentResponse {
+    pub id: String,
+    pub content_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryPinResponse {
+    pub channels: Vec<String>,
+    pub expires_at: String,
+}
+
+/// Evidence that passed validation but has not been written yet. Built
+/// before the delivery transaction so PIN attempts survive a rollback.
+pub(crate) struct DeliveryEvidence {
+    shipment_id: Uuid,
+    tracking_number: String,
+    receiver_id: Uuid,
+    recipient_name: Option<String>,
+    signature: Option<(&'static str, Vec<u8>)>,
+    photos: Vec<(&'static str, Vec<u8>)>,
+    pin_verified: bool,
+    latitude: f64,
+    longitude: f64,
+    accuracy_meters: Option<f64>,
+    distance_from_address_meters: Option<f64>,
+    outside_delivery_area: bool,
+}
+
+/// Sends the receiver a one-time PIN they hand to the driver at the door.
+/// Only the sender, the receiver and staff may ask for one; reissuing
+/// replaces the previous PIN and is rate limited per shipment.
+pub async fn issue_delivery_pin(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<String>,
+) -> Result<Json<DeliveryPinResponse>, AppError> {
+    let id = Uuid::parse_str(&shipment_id)
+        .map_err(|_| AppError::InvalidInput("Invalid shipment id".to_string()))?;
+
+    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    // Drivers collect the PIN at the door and must not be able to mint one
+    let may_issue = matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin)
+        || shipment_row.get::<Uuid, _>("sender_id") == auth_user.user_id
+        || shipment_row.get::<Uuid, _>("receiver_id") == auth_user.user_id;
+    if !may_issue {
+        return Err(AppError::Authorization("Only the sender or receiver can request a delivery PIN".to_string()));
+    }
+
+    let status = shipment_row.get::<ShipmentStatus, _>("status");
+    if status.is_terminal() {
+        return Err(AppError::Conflict(format!("Shipment is {}", status.as_str())));
+    }
+
+    let pin = format!("{:06}", generate_random_number(0, 999_999));
+    let salt = generate_random_string(32);
+    let now = Utc::now();
+    let expires_at = now + Duration::hours(PIN_TTL_HOURS);
+
+    // An expired PIN may be replaced right away, a live one only after a while
+    let issued = sqlx::query(
+        r#"
+        INSERT INTO delivery_pins (shipment_id, pin_hash, pin_salt, attempts, issued_by, expires_at, used_at, created_at)
+        VALUES ($1, $2, $3, 0, $4, $5, NULL, $6)
+        ON CONFLICT (shipment_id) DO UPDATE SET
+            pin_hash = EXCLUDED.pin_hash,
+            pin_salt = EXCLUDED.pin_salt,
+            attempts = 0,
+            issued_by = EXCLUDED.issued_by,
+            expires_at = EXCLUDED.expires_at,
+            used_at = NULL,
+            created_at = EXCLUDED.created_at
+        WHERE delivery_pins.created_at < $7 OR delivery_pins.expires_at <= $6
+        "#,
+    )
+    .bind(id)
+    .bind(hash_pin(id, &salt, &pin))
+    .bind(&salt)
+    .bind(auth_user.user_id)
+    .bind(expires_at)
+    .bind(now)
+    .bind(now - Duration::minutes(PIN_REISSUE_MINUTES))
+    .execute(&state.db.pool)
+    .await?
+    .rows_affected();
+
+    if issued == 0 {
+        return Err(AppError::RateLimitExceeded);
+    }
+
+    let receiver_id = shipment_row.get::<Uuid, _>("receiver_id");
+    let tracking_number = shipment_row.get::<String, _>("tracking_number");
+    let message = format!(
+        "Your delivery PIN for shipment {} is {}. Only share it with the driver when you receive the parcel.",
+        tracking_number, pin
+    );
+
+    let mut channels = Vec::new();
+    match notify_user(
+        &state,
+        receiver_id,
+        "Delivery PIN",
+        &message,
+        "delivery_pin",
+        serde_json::json!({ "shipment_id": id, "expires_at": expires_at.to_rfc3339() }),
+    )
+    .await
+    {
+        Ok(()) => channels.push("in_app".to_string()),
+        Err(_) => warn!("Could not notify receiver of delivery PIN for shipment {}", id),
+    }
+
+    let phone = sqlx::query("SELECT phone FROM users WHERE id = $1")
+        .bind(receiver_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .and_then(|row| row.get::<Option<String>, _>("phone"));
+    if let Some(phone) = phone {
+        match send_sms(&phone, &message).await {
+            Ok(()) => channels.push("sms".to_string()),
+            Err(e) => warn!("Failed to send delivery PIN SMS for shipment {}: {}", id, e),
+        }
+    }
+
+    if channels.is_empty() {
+        return Err(AppError::ExternalService("Could not deliver the PIN to the receiver".to_string()));
+    }
+
+    info!("Delivery PIN issued for shipment {} by {}", id, auth_user.user_id);
+
+    Ok(Json(DeliveryPinResponse {
+        channels,
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
+pub async fn get_proof_of_delivery(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<String>,
+) -> Result<Json<ProofOfDeliveryResponse>, StatusCode> {
+    let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    authorize(&state, &auth_user, id).await?;
+
+    let proof = load_proof(&state, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(proof))
+}
+
+/// Returns one signature or photo attached to the shipment's proof of delivery.
+pub async fn get_evidence_file(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path((shipment_id, upload_id)): Path<(String, Uuid)>,
+) -> Result<Json<EvidenceFileContentResponse>, StatusCode> {
+    let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    authorize(&state, &auth_user, id).await?;
+
+    // Only files referenced by this shipment's record are reachable here
+    sqlx::query(
+        "SELECT 1 FROM proof_of_delivery WHERE shipment_id = $1 AND (signature_upload_id = $2 OR $2 = ANY(photo_upload_ids))"
+    )
+    .bind(id)
+    .bind(upload_id)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let (content_type, bytes) = load_upload(&state, upload_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to load evidence file {}: {}", upload_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(EvidenceFileContentResponse {
+        id: upload_id.to_string(),
+        content_type,
+        data: BASE64.encode(bytes),
+    }))
+}
+
+// Helper functions
+
+/// Validates a proof-of-delivery submission and checks the delivery PIN, if
+/// one is outstanding. Wrong PINs are counted here, outside the delivery
+/// transaction, so a failed attempt can't be rolled back.
+pub(crate) async fn prepare(
+    state: &crate::AppState,
+    shipment_id: Uuid,
+    request: &ProofOfDeliveryRequest,
+) -> Result<DeliveryEvidence, AppError> {
+    if !(-90.0..=90.0).contains(&request.latitude) || !(-180.0..=180.0).contains(&request.longitude) {
+        return Err(AppError::InvalidInput("Invalid delivery coordinates".to_string()));
+    }
+    if request.accuracy_meters.is_some_and(|accuracy| accuracy < 0.0) {
+        return Err(AppError::InvalidInput("Accuracy must not be negative".to_string()));
+    }
+    if request.photos.len() > MAX_PHOTOS {
+        return Err(AppError::Validation(format!("At most {} photos can be attached", MAX_PHOTOS)));
+    }
+
+    let signature = request.signature_image.as_deref().map(decode_image).transpose()?;
+    let photos = request
+        .photos
+        .iter()
+        .map(|photo| decode_image(photo))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if signature.is_none() && photos.is_empty() {
+        return Err(AppError::Validation(
+            "A recipient signature or at least one photo is required".to_string(),
+        ));
+    }
+
+    let shipment_row = sqlx::query("SELECT tracking_number, receiver_id, delivery_address FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    let pin_verified = verify_pin(state, shipment_id, request.pin.as_deref()).await?;
+
+    let distance_from_address_meters = address_coordinates(&shipment_row.get::<serde_json::Value, _>("delivery_address"))
+        .map(|(latitude, longitude)| {
+            calculate_distance(request.latitude, request.longitude, latitude, longitude) * 1000.0
+        });
+    let outside_delivery_area =
+        distance_from_address_meters.is_some_and(|distance| distance > state.config.pod_max_distance_meters);
+
+    if outside_delivery_area {
+        warn!(
+            "Shipment {} delivered {:.0} m from its address",
+            shipment_id,
+            distance_from_address_meters.unwrap_or_default()
+        );
+    }
+
+    Ok(DeliveryEvidence {
+        shipment_id,
+        tracking_number: shipment_row.get::<String, _>("tracking_number"),
+        receiver_id: shipment_row.get::<Uuid, _>("receiver_id"),
+        recipient_name: request
+            .recipient_name
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+        signature,
+        photos,
+        pin_verified,
+        latitude: request.latitude,
+        longitude: request.longitude,
+        accuracy_meters: request.accuracy_meters,
+        distance_from_address_meters,
+        outside_delivery_area,
+    })
+}
+
+/// Writes the evidence inside the delivery transaction: the files go to
+/// `uploads`, the bundle hash is anchored on the shipment and on a delivery
+/// confirmation the driver has signed and the receiver still has to.
+pub(crate) async fn record(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    state: &crate::AppState,
+    actor: &AuthUser,
+    evidence: DeliveryEvidence,
+) -> Result<ProofOfDeliveryResponse, AppError> {
+    use sha2::{Digest, Sha256};
+
+    let key = &state.config.encryption_key;
+    let now = Utc::now();
+
+    let signature = match &evidence.signature {
+        Some((content_type, bytes)) => {
+            Some(store_upload(tx, key, actor.user_id, "pod_signature", content_type, bytes).await?)
+        }
+        None => None,
+    };
+    let mut photos = Vec::with_capacity(evidence.photos.len());
+    for (content_type, bytes) in &evidence.photos {
+        photos.push(store_upload(tx, key, actor.user_id, "pod_photo", content_type, bytes).await?);
+    }
+
+    // Keys serialize in sorted order, so the hash is reproducible from the
+    // stored `evidence` column
+    let bundle = serde_json::json!({
+        "shipment_id": evidence.shipment_id,
+        "captured_by": actor.user_id,
+        "captured_at": now.to_rfc3339(),
+        "recipient_name": evidence.recipient_name,
+        "signature_sha256": signature.as_ref().map(|upload| upload.sha256.clone()),
+        "photo_sha256": photos.iter().map(|upload| upload.sha256.clone()).collect::<Vec<_>>(),
+        "pin_verified": evidence.pin_verified,
+        "latitude": evidence.latitude,
+        "longitude": evidence.longitude,
+        "accuracy_meters": evidence.accuracy_meters,
+    });
+    let evidence_hash = format!("{:x}", Sha256::digest(bundle.to_string().as_bytes()));
+    let anchor = format!("0x{}", evidence_hash);
+
+    let confirmation_id = Uuid::new_v4();
+    let participants = serde_json::json!([
+        {
+            "id": actor.user_id.to_string(),
+            "role": "driver",
+            "status": "confirmed",
+            "confirmed_at": now.to_rfc3339(),
+            "verification_data": { "evidence_hash": evidence_hash }
+        },
+        {
+            "id": evidence.receiver_id.to_string(),
+            "role": "receiver",
+            "status": "pending"
+        }
+    ]);
+    let verification_methods = serde_json::json!({
+        "signature": signature.is_some(),
+        "photo": !photos.is_empty(),
+        "pin": evidence.pin_verified,
+        "gps": true
+    });
+    let location = serde_json::json!({
+        "latitude": evidence.latitude,
+        "longitude": evidence.longitude,
+        "accuracy_meters": evidence.accuracy_meters
+    });
+
+    sqlx::query(
+        r#"
+        INSERT INTO confirmations (
+            id, confirmation_type, title, description, status, priority,
+            shipment_id, participants, verification_methods, location,
+            blockchain_tx_hash, expires_at, created_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        "#,
+    )
+    .bind(confirmation_id)
+    .bind(&ConfirmationType::DeliveryConfirmation)
+    .bind(format!("Delivery of {}", evidence.tracking_number))
+    .bind("Driver has recorded proof of delivery; awaiting receiver confirmation")
+    .bind(&ConfirmationStatus::Pending)
+    .bind(&ConfirmationPriority::Medium)
+    .bind(evidence.shipment_id)
+    .bind(&participants)
+    .bind(&verification_methods)
+    .bind(&location)
+    .bind(&anchor)
+    .bind(now + Duration::days(CONFIRMATION_TTL_DAYS))
+    .bind(now)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query("UPDATE shipments SET blockchain_tx_hash = COALESCE(blockchain_tx_hash, $1) WHERE id = $2")
+        .bind(&anchor)
+        .bind(evidence.shipment_id)
+        .execute(&mut **tx)
+        .await?;
+
+    if evidence.pin_verified {
+        sqlx::query("UPDATE delivery_pins SET used_at = $1 WHERE shipment_id = $2 AND used_at IS NULL")
+            .bind(now)
+            .bind(evidence.shipment_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    let proof_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO proof_of_delivery (
+            id, shipment_id, captured_by, recipient_name, signature_upload_id, photo_upload_ids,
+            pin_verified, latitude, longitude, accuracy_meters, distance_from_address_meters,
+            outside_delivery_area, evidence, evidence_hash, confirmation_id, captured_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        "#,
+    )
+    .bind(proof_id)
+    .bind(evidence.shipment_id)
+    .bind(actor.user_id)
+    .bind(&evidence.recipient_name)
+    .bind(signature.as_ref().map(|upload| upload.id))
+    .bind(photos.iter().map(|upload| upload.id).collect::<Vec<_>>())
+    .bind(evidence.pin_verified)
+    .bind(evidence.latitude)
+    .bind(evidence.longitude)
+    .bind(evidence.accuracy_meters)
+    .bind(evidence.distance_from_address_meters)
+    .bind(evidence.outside_delivery_area)
+    .bind(&bundle)
+    .bind(&evidence_hash)
+    .bind(confirmation_id)
+    .bind(now)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => {
+            AppError::Conflict("Proof of delivery already recorded".to_string())
+        }
+        _ => AppError::Database(e),
+    })?;
+
+    record_shipment_event(tx, ShipmentEventRecord {
+        shipment_id: evidence.shipment_id,
+        event_type: "proof_of_delivery_captured",
+        from_status: None,
+        to_status: None,
+        actor: Some(actor),
+        notes: None,
+        metadata: serde_json::json!({
+            "proof_of_delivery_id": proof_id,
+            "evidence_hash": evidence_hash,
+            "pin_verified": evidence.pin_verified,
+            "distance_from_address_meters": evidence.distance_from_address_meters,
+            "outside_delivery_area": evidence.outside_delivery_area
+        }),
+    })
+    .await?;
+
+    Ok(ProofOfDeliveryResponse {
+        id: proof_id.to_string(),
+        shipment_id: evidence.shipment_id.to_string(),
+        captured_by: actor.user_id.to_string(),
+        recipient_name: evidence.recipient_name,
+        signature: signature.as_ref().map(file_response),
+        photos: photos.iter().map(file_response).collect(),
+        pin_verified: evidence.pin_verified,
+        latitude: evidence.latitude,
+        longitude: evidence.longitude,
+        accuracy_meters: evidence.accuracy_meters,
+        distance_from_address_meters: evidence.distance_from_address_meters,
+        outside_delivery_area: evidence.outside_delivery_area,
+        evidence_hash,
+        confirmation_id: Some(confirmation_id.to_string()),
+        confirmation_status: Some("pending".to_string()),
+        captured_at: now.to_rfc3339(),
+    })
+}
+
+pub(crate) async fn load_proof(
+    state: &crate::AppState,
+    shipment_id: Uuid,
+) -> Result<Option<ProofOfDeliveryResponse>, sqlx::Error> {
+    let Some(row) = sqlx::query(
+        r#"
+        SELECT p.*, c.status AS confirmation_status
+        FROM proof_of_delivery p
+        LEFT JOIN confirmations c ON c.id = p.confirmation_id
+        WHERE p.shipment_id = $1
+        "#,
+    )
+    .bind(shipment_id)
+    .fetch_optional(&state.db.pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let file_rows = sqlx::query("SELECT id, content_type, size_bytes, sha256 FROM uploads WHERE id = ANY($1)")
+        .bind(
+            row.get::<Option<Uuid>, _>("signature_upload_id")
+                .into_iter()
+                .chain(row.get::<Vec<Uuid>, _>("photo_upload_ids"))
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(&state.db.pool)
+        .await?;
+    let file = |id: Uuid| {
+        file_rows
+            .iter()
+            .find(|file| file.get::<Uuid, _>("id") == id)
+            .map(|file| EvidenceFileResponse {
+                id: id.to_string(),
+                content_type: file.get::<String, _>("content_type"),
+                size_bytes: file.get::<i64, _>("size_bytes"),
+                sha256: file.get::<String, _>("sha256"),
+            })
+    };
+
+    Ok(Some(ProofOfDeliveryResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        shipment_id: shipment_id.to_string(),
+        captured_by: row.get::<Uuid, _>("captured_by").to_string(),
+        recipient_name: row.get::<Option<String>, _>("recipient_name"),
+        signature: row.get::<Option<Uuid>, _>("signature_upload_id").and_then(file),
+        photos: row
+            .get::<Vec<Uuid>, _>("photo_upload_ids")
+            .into_iter()
+            .filter_map(file)
+            .collect(),
+        pin_verified: row.get::<bool, _>("pin_verified"),
+        latitude: row.get::<f64, _>("latitude"),
+        longitude: row.get::<f64, _>("longitude"),
+        accuracy_meters: row.get::<Option<f64>, _>("accuracy_meters"),
+        distance_from_address_meters: row.get::<Option<f64>, _>("distance_from_address_meters"),
+        outside_delivery_area: row.get::<bool, _>("outside_delivery_area"),
+        evidence_hash: row.get::<String, _>("evidence_hash"),
+        confirmation_id: row.get::<Option<Uuid>, _>("confirmation_id").map(|id| id.to_string()),
+        confirmation_status: row
+            .get::<Option<ConfirmationStatus>, _>("confirmation_status")
+            .map(|status| format!("{:?}", status).to_lowercase()),
+        captured_at: row.get::<DateTime<Utc>, _>("captured_at").to_rfc3339(),
+    }))
+}
+
+/// Returns whether a PIN was checked. Shipments without an outstanding PIN
+/// need none; otherwise the PIN has to match before it expires.
+async fn verify_pin(state: &crate::AppState, shipment_id: Uuid, pin: Option<&str>) -> Result<bool, AppError> {
+    let Some(stored) = sqlx::query("SELECT pin_hash, pin_salt, expires_at FROM delivery_pins WHERE shipment_id = $1 AND used_at IS NULL")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+    else {
+        return Ok(false);
+    };
+
+    let pin = pin
+        .map(str::trim)
+        .filter(|pin| !pin.is_empty())
+        .ok_or_else(|| AppError::Validation("The receiver's delivery PIN is required".to_string()))?;
+
+    if stored.get::<DateTime<Utc>, _>("expires_at") <= Utc::now() {
+        return Err(AppError::Validation("Delivery PIN has expired; issue a new one".to_string()));
+    }
+
+    // Spend an attempt before comparing, so concurrent guesses can't exceed the limit
+    let attempts = sqlx::query(
+        "UPDATE delivery_pins SET attempts = attempts + 1 WHERE shipment_id = $1 AND attempts < $2 RETURNING attempts",
+    )
+    .bind(shipment_id)
+    .bind(MAX_PIN_ATTEMPTS)
+    .fetch_optional(&state.db.pool)
+    .await?
+    .ok_or(AppError::RateLimitExceeded)?
+    .get::<i32, _>("attempts");
+
+    if stored.get::<String, _>("pin_hash") != hash_pin(shipment_id, &stored.get::<String, _>("pin_salt"), pin) {
+        warn!("Wrong delivery PIN for shipment {} (attempt {})", shipment_id, attempts);
+        return Err(AppError::Validation("Delivery PIN does not match".to_string()));
+    }
+
+    Ok(true)
+}
+
+async fn authorize(state: &crate::AppState, auth_user: &AuthUser, shipment_id: Uuid) -> Result<(), StatusCode> {
+    let shipment_row = sqlx::query("SELECT sender_id, receiver_id, driver_id FROM shipments WHERE id = $1")
+        .bind(shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if !can_access_shipment(auth_user, &shipment_row) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(())
+}
+
+fn hash_pin(shipment_id: Uuid, salt: &str, pin: &str) -> String {
+    hash_data(&format!("delivery_pin:{}:{}:{}", shipment_id, salt, pin))
+}
+
+fn file_response(upload: &StoredUpload) -> EvidenceFileResponse {
+    EvidenceFileResponse {
+        id: upload.id.to_string(),
+        content_type: upload.content_type.to_string(),
+        size_bytes: upload.size_bytes,
+        sha256: upload.sha256.clone(),
+    }
+}
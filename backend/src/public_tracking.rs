@@ -0,0 +1,198 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::net::{IpAddr, Ipv4Addr};
+use std::num::NonZeroU32;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::eta::load_eta;
+use crate::geofencing::address_city;
+use crate::login_protection::{ClientIp, LoginGuard};
+use crate::models::*;
+
+// Unauthenticated tracking lookups with a redacted shipment view
+
+const REQUESTS_PER_MINUTE: u32 = 30;
+const PHONE_SUFFIX_LENGTH: usize = 4;
+const LIMITER_SWEEP_SECONDS: u64 = 60;
+// Requests whose client address is unknown share a single bucket
+const UNKNOWN_CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+static LIMITER: OnceLock<DefaultKeyedRateLimiter<IpAddr>> = OnceLock::new();
+
+#[derive(Debug, Deserialize)]
+pub struct PublicTrackQuery {
+    /// Last digits of the recipient's phone number; unlocks extra detail.
+    pub phone_suffix: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicTimelineEntry {
+    pub status: Option<String>,
+    pub event_type: String,
+    pub notes: Option<String>,
+    pub occurred_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicLocation {
+    pub city: String,
+    pub country: String,
+    pub address: Option<String>,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicTrackingResponse {
+    pub tracking_number: String,
+    pub status: String,
+    pub origin_city: Option<String>,
+    pub destination_city: Option<String>,
+    pub current_location: Option<PublicLocation>,
+    pub estimated_delivery: Option<String>,
+    pub delivered_at: Option<String>,
+    pub timeline: Vec<PublicTimelineEntry>,
+    pub recipient_verified: bool,
+    pub delivery_address: Option<serde_json::Value>,
+}
+
+pub async fn track(
+    State(state): State<crate::AppState>,
+    client_ip: ClientIp,
+    Path(tracking_number): Path<String>,
+    Query(query): Query<PublicTrackQuery>,
+) -> Result<Json<PublicTrackingResponse>, StatusCode> {
+    if limiter().check_key(&client_ip.0.unwrap_or(UNKNOWN_CLIENT)).is_err() {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let tracking_number = tracking_number.trim().to_uppercase();
+    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE tracking_number = $1")
+        .bind(&tracking_number)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let shipment_id = shipment_row.get::<Uuid, _>("id");
+
+    // Suffix guesses are throttled per shipment like failed logins, so the
+    // short suffix can't be brute-forced from many addresses
+    let recipient_verified = match query.phone_suffix.as_deref().map(str::trim) {
+        Some(suffix) if !suffix.is_empty() => {
+            let guard = LoginGuard::for_identifier(&format!("track:{}", tracking_number), client_ip);
+            guard.check(&state).await?;
+
+            if phone_suffix_matches(&state, shipment_row.get::<Uuid, _>("receiver_id"), suffix).await? {
+                true
+            } else {
+                guard.record_failure(&state, None, "tracking_suffix_failed").await?;
+                warn!("Phone suffix mismatch for tracking number {}", tracking_number);
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+        _ => false,
+    };
+
+    let event_rows = sqlx::query(
+        "SELECT event_type, to_status, notes, created_at FROM shipment_events WHERE shipment_id = $1 ORDER BY created_at ASC, sequence ASC",
+    )
+    .bind(shipment_id)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let timeline = event_rows
+        .iter()
+        .map(|row| PublicTimelineEntry {
+            status: row.get::<Option<ShipmentStatus>, _>("to_status").map(|s| s.as_str().to_string()),
+            event_type: row.get::<String, _>("event_type"),
+            notes: if recipient_verified { row.get::<Option<String>, _>("notes") } else { None },
+            occurred_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+        })
+        .collect();
+
+    let current_location = sqlx::query(
+        "SELECT address, city, country, timestamp FROM location_updates WHERE shipment_id = $1 ORDER BY timestamp DESC LIMIT 1",
+    )
+    .bind(shipment_id)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(|row| PublicLocation {
+        city: row.get::<String, _>("city"),
+        country: row.get::<String, _>("country"),
+        address: if recipient_verified { Some(row.get::<String, _>("address")) } else { None },
+        updated_at: row.get::<DateTime<Utc>, _>("timestamp").to_rfc3339(),
+    });
+
+    let delivery_address = shipment_row.get::<serde_json::Value, _>("delivery_address");
+    let eta = load_eta(&state, shipment_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    info!("Public tracking lookup for {}", tracking_number);
+
+    Ok(Json(PublicTrackingResponse {
+        tracking_number,
+        status: shipment_row.get::<ShipmentStatus, _>("status").as_str().to_string(),
+        origin_city: address_city(&shipment_row.get::<serde_json::Value, _>("pickup_address")),
+        destination_city: address_city(&delivery_address),
+        current_location,
+        estimated_delivery: eta
+            .map(|eta| eta.estimated_arrival)
+            .or_else(|| {
+                shipment_row
+                    .get::<Option<DateTime<Utc>>, _>("estimated_delivery")
+                    .map(|at| at.to_rfc3339())
+            }),
+        delivered_at: shipment_row
+            .get::<Option<DateTime<Utc>>, _>("actual_delivery")
+            .map(|at| at.to_rfc3339()),
+        timeline,
+        recipient_verified,
+        delivery_address: recipient_verified.then_some(delivery_address),
+    }))
+}
+
+// Helper functions
+
+async fn phone_suffix_matches(state: &crate::AppState, receiver_id: Uuid, suffix: &str) -> Result<bool, StatusCode> {
+    if suffix.len() < PHONE_SUFFIX_LENGTH || !suffix.chars().all(|c| c.is_ascii_digit()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let phone = sqlx::query("SELECT phone FROM users WHERE id = $1")
+        .bind(receiver_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .and_then(|row| row.get::<Option<String>, _>("phone"));
+
+    let digits: String = phone.unwrap_or_default().chars().filter(|c| c.is_ascii_digit()).collect();
+
+    Ok(digits.len() >= suffix.len() && digits.ends_with(suffix))
+}
+
+/// Background loop that forgets clients whose quota has fully replenished,
+/// so the limiter doesn't keep every address it has ever seen.
+pub async fn run_limiter_cleanup() {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(LIMITER_SWEEP_SECONDS));
+
+    loop {
+        interval.tick().await;
+        let limiter = limiter();
+        limiter.retain_recent();
+        limiter.shrink_to_fit();
+    }
+}
+
+fn limiter() -> &'static DefaultKeyedRateLimiter<IpAddr> {
+    LIMITER.get_or_init(|| RateLimiter::keyed(Quota::per_minute(NonZeroU32::new(REQUESTS_PER_MINUTE).unwrap())))
+}
@@ -0,0 +1,662 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashMap;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::models::*;
+use crate::tracking::{CreateShipmentRequest, ValidatedShipment};
+use crate::utils::AppError;
+
+// Parcel pricing from rate cards. A quote prices a parcel against the active
+// card for its currency and holds that price until it expires; a shipment
+// created with the quote id is billed at the quoted total.
+
+const ZONES: [&str; 4] = ["local", "regional", "national", "international"];
+const DEFAULT_CURRENCY: &str = "USD";
+// Chargeable weight is rounded up to this step, in kilograms
+const WEIGHT_STEP_KG: f64 = 0.5;
+// Tolerance when matching a shipment's weight and value against its quote
+const MATCH_EPSILON: f64 = 0.001;
+
+#[derive(Debug, Deserialize)]
+pub struct QuoteRequest {
+    pub weight: f64,
+    /// `length`, `width` and `height` in centimetres.
+    #[serde(default)]
+    pub dimensions: serde_json::Value,
+    pub pickup_address: serde_json::Value,
+    pub delivery_address: serde_json::Value,
+    pub priority: Option<String>,
+    pub currency: Option<String>,
+    /// Declared value, the base for insurance.
+    pub value: Option<f64>,
+    #[serde(default)]
+    pub insurance: bool,
+    /// Amount to collect from the receiver on delivery.
+    pub cod_amount: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuoteLine {
+    pub code: String,
+    pub description: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuoteResponse {
+    pub id: String,
+    pub rate_card_id: String,
+    pub status: String,
+    pub zone: String,
+    pub actual_weight: f64,
+    pub volumetric_weight: f64,
+    pub chargeable_weight: f64,
+    pub lines: Vec<QuoteLine>,
+    pub total: f64,
+    pub currency: String,
+    pub expires_at: String,
+    pub shipment_id: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ZoneRateRequest {
+    pub zone: String,
+    pub base_price: f64,
+    pub per_kg: f64,
+    #[serde(default)]
+    pub minimum_charge: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRateCardRequest {
+    pub name: String,
+    pub currency: String,
+    pub volumetric_divisor: Option<f64>,
+    /// Percent per priority; priorities left out have no surcharge.
+    #[serde(default)]
+    pub priority_surcharges: HashMap<String, f64>,
+    #[serde(default)]
+    pub fuel_surcharge_percent: f64,
+    #[serde(default)]
+    pub insurance_percent: f64,
+    #[serde(default)]
+    pub insurance_minimum: f64,
+    #[serde(default)]
+    pub cod_fee_flat: f64,
+    #[serde(default)]
+    pub cod_fee_percent: f64,
+    #[serde(default)]
+    pub tax_percent: f64,
+    /// One entry for each of local, regional, national and international.
+    pub zones: Vec<ZoneRateRequest>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ZoneRate {
+    pub zone: String,
+    pub base_price: f64,
+    pub per_kg: f64,
+    pub minimum_charge: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateCardResponse {
+    pub id: String,
+    pub name: String,
+    pub currency: String,
+    pub volumetric_divisor: f64,
+    pub priority_surcharges: serde_json::Value,
+    pub fuel_surcharge_percent: f64,
+    pub insurance_percent: f64,
+    pub insurance_minimum: f64,
+    pub cod_fee_flat: f64,
+    pub cod_fee_percent: f64,
+    pub tax_percent: f64,
+    pub is_active: bool,
+    pub zones: Vec<ZoneRate>,
+    pub created_at: String,
+}
+
+/// What a parcel costs on a given card.
+struct PricedParcel {
+    zone: &'static str,
+    volumetric_weight: f64,
+    chargeable_weight: f64,
+    lines: Vec<QuoteLine>,
+    total: f64,
+}
+
+pub async fn create_quote(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<QuoteRequest>,
+) -> Result<Json<QuoteResponse>, AppError> {
+    if !payload.weight.is_finite() || payload.weight <= 0.0 {
+        return Err(AppError::Validation("weight must be greater than zero".to_string()));
+    }
+    let value = payload.value.unwrap_or(0.0);
+    if !value.is_finite() || value < 0.0 {
+        return Err(AppError::Validation("value must not be negative".to_string()));
+    }
+    if payload.cod_amount.is_some_and(|amount| !amount.is_finite() || amount <= 0.0) {
+        return Err(AppError::Validation("cod_amount must be greater than zero".to_string()));
+    }
+    let priority_name = payload.priority.as_deref().unwrap_or("medium");
+    let priority = ShipmentPriority::parse(priority_name).ok_or_else(|| {
+        AppError::Validation(format!("priority '{}' is not one of low, medium, high, urgent", priority_name))
+    })?;
+    let currency = payload
+        .currency
+        .as_deref()
+        .map(|currency| currency.trim().to_uppercase())
+        .unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+
+    let card = sqlx::query("SELECT * FROM rate_cards WHERE currency = $1 AND is_active")
+        .bind(&currency)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No active rate card for {}", currency)))?;
+    let card_id = card.get::<Uuid, _>("id");
+    let zone_rates = load_zone_rates(&state, &[card_id]).await?.remove(&card_id).unwrap_or_default();
+
+    let zone = derive_zone(&payload.pickup_address, &payload.delivery_address)?;
+    let priced = price_parcel(&card, &zone_rates, zone, &payload, priority, value)?;
+
+    let now = Utc::now();
+    let expires_at = now + Duration::minutes(state.config.quote_ttl_minutes);
+    let quote_id = Uuid::new_v4();
+
+    sqlx::query(
+        r#"
+        INSERT INTO quotes (
+            id, rate_card_id, requested_by, weight, dimensions, priority, value,
+            pickup_address, delivery_address, insured, cod_amount, zone,
+            volumetric_weight, chargeable_weight, breakdown, total, currency, expires_at, created_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16::numeric, $17, $18, $19)
+        "#,
+    )
+    .bind(quote_id)
+    .bind(card_id)
+    .bind(auth_user.user_id)
+    .bind(payload.weight)
+    .bind(&payload.dimensions)
+    .bind(priority)
+    .bind(value)
+    .bind(&payload.pickup_address)
+    .bind(&payload.delivery_address)
+    .bind(payload.insurance)
+    .bind(payload.cod_amount)
+    .bind(priced.zone)
+    .bind(priced.volumetric_weight)
+    .bind(priced.chargeable_weight)
+    .bind(serde_json::to_value(&priced.lines).unwrap_or_default())
+    .bind(priced.total)
+    .bind(&currency)
+    .bind(expires_at)
+    .bind(now)
+    .execute(&state.db.pool)
+    .await?;
+
+    info!(
+        "Quote {} for user {}: {:.2} {} ({} zone, {:.1} kg chargeable)",
+        quote_id, auth_user.user_id, priced.total, currency, priced.zone, priced.chargeable_weight
+    );
+
+    Ok(Json(QuoteResponse {
+        id: quote_id.to_string(),
+        rate_card_id: card_id.to_string(),
+        status: "open".to_string(),
+        zone: priced.zone.to_string(),
+        actual_weight: payload.weight,
+        volumetric_weight: priced.volumetric_weight,
+        chargeable_weight: priced.chargeable_weight,
+        lines: priced.lines,
+        total: priced.total,
+        currency,
+        expires_at: expires_at.to_rfc3339(),
+        shipment_id: None,
+        created_at: now.to_rfc3339(),
+    }))
+}
+
+pub async fn get_quote(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(quote_id): Path<Uuid>,
+) -> Result<Json<QuoteResponse>, AppError> {
+    let row = sqlx::query("SELECT *, total::float8 AS total_amount FROM quotes WHERE id = $1")
+        .bind(quote_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Quote not found".to_string()))?;
+
+    if row.get::<Uuid, _>("requested_by") != auth_user.user_id
+        && !matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin)
+    {
+        return Err(AppError::Authorization("Not allowed to view this quote".to_string()));
+    }
+
+    let expires_at = row.get::<DateTime<Utc>, _>("expires_at");
+    let shipment_id = row.get::<Option<Uuid>, _>("shipment_id");
+    let status = if row.get::<Option<DateTime<Utc>>, _>("used_at").is_some() {
+        "used"
+    } else if expires_at <= Utc::now() {
+        "expired"
+    } else {
+        "open"
+    };
+
+    Ok(Json(QuoteResponse {
+        id: quote_id.to_string(),
+        rate_card_id: row.get::<Uuid, _>("rate_card_id").to_string(),
+        status: status.to_string(),
+        zone: row.get::<String, _>("zone"),
+        actual_weight: row.get::<f64, _>("weight"),
+        volumetric_weight: row.get::<f64, _>("volumetric_weight"),
+        chargeable_weight: row.get::<f64, _>("chargeable_weight"),
+        lines: serde_json::from_value(row.get::<serde_json::Value, _>("breakdown")).unwrap_or_default(),
+        total: row.get::<f64, _>("total_amount"),
+        currency: row.get::<String, _>("currency"),
+        expires_at: expires_at.to_rfc3339(),
+        shipment_id: shipment_id.map(|id| id.to_string()),
+        created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    }))
+}
+
+/// Active cards, one per currency.
+pub async fn list_rate_cards(
+    State(state): State<crate::AppState>,
+    _auth_user: AuthUser,
+) -> Result<Json<Vec<RateCardResponse>>, AppError> {
+    let cards = sqlx::query("SELECT * FROM rate_cards WHERE is_active ORDER BY currency")
+        .fetch_all(&state.db.pool)
+        .await?;
+
+    let card_ids: Vec<Uuid> = cards.iter().map(|card| card.get::<Uuid, _>("id")).collect();
+    let mut zones = load_zone_rates(&state, &card_ids).await?;
+
+    Ok(Json(
+        cards
+            .iter()
+            .map(|card| {
+                let zone_rates = zones.remove(&card.get::<Uuid, _>("id")).unwrap_or_default();
+                rate_card_response(card, zone_rates)
+            })
+            .collect(),
+    ))
+}
+
+/// Replaces the active card for the currency. Open quotes keep the price
+/// they were given.
+pub async fn create_rate_card(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateRateCardRequest>,
+) -> Result<Json<RateCardResponse>, AppError> {
+    auth_user
+        .require_role(&[UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Only admins may manage rate cards".to_string()))?;
+
+    validate_rate_card(&payload)?;
+
+    let currency = payload.currency.trim().to_uppercase();
+    let card_id = Uuid::new_v4();
+    let mut tx = state.db.pool.begin().await?;
+
+    sqlx::query("UPDATE rate_cards SET is_active = FALSE WHERE currency = $1 AND is_active")
+        .bind(&currency)
+        .execute(&mut *tx)
+        .await?;
+
+    let card = sqlx::query(
+        r#"
+        INSERT INTO rate_cards (
+            id, name, currency, volumetric_divisor, priority_surcharges, fuel_surcharge_percent,
+            insurance_percent, insurance_minimum, cod_fee_flat, cod_fee_percent, tax_percent, created_by
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        RETURNING *
+        "#,
+    )
+    .bind(card_id)
+    .bind(payload.name.trim())
+    .bind(&currency)
+    .bind(payload.volumetric_divisor.unwrap_or(5000.0))
+    .bind(serde_json::to_value(&payload.priority_surcharges).unwrap_or_default())
+    .bind(payload.fuel_surcharge_percent)
+    .bind(payload.insurance_percent)
+    .bind(payload.insurance_minimum)
+    .bind(payload.cod_fee_flat)
+    .bind(payload.cod_fee_percent)
+    .bind(payload.tax_percent)
+    .bind(auth_user.user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut zone_rates = Vec::new();
+    for zone in &payload.zones {
+        sqlx::query(
+            "INSERT INTO rate_card_zones (rate_card_id, zone, base_price, per_kg, minimum_charge) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(card_id)
+        .bind(&zone.zone)
+        .bind(zone.base_price)
+        .bind(zone.per_kg)
+        .bind(zone.minimum_charge)
+        .execute(&mut *tx)
+        .await?;
+
+        zone_rates.push(ZoneRate {
+            zone: zone.zone.clone(),
+            base_price: zone.base_price,
+            per_kg: zone.per_kg,
+            minimum_charge: zone.minimum_charge,
+        });
+    }
+
+    tx.commit().await?;
+
+    info!("Rate card {} ({}) activated by {}", card_id, currency, auth_user.user_id);
+
+    Ok(Json(rate_card_response(&card, zone_rates)))
+}
+
+/// Bills a just-inserted shipment at its quote and marks the quote used.
+/// The quote must be open, belong to the creator or the sender, and
+/// describe the same parcel, currency and cash on delivery.
+pub(crate) async fn apply_quote(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    quote_id: Uuid,
+    payload: &CreateShipmentRequest,
+    shipment: &ValidatedShipment,
+    actor: &AuthUser,
+    shipment_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    let quote = sqlx::query("SELECT *, total::float8 AS total_amount FROM quotes WHERE id = $1 FOR UPDATE")
+        .bind(quote_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Quote not found".to_string()))?;
+
+    let requested_by = quote.get::<Uuid, _>("requested_by");
+    if requested_by != actor.user_id && requested_by != shipment.sender_id {
+        return Err(AppError::Authorization("Quote belongs to another user".to_string()));
+    }
+    if quote.get::<Option<DateTime<Utc>>, _>("used_at").is_some() {
+        return Err(AppError::Conflict("Quote has already been used".to_string()));
+    }
+    if quote.get::<DateTime<Utc>, _>("expires_at") <= now {
+        return Err(AppError::Conflict("Quote has expired".to_string()));
+    }
+
+    let mut mismatched = Vec::new();
+    if (quote.get::<f64, _>("weight") - payload.weight).abs() > MATCH_EPSILON {
+        mismatched.push("weight");
+    }
+    if quote.get::<serde_json::Value, _>("dimensions") != payload.dimensions {
+        mismatched.push("dimensions");
+    }
+    if quote.get::<ShipmentPriority, _>("priority") != shipment.priority {
+        mismatched.push("priority");
+    }
+    if quote.get::<bool, _>("insured") && (quote.get::<f64, _>("value") - payload.value).abs() > MATCH_EPSILON {
+        mismatched.push("value");
+    }
+    if quote.get::<serde_json::Value, _>("pickup_address") != payload.pickup_address {
+        mismatched.push("pickup_address");
+    }
+    if quote.get::<serde_json::Value, _>("delivery_address") != payload.delivery_address {
+        mismatched.push("delivery_address");
+    }
+    let currency = quote.get::<String, _>("currency");
+    if !currency.eq_ignore_ascii_case(payload.currency.trim()) {
+        mismatched.push("currency");
+    }
+    let cod_matches = match (quote.get::<Option<f64>, _>("cod_amount"), payload.cod_amount) {
+        (Some(quoted), Some(requested)) => (quoted - requested).abs() <= MATCH_EPSILON,
+        (None, None) => true,
+        _ => false,
+    };
+    if !cod_matches {
+        mismatched.push("cod_amount");
+    }
+    if !mismatched.is_empty() {
+        return Err(AppError::Conflict(format!("Shipment differs from the quote in {}", mismatched.join(", "))));
+    }
+
+    sqlx::query("UPDATE quotes SET shipment_id = $2, used_at = $3 WHERE id = $1")
+        .bind(quote_id)
+        .bind(shipment_id)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query("UPDATE shipments SET quote_id = $2, shipping_cost = $3::numeric, shipping_cost_currency = $4 WHERE id = $1")
+        .bind(shipment_id)
+        .bind(quote_id)
+        .bind(quote.get::<f64, _>("total_amount"))
+        .bind(&currency)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+// Helper functions
+
+fn validate_rate_card(payload: &CreateRateCardRequest) -> Result<(), AppError> {
+    let invalid = |message: &str| Err(AppError::Validation(message.to_string()));
+    let non_negative = |value: f64| value.is_finite() && value >= 0.0;
+
+    if payload.name.trim().is_empty() {
+        return invalid("name is required");
+    }
+    if payload.currency.trim().is_empty() {
+        return invalid("currency is required");
+    }
+    if payload.volumetric_divisor.is_some_and(|divisor| !divisor.is_finite() || divisor <= 0.0) {
+        return invalid("volumetric_divisor must be greater than zero");
+    }
+    for (priority, percent) in &payload.priority_surcharges {
+        if ShipmentPriority::parse(priority).is_none() {
+            return Err(AppError::Validation(format!("priority '{}' is not one of low, medium, high, urgent", priority)));
+        }
+        if !percent.is_finite() || *percent <= -100.0 {
+            return invalid("priority surcharges must be above -100 percent");
+        }
+    }
+    let fees = [
+        payload.fuel_surcharge_percent,
+        payload.insurance_percent,
+        payload.insurance_minimum,
+        payload.cod_fee_flat,
+        payload.cod_fee_percent,
+        payload.tax_percent,
+    ];
+    if !fees.into_iter().all(non_negative) {
+        return invalid("surcharges, fees and taxes must not be negative");
+    }
+
+    for zone in ZONES {
+        match payload.zones.iter().filter(|rate| rate.zone == zone).count() {
+            0 => return Err(AppError::Validation(format!("a rate for the {} zone is required", zone))),
+            1 => {}
+            _ => return Err(AppError::Validation(format!("the {} zone is listed more than once", zone))),
+        }
+    }
+    for rate in &payload.zones {
+        if !ZONES.contains(&rate.zone.as_str()) {
+            return Err(AppError::Validation(format!("zone '{}' is not one of {}", rate.zone, ZONES.join(", "))));
+        }
+        if !non_negative(rate.base_price) || !non_negative(rate.per_kg) || !non_negative(rate.minimum_charge) {
+            return Err(AppError::Validation(format!("rates for the {} zone must not be negative", rate.zone)));
+        }
+    }
+
+    Ok(())
+}
+
+async fn load_zone_rates(
+    state: &crate::AppState,
+    card_ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<ZoneRate>>, AppError> {
+    let rows = sqlx::query("SELECT * FROM rate_card_zones WHERE rate_card_id = ANY($1)")
+        .bind(card_ids)
+        .fetch_all(&state.db.pool)
+        .await?;
+
+    let mut zones: HashMap<Uuid, Vec<ZoneRate>> = HashMap::new();
+    for row in rows {
+        zones.entry(row.get::<Uuid, _>("rate_card_id")).or_default().push(ZoneRate {
+            zone: row.get::<String, _>("zone"),
+            base_price: row.get::<f64, _>("base_price"),
+            per_kg: row.get::<f64, _>("per_kg"),
+            minimum_charge: row.get::<f64, _>("minimum_charge"),
+        });
+    }
+    for rates in zones.values_mut() {
+        rates.sort_by_key(|rate| ZONES.iter().position(|zone| *zone == rate.zone));
+    }
+
+    Ok(zones)
+}
+
+fn rate_card_response(card: &sqlx::postgres::PgRow, zones: Vec<ZoneRate>) -> RateCardResponse {
+    RateCardResponse {
+        id: card.get::<Uuid, _>("id").to_string(),
+        name: card.get::<String, _>("name"),
+        currency: card.get::<String, _>("currency"),
+        volumetric_divisor: card.get::<f64, _>("volumetric_divisor"),
+        priority_surcharges: card.get::<serde_json::Value, _>("priority_surcharges"),
+        fuel_surcharge_percent: card.get::<f64, _>("fuel_surcharge_percent"),
+        insurance_percent: card.get::<f64, _>("insurance_percent"),
+        insurance_minimum: card.get::<f64, _>("insurance_minimum"),
+        cod_fee_flat: card.get::<f64, _>("cod_fee_flat"),
+        cod_fee_percent: card.get::<f64, _>("cod_fee_percent"),
+        tax_percent: card.get::<f64, _>("tax_percent"),
+        is_active: card.get::<bool, _>("is_active"),
+        zones,
+        created_at: card.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    }
+}
+
+/// Zones by how far apart the two cities are: the same city, the same
+/// state or region, the same country, or abroad. A country missing on
+/// either side is taken to be domestic.
+fn derive_zone(pickup: &serde_json::Value, delivery: &serde_json::Value) -> Result<&'static str, AppError> {
+    let field = |address: &serde_json::Value, keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| address.get(*key)?.as_str())
+            .map(|value| value.trim().to_lowercase())
+            .filter(|value| !value.is_empty())
+    };
+
+    let (Some(from_city), Some(to_city)) = (field(pickup, &["city"]), field(delivery, &["city"])) else {
+        return Err(AppError::Validation("pickup_address and delivery_address need a city for pricing".to_string()));
+    };
+    let from_country = field(pickup, &["country"]);
+    let to_country = field(delivery, &["country"]);
+    if from_country.is_some() && to_country.is_some() && from_country != to_country {
+        return Ok("international");
+    }
+
+    let from_region = field(pickup, &["state", "region"]);
+    let to_region = field(delivery, &["state", "region"]);
+    let zone = if from_city == to_city && from_region == to_region {
+        "local"
+    } else if from_region.is_some() && from_region == to_region {
+        "regional"
+    } else {
+        "national"
+    };
+
+    Ok(zone)
+}
+
+fn price_parcel(
+    card: &sqlx::postgres::PgRow,
+    zone_rates: &[ZoneRate],
+    zone: &'static str,
+    payload: &QuoteRequest,
+    priority: ShipmentPriority,
+    value: f64,
+) -> Result<PricedParcel, AppError> {
+    let rate = zone_rates
+        .iter()
+        .find(|rate| rate.zone == zone)
+        .ok_or_else(|| AppError::Internal(format!("Rate card has no {} zone", zone)))?;
+
+    // Dimensions are in centimetres, the divisor in cm³ per kg
+    let volume_cm3 = crate::routing::dimensions_volume_m3(&payload.dimensions) * 1_000_000.0;
+    let volumetric_weight = round_to(volume_cm3 / card.get::<f64, _>("volumetric_divisor"), 3);
+    let chargeable_weight = (payload.weight.max(volumetric_weight) / WEIGHT_STEP_KG).ceil() * WEIGHT_STEP_KG;
+
+    let mut lines = Vec::new();
+    let mut line = |code: &str, description: String, amount: f64| {
+        let amount = round_to(amount, 2);
+        lines.push(QuoteLine { code: code.to_string(), description, amount });
+        amount
+    };
+
+    let freight = line(
+        "freight",
+        format!("{} freight, {} kg chargeable", zone, chargeable_weight),
+        (rate.base_price + rate.per_kg * chargeable_weight).max(rate.minimum_charge),
+    );
+    let mut subtotal = freight;
+
+    let priority_percent = card
+        .get::<serde_json::Value, _>("priority_surcharges")
+        .get(priority.as_str())
+        .and_then(|percent| percent.as_f64())
+        .unwrap_or(0.0);
+    if priority_percent != 0.0 {
+        subtotal += line(
+            "priority",
+            format!("{} priority ({:+}%)", priority.as_str(), priority_percent),
+            freight * priority_percent / 100.0,
+        );
+    }
+
+    let fuel_percent = card.get::<f64, _>("fuel_surcharge_percent");
+    if fuel_percent > 0.0 {
+        subtotal += line("fuel", format!("Fuel surcharge ({}%)", fuel_percent), subtotal * fuel_percent / 100.0);
+    }
+
+    if payload.insurance {
+        let premium = (value * card.get::<f64, _>("insurance_percent") / 100.0).max(card.get::<f64, _>("insurance_minimum"));
+        subtotal += line("insurance", format!("Insurance on declared value {:.2}", value), premium);
+    }
+
+    if let Some(cod_amount) = payload.cod_amount {
+        let fee = card.get::<f64, _>("cod_fee_flat") + cod_amount * card.get::<f64, _>("cod_fee_percent") / 100.0;
+        subtotal += line("cod", format!("Cash on delivery of {:.2}", cod_amount), fee);
+    }
+
+    let tax_percent = card.get::<f64, _>("tax_percent");
+    if tax_percent > 0.0 {
+        subtotal += line("tax", format!("Tax ({}%)", tax_percent), subtotal * tax_percent / 100.0);
+    }
+
+    Ok(PricedParcel {
+        zone,
+        volumetric_weight,
+        chargeable_weight,
+        lines,
+        total: round_to(subtotal, 2),
+    })
+}
+
+fn round_to(value: f64, decimals: i32) -> f64 {
+    let scale = 10f64.powi(decimals);
+    (value * scale).round() / scale
+}
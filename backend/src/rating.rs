@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct RatingService {
@@ -66,6 +67,7 @@ pub struct RatingBreakdown {
 
 pub async fn create_rating(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateRatingRequest>,
 ) -> Result<Json<RatingResponse>, StatusCode> {
     info!("Creating rating for user: {}", payload.ratee_id);
@@ -76,7 +78,7 @@ pub async fn create_rating(
     }
 
     let ratee_id = Uuid::parse_str(&payload.ratee_id).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let rater_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let rater_id = auth_user.user_id;
     let rating_id = Uuid::new_v4();
 
     // Parse shipment_id if provided
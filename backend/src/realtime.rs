@@ -0,0 +1,269 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{authenticate_access_token, extract_token_from_headers};
+use crate::tracking::can_access_shipment;
+
+// Real-time shipment updates fanned out to WebSocket and SSE subscribers
+
+const REDIS_CHANNEL: &str = "shipment_updates";
+const LOCAL_BUFFER: usize = 1024;
+const REDIS_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShipmentUpdate {
+    pub shipment_id: Uuid,
+    pub kind: String, // location, status
+    pub data: serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl ShipmentUpdate {
+    pub fn new(shipment_id: Uuid, kind: &str, data: serde_json::Value) -> Self {
+        Self {
+            shipment_id,
+            kind: kind.to_string(),
+            data,
+            occurred_at: Utc::now(),
+        }
+    }
+}
+
+/// Fan-out hub for shipment updates. With Redis every instance publishes to
+/// and listens on one channel, so subscribers see updates made anywhere;
+/// without it updates only reach subscribers on this instance.
+#[derive(Clone)]
+pub struct TrackingHub {
+    local: broadcast::Sender<ShipmentUpdate>,
+    redis: Option<redis::aio::MultiplexedConnection>,
+}
+
+impl TrackingHub {
+    pub fn in_process() -> Self {
+        let (local, _) = broadcast::channel(LOCAL_BUFFER);
+        Self { local, redis: None }
+    }
+
+    /// Connects to Redis pub/sub, falling back to in-process broadcast if
+    /// Redis can't be reached.
+    pub async fn connect(redis_url: &str) -> Self {
+        let hub = Self::in_process();
+
+        let client = match redis::Client::open(redis_url) {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Invalid Redis URL, using in-process tracking hub: {}", e);
+                return hub;
+            }
+        };
+
+        let connections = tokio::time::timeout(REDIS_CONNECT_TIMEOUT, async {
+            let publisher = client.get_multiplexed_async_connection().await?;
+            let mut subscriber = client.get_async_pubsub().await?;
+            subscriber.subscribe(REDIS_CHANNEL).await?;
+            Ok::<_, redis::RedisError>((publisher, subscriber))
+        })
+        .await;
+
+        let (publisher, subscriber) = match connections {
+            Ok(Ok(connections)) => connections,
+            Ok(Err(e)) => {
+                warn!("Redis unavailable, using in-process tracking hub: {}", e);
+                return hub;
+            }
+            Err(_) => {
+                warn!("Redis connection timed out, using in-process tracking hub");
+                return hub;
+            }
+        };
+
+        // Relay everything published on the channel (including our own
+        // messages) to local subscribers
+        let local = hub.local.clone();
+        tokio::spawn(async move {
+            let mut messages = subscriber.into_on_message();
+            while let Some(message) = messages.next().await {
+                let payload: String = match message.get_payload() {
+                    Ok(payload) => payload,
+                    Err(_) => continue,
+                };
+                match serde_json::from_str::<ShipmentUpdate>(&payload) {
+                    Ok(update) => {
+                        let _ = local.send(update);
+                    }
+                    Err(e) => warn!("Dropping malformed shipment update from Redis: {}", e),
+                }
+            }
+            error!("Redis tracking subscription ended; cross-instance updates stopped");
+        });
+
+        info!("Tracking hub connected to Redis pub/sub");
+
+        Self {
+            local: hub.local,
+            redis: Some(publisher),
+        }
+    }
+
+    /// Best effort: a failed publish never fails the request that caused it.
+    pub async fn publish(&self, update: ShipmentUpdate) {
+        if let Some(redis) = &self.redis {
+            let mut connection = redis.clone();
+            let published = match serde_json::to_string(&update) {
+                Ok(payload) => redis::cmd("PUBLISH")
+                    .arg(REDIS_CHANNEL)
+                    .arg(payload)
+                    .query_async::<_, i64>(&mut connection)
+                    .await
+                    .is_ok(),
+                Err(_) => false,
+            };
+            if published {
+                return;
+            }
+            warn!("Redis publish failed; delivering shipment update locally only");
+        }
+
+        // No receivers is not an error
+        let _ = self.local.send(update);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ShipmentUpdate> {
+        self.local.subscribe()
+    }
+}
+
+/// Browsers can't set headers on WebSocket or EventSource requests, so the
+/// access token may also be passed as `?token=`.
+#[derive(Debug, Deserialize)]
+pub struct StreamAuthQuery {
+    pub token: Option<String>,
+}
+
+pub async fn shipment_ws(
+    State(state): State<crate::AppState>,
+    Path(shipment_id): Path<String>,
+    Query(query): Query<StreamAuthQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let shipment_id = authorize_subscription(&state, &shipment_id, &headers, query.token).await?;
+    let receiver = state.tracking_hub.subscribe();
+
+    Ok(ws
+        .on_upgrade(move |socket| forward_to_socket(socket, shipment_id, receiver))
+        .into_response())
+}
+
+pub async fn shipment_sse(
+    State(state): State<crate::AppState>,
+    Path(shipment_id): Path<String>,
+    Query(query): Query<StreamAuthQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let shipment_id = authorize_subscription(&state, &shipment_id, &headers, query.token).await?;
+    let receiver = state.tracking_hub.subscribe();
+
+    let events = updates_for(shipment_id, receiver).map(|update| {
+        let event = Event::default()
+            .event(update.kind.clone())
+            .json_data(&update)
+            .unwrap_or_else(|_| Event::default().comment("unserializable update"));
+        Ok(event)
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// Helper functions
+
+async fn authorize_subscription(
+    state: &crate::AppState,
+    shipment_id: &str,
+    headers: &HeaderMap,
+    query_token: Option<String>,
+) -> Result<Uuid, StatusCode> {
+    let token = match extract_token_from_headers(headers) {
+        Ok(token) => token,
+        Err(status) => query_token.ok_or(status)?,
+    };
+    let auth_user = crate::auth::auth_user_from_claims(authenticate_access_token(state, &token).await?)?;
+
+    let id = Uuid::parse_str(shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if !can_access_shipment(&auth_user, &shipment_row) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    info!("User {} subscribed to shipment {}", auth_user.user_id, id);
+
+    Ok(id)
+}
+
+/// Updates for one shipment. Slow consumers skip what they missed rather
+/// than being disconnected.
+fn updates_for(
+    shipment_id: Uuid,
+    receiver: broadcast::Receiver<ShipmentUpdate>,
+) -> impl Stream<Item = ShipmentUpdate> {
+    stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(update) if update.shipment_id == shipment_id => return Some((update, receiver)),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Subscriber for shipment {} lagged, skipped {} updates", shipment_id, skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+async fn forward_to_socket(socket: WebSocket, shipment_id: Uuid, receiver: broadcast::Receiver<ShipmentUpdate>) {
+    let (mut sender, mut incoming) = socket.split();
+    let mut updates = Box::pin(updates_for(shipment_id, receiver));
+
+    loop {
+        tokio::select! {
+            update = updates.next() => {
+                let Some(update) = update else { break };
+                let Ok(text) = serde_json::to_string(&update) else { continue };
+                if sender.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = incoming.next() => {
+                // Pings are answered by axum; anything else from the client is ignored
+                match message {
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => continue,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,748 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::dispatch::dispatch_shipment;
+use crate::eta::recalculate as recalculate_eta;
+use crate::models::*;
+use crate::notifications::notify_user;
+use crate::payment::{refund_shipment_payments, RefundResponse};
+use crate::proof_of_delivery::EvidenceFileContentResponse;
+use crate::realtime::ShipmentUpdate;
+use crate::tracking::{
+    can_access_shipment, insert_shipment, record_shipment_event, validate_shipment_request, CreateShipmentRequest,
+    ShipmentEventRecord,
+};
+use crate::upload::{decode_image, load_upload, store_upload};
+use crate::utils::AppError;
+
+// Returns (RMA): the receiver of a delivered shipment asks to send it back,
+// the sender approves or rejects, and an approved return travels as its own
+// shipment in the opposite direction. When that shipment is delivered the
+// original is marked returned and its payments are refunded.
+
+const REASON_CODES: &[&str] = &["damaged", "wrong_item", "not_as_described", "no_longer_needed", "other"];
+const MAX_PHOTOS: usize = 5;
+const MAX_REASON_LENGTH: usize = 2000;
+/// A refund still pending after this long was interrupted and may be retried.
+const STALE_REFUND_MINUTES: i64 = 15;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateReturnRequest {
+    pub shipment_id: Uuid,
+    pub reason_code: String,
+    /// Required when the reason code is `other`.
+    pub reason: Option<String>,
+    /// Base64 JPEG or PNG images, optionally as data URLs.
+    #[serde(default)]
+    pub photos: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReturnDecisionRequest {
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReturnResponse {
+    pub id: String,
+    pub rma_number: String,
+    pub shipment_id: String,
+    pub status: String,
+    pub reason_code: String,
+    pub reason: Option<String>,
+    pub photo_ids: Vec<String>,
+    pub requested_by: String,
+    pub decided_by: Option<String>,
+    pub decision_notes: Option<String>,
+    pub decided_at: Option<String>,
+    pub return_shipment_id: Option<String>,
+    pub return_tracking_number: Option<String>,
+    pub received_at: Option<String>,
+    pub refund_amount: Option<f64>,
+    pub refunded_at: Option<String>,
+    pub created_at: String,
+}
+
+/// A received return whose payments are being refunded.
+pub(crate) struct ReturnReceipt {
+    pub return_id: Uuid,
+    pub shipment_id: Uuid,
+    pub requested_by: Uuid,
+    pub rma_number: String,
+}
+
+impl ReturnReceipt {
+    fn from_row(row: &sqlx::postgres::PgRow) -> Self {
+        Self {
+            return_id: row.get::<Uuid, _>("id"),
+            shipment_id: row.get::<Uuid, _>("shipment_id"),
+            requested_by: row.get::<Uuid, _>("requested_by"),
+            rma_number: row.get::<String, _>("rma_number"),
+        }
+    }
+}
+
+pub async fn create_return(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateReturnRequest>,
+) -> Result<Json<ReturnResponse>, AppError> {
+    if !REASON_CODES.contains(&payload.reason_code.as_str()) {
+        return Err(AppError::Validation(format!(
+            "reason_code '{}' is not one of {}",
+            payload.reason_code,
+            REASON_CODES.join(", ")
+        )));
+    }
+    let reason = payload.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+    if payload.reason_code == "other" && reason.is_none() {
+        return Err(AppError::Validation("A reason is required when reason_code is other".to_string()));
+    }
+    if reason.is_some_and(|reason| reason.len() > MAX_REASON_LENGTH) {
+        return Err(AppError::Validation(format!("reason must be at most {} characters", MAX_REASON_LENGTH)));
+    }
+    if payload.photos.len() > MAX_PHOTOS {
+        return Err(AppError::Validation(format!("At most {} photos can be attached", MAX_PHOTOS)));
+    }
+    let photos = payload
+        .photos
+        .iter()
+        .map(|photo| decode_image(photo))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let shipment = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(payload.shipment_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?;
+
+    let receiver_id = shipment.get::<Uuid, _>("receiver_id");
+    if receiver_id != auth_user.user_id && auth_user.role != UserRole::Admin {
+        return Err(AppError::Authorization("Only the receiver can return a shipment".to_string()));
+    }
+    if shipment.get::<ShipmentStatus, _>("status") != ShipmentStatus::Delivered {
+        return Err(AppError::Conflict("Only delivered shipments can be returned".to_string()));
+    }
+
+    let now = Utc::now();
+    let delivered_at = shipment
+        .get::<Option<DateTime<Utc>>, _>("actual_delivery")
+        .unwrap_or_else(|| shipment.get::<DateTime<Utc>, _>("updated_at"));
+    if now - delivered_at > Duration::days(state.config.return_window_days) {
+        return Err(AppError::Conflict(format!(
+            "Returns must be requested within {} days of delivery",
+            state.config.return_window_days
+        )));
+    }
+
+    let tracking_number = shipment.get::<String, _>("tracking_number");
+    let mut tx = state.db.pool.begin().await?;
+
+    let open = sqlx::query("SELECT 1 FROM return_requests WHERE shipment_id = $1 AND status <> 'rejected'")
+        .bind(payload.shipment_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if open.is_some() {
+        return Err(AppError::Conflict("A return is already open for this shipment".to_string()));
+    }
+
+    let previous: i64 = sqlx::query("SELECT COUNT(*) AS count FROM return_requests WHERE shipment_id = $1")
+        .bind(payload.shipment_id)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("count");
+    let rma_number = format!("RMA-{}-{}", tracking_number, previous + 1);
+
+    let mut photo_ids = Vec::with_capacity(photos.len());
+    for (content_type, bytes) in &photos {
+        let upload = store_upload(
+            &mut tx,
+            &state.config.encryption_key,
+            auth_user.user_id,
+            "return_photo",
+            content_type,
+            bytes,
+        )
+        .await?;
+        photo_ids.push(upload.id);
+    }
+
+    let return_id = Uuid::new_v4();
+    let row = sqlx::query(
+        r#"
+        INSERT INTO return_requests (
+            id, rma_number, shipment_id, requested_by, reason_code, reason, photo_upload_ids, created_at, updated_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+        RETURNING *, refund_amount::float8 AS refund_total
+        "#,
+    )
+    .bind(return_id)
+    .bind(&rma_number)
+    .bind(payload.shipment_id)
+    .bind(auth_user.user_id)
+    .bind(&payload.reason_code)
+    .bind(reason)
+    .bind(&photo_ids)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id: payload.shipment_id,
+        event_type: "return_requested",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: reason,
+        metadata: serde_json::json!({
+            "return_id": return_id,
+            "rma_number": rma_number,
+            "reason_code": payload.reason_code
+        }),
+    })
+    .await?;
+
+    tx.commit().await?;
+
+    info!("Return {} requested for shipment {} by {}", rma_number, payload.shipment_id, auth_user.user_id);
+
+    if let Err(status) = notify_user(
+        &state,
+        shipment.get::<Uuid, _>("sender_id"),
+        "Return requested",
+        &format!("The receiver of shipment {} asked to return it", tracking_number),
+        "return_requested",
+        serde_json::json!({ "return_id": return_id, "shipment_id": payload.shipment_id }),
+    )
+    .await
+    {
+        warn!("Could not notify sender about return {}: {}", return_id, status);
+    }
+
+    Ok(Json(return_response(&row, None)))
+}
+
+/// Returns the caller asked for or, for senders, was asked to take back.
+/// Shipping companies and admins see every return.
+pub async fn list_returns(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ReturnResponse>>, AppError> {
+    let see_all = matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin);
+
+    let rows = sqlx::query(
+        r#"
+        SELECT r.*, r.refund_amount::float8 AS refund_total, rs.tracking_number AS return_tracking_number
+        FROM return_requests r
+        JOIN shipments s ON s.id = r.shipment_id
+        LEFT JOIN shipments rs ON rs.id = r.return_shipment_id
+        WHERE $1 OR r.requested_by = $2 OR s.sender_id = $2
+        ORDER BY r.created_at DESC
+        LIMIT 200
+        "#,
+    )
+    .bind(see_all)
+    .bind(auth_user.user_id)
+    .fetch_all(&state.db.pool)
+    .await?;
+
+    Ok(Json(
+        rows.iter()
+            .map(|row| return_response(row, row.get::<Option<String>, _>("return_tracking_number")))
+            .collect(),
+    ))
+}
+
+pub async fn get_return(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(return_id): Path<Uuid>,
+) -> Result<Json<ReturnResponse>, AppError> {
+    let row = load_authorized(&state, &auth_user, return_id).await?;
+    Ok(Json(return_response(&row, row.get::<Option<String>, _>("return_tracking_number"))))
+}
+
+/// Returns one photo attached to the return request.
+pub async fn get_return_photo(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path((return_id, upload_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<EvidenceFileContentResponse>, AppError> {
+    let row = load_authorized(&state, &auth_user, return_id).await?;
+
+    // Only files referenced by this return are reachable here
+    if !row.get::<Vec<Uuid>, _>("photo_upload_ids").contains(&upload_id) {
+        return Err(AppError::NotFound("Photo not found".to_string()));
+    }
+
+    let (content_type, bytes) = load_upload(&state, upload_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Photo not found".to_string()))?;
+
+    Ok(Json(EvidenceFileContentResponse {
+        id: upload_id.to_string(),
+        content_type,
+        data: BASE64.encode(bytes),
+    }))
+}
+
+/// Authorizes the return and books the reverse shipment from the receiver
+/// back to the sender.
+pub async fn approve_return(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(return_id): Path<Uuid>,
+    Json(payload): Json<ReturnDecisionRequest>,
+) -> Result<Json<ReturnResponse>, AppError> {
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+    let now = Utc::now();
+    let mut tx = state.db.pool.begin().await?;
+
+    let (request, original) = lock_for_decision(&mut tx, &auth_user, return_id).await?;
+    let rma_number = request.get::<String, _>("rma_number");
+    let shipment_id = original.get::<Uuid, _>("id");
+
+    let reverse = CreateShipmentRequest {
+        sender_id: original.get::<Uuid, _>("receiver_id").to_string(),
+        receiver_id: original.get::<Uuid, _>("sender_id").to_string(),
+        weight: original.get::<f64, _>("weight_kg"),
+        dimensions: original.get::<serde_json::Value, _>("dimensions"),
+        description: format!("Return {}: {}", rma_number, original.get::<String, _>("description")),
+        value: original.get::<f64, _>("value_amount"),
+        currency: original.get::<String, _>("currency"),
+        pickup_address: original.get::<serde_json::Value, _>("delivery_address"),
+        delivery_address: original.get::<serde_json::Value, _>("pickup_address"),
+        priority: original.get::<ShipmentPriority, _>("priority").as_str().to_string(),
+        estimated_delivery: None,
+        pickup_window: None,
+        delivery_window: None,
+        quote_id: None,
+        cod_amount: None,
+        parcels: Vec::new(),
+        delivery_slot_id: None,
+        delivery_instructions: None,
+        leave_authorized: false,
+    };
+    let validated = validate_shipment_request(&reverse)
+        .map_err(|errors| AppError::Validation(format!("Return shipment is invalid: {}", errors.join("; "))))?;
+    let (return_shipment_id, return_tracking_number) =
+        insert_shipment(&mut tx, &state, &reverse, &validated, &auth_user, now).await?;
+
+    let row = sqlx::query(
+        r#"
+        UPDATE return_requests
+        SET status = 'approved', decided_by = $2, decision_notes = $3, decided_at = $4,
+            return_shipment_id = $5, updated_at = $4
+        WHERE id = $1
+        RETURNING *, refund_amount::float8 AS refund_total
+        "#,
+    )
+    .bind(return_id)
+    .bind(auth_user.user_id)
+    .bind(notes)
+    .bind(now)
+    .bind(return_shipment_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "return_approved",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes,
+        metadata: serde_json::json!({
+            "return_id": return_id,
+            "rma_number": rma_number,
+            "return_shipment_id": return_shipment_id,
+            "return_tracking_number": return_tracking_number
+        }),
+    })
+    .await?;
+
+    tx.commit().await?;
+
+    info!("Return {} approved by {}; return shipment {}", rma_number, auth_user.user_id, return_shipment_id);
+
+    // Same follow-up as a newly created shipment
+    if let Err(e) = recalculate_eta(&state, return_shipment_id).await {
+        warn!("Could not estimate arrival for shipment {}: {}", return_shipment_id, e);
+    }
+    if let Err(e) = dispatch_shipment(&state, return_shipment_id, &[]).await {
+        warn!("Automatic dispatch failed for shipment {}: {}", return_shipment_id, e);
+    }
+
+    notify_requester(
+        &state,
+        &row,
+        "Return approved",
+        &format!("Return {} was approved; it ships as {}", rma_number, return_tracking_number),
+        "return_approved",
+    )
+    .await;
+
+    Ok(Json(return_response(&row, Some(return_tracking_number))))
+}
+
+pub async fn reject_return(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(return_id): Path<Uuid>,
+    Json(payload): Json<ReturnDecisionRequest>,
+) -> Result<Json<ReturnResponse>, AppError> {
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+    let Some(notes) = notes else {
+        return Err(AppError::Validation("A reason is required to reject a return".to_string()));
+    };
+    let now = Utc::now();
+    let mut tx = state.db.pool.begin().await?;
+
+    let (request, original) = lock_for_decision(&mut tx, &auth_user, return_id).await?;
+    let rma_number = request.get::<String, _>("rma_number");
+
+    let row = sqlx::query(
+        r#"
+        UPDATE return_requests
+        SET status = 'rejected', decided_by = $2, decision_notes = $3, decided_at = $4, updated_at = $4
+        WHERE id = $1
+        RETURNING *, refund_amount::float8 AS refund_total
+        "#,
+    )
+    .bind(return_id)
+    .bind(auth_user.user_id)
+    .bind(notes)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_shipment_event(&mut tx, ShipmentEventRecord {
+        shipment_id: original.get::<Uuid, _>("id"),
+        event_type: "return_rejected",
+        from_status: None,
+        to_status: None,
+        actor: Some(&auth_user),
+        notes: Some(notes),
+        metadata: serde_json::json!({ "return_id": return_id, "rma_number": rma_number }),
+    })
+    .await?;
+
+    tx.commit().await?;
+
+    info!("Return {} rejected by {}", rma_number, auth_user.user_id);
+
+    notify_requester(
+        &state,
+        &row,
+        "Return rejected",
+        &format!("Return {} was rejected: {}", rma_number, notes),
+        "return_rejected",
+    )
+    .await;
+
+    Ok(Json(return_response(&row, None)))
+}
+
+/// Called when a shipment is marked delivered. If it carries an approved
+/// return, the return is received and the original shipment becomes
+/// returned. Delivered shipments only reach that status through here. The
+/// refund itself runs after commit, so the return waits in `refund_pending`.
+pub(crate) async fn record_receipt(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    return_shipment_id: Uuid,
+    actor: &AuthUser,
+) -> Result<Option<ReturnReceipt>, AppError> {
+    let now = Utc::now();
+
+    let Some(row) = sqlx::query(
+        r#"
+        UPDATE return_requests
+        SET status = 'refund_pending', received_at = $2, updated_at = $2
+        WHERE return_shipment_id = $1 AND status = 'approved'
+        RETURNING id, shipment_id, requested_by, rma_number
+        "#,
+    )
+    .bind(return_shipment_id)
+    .bind(now)
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let receipt = ReturnReceipt::from_row(&row);
+
+    let updated = sqlx::query("UPDATE shipments SET status = $2, updated_at = $3 WHERE id = $1 AND status = $4")
+        .bind(receipt.shipment_id)
+        .bind(&ShipmentStatus::Returned)
+        .bind(now)
+        .bind(&ShipmentStatus::Delivered)
+        .execute(&mut **tx)
+        .await?;
+
+    if updated.rows_affected() > 0 {
+        sqlx::query("UPDATE parcels SET status = 'returned', updated_at = $2 WHERE shipment_id = $1 AND status = 'delivered'")
+            .bind(receipt.shipment_id)
+            .bind(now)
+            .execute(&mut **tx)
+            .await?;
+
+        record_shipment_event(tx, ShipmentEventRecord {
+            shipment_id: receipt.shipment_id,
+            event_type: "status_changed",
+            from_status: Some(ShipmentStatus::Delivered),
+            to_status: Some(ShipmentStatus::Returned),
+            actor: Some(actor),
+            notes: None,
+            metadata: serde_json::json!({
+                "return_id": receipt.return_id,
+                "rma_number": receipt.rma_number,
+                "return_shipment_id": return_shipment_id
+            }),
+        })
+        .await?;
+    }
+
+    Ok(Some(receipt))
+}
+
+/// Follow-up once a receipt is committed: tells subscribers the original
+/// shipment was returned and refunds its payments. Failures are logged; the
+/// return is left `refund_failed` for an admin to retry.
+pub(crate) async fn complete_receipt(state: &crate::AppState, receipt: ReturnReceipt) {
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(receipt.shipment_id, "status", serde_json::json!({
+            "previous_status": ShipmentStatus::Delivered.as_str(),
+            "status": ShipmentStatus::Returned.as_str(),
+            "return_id": receipt.return_id
+        })))
+        .await;
+
+    if let Err(e) = refund_return(state, &receipt).await {
+        warn!("Refund for return {} failed: {}", receipt.rma_number, e);
+    }
+}
+
+/// Retries the refund of a return whose refund failed, or that has been
+/// pending longer than a refund run can take. Admin only.
+pub async fn retry_refund(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(return_id): Path<Uuid>,
+) -> Result<Json<ReturnResponse>, AppError> {
+    if auth_user.role != UserRole::Admin {
+        return Err(AppError::Authorization("Only admins can retry refunds".to_string()));
+    }
+    let now = Utc::now();
+
+    // Claiming the return keeps two refund runs from paying out twice
+    let claimed = sqlx::query(
+        r#"
+        UPDATE return_requests
+        SET status = 'refund_pending', updated_at = $2
+        WHERE id = $1 AND (status = 'refund_failed' OR (status = 'refund_pending' AND updated_at < $3))
+        RETURNING id, shipment_id, requested_by, rma_number
+        "#,
+    )
+    .bind(return_id)
+    .bind(now)
+    .bind(now - Duration::minutes(STALE_REFUND_MINUTES))
+    .fetch_optional(&state.db.pool)
+    .await?;
+
+    let Some(claimed) = claimed else {
+        let exists = sqlx::query("SELECT 1 FROM return_requests WHERE id = $1")
+            .bind(return_id)
+            .fetch_optional(&state.db.pool)
+            .await?;
+        return Err(match exists {
+            Some(_) => AppError::Conflict("Return has no failed refund to retry".to_string()),
+            None => AppError::NotFound("Return not found".to_string()),
+        });
+    };
+    let receipt = ReturnReceipt::from_row(&claimed);
+
+    info!("Refund for return {} retried by {}", receipt.rma_number, auth_user.user_id);
+    refund_return(&state, &receipt).await?;
+
+    let row = load_authorized(&state, &auth_user, return_id).await?;
+    Ok(Json(return_response(&row, row.get::<Option<String>, _>("return_tracking_number"))))
+}
+
+// Helper functions
+
+/// Refunds the original shipment's payments for a return the caller holds
+/// in `refund_pending`, then records the outcome: `refunded` once every
+/// payment is refunded, `refund_failed` otherwise.
+async fn refund_return(state: &crate::AppState, receipt: &ReturnReceipt) -> Result<(), AppError> {
+    let result = refund_shipment_payments(
+        state,
+        receipt.shipment_id,
+        Some(receipt.return_id),
+        &format!("Return {}", receipt.rma_number),
+    )
+    .await;
+    let complete = matches!(&result, Ok(refunds) if refunds.iter().all(|refund| refund.status == "completed"));
+    let now = Utc::now();
+
+    let row = sqlx::query(
+        r#"
+        UPDATE return_requests
+        SET status = CASE WHEN $2 THEN 'refunded' ELSE 'refund_failed' END,
+            refund_amount = (
+                SELECT COALESCE(SUM(amount), 0) FROM payment_refunds
+                WHERE return_request_id = $1 AND status = 'completed'
+            ),
+            refunded_at = CASE WHEN $2 THEN $3 ELSE refunded_at END,
+            updated_at = $3
+        WHERE id = $1
+        RETURNING refund_amount::float8 AS refund_total
+        "#,
+    )
+    .bind(receipt.return_id)
+    .bind(complete)
+    .bind(now)
+    .fetch_one(&state.db.pool)
+    .await?;
+    let refunds = result?;
+    let refunded = row.get::<f64, _>("refund_total");
+
+    info!("Return {} received; refunded {:.2} over {} payments", receipt.rma_number, refunded, refunds.len());
+
+    if let Err(status) = notify_user(
+        state,
+        receipt.requested_by,
+        "Return received",
+        &refund_message(&receipt.rma_number, &refunds, refunded),
+        "return_received",
+        serde_json::json!({ "return_id": receipt.return_id, "refund_amount": refunded }),
+    )
+    .await
+    {
+        warn!("Could not notify requester about return {}: {}", receipt.return_id, status);
+    }
+
+    Ok(())
+}
+
+/// Loads a return with the shipment parties, for anyone involved in it.
+async fn load_authorized(
+    state: &crate::AppState,
+    auth_user: &AuthUser,
+    return_id: Uuid,
+) -> Result<sqlx::postgres::PgRow, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT r.*, r.refund_amount::float8 AS refund_total, s.sender_id, s.receiver_id, s.driver_id, rs.tracking_number AS return_tracking_number
+        FROM return_requests r
+        JOIN shipments s ON s.id = r.shipment_id
+        LEFT JOIN shipments rs ON rs.id = r.return_shipment_id
+        WHERE r.id = $1
+        "#,
+    )
+    .bind(return_id)
+    .fetch_optional(&state.db.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Return not found".to_string()))?;
+
+    if !can_access_shipment(auth_user, &row) {
+        return Err(AppError::Authorization("Not allowed to view this return".to_string()));
+    }
+
+    Ok(row)
+}
+
+/// Locks a pending return and its original shipment for the sender, a
+/// shipping company or an admin to decide on.
+async fn lock_for_decision(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    auth_user: &AuthUser,
+    return_id: Uuid,
+) -> Result<(sqlx::postgres::PgRow, sqlx::postgres::PgRow), AppError> {
+    let request = sqlx::query("SELECT * FROM return_requests WHERE id = $1 FOR UPDATE")
+        .bind(return_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Return not found".to_string()))?;
+
+    let original = sqlx::query("SELECT *, weight::float8 AS weight_kg, value::float8 AS value_amount FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(request.get::<Uuid, _>("shipment_id"))
+        .fetch_one(&mut **tx)
+        .await?;
+
+    let may_decide = original.get::<Uuid, _>("sender_id") == auth_user.user_id
+        || matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin);
+    if !may_decide {
+        return Err(AppError::Authorization("Only the sender can decide on a return".to_string()));
+    }
+    if request.get::<String, _>("status") != "requested" {
+        return Err(AppError::Conflict("Return has already been decided".to_string()));
+    }
+
+    Ok((request, original))
+}
+
+async fn notify_requester(state: &crate::AppState, row: &sqlx::postgres::PgRow, title: &str, message: &str, kind: &str) {
+    let return_id = row.get::<Uuid, _>("id");
+
+    if let Err(status) = notify_user(
+        state,
+        row.get::<Uuid, _>("requested_by"),
+        title,
+        message,
+        kind,
+        serde_json::json!({ "return_id": return_id, "shipment_id": row.get::<Uuid, _>("shipment_id") }),
+    )
+    .await
+    {
+        warn!("Could not notify requester about return {}: {}", return_id, status);
+    }
+}
+
+fn refund_message(rma_number: &str, refunds: &[RefundResponse], refunded: f64) -> String {
+    match refunds.first() {
+        None => format!("Return {} was received; there was no payment to refund", rma_number),
+        Some(refund) if refunds.iter().all(|refund| refund.status == "completed") => {
+            format!("Return {} was received and {:.2} {} refunded", rma_number, refunded, refund.currency)
+        }
+        Some(_) => format!("Return {} was received; part of the refund is still being processed", rma_number),
+    }
+}
+
+fn return_response(row: &sqlx::postgres::PgRow, return_tracking_number: Option<String>) -> ReturnResponse {
+    let time = |column: &str| row.get::<Option<DateTime<Utc>>, _>(column).map(|at| at.to_rfc3339());
+
+    ReturnResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        rma_number: row.get::<String, _>("rma_number"),
+        shipment_id: row.get::<Uuid, _>("shipment_id").to_string(),
+        status: row.get::<String, _>("status"),
+        reason_code: row.get::<String, _>("reason_code"),
+        reason: row.get::<Option<String>, _>("reason"),
+        photo_ids: row
+            .get::<Vec<Uuid>, _>("photo_upload_ids")
+            .iter()
+            .map(|id| id.to_string())
+            .collect(),
+        requested_by: row.get::<Uuid, _>("requested_by").to_string(),
+        decided_by: row.get::<Option<Uuid>, _>("decided_by").map(|id| id.to_string()),
+        decision_notes: row.get::<Option<String>, _>("decision_notes"),
+        decided_at: time("decided_at"),
+        return_shipment_id: row.get::<Option<Uuid>, _>("return_shipment_id").map(|id| id.to_string()),
+        return_tracking_number,
+        received_at: time("received_at"),
+        refund_amount: row.get::<Option<f64>, _>("refund_total"),
+        refunded_at: time("refunded_at"),
+        created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+    }
+}
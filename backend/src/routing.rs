@@ -0,0 +1,844 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashSet;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::eta::ROAD_FACTOR;
+use crate::geofencing::address_coordinates;
+use crate::models::*;
+use crate::utils::calculate_distance;
+
+// Multi-stop route planning for a driver's assigned shipments: a greedy
+// construction followed by 2-opt and relocate passes, with pickups kept
+// before their deliveries, vehicle capacity respected and time windows
+// treated as soft constraints
+
+const DEFAULT_SPEED_KMH: f64 = 40.0;
+// Accepted vehicle settings; slower speeds would overflow travel times
+const SPEED_RANGE_KMH: std::ops::RangeInclusive<f64> = 1.0..=200.0;
+const MAX_WEIGHT_RANGE_KG: std::ops::RangeInclusive<f64> = 0.1..=50_000.0;
+const MAX_VOLUME_RANGE_M3: std::ops::RangeInclusive<f64> = 0.001..=200.0;
+const SERVICE_MINUTES: f64 = 5.0;
+// Minutes of lateness are weighted this much more than minutes of driving
+const LATE_PENALTY: f64 = 10.0;
+const MAX_IMPROVEMENT_PASSES: usize = 50;
+const MAX_STOPS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopKind {
+    Pickup,
+    Delivery,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteStop {
+    pub shipment_id: Uuid,
+    pub tracking_number: String,
+    pub kind: StopKind,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    pub weight_kg: f64,
+    pub volume_m3: f64,
+    /// For a delivery, the index of its pickup stop when that is part of the
+    /// route; `None` means the parcel is already on board.
+    pub pickup_index: Option<usize>,
+    /// What the receiver asked the driver to do at a delivery.
+    pub instructions: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vehicle {
+    pub max_weight_kg: Option<f64>,
+    pub max_volume_m3: Option<f64>,
+    pub speed_kmh: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduledStop {
+    pub index: usize,
+    pub arrival: DateTime<Utc>,
+    pub departure: DateTime<Utc>,
+    pub wait_minutes: f64,
+    pub late_minutes: f64,
+    pub distance_from_previous_km: f64,
+    pub load_weight_kg: f64,
+    pub load_volume_m3: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub stops: Vec<ScheduledStop>,
+    pub total_distance_km: f64,
+    pub total_minutes: f64,
+    pub late_minutes: f64,
+}
+
+impl Schedule {
+    fn cost(&self) -> f64 {
+        self.total_minutes + LATE_PENALTY * self.late_minutes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoutePlan {
+    pub schedule: Schedule,
+    /// Stops left out, with the reason.
+    pub unassigned: Vec<(usize, &'static str)>,
+}
+
+/// Plans the order in which to visit `stops`, starting at `start` at
+/// `departure`.
+pub fn plan_route(
+    stops: &[RouteStop],
+    vehicle: &Vehicle,
+    start: (f64, f64),
+    departure: DateTime<Utc>,
+) -> RoutePlan {
+    let mut unassigned = Vec::new();
+    let mut excluded = HashSet::new();
+
+    // A shipment that can never fit is dropped together with its delivery
+    for (index, stop) in stops.iter().enumerate() {
+        if stop.kind == StopKind::Pickup && !fits(vehicle, stop.weight_kg, stop.volume_m3) {
+            excluded.insert(index);
+            unassigned.push((index, "exceeds_vehicle_capacity"));
+        }
+    }
+    for (index, stop) in stops.iter().enumerate() {
+        if stop.pickup_index.is_some_and(|pickup| excluded.contains(&pickup)) {
+            excluded.insert(index);
+            unassigned.push((index, "exceeds_vehicle_capacity"));
+        }
+    }
+
+    let candidates: Vec<usize> = (0..stops.len()).filter(|index| !excluded.contains(index)).collect();
+    let (mut order, stranded) = construct(stops, &candidates, vehicle, start, departure);
+    unassigned.extend(stranded.into_iter().map(|index| (index, "no_feasible_position")));
+
+    // Construction only makes feasible moves, so this holds; fall back to
+    // reporting everything as unplanned rather than panicking
+    let Some(mut best) = simulate(stops, &order, vehicle, start, departure) else {
+        unassigned.extend(order.iter().map(|&index| (index, "no_feasible_position")));
+        return RoutePlan {
+            schedule: Schedule {
+                stops: Vec::new(),
+                total_distance_km: 0.0,
+                total_minutes: 0.0,
+                late_minutes: 0.0,
+            },
+            unassigned,
+        };
+    };
+
+    for _ in 0..MAX_IMPROVEMENT_PASSES {
+        match improve(stops, &order, &best, vehicle, start, departure) {
+            Some((improved_order, improved)) => {
+                order = improved_order;
+                best = improved;
+            }
+            None => break,
+        }
+    }
+
+    RoutePlan {
+        schedule: best,
+        unassigned,
+    }
+}
+
+/// Greedy construction: repeatedly go to the reachable stop with the
+/// lowest cost of getting there (driving, waiting and lateness).
+fn construct(
+    stops: &[RouteStop],
+    candidates: &[usize],
+    vehicle: &Vehicle,
+    start: (f64, f64),
+    departure: DateTime<Utc>,
+) -> (Vec<usize>, Vec<usize>) {
+    let mut remaining: Vec<usize> = candidates.to_vec();
+    let mut order = Vec::with_capacity(remaining.len());
+    let mut visited = HashSet::new();
+    let (mut weight, mut volume) = initial_load(stops, candidates);
+    let mut position = start;
+    let mut clock = departure;
+
+    while !remaining.is_empty() {
+        let next = remaining
+            .iter()
+            .copied()
+            .filter(|&index| {
+                let stop = &stops[index];
+                match stop.kind {
+                    StopKind::Pickup => fits(vehicle, weight + stop.weight_kg, volume + stop.volume_m3),
+                    StopKind::Delivery => stop.pickup_index.is_none_or(|pickup| visited.contains(&pickup)),
+                }
+            })
+            .map(|index| {
+                let (_, arrival, wait, late) = visit(&stops[index], position, clock, vehicle);
+                (index, minutes(arrival - clock) + wait + LATE_PENALTY * late)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((index, _)) = next else { break };
+
+        let stop = &stops[index];
+        let (_, arrival, wait, _) = visit(stop, position, clock, vehicle);
+        clock = arrival + minutes_duration(wait + SERVICE_MINUTES);
+        position = (stop.latitude, stop.longitude);
+        match stop.kind {
+            StopKind::Pickup => {
+                weight += stop.weight_kg;
+                volume += stop.volume_m3;
+            }
+            StopKind::Delivery => {
+                weight -= stop.weight_kg;
+                volume -= stop.volume_m3;
+            }
+        }
+
+        visited.insert(index);
+        order.push(index);
+        remaining.retain(|&other| other != index);
+    }
+
+    (order, remaining)
+}
+
+/// One improving move (segment reversal or single-stop relocation), or
+/// `None` once the route is locally optimal.
+fn improve(
+    stops: &[RouteStop],
+    order: &[usize],
+    current: &Schedule,
+    vehicle: &Vehicle,
+    start: (f64, f64),
+    departure: DateTime<Utc>,
+) -> Option<(Vec<usize>, Schedule)> {
+    let n = order.len();
+    let better = |candidate: Vec<usize>| {
+        simulate(stops, &candidate, vehicle, start, departure)
+            .filter(|schedule| schedule.cost() + 1e-6 < current.cost())
+            .map(|schedule| (candidate, schedule))
+    };
+
+    for i in 0..n {
+        for j in i + 1..n {
+            let mut candidate = order.to_vec();
+            candidate[i..=j].reverse();
+            if let Some(found) = better(candidate) {
+                return Some(found);
+            }
+        }
+    }
+
+    for i in 0..n {
+        for j in 0..n {
+            if i == j {
+                continue;
+            }
+            let mut candidate = order.to_vec();
+            let stop = candidate.remove(i);
+            candidate.insert(j, stop);
+            if let Some(found) = better(candidate) {
+                return Some(found);
+            }
+        }
+    }
+
+    None
+}
+
+/// Times a fixed visiting order. Returns `None` if the order delivers a
+/// parcel before picking it up or overloads the vehicle.
+pub fn simulate(
+    stops: &[RouteStop],
+    order: &[usize],
+    vehicle: &Vehicle,
+    start: (f64, f64),
+    departure: DateTime<Utc>,
+) -> Option<Schedule> {
+    let mut visited = HashSet::new();
+    let (mut weight, mut volume) = initial_load(stops, order);
+    let mut position = start;
+    let mut clock = departure;
+    let mut scheduled = Vec::with_capacity(order.len());
+    let mut total_distance_km = 0.0;
+    let mut late_minutes = 0.0;
+
+    for &index in order {
+        let stop = &stops[index];
+        match stop.kind {
+            StopKind::Pickup => {
+                weight += stop.weight_kg;
+                volume += stop.volume_m3;
+                if !fits(vehicle, weight, volume) {
+                    return None;
+                }
+            }
+            StopKind::Delivery => {
+                if stop.pickup_index.is_some_and(|pickup| !visited.contains(&pickup)) {
+                    return None;
+                }
+                weight -= stop.weight_kg;
+                volume -= stop.volume_m3;
+            }
+        }
+
+        let (distance, arrival, wait, late) = visit(stop, position, clock, vehicle);
+        let departure_at = arrival + minutes_duration(wait + SERVICE_MINUTES);
+
+        scheduled.push(ScheduledStop {
+            index,
+            arrival,
+            departure: departure_at,
+            wait_minutes: wait,
+            late_minutes: late,
+            distance_from_previous_km: distance,
+            load_weight_kg: weight.max(0.0),
+            load_volume_m3: volume.max(0.0),
+        });
+
+        total_distance_km += distance;
+        late_minutes += late;
+        visited.insert(index);
+        position = (stop.latitude, stop.longitude);
+        clock = departure_at;
+    }
+
+    Some(Schedule {
+        stops: scheduled,
+        total_distance_km,
+        total_minutes: minutes(clock - departure),
+        late_minutes,
+    })
+}
+
+/// Distance driven, arrival time, minutes waited for the window to open and
+/// minutes late.
+fn visit(
+    stop: &RouteStop,
+    from: (f64, f64),
+    clock: DateTime<Utc>,
+    vehicle: &Vehicle,
+) -> (f64, DateTime<Utc>, f64, f64) {
+    let distance = calculate_distance(from.0, from.1, stop.latitude, stop.longitude) * ROAD_FACTOR;
+    let arrival = clock + minutes_duration(distance / vehicle.speed_kmh * 60.0);
+
+    match stop.window {
+        Some((opens, _)) if arrival < opens => (distance, arrival, minutes(opens - arrival), 0.0),
+        Some((_, closes)) if arrival > closes => (distance, arrival, 0.0, minutes(arrival - closes)),
+        _ => (distance, arrival, 0.0, 0.0),
+    }
+}
+
+/// Parcels delivered on this route without being picked up on it.
+fn initial_load(stops: &[RouteStop], included: &[usize]) -> (f64, f64) {
+    included
+        .iter()
+        .map(|&index| &stops[index])
+        .filter(|stop| stop.kind == StopKind::Delivery && stop.pickup_index.is_none())
+        .fold((0.0, 0.0), |(weight, volume), stop| (weight + stop.weight_kg, volume + stop.volume_m3))
+}
+
+fn fits(vehicle: &Vehicle, weight: f64, volume: f64) -> bool {
+    vehicle.max_weight_kg.is_none_or(|max| weight <= max + 1e-9)
+        && vehicle.max_volume_m3.is_none_or(|max| volume <= max + 1e-9)
+}
+
+fn minutes(duration: Duration) -> f64 {
+    duration.num_seconds() as f64 / 60.0
+}
+
+fn minutes_duration(minutes: f64) -> Duration {
+    Duration::seconds((minutes * 60.0).round() as i64)
+}
+
+// HTTP API
+
+#[derive(Debug, Deserialize)]
+pub struct RouteQuery {
+    pub start_latitude: Option<f64>,
+    pub start_longitude: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteStopResponse {
+    pub sequence: usize,
+    pub shipment_id: String,
+    pub tracking_number: String,
+    pub kind: StopKind,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub arrival: String,
+    pub departure: String,
+    pub window_start: Option<String>,
+    pub window_end: Option<String>,
+    pub wait_minutes: f64,
+    pub late_minutes: f64,
+    pub distance_from_previous_km: f64,
+    pub load_weight_kg: f64,
+    pub load_volume_m3: f64,
+    pub instructions: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnassignedStopResponse {
+    pub shipment_id: String,
+    pub tracking_number: String,
+    pub kind: StopKind,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteResponse {
+    pub driver_id: String,
+    pub stops: Vec<RouteStopResponse>,
+    pub total_distance_km: f64,
+    pub total_duration_minutes: f64,
+    pub late_minutes: f64,
+    /// The same stops visited in assignment order, for comparison.
+    pub unoptimized_distance_km: Option<f64>,
+    pub unoptimized_duration_minutes: Option<f64>,
+    pub unassigned: Vec<UnassignedStopResponse>,
+    /// Shipments without usable coordinates for a stop they still need.
+    pub skipped_shipments: Vec<String>,
+    pub planned_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateVehicleRequest {
+    pub vehicle_type: String,
+    pub max_weight_kg: Option<f64>,
+    pub max_volume_m3: Option<f64>,
+    pub average_speed_kmh: Option<f64>,
+}
+
+pub async fn get_driver_route(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(driver_id): Path<Uuid>,
+    Query(query): Query<RouteQuery>,
+) -> Result<Json<RouteResponse>, StatusCode> {
+    if driver_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
+    }
+
+    let start = match (query.start_latitude, query.start_longitude) {
+        (Some(latitude), Some(longitude)) => Some((latitude, longitude)),
+        (None, None) => None,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let response = build_driver_route(&state, driver_id, start).await?;
+
+    info!(
+        "Planned {} stops for driver {} ({:.1} km)",
+        response.stops.len(),
+        driver_id,
+        response.total_distance_km
+    );
+
+    Ok(Json(response))
+}
+
+pub async fn update_vehicle(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(driver_id): Path<Uuid>,
+    Json(payload): Json<UpdateVehicleRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if driver_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
+    } else {
+        auth_user.require_role(&[UserRole::Driver])?;
+    }
+
+    let within = |value: Option<f64>, range: std::ops::RangeInclusive<f64>| {
+        value.is_none_or(|value| range.contains(&value))
+    };
+    if payload.vehicle_type.trim().is_empty()
+        || !within(payload.max_weight_kg, MAX_WEIGHT_RANGE_KG)
+        || !within(payload.max_volume_m3, MAX_VOLUME_RANGE_M3)
+        || !within(payload.average_speed_kmh, SPEED_RANGE_KMH)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO driver_vehicles (driver_id, vehicle_type, max_weight_kg, max_volume_m3, average_speed_kmh, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (driver_id) DO UPDATE SET
+            vehicle_type = EXCLUDED.vehicle_type,
+            max_weight_kg = EXCLUDED.max_weight_kg,
+            max_volume_m3 = EXCLUDED.max_volume_m3,
+            average_speed_kmh = EXCLUDED.average_speed_kmh,
+            updated_at = EXCLUDED.updated_at
+        "#,
+    )
+    .bind(driver_id)
+    .bind(payload.vehicle_type.trim())
+    .bind(payload.max_weight_kg)
+    .bind(payload.max_volume_m3)
+    .bind(payload.average_speed_kmh)
+    .bind(Utc::now())
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error updating vehicle for driver {}: {}", driver_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(serde_json::json!({
+        "message": "Vehicle updated",
+        "driver_id": driver_id
+    })))
+}
+
+/// Loads the driver's open shipments and vehicle and plans the route. With
+/// no explicit start the driver's latest reported position is used.
+pub(crate) async fn build_driver_route(
+    state: &crate::AppState,
+    driver_id: Uuid,
+    start: Option<(f64, f64)>,
+) -> Result<RouteResponse, StatusCode> {
+    let now = Utc::now();
+
+    let vehicle = sqlx::query("SELECT * FROM driver_vehicles WHERE driver_id = $1")
+        .bind(driver_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(|row| Vehicle {
+            max_weight_kg: row.get::<Option<f64>, _>("max_weight_kg"),
+            max_volume_m3: row.get::<Option<f64>, _>("max_volume_m3"),
+            speed_kmh: row
+                .get::<Option<f64>, _>("average_speed_kmh")
+                .filter(|speed| SPEED_RANGE_KMH.contains(speed))
+                .unwrap_or(DEFAULT_SPEED_KMH),
+        })
+        .unwrap_or(Vehicle {
+            max_weight_kg: None,
+            max_volume_m3: None,
+            speed_kmh: DEFAULT_SPEED_KMH,
+        });
+
+    let rows = sqlx::query(
+        r#"
+        SELECT id, tracking_number, status, weight::float8 AS weight, dimensions,
+               pickup_address, delivery_address,
+               pickup_window_start, pickup_window_end, delivery_window_start, delivery_window_end,
+               delivery_instructions, leave_authorized
+        FROM shipments
+        WHERE driver_id = $1 AND status IN ('pending', 'picked_up', 'in_transit', 'out_for_delivery')
+        ORDER BY created_at ASC
+        LIMIT $2
+        "#,
+    )
+    .bind(driver_id)
+    .bind((MAX_STOPS / 2) as i64)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut stops = Vec::new();
+    let mut skipped_shipments = Vec::new();
+
+    for row in &rows {
+        let shipment_id = row.get::<Uuid, _>("id");
+        let tracking_number = row.get::<String, _>("tracking_number");
+        let weight_kg = row.get::<f64, _>("weight");
+        let volume_m3 = dimensions_volume_m3(&row.get::<serde_json::Value, _>("dimensions"));
+        let needs_pickup = row.get::<ShipmentStatus, _>("status") == ShipmentStatus::Pending;
+
+        let window = |start: &str, end: &str| {
+            row.get::<Option<DateTime<Utc>>, _>(start)
+                .zip(row.get::<Option<DateTime<Utc>>, _>(end))
+        };
+
+        let pickup = address_coordinates(&row.get::<serde_json::Value, _>("pickup_address"));
+        let Some(delivery) = address_coordinates(&row.get::<serde_json::Value, _>("delivery_address")) else {
+            skipped_shipments.push(shipment_id.to_string());
+            continue;
+        };
+        if needs_pickup && pickup.is_none() {
+            skipped_shipments.push(shipment_id.to_string());
+            continue;
+        }
+
+        let pickup_index = match pickup.filter(|_| needs_pickup) {
+            Some((latitude, longitude)) => {
+                stops.push(RouteStop {
+                    shipment_id,
+                    tracking_number: tracking_number.clone(),
+                    kind: StopKind::Pickup,
+                    latitude,
+                    longitude,
+                    window: window("pickup_window_start", "pickup_window_end"),
+                    weight_kg,
+                    volume_m3,
+                    pickup_index: None,
+                    instructions: None,
+                });
+                Some(stops.len() - 1)
+            }
+            None => None,
+        };
+
+        stops.push(RouteStop {
+            shipment_id,
+            tracking_number,
+            kind: StopKind::Delivery,
+            latitude: delivery.0,
+            longitude: delivery.1,
+            window: window("delivery_window_start", "delivery_window_end"),
+            weight_kg,
+            volume_m3,
+            pickup_index,
+            instructions: delivery_instructions(
+                row.get::<bool, _>("leave_authorized"),
+                row.get::<Option<String>, _>("delivery_instructions"),
+            ),
+        });
+    }
+
+    let start = match start {
+        Some(start) => Some(start),
+        None => sqlx::query(
+            r#"
+            SELECT lu.latitude::float8 AS latitude, lu.longitude::float8 AS longitude
+            FROM location_updates lu
+            JOIN shipments s ON s.id = lu.shipment_id
+            WHERE s.driver_id = $1
+            ORDER BY lu.timestamp DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(driver_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(|row| (row.get::<f64, _>("latitude"), row.get::<f64, _>("longitude"))),
+    }
+    .or_else(|| stops.first().map(|stop| (stop.latitude, stop.longitude)));
+
+    let Some(start) = start else {
+        return Ok(RouteResponse {
+            driver_id: driver_id.to_string(),
+            stops: Vec::new(),
+            total_distance_km: 0.0,
+            total_duration_minutes: 0.0,
+            late_minutes: 0.0,
+            unoptimized_distance_km: None,
+            unoptimized_duration_minutes: None,
+            unassigned: Vec::new(),
+            skipped_shipments,
+            planned_at: now.to_rfc3339(),
+        });
+    };
+
+    let plan = plan_route(&stops, &vehicle, start, now);
+    let round = |value: f64| (value * 10.0).round() / 10.0;
+
+    // Stops were collected pickup-then-delivery per shipment, which is
+    // always a valid order unless the vehicle is too small to carry it
+    let unoptimized = plan
+        .unassigned
+        .is_empty()
+        .then(|| simulate(&stops, &(0..stops.len()).collect::<Vec<_>>(), &vehicle, start, now))
+        .flatten();
+
+    Ok(RouteResponse {
+        driver_id: driver_id.to_string(),
+        stops: plan
+            .schedule
+            .stops
+            .iter()
+            .enumerate()
+            .map(|(sequence, scheduled)| {
+                let stop = &stops[scheduled.index];
+                RouteStopResponse {
+                    sequence: sequence + 1,
+                    shipment_id: stop.shipment_id.to_string(),
+                    tracking_number: stop.tracking_number.clone(),
+                    kind: stop.kind,
+                    latitude: stop.latitude,
+                    longitude: stop.longitude,
+                    arrival: scheduled.arrival.to_rfc3339(),
+                    departure: scheduled.departure.to_rfc3339(),
+                    window_start: stop.window.map(|(start, _)| start.to_rfc3339()),
+                    window_end: stop.window.map(|(_, end)| end.to_rfc3339()),
+                    wait_minutes: round(scheduled.wait_minutes),
+                    late_minutes: round(scheduled.late_minutes),
+                    distance_from_previous_km: round(scheduled.distance_from_previous_km),
+                    load_weight_kg: round(scheduled.load_weight_kg),
+                    load_volume_m3: (scheduled.load_volume_m3 * 1000.0).round() / 1000.0,
+                    instructions: stop.instructions.clone(),
+                }
+            })
+            .collect(),
+        total_distance_km: round(plan.schedule.total_distance_km),
+        total_duration_minutes: round(plan.schedule.total_minutes),
+        late_minutes: round(plan.schedule.late_minutes),
+        unoptimized_distance_km: unoptimized.as_ref().map(|schedule| round(schedule.total_distance_km)),
+        unoptimized_duration_minutes: unoptimized.as_ref().map(|schedule| round(schedule.total_minutes)),
+        unassigned: plan
+            .unassigned
+            .iter()
+            .map(|&(index, reason)| UnassignedStopResponse {
+                shipment_id: stops[index].shipment_id.to_string(),
+                tracking_number: stops[index].tracking_number.clone(),
+                kind: stops[index].kind,
+                reason: reason.to_string(),
+            })
+            .collect(),
+        skipped_shipments,
+        planned_at: now.to_rfc3339(),
+    })
+}
+
+/// Dimensions are free-form JSON in centimetres (`length`, `width`,
+/// `height`); anything missing counts as zero volume.
+pub(crate) fn dimensions_volume_m3(dimensions: &serde_json::Value) -> f64 {
+    let side = |key: &str| dimensions.get(key).and_then(|value| value.as_f64()).unwrap_or(0.0);
+    (side("length") * side("width") * side("height") / 1_000_000.0).max(0.0)
+}
+
+fn delivery_instructions(leave_authorized: bool, instructions: Option<String>) -> Option<String> {
+    match (leave_authorized, instructions) {
+        (true, Some(instructions)) => Some(format!("May be left without a signature. {}", instructions)),
+        (true, None) => Some("May be left without a signature".to_string()),
+        (false, instructions) => instructions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: (f64, f64) = (52.0, 4.0);
+
+    fn departure() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T08:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn vehicle(max_weight_kg: Option<f64>) -> Vehicle {
+        Vehicle {
+            max_weight_kg,
+            max_volume_m3: None,
+            speed_kmh: DEFAULT_SPEED_KMH,
+        }
+    }
+
+    fn stop(kind: StopKind, latitude: f64, weight_kg: f64, pickup_index: Option<usize>) -> RouteStop {
+        RouteStop {
+            shipment_id: Uuid::new_v4(),
+            tracking_number: String::new(),
+            kind,
+            latitude,
+            longitude: START.1,
+            window: None,
+            weight_kg,
+            volume_m3: 0.0,
+            pickup_index,
+            instructions: None,
+        }
+    }
+
+    fn window(opens_after: i64, closes_after: i64) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        Some((departure() + Duration::minutes(opens_after), departure() + Duration::minutes(closes_after)))
+    }
+
+    fn planned_order(plan: &RoutePlan) -> Vec<usize> {
+        plan.schedule.stops.iter().map(|stop| stop.index).collect()
+    }
+
+    #[test]
+    fn simulate_rejects_delivery_before_pickup() {
+        // The pickup is further away, so distance alone would deliver first
+        let stops = vec![stop(StopKind::Delivery, 52.01, 5.0, Some(1)), stop(StopKind::Pickup, 52.1, 5.0, None)];
+
+        assert!(simulate(&stops, &[0, 1], &vehicle(None), START, departure()).is_none());
+        assert!(simulate(&stops, &[1, 0], &vehicle(None), START, departure()).is_some());
+
+        let plan = plan_route(&stops, &vehicle(None), START, departure());
+        assert_eq!(planned_order(&plan), vec![1, 0]);
+        assert!(plan.unassigned.is_empty());
+    }
+
+    #[test]
+    fn simulate_rejects_overloading() {
+        let stops = vec![
+            stop(StopKind::Pickup, 52.01, 6.0, None),
+            stop(StopKind::Pickup, 52.02, 6.0, None),
+            stop(StopKind::Delivery, 52.03, 6.0, Some(0)),
+            stop(StopKind::Delivery, 52.04, 6.0, Some(1)),
+        ];
+        let van = vehicle(Some(10.0));
+
+        assert!(simulate(&stops, &[0, 1, 2, 3], &van, START, departure()).is_none());
+        let schedule = simulate(&stops, &[0, 2, 1, 3], &van, START, departure()).unwrap();
+        assert!(schedule.stops.iter().all(|stop| stop.load_weight_kg <= 10.0));
+    }
+
+    #[test]
+    fn plan_route_excludes_shipments_over_capacity() {
+        let stops = vec![
+            stop(StopKind::Pickup, 52.01, 20.0, None),
+            stop(StopKind::Delivery, 52.02, 20.0, Some(0)),
+            stop(StopKind::Pickup, 52.03, 5.0, None),
+            stop(StopKind::Delivery, 52.04, 5.0, Some(2)),
+        ];
+
+        let plan = plan_route(&stops, &vehicle(Some(10.0)), START, departure());
+
+        assert_eq!(planned_order(&plan), vec![2, 3]);
+        let mut unassigned = plan.unassigned.clone();
+        unassigned.sort();
+        assert_eq!(unassigned, vec![(0, "exceeds_vehicle_capacity"), (1, "exceeds_vehicle_capacity")]);
+    }
+
+    #[test]
+    fn simulate_waits_for_and_reports_lateness_against_windows() {
+        let mut early = stop(StopKind::Delivery, 52.01, 1.0, None);
+        early.window = window(60, 120);
+        let mut late = stop(StopKind::Delivery, 52.1, 1.0, None);
+        late.window = window(0, 1);
+        let stops = vec![early, late];
+
+        let schedule = simulate(&stops, &[0], &vehicle(None), START, departure()).unwrap();
+        let first = &schedule.stops[0];
+        assert!(first.wait_minutes > 50.0);
+        assert_eq!(first.late_minutes, 0.0);
+        assert_eq!(first.departure, departure() + Duration::minutes(60) + minutes_duration(SERVICE_MINUTES));
+
+        let schedule = simulate(&stops, &[1], &vehicle(None), START, departure()).unwrap();
+        assert!(schedule.late_minutes > 15.0);
+        assert_eq!(schedule.stops[0].wait_minutes, 0.0);
+    }
+
+    #[test]
+    fn plan_route_visits_the_closing_window_first() {
+        // The nearby stop only opens in an hour; the far one closes in 30 minutes
+        let mut near = stop(StopKind::Delivery, 52.01, 1.0, None);
+        near.window = window(60, 120);
+        let mut far = stop(StopKind::Delivery, 52.1, 1.0, None);
+        far.window = window(0, 30);
+        let stops = vec![near, far];
+
+        let plan = plan_route(&stops, &vehicle(None), START, departure());
+
+        assert_eq!(planned_order(&plan), vec![1, 0]);
+        assert_eq!(plan.schedule.late_minutes, 0.0);
+    }
+}
@@ -19,10 +19,6 @@ use crate::database::Database;
 pub mod utils {
     use super::*;
 
-    pub fn generate_tracking_number() -> String {
-        format!("SH{:08}", rand::random::<u32>())
-    }
-
     pub fn generate_policy_number() -> String {
         format!("POL{:08}", rand::random::<u32>())
     }
@@ -41,9 +37,11 @@ pub mod utils {
         format!("0x{:x}", rand::random::<u64>())
     }
 
-    pub async fn verify_wallet_signature(signature: &str, address: &str) -> bool {
-        // In a real implementation, this would verify the signature
-        !signature.is_empty() && !address.is_empty()
+    pub async fn verify_wallet_signature(message: &str, signature: &str, address: &str) -> bool {
+        match address.parse::<ethers::types::Address>() {
+            Ok(expected) => crate::siwe::verify_signature(message, signature, expected).is_ok(),
+            Err(_) => false,
+        }
     }
 
     pub async fn get_wallet_balance(address: &str, chain_id: &u64) -> Result<String> {
@@ -135,9 +133,13 @@ pub mod utils {
         Ok(shipments.to_vec())
     }
 
+    /// QR code for `data` as an SVG document.
     pub async fn generate_qr_code(data: &str) -> Result<String> {
-        // In a real implementation, this would generate QR code
-        Ok(format!("qr_code_{}", data))
+        let code = qrcode::QrCode::new(data.as_bytes())?;
+        Ok(code
+            .render::<qrcode::render::svg::Color>()
+            .min_dimensions(200, 200)
+            .build())
     }
 
     pub async fn encrypt_sensitive_data(data: &str) -> Result<String> {
@@ -230,22 +232,6 @@ pub mod utils {
         distance / base_speed
     }
 
-    pub async fn calculate_shipping_cost(weight: f64, distance: f64, priority: &str) -> f64 {
-        // In a real implementation, this would use more sophisticated pricing
-        let base_rate = 5.0; // Base rate per kg
-        let distance_rate = 0.1; // Rate per km
-        
-        let base_cost = weight * base_rate + distance * distance_rate;
-        
-        match priority {
-            "urgent" => base_cost * 2.0,
-            "high" => base_cost * 1.5,
-            "medium" => base_cost,
-            "low" => base_cost * 0.8,
-            _ => base_cost,
-        }
-    }
-
     pub async fn generate_insurance_quote(shipment_value: f64, risk_level: &str) -> f64 {
         // In a real implementation, this would use actuarial calculations
         let base_rate = 0.02; // 2% base rate
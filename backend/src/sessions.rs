@@ -0,0 +1,298 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{header::USER_AGENT, request::Parts, StatusCode},
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::login_protection::ClientIp;
+
+// Login sessions; a session shares its id with the refresh token family
+// issued for it, so revoking one revokes the other
+
+// Avoid a write on every request just to bump last_seen_at
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Request metadata recorded when a new session is started.
+#[derive(Debug, Clone)]
+pub struct SessionContext {
+    pub client_ip: ClientIp,
+    pub user_agent: Option<String>,
+    pub device_info: Option<serde_json::Value>,
+}
+
+#[async_trait]
+impl FromRequestParts<crate::AppState> for SessionContext {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &crate::AppState) -> Result<Self, Self::Rejection> {
+        let client_ip = ClientIp::from_request_parts(parts, state).await?;
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        // Clients may describe the device up front; biometric enrollment
+        // fills it in later otherwise
+        let device_info = parts
+            .headers
+            .get("x-device-info")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| serde_json::from_str(value).ok());
+
+        Ok(SessionContext {
+            client_ip,
+            user_agent,
+            device_info,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub device_info: Option<serde_json::Value>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: String,
+    pub current: bool,
+}
+
+pub async fn list_sessions(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<SessionResponse>>, StatusCode> {
+    let rows = sqlx::query(
+        "SELECT * FROM sessions WHERE user_id = $1 AND revoked_at IS NULL ORDER BY last_seen_at DESC",
+    )
+    .bind(auth_user.user_id)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let sessions = rows
+        .iter()
+        .map(|row| {
+            let id = row.get::<Uuid, _>("id");
+            SessionResponse {
+                id: id.to_string(),
+                device_info: row.get::<Option<serde_json::Value>, _>("device_info"),
+                ip_address: row.get::<Option<String>, _>("ip_address"),
+                user_agent: row.get::<Option<String>, _>("user_agent"),
+                created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+                last_seen_at: row.get::<DateTime<Utc>, _>("last_seen_at").to_rfc3339(),
+                current: auth_user.session_id == Some(id),
+            }
+        })
+        .collect();
+
+    Ok(Json(sessions))
+}
+
+pub async fn revoke_session(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(session_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let revoked = sqlx::query(
+        "UPDATE sessions SET revoked_at = $1 WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL",
+    )
+    .bind(Utc::now())
+    .bind(session_id)
+    .bind(auth_user.user_id)
+    .execute(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .rows_affected();
+
+    if revoked == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    revoke_session_refresh_tokens(&state, &[session_id]).await?;
+
+    info!("Session {} revoked by user {}", session_id, auth_user.user_id);
+
+    Ok(Json(serde_json::json!({
+        "message": "Session revoked",
+        "session_id": session_id
+    })))
+}
+
+pub async fn revoke_other_sessions(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Tokens from before sessions existed can't say which session to keep
+    let current = auth_user.session_id.ok_or(StatusCode::BAD_REQUEST)?;
+
+    let revoked: Vec<Uuid> = sqlx::query(
+        "UPDATE sessions SET revoked_at = $1 WHERE user_id = $2 AND id <> $3 AND revoked_at IS NULL RETURNING id",
+    )
+    .bind(Utc::now())
+    .bind(auth_user.user_id)
+    .bind(current)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .iter()
+    .map(|row| row.get::<Uuid, _>("id"))
+    .collect();
+
+    revoke_session_refresh_tokens(&state, &revoked).await?;
+
+    info!("User {} revoked {} other sessions", auth_user.user_id, revoked.len());
+
+    Ok(Json(serde_json::json!({
+        "message": "Other sessions revoked",
+        "revoked": revoked.len()
+    })))
+}
+
+// Helper functions
+
+pub(crate) async fn create_session(
+    state: &crate::AppState,
+    user_id: Uuid,
+    context: &SessionContext,
+) -> Result<Uuid, StatusCode> {
+    let session_id = Uuid::new_v4();
+    let now = Utc::now();
+
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, device_info, ip_address, user_agent, created_at, last_seen_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .bind(&context.device_info)
+    .bind(context.client_ip.0.map(|ip| ip.to_string()))
+    .bind(&context.user_agent)
+    .bind(now)
+    .bind(now)
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error creating session: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(session_id)
+}
+
+/// Rejects revoked sessions and refreshes `last_seen_at` at most once per
+/// resolution window.
+pub(crate) async fn ensure_session_active(state: &crate::AppState, session_id: Uuid) -> Result<(), StatusCode> {
+    let row = sqlx::query("SELECT revoked_at, last_seen_at FROM sessions WHERE id = $1")
+        .bind(session_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if row.get::<Option<DateTime<Utc>>, _>("revoked_at").is_some() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let now = Utc::now();
+    if row.get::<DateTime<Utc>, _>("last_seen_at") + Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS) < now {
+        sqlx::query("UPDATE sessions SET last_seen_at = $1 WHERE id = $2")
+            .bind(now)
+            .bind(session_id)
+            .execute(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    Ok(())
+}
+
+/// Called on refresh: bumps the session's address and last-seen time, or
+/// rejects the refresh if the session was revoked. Refresh families from
+/// before sessions existed are adopted as sessions here.
+pub(crate) async fn resume_session(
+    state: &crate::AppState,
+    session_id: Uuid,
+    user_id: Uuid,
+    context: &SessionContext,
+) -> Result<(), StatusCode> {
+    let now = Utc::now();
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, device_info, ip_address, user_agent, created_at, last_seen_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        ON CONFLICT (id) DO UPDATE SET
+            ip_address = CASE WHEN sessions.revoked_at IS NULL THEN EXCLUDED.ip_address ELSE sessions.ip_address END,
+            last_seen_at = CASE WHEN sessions.revoked_at IS NULL THEN EXCLUDED.last_seen_at ELSE sessions.last_seen_at END
+        RETURNING user_id, revoked_at
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .bind(&context.device_info)
+    .bind(context.client_ip.0.map(|ip| ip.to_string()))
+    .bind(&context.user_agent)
+    .bind(now)
+    .fetch_one(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error resuming session: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if row.get::<Uuid, _>("user_id") != user_id || row.get::<Option<DateTime<Utc>>, _>("revoked_at").is_some() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn update_device_info(
+    state: &crate::AppState,
+    session_id: Uuid,
+    device_info: &serde_json::Value,
+) -> Result<(), StatusCode> {
+    sqlx::query("UPDATE sessions SET device_info = $1 WHERE id = $2")
+        .bind(device_info)
+        .bind(session_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(())
+}
+
+pub(crate) async fn revoke_user_sessions(state: &crate::AppState, user_id: Uuid) -> Result<(), StatusCode> {
+    sqlx::query("UPDATE sessions SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(())
+}
+
+async fn revoke_session_refresh_tokens(state: &crate::AppState, session_ids: &[Uuid]) -> Result<(), StatusCode> {
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = $1 WHERE family_id = ANY($2) AND revoked_at IS NULL")
+        .bind(Utc::now())
+        .bind(session_ids)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(())
+}
@@ -0,0 +1,872 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashSet;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthUser;
+use crate::dispatch::dispatch_shipment;
+use crate::eta::recalculate as recalculate_eta;
+use crate::kyc::{fiat_value, require_kyc_for_amount};
+use crate::models::*;
+use crate::tracking::{insert_shipment, validate_shipment_request, CreateShipmentRequest, ValidatedShipment};
+use crate::utils::AppError;
+use crate::verification::VerifiedUser;
+
+// Bulk shipment import: CSV or JSON-lines uploads processed as background
+// jobs, either all-or-nothing or row by row. Rows take the same fields as
+// `tracking::create_shipment`; `sender_id` defaults to the importer,
+// `currency` to USD and `priority` to medium.
+
+const MAX_IMPORT_ROWS: usize = 5000;
+// Progress is written back after this many rows
+const PROGRESS_INTERVAL: usize = 25;
+// A running job renews its lease on this cadence; jobs whose lease lapses
+// are treated as abandoned by whichever instance sweeps first
+const LEASE_SECONDS: i64 = 60;
+const LEASE_RENEW_SECONDS: u64 = 15;
+const ABANDONED_SWEEP_SECONDS: u64 = 60;
+const DEFAULT_CURRENCY: &str = "USD";
+const DEFAULT_PRIORITY: &str = "medium";
+const REQUIRED_CSV_COLUMNS: &[&str] = &["receiver_id", "weight", "value", "description"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    JsonLines,
+}
+
+impl ImportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::JsonLines => "jsonl",
+        }
+    }
+
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        if content_type.contains("csv") {
+            Some(ImportFormat::Csv)
+        } else if ["ndjson", "jsonl", "json"].iter().any(|kind| content_type.contains(kind)) {
+            Some(ImportFormat::JsonLines)
+        } else {
+            None
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "csv" => Some(ImportFormat::Csv),
+            "jsonl" | "ndjson" => Some(ImportFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Nothing is created unless every row is valid and inserts cleanly
+    Atomic,
+    /// Valid rows are created and invalid ones reported
+    Partial,
+}
+
+impl ImportMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportMode::Atomic => "atomic",
+            ImportMode::Partial => "partial",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "atomic" | "transactional" => Some(ImportMode::Atomic),
+            "partial" => Some(ImportMode::Partial),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportParams {
+    /// `csv` or `jsonl`; taken from the content type when omitted
+    pub format: Option<String>,
+    /// `atomic` (default) or `partial`
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportJobResponse {
+    pub id: String,
+    pub format: String,
+    pub mode: String,
+    pub status: String,
+    pub total_rows: i32,
+    pub processed_rows: i32,
+    pub created_rows: i32,
+    pub failed_rows: i32,
+    pub progress_percent: f64,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub completed_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRowResponse {
+    pub row_number: i32,
+    pub reference: Option<String>,
+    pub status: String,
+    pub shipment_id: Option<String>,
+    pub tracking_number: Option<String>,
+    pub errors: Vec<String>,
+}
+
+/// A parsed row of the upload. `row_number` is its line in the file.
+struct ImportRow {
+    row_number: i32,
+    reference: Option<String>,
+    outcome: Result<(CreateShipmentRequest, ValidatedShipment), Vec<String>>,
+}
+
+/// Accepts a CSV or JSON-lines body and queues it as an import job.
+pub async fn create_import(
+    State(state): State<crate::AppState>,
+    VerifiedUser(auth_user): VerifiedUser,
+    Query(params): Query<ImportParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<(StatusCode, Json<ImportJobResponse>), AppError> {
+    let format = match params.format.as_deref() {
+        Some(format) => ImportFormat::parse(format),
+        None => headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(ImportFormat::from_content_type),
+    }
+    .ok_or_else(|| AppError::InvalidInput("Upload format must be csv or jsonl".to_string()))?;
+
+    let mode = ImportMode::parse(params.mode.as_deref().unwrap_or("atomic"))
+        .ok_or_else(|| AppError::InvalidInput("Import mode must be atomic or partial".to_string()))?;
+
+    let rows = match format {
+        ImportFormat::Csv => parse_csv(&body, &auth_user)?,
+        ImportFormat::JsonLines => parse_json_lines(&body, &auth_user),
+    };
+
+    if rows.is_empty() {
+        return Err(AppError::Validation("The upload contains no rows".to_string()));
+    }
+    if rows.len() > MAX_IMPORT_ROWS {
+        return Err(AppError::Validation(format!("Imports are limited to {} rows", MAX_IMPORT_ROWS)));
+    }
+
+    let job_id = Uuid::new_v4();
+    let row = sqlx::query(
+        r#"
+        INSERT INTO shipment_imports (id, created_by, format, mode, status, total_rows, created_at, lease_expires_at)
+        VALUES ($1, $2, $3, $4, 'queued', $5, $6, $7)
+        RETURNING *
+        "#,
+    )
+    .bind(job_id)
+    .bind(auth_user.user_id)
+    .bind(format.as_str())
+    .bind(mode.as_str())
+    .bind(rows.len() as i32)
+    .bind(Utc::now())
+    .bind(Utc::now() + chrono::Duration::seconds(LEASE_SECONDS))
+    .fetch_one(&state.db.pool)
+    .await?;
+
+    info!(
+        "Shipment import {} queued by {}: {} {} rows ({})",
+        job_id,
+        auth_user.user_id,
+        rows.len(),
+        format.as_str(),
+        mode.as_str()
+    );
+
+    tokio::spawn(run_import(state.clone(), job_id, auth_user, mode, rows));
+
+    Ok((StatusCode::ACCEPTED, Json(job_response_from_row(&row))))
+}
+
+pub async fn get_import(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(import_id): Path<Uuid>,
+) -> Result<Json<ImportJobResponse>, StatusCode> {
+    let row = fetch_job(&state, &auth_user, import_id).await?;
+    Ok(Json(job_response_from_row(&row)))
+}
+
+/// Per-row outcome, including the tracking numbers of created shipments.
+pub async fn get_import_rows(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(import_id): Path<Uuid>,
+) -> Result<Json<Vec<ImportRowResponse>>, StatusCode> {
+    fetch_job(&state, &auth_user, import_id).await?;
+
+    let rows = sqlx::query("SELECT * FROM shipment_import_rows WHERE import_id = $1 ORDER BY row_number")
+        .bind(import_id)
+        .fetch_all(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(rows.iter().map(row_response_from_row).collect()))
+}
+
+/// CSV download of the rows that failed, for fixing and re-uploading.
+pub async fn get_error_report(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(import_id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let job = fetch_job(&state, &auth_user, import_id).await?;
+
+    // Atomic imports only write their rows once they finish
+    if matches!(job.get::<String, _>("status").as_str(), "queued" | "processing") {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let rows = sqlx::query(
+        "SELECT * FROM shipment_import_rows WHERE import_id = $1 AND status = 'failed' ORDER BY row_number"
+    )
+    .bind(import_id)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(["row_number", "reference", "errors"])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    for row in rows.iter().map(row_response_from_row) {
+        writer
+            .write_record([
+                row.row_number.to_string(),
+                row.reference.unwrap_or_default(),
+                row.errors.join("; "),
+            ])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    let report = writer.into_inner().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"shipment-import-{}-errors.csv\"", import_id),
+            ),
+        ],
+        report,
+    ))
+}
+
+/// Periodically fails imports whose lease has lapsed. Imports run as
+/// in-process tasks, so a job whose instance stopped will never finish.
+pub async fn run_abandoned_import_sweep(state: crate::AppState) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(ABANDONED_SWEEP_SECONDS));
+
+    loop {
+        interval.tick().await;
+        match fail_abandoned_imports(&state).await {
+            Ok(0) => {}
+            Ok(count) => warn!("Marked {} abandoned shipment imports as failed", count),
+            Err(e) => error!("Abandoned shipment import sweep failed: {}", e),
+        }
+    }
+}
+
+async fn fail_abandoned_imports(state: &crate::AppState) -> Result<u64, sqlx::Error> {
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"
+        UPDATE shipment_imports SET status = 'failed', error = $1, completed_at = $2
+        WHERE status IN ('queued', 'processing') AND (lease_expires_at IS NULL OR lease_expires_at < $2)
+        "#,
+    )
+    .bind("The import was interrupted, most likely by a server restart; please upload it again")
+    .bind(now)
+    .execute(&state.db.pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Keeps the job's lease alive while this instance works on it.
+async fn renew_lease(state: crate::AppState, job_id: Uuid) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(LEASE_RENEW_SECONDS));
+
+    loop {
+        interval.tick().await;
+        let result = sqlx::query(
+            "UPDATE shipment_imports SET lease_expires_at = $1 WHERE id = $2 AND status IN ('queued', 'processing')",
+        )
+        .bind(Utc::now() + chrono::Duration::seconds(LEASE_SECONDS))
+        .bind(job_id)
+        .execute(&state.db.pool)
+        .await;
+
+        if let Err(e) = result {
+            warn!("Failed to renew the lease of shipment import {}: {}", job_id, e);
+        }
+    }
+}
+
+// Helper functions
+
+async fn run_import(state: crate::AppState, job_id: Uuid, actor: AuthUser, mode: ImportMode, rows: Vec<ImportRow>) {
+    let lease = tokio::spawn(renew_lease(state.clone(), job_id));
+    let result = process_import(&state, job_id, &actor, mode, rows).await;
+    lease.abort();
+
+    if let Err(e) = result {
+        error!("Shipment import {} failed: {}", job_id, e);
+
+        let result = sqlx::query(
+            r#"
+            UPDATE shipment_imports SET status = 'failed', error = $1, completed_at = $2
+            WHERE id = $3 AND status IN ('queued', 'processing')
+            "#,
+        )
+        .bind("The import could not be completed")
+        .bind(Utc::now())
+        .bind(job_id)
+        .execute(&state.db.pool)
+        .await;
+
+        if let Err(e) = result {
+            error!("Failed to mark shipment import {} as failed: {}", job_id, e);
+        }
+    }
+}
+
+async fn process_import(
+    state: &crate::AppState,
+    job_id: Uuid,
+    actor: &AuthUser,
+    mode: ImportMode,
+    mut rows: Vec<ImportRow>,
+) -> Result<(), sqlx::Error> {
+    // A job the sweep already gave up on stays failed
+    let started = sqlx::query(
+        "UPDATE shipment_imports SET status = 'processing', started_at = $1 WHERE id = $2 AND status = 'queued'",
+    )
+    .bind(Utc::now())
+    .bind(job_id)
+    .execute(&state.db.pool)
+    .await?
+    .rows_affected();
+    if started == 0 {
+        warn!("Shipment import {} was no longer queued; not processing it", job_id);
+        return Ok(());
+    }
+
+    check_parties(state, actor, &mut rows).await?;
+
+    let total = rows.len();
+    let invalid = rows.iter().filter(|row| row.outcome.is_err()).count();
+    let mut created = Vec::new();
+
+    match mode {
+        ImportMode::Atomic if invalid > 0 => {
+            for row in &rows {
+                let (status, errors) = match &row.outcome {
+                    Ok(_) => ("skipped", Vec::new()),
+                    Err(errors) => ("failed", errors.clone()),
+                };
+                record_row(&state.db.pool, job_id, row, status, None, &errors).await?;
+            }
+
+            let message = format!("{} of {} rows failed validation; nothing was imported", invalid, total);
+            finish(state, job_id, "failed", total, 0, invalid, Some(&message)).await?;
+            info!("Shipment import {} rejected: {}", job_id, message);
+            return Ok(());
+        }
+        ImportMode::Atomic => {
+            let mut tx = state.db.pool.begin().await?;
+            let mut failures = Vec::new();
+
+            // Each row gets its own savepoint so one failure doesn't hide
+            // the errors of the rows after it
+            for (index, row) in rows.iter().enumerate() {
+                let Ok((request, shipment)) = &row.outcome else { continue };
+                let mut savepoint = sqlx::Acquire::begin(&mut *tx).await?;
+
+                match insert_shipment(&mut savepoint, state, request, shipment, actor, Utc::now()).await {
+                    Ok((shipment_id, tracking_number)) => {
+                        record_row(&mut *savepoint, job_id, row, "created", Some((shipment_id, &tracking_number)), &[])
+                            .await?;
+                        savepoint.commit().await?;
+                        created.push(shipment_id);
+                    }
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        failures.push((row.row_number, row_error(&e)));
+                    }
+                }
+
+                if (index + 1) % PROGRESS_INTERVAL == 0 {
+                    update_progress(state, job_id, index + 1, 0, failures.len()).await?;
+                }
+            }
+
+            if !failures.is_empty() {
+                tx.rollback().await?;
+
+                for row in &rows {
+                    let (status, errors) = match failures.iter().find(|(row_number, _)| *row_number == row.row_number) {
+                        Some((_, error)) => ("failed", vec![error.clone()]),
+                        None => ("skipped", Vec::new()),
+                    };
+                    record_row(&state.db.pool, job_id, row, status, None, &errors).await?;
+                }
+
+                let message = format!("{} of {} rows could not be created; nothing was imported", failures.len(), total);
+                finish(state, job_id, "failed", total, 0, failures.len(), Some(&message)).await?;
+                warn!("Shipment import {} rolled back: {}", job_id, message);
+                return Ok(());
+            }
+
+            tx.commit().await?;
+        }
+        ImportMode::Partial => {
+            let mut failed = 0;
+
+            for (index, row) in rows.iter().enumerate() {
+                let result = match &row.outcome {
+                    Ok((request, shipment)) => create_row(state, actor, request, shipment)
+                        .await
+                        .map_err(|e| vec![row_error(&e)]),
+                    Err(errors) => Err(errors.clone()),
+                };
+
+                match result {
+                    Ok((shipment_id, tracking_number)) => {
+                        record_row(&state.db.pool, job_id, row, "created", Some((shipment_id, &tracking_number)), &[]).await?;
+                        created.push(shipment_id);
+                    }
+                    Err(errors) => {
+                        record_row(&state.db.pool, job_id, row, "failed", None, &errors).await?;
+                        failed += 1;
+                    }
+                }
+
+                if (index + 1) % PROGRESS_INTERVAL == 0 {
+                    update_progress(state, job_id, index + 1, created.len(), failed).await?;
+                }
+            }
+        }
+    }
+
+    finish(state, job_id, "completed", total, created.len(), total - created.len(), None).await?;
+    info!("Shipment import {} completed: {} of {} rows created", job_id, created.len(), total);
+
+    // Same follow-up as a single create; failures here don't undo the import
+    for shipment_id in created {
+        if let Err(e) = recalculate_eta(state, shipment_id).await {
+            warn!("Could not estimate arrival for shipment {}: {}", shipment_id, e);
+        }
+        if let Err(e) = dispatch_shipment(state, shipment_id, &[]).await {
+            warn!("Automatic dispatch failed for shipment {}: {}", shipment_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks what the row validator can't see on its own: that sender and
+/// receiver exist, that the importer may ship for the sender and that the
+/// sender has KYC for high-value rows.
+async fn check_parties(state: &crate::AppState, actor: &AuthUser, rows: &mut [ImportRow]) -> Result<(), sqlx::Error> {
+    let user_ids: Vec<Uuid> = rows
+        .iter()
+        .filter_map(|row| row.outcome.as_ref().ok())
+        .flat_map(|(_, shipment)| [shipment.sender_id, shipment.receiver_id])
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    let known: HashSet<Uuid> = sqlx::query("SELECT id FROM users WHERE id = ANY($1)")
+        .bind(&user_ids)
+        .fetch_all(&state.db.pool)
+        .await?
+        .iter()
+        .map(|row| row.get::<Uuid, _>("id"))
+        .collect();
+
+    let may_ship_for_others = matches!(actor.role, UserRole::ShippingCompany | UserRole::Admin);
+
+    for row in rows.iter_mut() {
+        let Ok((request, shipment)) = &row.outcome else { continue };
+        let mut errors = Vec::new();
+
+        if shipment.sender_id != actor.user_id && !may_ship_for_others {
+            errors.push("Only shipping companies and admins may import shipments for other senders".to_string());
+        } else if !known.contains(&shipment.sender_id) {
+            errors.push("sender_id does not match a user".to_string());
+        }
+        if !known.contains(&shipment.receiver_id) {
+            errors.push("receiver_id does not match a user".to_string());
+        }
+        let fiat_amount = fiat_value(&request.currency, request.value).await;
+        if errors.is_empty() && require_kyc_for_amount(state, shipment.sender_id, fiat_amount).await.is_err() {
+            errors.push("Sender needs KYC verification for a shipment of this value".to_string());
+        }
+
+        if !errors.is_empty() {
+            row.outcome = Err(errors);
+        }
+    }
+
+    Ok(())
+}
+
+async fn create_row(
+    state: &crate::AppState,
+    actor: &AuthUser,
+    request: &CreateShipmentRequest,
+    shipment: &ValidatedShipment,
+) -> Result<(Uuid, String), AppError> {
+    let mut tx = state.db.pool.begin().await?;
+    let created = insert_shipment(&mut tx, state, request, shipment, actor, Utc::now()).await?;
+    tx.commit().await?;
+    Ok(created)
+}
+
+/// The message stored for a row the database refused. Errors that describe
+/// the row are passed through; anything internal stays in the logs.
+fn row_error(error: &AppError) -> String {
+    match error {
+        AppError::Validation(message)
+        | AppError::InvalidInput(message)
+        | AppError::Conflict(message)
+        | AppError::NotFound(message)
+        | AppError::Authorization(message) => message.clone(),
+        _ => "Shipment could not be created".to_string(),
+    }
+}
+
+async fn record_row<'e, E>(
+    executor: E,
+    job_id: Uuid,
+    row: &ImportRow,
+    status: &str,
+    created: Option<(Uuid, &str)>,
+    errors: &[String],
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO shipment_import_rows (import_id, row_number, reference, status, shipment_id, tracking_number, errors)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(job_id)
+    .bind(row.row_number)
+    .bind(&row.reference)
+    .bind(status)
+    .bind(created.map(|(shipment_id, _)| shipment_id))
+    .bind(created.map(|(_, tracking_number)| tracking_number))
+    .bind(serde_json::json!(errors))
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+async fn update_progress(
+    state: &crate::AppState,
+    job_id: Uuid,
+    processed: usize,
+    created: usize,
+    failed: usize,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE shipment_imports SET processed_rows = $1, created_rows = $2, failed_rows = $3 WHERE id = $4")
+        .bind(processed as i32)
+        .bind(created as i32)
+        .bind(failed as i32)
+        .bind(job_id)
+        .execute(&state.db.pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn finish(
+    state: &crate::AppState,
+    job_id: Uuid,
+    status: &str,
+    processed: usize,
+    created: usize,
+    failed: usize,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let finished = sqlx::query(
+        r#"
+        UPDATE shipment_imports
+        SET status = $1, processed_rows = $2, created_rows = $3, failed_rows = $4, error = $5, completed_at = $6
+        WHERE id = $7 AND status = 'processing'
+        "#,
+    )
+    .bind(status)
+    .bind(processed as i32)
+    .bind(created as i32)
+    .bind(failed as i32)
+    .bind(error)
+    .bind(Utc::now())
+    .bind(job_id)
+    .execute(&state.db.pool)
+    .await?
+    .rows_affected();
+
+    // The sweep failed the job after its lease lapsed; keep that outcome
+    if finished == 0 {
+        warn!("Shipment import {} was already marked failed; not recording it as {}", job_id, status);
+    }
+
+    Ok(())
+}
+
+/// Flat CSV columns map onto the create request: `pickup_*` and
+/// `delivery_*` become address fields, `length`/`width`/`height` the
+/// dimensions and `*_window_start`/`*_window_end` the time windows.
+fn parse_csv(body: &str, actor: &AuthUser) -> Result<Vec<ImportRow>, AppError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+
+    let headers = reader
+        .headers()
+        .map_err(|e| AppError::InvalidInput(format!("Unreadable CSV header: {}", e)))?
+        .clone();
+
+    if let Some(missing) = REQUIRED_CSV_COLUMNS.iter().find(|column| !headers.iter().any(|header| header == **column)) {
+        return Err(AppError::Validation(format!("Missing CSV column '{}'", missing)));
+    }
+
+    let mut rows = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        // The header is line 1
+        let fallback_line = index as u64 + 2;
+
+        let row = match record {
+            Ok(record) => {
+                let row_number = record.position().map_or(fallback_line, |position| position.line()) as i32;
+                let (reference, fields) = csv_record_fields(&headers, &record);
+                ImportRow {
+                    row_number,
+                    reference,
+                    outcome: fields.and_then(|fields| request_from_fields(fields, actor)),
+                }
+            }
+            Err(e) => ImportRow {
+                row_number: e.position().map_or(fallback_line, |position| position.line()) as i32,
+                reference: None,
+                outcome: Err(vec![format!("Unreadable CSV row: {}", e)]),
+            },
+        };
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+fn csv_record_fields(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+) -> (Option<String>, Result<serde_json::Map<String, serde_json::Value>, Vec<String>>) {
+    use serde_json::{json, Map, Value};
+
+    let mut reference = None;
+    let mut fields = Map::new();
+    let mut errors = Vec::new();
+
+    let mut number = |column: &str, value: &str| match value.parse::<f64>() {
+        Ok(number) => Some(json!(number)),
+        Err(_) => {
+            errors.push(format!("{} must be a number", column));
+            None
+        }
+    };
+
+    // Empty cells count as absent
+    for (column, value) in headers.iter().zip(record.iter()).filter(|(_, value)| !value.is_empty()) {
+        match column {
+            "reference" => reference = Some(value.to_string()),
+            "weight" | "value" | "cod_amount" => {
+                if let Some(number) = number(column, value) {
+                    fields.insert(column.to_string(), number);
+                }
+            }
+            "length" | "width" | "height" => {
+                if let Some(number) = number(column, value) {
+                    let dimensions = fields.entry("dimensions").or_insert_with(|| json!({}));
+                    dimensions[column] = number;
+                }
+            }
+            "pickup_window_start" | "pickup_window_end" | "delivery_window_start" | "delivery_window_end" => {
+                let (window, bound) = column.rsplit_once('_').unwrap_or((column, ""));
+                let window = fields.entry(window).or_insert_with(|| json!({}));
+                window[bound] = json!(value);
+            }
+            "sender_id" | "receiver_id" | "description" | "currency" | "priority" | "estimated_delivery" | "quote_id"
+            | "delivery_slot_id" | "delivery_instructions" => {
+                fields.insert(column.to_string(), json!(value));
+            }
+            "leave_authorized" => {
+                let authorized = matches!(value.to_ascii_lowercase().as_str(), "true" | "yes" | "1");
+                fields.insert(column.to_string(), json!(authorized));
+            }
+            _ => {
+                let Some((address, key)) = column
+                    .strip_prefix("pickup_")
+                    .map(|key| ("pickup_address", key))
+                    .or_else(|| column.strip_prefix("delivery_").map(|key| ("delivery_address", key)))
+                else {
+                    // Store exports carry extra columns; ignore them
+                    continue;
+                };
+
+                let value = if ["latitude", "lat", "longitude", "lng", "lon"].contains(&key) {
+                    match number(column, value) {
+                        Some(number) => number,
+                        None => continue,
+                    }
+                } else {
+                    Value::String(value.to_string())
+                };
+                fields.entry(address).or_insert_with(|| json!({}))[key] = value;
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        (reference, Ok(fields))
+    } else {
+        (reference, Err(errors))
+    }
+}
+
+fn parse_json_lines(body: &str, actor: &AuthUser) -> Vec<ImportRow> {
+    body.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let row_number = index as i32 + 1;
+
+            match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(serde_json::Value::Object(mut fields)) => {
+                    let reference = fields.remove("reference").and_then(|reference| match reference {
+                        serde_json::Value::String(reference) => Some(reference),
+                        serde_json::Value::Null => None,
+                        other => Some(other.to_string()),
+                    });
+                    ImportRow {
+                        row_number,
+                        reference,
+                        outcome: request_from_fields(fields, actor),
+                    }
+                }
+                Ok(_) => ImportRow {
+                    row_number,
+                    reference: None,
+                    outcome: Err(vec!["Line is not a JSON object".to_string()]),
+                },
+                Err(e) => ImportRow {
+                    row_number,
+                    reference: None,
+                    outcome: Err(vec![format!("Invalid JSON: {}", e)]),
+                },
+            }
+        })
+        .collect()
+}
+
+fn request_from_fields(
+    mut fields: serde_json::Map<String, serde_json::Value>,
+    actor: &AuthUser,
+) -> Result<(CreateShipmentRequest, ValidatedShipment), Vec<String>> {
+    use serde_json::json;
+
+    fields.entry("sender_id").or_insert_with(|| json!(actor.user_id.to_string()));
+    fields.entry("currency").or_insert_with(|| json!(DEFAULT_CURRENCY));
+    fields.entry("priority").or_insert_with(|| json!(DEFAULT_PRIORITY));
+    fields.entry("dimensions").or_insert_with(|| json!({}));
+
+    let request: CreateShipmentRequest =
+        serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| vec![e.to_string()])?;
+    let shipment = validate_shipment_request(&request)?;
+
+    Ok((request, shipment))
+}
+
+/// Loads a job for its creator; admins may see every job.
+async fn fetch_job(
+    state: &crate::AppState,
+    auth_user: &AuthUser,
+    import_id: Uuid,
+) -> Result<sqlx::postgres::PgRow, StatusCode> {
+    let row = sqlx::query("SELECT * FROM shipment_imports WHERE id = $1")
+        .bind(import_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if row.get::<Uuid, _>("created_by") != auth_user.user_id && !matches!(auth_user.role, UserRole::Admin) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(row)
+}
+
+fn job_response_from_row(row: &sqlx::postgres::PgRow) -> ImportJobResponse {
+    let total_rows = row.get::<i32, _>("total_rows");
+    let processed_rows = row.get::<i32, _>("processed_rows");
+
+    ImportJobResponse {
+        id: row.get::<Uuid, _>("id").to_string(),
+        format: row.get::<String, _>("format"),
+        mode: row.get::<String, _>("mode"),
+        status: row.get::<String, _>("status"),
+        total_rows,
+        processed_rows,
+        created_rows: row.get::<i32, _>("created_rows"),
+        failed_rows: row.get::<i32, _>("failed_rows"),
+        progress_percent: if total_rows > 0 {
+            (processed_rows as f64 * 1000.0 / total_rows as f64).round() / 10.0
+        } else {
+            0.0
+        },
+        error: row.get::<Option<String>, _>("error"),
+        created_at: row.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+        started_at: row.get::<Option<DateTime<Utc>>, _>("started_at").map(|at| at.to_rfc3339()),
+        completed_at: row.get::<Option<DateTime<Utc>>, _>("completed_at").map(|at| at.to_rfc3339()),
+    }
+}
+
+fn row_response_from_row(row: &sqlx::postgres::PgRow) -> ImportRowResponse {
+    ImportRowResponse {
+        row_number: row.get::<i32, _>("row_number"),
+        reference: row.get::<Option<String>, _>("reference"),
+        status: row.get::<String, _>("status"),
+        shipment_id: row.get::<Option<Uuid>, _>("shipment_id").map(|id| id.to_string()),
+        tracking_number: row.get::<Option<String>, _>("tracking_number"),
+        errors: serde_json::from_value(row.get::<serde_json::Value, _>("errors")).unwrap_or_default(),
+    }
+}
@@ -0,0 +1,266 @@
+use chrono::{DateTime, Duration, Utc};
+use ethers::types::{Address, Signature};
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::utils::AppError;
+
+// Sign-In with Ethereum (EIP-4361) message parsing and verification
+
+pub const NONCE_TTL_MINUTES: i64 = 10;
+
+const PREAMBLE_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+#[derive(Debug, Clone)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+impl SiweMessage {
+    /// Parses the plain-text message format defined by EIP-4361.
+    pub fn parse(message: &str) -> Result<Self, AppError> {
+        let invalid = |reason: &str| AppError::InvalidInput(format!("Invalid SIWE message: {}", reason));
+        let mut lines = message.lines();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| invalid("missing preamble"))?
+            .to_string();
+
+        let address = lines
+            .next()
+            .filter(|line| line.starts_with("0x") && line.len() == 42)
+            .and_then(|line| Address::from_str(line).ok())
+            .ok_or_else(|| invalid("bad address"))?;
+
+        if lines.next() != Some("") {
+            return Err(invalid("expected blank line after address"));
+        }
+
+        // Optional statement; either way a blank line comes before the URI
+        let statement = match lines.next() {
+            Some("") => None,
+            Some(line) => {
+                if lines.next() != Some("") {
+                    return Err(invalid("expected blank line after statement"));
+                }
+                Some(line.to_string())
+            }
+            None => return Err(invalid("missing URI")),
+        };
+
+        let mut uri = None;
+        let mut version = None;
+        let mut chain_id = None;
+        let mut nonce = None;
+        let mut issued_at = None;
+        let mut expiration_time = None;
+        let mut not_before = None;
+        let mut request_id = None;
+        let mut resources = Vec::new();
+        let mut in_resources = false;
+
+        for line in lines {
+            if in_resources {
+                let resource = line.strip_prefix("- ").ok_or_else(|| invalid("bad resource line"))?;
+                resources.push(resource.to_string());
+                continue;
+            }
+
+            let (key, value) = match line.split_once(": ") {
+                Some(pair) => pair,
+                None if line == "Resources:" => {
+                    in_resources = true;
+                    continue;
+                }
+                None => return Err(invalid("unexpected line")),
+            };
+
+            match key {
+                "URI" => uri = Some(value.to_string()),
+                "Version" => version = Some(value.to_string()),
+                "Chain ID" => chain_id = Some(value.parse::<u64>().map_err(|_| invalid("bad chain id"))?),
+                "Nonce" => nonce = Some(value.to_string()),
+                "Issued At" => issued_at = Some(parse_timestamp(value).ok_or_else(|| invalid("bad issued at"))?),
+                "Expiration Time" => {
+                    expiration_time = Some(parse_timestamp(value).ok_or_else(|| invalid("bad expiration time"))?)
+                }
+                "Not Before" => not_before = Some(parse_timestamp(value).ok_or_else(|| invalid("bad not before"))?),
+                "Request ID" => request_id = Some(value.to_string()),
+                _ => return Err(invalid("unknown field")),
+            }
+        }
+
+        let message = SiweMessage {
+            domain,
+            address,
+            statement,
+            uri: uri.ok_or_else(|| invalid("missing URI"))?,
+            version: version.ok_or_else(|| invalid("missing version"))?,
+            chain_id: chain_id.ok_or_else(|| invalid("missing chain id"))?,
+            nonce: nonce.ok_or_else(|| invalid("missing nonce"))?,
+            issued_at: issued_at.ok_or_else(|| invalid("missing issued at"))?,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        };
+
+        if message.version != "1" {
+            return Err(invalid("unsupported version"));
+        }
+
+        if message.nonce.len() < 8 || !message.nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid("bad nonce"));
+        }
+
+        Ok(message)
+    }
+
+    /// Checks the message is bound to this service and currently valid.
+    pub fn validate(&self, expected_domain: &str, expected_chain_id: u64, now: DateTime<Utc>) -> Result<(), AppError> {
+        if self.domain != expected_domain {
+            warn!("SIWE domain mismatch: {} != {}", self.domain, expected_domain);
+            return Err(AppError::Authentication("SIWE domain mismatch".to_string()));
+        }
+
+        if self.chain_id != expected_chain_id {
+            return Err(AppError::Authentication("SIWE chain id mismatch".to_string()));
+        }
+
+        if self.expiration_time.is_some_and(|expires| expires <= now) {
+            return Err(AppError::Authentication("SIWE message expired".to_string()));
+        }
+
+        if self.not_before.is_some_and(|not_before| not_before > now) {
+            return Err(AppError::Authentication("SIWE message not yet valid".to_string()));
+        }
+
+        // Messages without an explicit expiry are only honoured while the nonce would be
+        if self.issued_at > now + Duration::minutes(1) || self.issued_at < now - Duration::minutes(NONCE_TTL_MINUTES) {
+            return Err(AppError::Authentication("SIWE issued at out of range".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Recovers the EIP-191 `personal_sign` signer of `message` and compares it
+/// with `expected`.
+pub fn verify_signature(message: &str, signature: &str, expected: Address) -> Result<(), AppError> {
+    let signature = Signature::from_str(signature.trim_start_matches("0x"))
+        .map_err(|_| AppError::InvalidInput("Malformed signature".to_string()))?;
+
+    let recovered = signature
+        .recover(message)
+        .map_err(|_| AppError::Authentication("Signature recovery failed".to_string()))?;
+
+    if recovered != expected {
+        warn!("SIWE signer mismatch: recovered {:?}, expected {:?}", recovered, expected);
+        return Err(AppError::Authentication("Signature does not match address".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Lower-case hex form used for storage and comparisons.
+pub fn format_address(address: &Address) -> String {
+    format!("{:#x}", address)
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example messages from EIP-4361
+    const WITH_STATEMENT: &str = "service.org wants you to sign in with your Ethereum account:
+0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2
+
+I accept the ServiceOrg Terms of Service: https://service.org/tos
+
+URI: https://service.org/login
+Version: 1
+Chain ID: 1
+Nonce: 32891756
+Issued At: 2021-09-30T16:25:24Z
+Resources:
+- ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/
+- https://example.com/my-web2-claim.json";
+
+    const WITHOUT_STATEMENT: &str = "service.org wants you to sign in with your Ethereum account:
+0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2
+
+
+URI: https://service.org/login
+Version: 1
+Chain ID: 1
+Nonce: 32891756
+Issued At: 2021-09-30T16:25:24Z
+Resources:
+- ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/
+- https://example.com/my-web2-claim.json";
+
+    #[test]
+    fn parses_message_with_statement() {
+        let message = SiweMessage::parse(WITH_STATEMENT).unwrap();
+
+        assert_eq!(message.domain, "service.org");
+        assert_eq!(format_address(&message.address), "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+        assert_eq!(
+            message.statement.as_deref(),
+            Some("I accept the ServiceOrg Terms of Service: https://service.org/tos")
+        );
+        assert_eq!(message.uri, "https://service.org/login");
+        assert_eq!(message.chain_id, 1);
+        assert_eq!(message.nonce, "32891756");
+        assert_eq!(message.issued_at, parse_timestamp("2021-09-30T16:25:24Z").unwrap());
+        assert_eq!(message.resources.len(), 2);
+    }
+
+    #[test]
+    fn parses_message_without_statement() {
+        let message = SiweMessage::parse(WITHOUT_STATEMENT).unwrap();
+
+        assert_eq!(message.statement, None);
+        assert_eq!(message.uri, "https://service.org/login");
+        assert_eq!(message.nonce, "32891756");
+        assert_eq!(
+            message.resources,
+            vec![
+                "ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/".to_string(),
+                "https://example.com/my-web2-claim.json".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_statement_without_following_blank_line() {
+        let message = WITH_STATEMENT.replace("service.org/tos\n\n", "service.org/tos\n");
+        assert!(SiweMessage::parse(&message).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_separator_before_uri() {
+        let message = WITHOUT_STATEMENT.replace("\n\n\nURI", "\n\nURI");
+        assert!(SiweMessage::parse(&message).is_err());
+    }
+}
@@ -14,6 +14,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct SmartContractService {
@@ -174,12 +175,15 @@ pub enum ContractStatus {
 
 pub async fn deploy_contract(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<DeployContractRequest>,
 ) -> Result<Json<ContractDeploymentResponse>, StatusCode> {
     info!("Deploying smart contract: {}", payload.contract_name);
 
+    auth_user.require_role(&[UserRole::Admin])?;
+
     let contract_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Deploy contract to blockchain
     let deployment_result = deploy_to_blockchain(
@@ -243,12 +247,13 @@ pub async fn deploy_contract(
 
 pub async fn execute_contract_function(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<ExecuteContractRequest>,
 ) -> Result<Json<ContractExecutionResponse>, StatusCode> {
     info!("Executing contract function: {} on {}", payload.function_name, payload.contract_address);
 
     let execution_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Execute contract function
     let execution_result = execute_contract_function_on_blockchain(
@@ -309,13 +314,14 @@ pub async fn execute_contract_function(
 
 pub async fn create_shipment_contract(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateShipmentContractRequest>,
 ) -> Result<Json<ShipmentContractResponse>, StatusCode> {
     info!("Creating shipment smart contract");
 
     let contract_id = Uuid::new_v4();
     let shipment_id = Uuid::new_v4();
-    let user_id = Uuid::new_v4(); // In real implementation, get from authenticated user
+    let user_id = auth_user.user_id;
 
     // Parse delivery deadline
     let delivery_deadline = chrono::DateTime::parse_from_rfc3339(&payload.delivery_deadline)
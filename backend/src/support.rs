@@ -6,6 +6,7 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +15,7 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
 
 #[derive(Debug, Clone)]
 pub struct SupportService {
@@ -46,7 +48,6 @@ pub struct UpdateTicketRequest {
 
 #[derive(Debug, Deserialize)]
 pub struct StartChatRequest {
-    pub user_id: String,
     pub initial_message: Option<String>,
 }
 
@@ -59,7 +60,6 @@ pub struct SendMessageRequest {
 
 #[derive(Debug, Deserialize)]
 pub struct StartVideoCallRequest {
-    pub user_id: String,
     pub agent_id: String,
     pub call_type: String, // audio, video, screen_share
 }
@@ -113,44 +113,45 @@ pub struct VideoCallResponse {
 
 pub async fn get_tickets(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<TicketResponse>>, StatusCode> {
     info!("Fetching support tickets");
 
-    let user_id = params.get("user_id").and_then(|v| v.as_str());
-    let agent_id = params.get("agent_id").and_then(|v| v.as_str());
+    let parse_id = |key: &str| {
+        params
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|id| Uuid::parse_str(id).map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()
+    };
+
+    // Customers only ever see their own tickets; admins may filter freely
+    let (user_id, agent_id) = if auth_user.role == UserRole::Admin {
+        (parse_id("user_id")?, parse_id("agent_id")?)
+    } else {
+        (Some(auth_user.user_id), None)
+    };
     let status = params.get("status").and_then(|v| v.as_str());
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(50);
 
-    let mut query = "SELECT * FROM support_tickets WHERE 1=1".to_string();
-    let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(uid) = user_id {
-        query.push_str(&format!(" AND user_id = ${}", param_count));
-        bind_values.push(Box::new(Uuid::parse_str(uid).map_err(|_| StatusCode::BAD_REQUEST)?));
-        param_count += 1;
-    }
-
-    if let Some(aid) = agent_id {
-        query.push_str(&format!(" AND agent_id = ${}", param_count));
-        bind_values.push(Box::new(Uuid::parse_str(aid).map_err(|_| StatusCode::BAD_REQUEST)?));
-        param_count += 1;
-    }
-
-    if let Some(st) = status {
-        query.push_str(&format!(" AND status = ${}", param_count));
-        bind_values.push(Box::new(st.to_string()));
-        param_count += 1;
-    }
-
-    query.push_str(&format!(" ORDER BY created_at DESC LIMIT ${}", param_count));
-    bind_values.push(Box::new(limit as i64));
-
-    let rows = sqlx::query(&query)
-        .fetch_all(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = sqlx::query(
+        r#"
+        SELECT * FROM support_tickets
+        WHERE ($1::uuid IS NULL OR user_id = $1)
+          AND ($2::uuid IS NULL OR agent_id = $2)
+          AND ($3::text IS NULL OR status::text = $3)
+        ORDER BY created_at DESC
+        LIMIT $4
+        "#,
+    )
+    .bind(user_id)
+    .bind(agent_id)
+    .bind(status)
+    .bind(limit as i64)
+    .fetch_all(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let tickets = rows
         .into_iter()
@@ -176,6 +177,7 @@ pub async fn get_tickets(
 
 pub async fn create_ticket(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<CreateTicketRequest>,
 ) -> Result<Json<TicketResponse>, StatusCode> {
     info!("Creating support ticket: {}", payload.title);
@@ -201,9 +203,7 @@ pub async fn create_ticket(
     let ticket_id = Uuid::new_v4();
     let now = Utc::now();
 
-    // For demo purposes, we'll use a hardcoded user_id
-    // In a real implementation, this would come from the authenticated user
-    let user_id = Uuid::new_v4();
+    let user_id = auth_user.user_id;
 
     sqlx::query(
         r#"
@@ -250,6 +250,7 @@ pub async fn create_ticket(
 
 pub async fn get_ticket(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(ticket_id): Path<String>,
 ) -> Result<Json<TicketResponse>, StatusCode> {
     info!("Fetching ticket: {}", ticket_id);
@@ -267,6 +268,8 @@ pub async fn get_ticket(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    ensure_ticket_access(&row, &auth_user)?;
+
     let response = TicketResponse {
         id: ticket_id,
         user_id: row.get::<Uuid, _>("user_id").to_string(),
@@ -288,6 +291,7 @@ pub async fn get_ticket(
 
 pub async fn update_ticket(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(ticket_id): Path<String>,
     Json(payload): Json<UpdateTicketRequest>,
 ) -> Result<Json<TicketResponse>, StatusCode> {
@@ -295,6 +299,21 @@ pub async fn update_ticket(
 
     let id = Uuid::parse_str(&ticket_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
+    let row = sqlx::query("SELECT * FROM support_tickets WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    ensure_ticket_access(&row, &auth_user)?;
+
+    // The customer may only rate their ticket; triage is for support staff
+    let is_staff = auth_user.role == UserRole::Admin
+        || row.get::<Option<Uuid>, _>("agent_id") == Some(auth_user.user_id);
+    if !is_staff && (payload.status.is_some() || payload.priority.is_some() || payload.agent_id.is_some()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     let mut update_fields = Vec::new();
     let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
     let mut param_count = 1;
@@ -363,16 +382,17 @@ pub async fn update_ticket(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     // Return updated ticket
-    get_ticket(state, Path(ticket_id)).await
+    get_ticket(State(state), auth_user, Path(ticket_id)).await
 }
 
 pub async fn start_chat(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<StartChatRequest>,
 ) -> Result<Json<ChatSessionResponse>, StatusCode> {
-    info!("Starting chat session for user: {}", payload.user_id);
+    info!("Starting chat session for user: {}", auth_user.user_id);
 
-    let user_id = Uuid::parse_str(&payload.user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let user_id = auth_user.user_id;
     let session_id = Uuid::new_v4();
     let now = Utc::now();
 
@@ -382,7 +402,7 @@ pub async fn start_chat(
 
     let response = ChatSessionResponse {
         id: session_id.to_string(),
-        user_id: payload.user_id,
+        user_id: user_id.to_string(),
         agent_id: None,
         status: "active".to_string(),
         created_at: now.to_rfc3339(),
@@ -417,11 +437,13 @@ pub async fn start_chat(
 
 pub async fn get_messages(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(session_id): Path<String>,
 ) -> Result<Json<Vec<MessageResponse>>, StatusCode> {
     info!("Fetching messages for session: {}", session_id);
 
     let id = Uuid::parse_str(&session_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    ensure_chat_participant(&state, id, &auth_user).await?;
 
     let rows = sqlx::query(
         "SELECT * FROM chat_messages WHERE chat_session_id = $1 ORDER BY created_at ASC"
@@ -449,18 +471,19 @@ pub async fn get_messages(
 
 pub async fn send_message(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(session_id): Path<String>,
     Json(payload): Json<SendMessageRequest>,
 ) -> Result<Json<MessageResponse>, StatusCode> {
     info!("Sending message to session: {}", session_id);
 
     let id = Uuid::parse_str(&session_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    ensure_chat_participant(&state, id, &auth_user).await?;
+
     let message_id = Uuid::new_v4();
     let now = Utc::now();
-
-    // For demo purposes, we'll use a hardcoded sender_id
-    // In a real implementation, this would come from the authenticated user
-    let sender_id = Uuid::new_v4();
+    let sender_id = auth_user.user_id;
+    let sender_type = if auth_user.role == UserRole::Admin { SenderType::Agent } else { SenderType::Customer };
 
     let message_type = match payload.message_type.as_deref().unwrap_or("text") {
         "text" => MessageType::Text,
@@ -482,7 +505,7 @@ pub async fn send_message(
     .bind(message_id)
     .bind(id)
     .bind(sender_id)
-    .bind(&SenderType::Customer)
+    .bind(&sender_type)
     .bind(&payload.message)
     .bind(&message_type)
     .bind(&payload.attachments.unwrap_or(serde_json::json!([])))
@@ -497,7 +520,7 @@ pub async fn send_message(
     let response = MessageResponse {
         id: message_id.to_string(),
         sender_id: sender_id.to_string(),
-        sender_type: "customer".to_string(),
+        sender_type: format!("{:?}", sender_type).to_lowercase(),
         message: payload.message,
         message_type: format!("{:?}", message_type).to_lowercase(),
         attachments: payload.attachments.unwrap_or(serde_json::json!([])),
@@ -511,11 +534,12 @@ pub async fn send_message(
 
 pub async fn start_video_call(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<StartVideoCallRequest>,
 ) -> Result<Json<VideoCallResponse>, StatusCode> {
-    info!("Starting video call between user: {} and agent: {}", payload.user_id, payload.agent_id);
+    info!("Starting video call between user: {} and agent: {}", auth_user.user_id, payload.agent_id);
 
-    let user_id = Uuid::parse_str(&payload.user_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let user_id = auth_user.user_id;
     let agent_id = Uuid::parse_str(&payload.agent_id).map_err(|_| StatusCode::BAD_REQUEST)?;
     let call_id = Uuid::new_v4();
     let now = Utc::now();
@@ -529,7 +553,7 @@ pub async fn start_video_call(
 
     let response = VideoCallResponse {
         id: call_id.to_string(),
-        user_id: payload.user_id,
+        user_id: user_id.to_string(),
         agent_id: payload.agent_id,
         call_type: payload.call_type,
         status: "active".to_string(),
@@ -542,6 +566,50 @@ pub async fn start_video_call(
     Ok(Json(response))
 }
 
+/// Tickets are visible to the customer who opened them, the assigned agent
+/// and admins.
+fn ensure_ticket_access(row: &sqlx::postgres::PgRow, auth_user: &AuthUser) -> Result<(), StatusCode> {
+    if auth_user.role == UserRole::Admin
+        || row.get::<Uuid, _>("user_id") == auth_user.user_id
+        || row.get::<Option<Uuid>, _>("agent_id") == Some(auth_user.user_id)
+    {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Chat sessions aren't stored on their own, so a session belongs to the
+/// customer who wrote its first message. Admins answer as agents.
+async fn ensure_chat_participant(
+    state: &crate::AppState,
+    session_id: Uuid,
+    auth_user: &AuthUser,
+) -> Result<(), StatusCode> {
+    if auth_user.role == UserRole::Admin {
+        return Ok(());
+    }
+
+    let owner = sqlx::query(
+        r#"
+        SELECT sender_id FROM chat_messages
+        WHERE chat_session_id = $1 AND sender_type = 'customer'
+        ORDER BY created_at ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(session_id)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(|row| row.get::<Uuid, _>("sender_id"));
+
+    match owner {
+        Some(owner) if owner != auth_user.user_id => Err(StatusCode::FORBIDDEN),
+        _ => Ok(()),
+    }
+}
+
 pub async fn get_knowledge_base(
     State(state): State<crate::AppState>,
     Query(params): Query<serde_json::Value>,
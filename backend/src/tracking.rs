@@ -6,6 +6,7 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +15,26 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
+use crate::verification::VerifiedUser;
+use crate::kyc::{fiat_value, require_kyc_for_amount};
+use crate::utils::AppError;
+use crate::realtime::ShipmentUpdate;
+use crate::geofencing::{address_coordinates, process_location};
+use crate::dispatch::{dispatch_shipment, record_driver_location};
+use crate::eta::{load_eta, recalculate as recalculate_eta, EtaResponse};
+use crate::proof_of_delivery::{self, ProofOfDeliveryRequest};
+use crate::quotes::apply_quote;
+use crate::returns;
+use crate::parcels::{self, ParcelRequest, ParcelResponse};
+use crate::consolidation;
+use crate::delivery_slots;
+use crate::delivery_exceptions;
+
+// Uppercase letters and digits without look-alikes (0/O, 1/I/L)
+const TRACKING_NUMBER_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+const TRACKING_NUMBER_LENGTH: usize = 12;
+const TRACKING_NUMBER_ATTEMPTS: usize = 5;
 
 #[derive(Debug, Clone)]
 pub struct TrackingService {
@@ -41,6 +62,30 @@ pub struct CreateShipmentRequest {
     pub delivery_address: serde_json::Value,
     pub priority: String,
     pub estimated_delivery: Option<String>,
+    pub pickup_window: Option<TimeWindowRequest>,
+    pub delivery_window: Option<TimeWindowRequest>,
+    /// Price-locked quote from `/api/quotes` to bill this shipment at.
+    pub quote_id: Option<String>,
+    /// Cash to collect from the receiver on delivery; must match the quote's.
+    pub cod_amount: Option<f64>,
+    /// The boxes making up the shipment; their weights must add up to
+    /// `weight`. Omit for a single parcel.
+    #[serde(default)]
+    pub parcels: Vec<ParcelRequest>,
+    /// Slot from `/api/delivery-slots` to deliver in; sets the delivery
+    /// window, so the two can't both be given.
+    pub delivery_slot_id: Option<String>,
+    pub delivery_instructions: Option<String>,
+    /// Whether the driver may leave the shipment without a signature
+    #[serde(default)]
+    pub leave_authorized: bool,
+}
+
+/// RFC 3339 bounds within which a stop should be visited.
+#[derive(Debug, Deserialize)]
+pub struct TimeWindowRequest {
+    pub start: String,
+    pub end: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,6 +102,23 @@ pub struct UpdateLocationRequest {
 pub struct UpdateStatusRequest {
     pub status: String,
     pub notes: Option<String>,
+    /// Required when moving to `delivered`
+    pub proof_of_delivery: Option<ProofOfDeliveryRequest>,
+    /// Parcels handed over on delivery; defaults to all of them
+    pub parcel_ids: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShipmentEventResponse {
+    pub id: String,
+    pub event_type: String,
+    pub from_status: Option<String>,
+    pub to_status: Option<String>,
+    pub actor_id: Option<String>,
+    pub actor_role: Option<String>,
+    pub notes: Option<String>,
+    pub metadata: serde_json::Value,
+    pub created_at: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -79,14 +141,23 @@ pub struct ShipmentResponse {
     pub description: String,
     pub value: f64,
     pub currency: String,
+    pub shipping_cost: Option<f64>,
+    pub shipping_cost_currency: Option<String>,
+    pub quote_id: Option<String>,
+    pub cod_amount: Option<f64>,
     pub pickup_address: serde_json::Value,
     pub delivery_address: serde_json::Value,
     pub estimated_delivery: Option<String>,
     pub actual_delivery: Option<String>,
+    pub eta: Option<EtaResponse>,
     pub nft_token_id: Option<String>,
     pub blockchain_tx_hash: Option<String>,
     pub current_location: Option<LocationResponse>,
     pub location_history: Vec<LocationResponse>,
+    pub parcels: Vec<ParcelResponse>,
+    pub delivery_slot_id: Option<String>,
+    pub delivery_instructions: Option<String>,
+    pub leave_authorized: bool,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -114,72 +185,63 @@ pub struct NFTConversionResponse {
 
 pub async fn create_shipment(
     State(state): State<crate::AppState>,
+    VerifiedUser(auth_user): VerifiedUser,
     Json(payload): Json<CreateShipmentRequest>,
 ) -> Result<Json<ShipmentResponse>, StatusCode> {
     info!("Creating shipment for sender: {}", payload.sender_id);
 
-    // Validate input
-    if payload.weight <= 0.0 || payload.value < 0.0 {
-        return Err(StatusCode::BAD_REQUEST);
+    // Only shipping companies and admins may create shipments on behalf of another sender
+    let sender_id = Uuid::parse_str(&payload.sender_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if sender_id != auth_user.user_id {
+        auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
     }
 
-    // Parse priority
-    let priority = match payload.priority.as_str() {
-        "low" => ShipmentPriority::Low,
-        "medium" => ShipmentPriority::Medium,
-        "high" => ShipmentPriority::High,
-        "urgent" => ShipmentPriority::Urgent,
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+    // Validate input
+    let shipment = validate_shipment_request(&payload).map_err(|errors| {
+        warn!("Rejected shipment request: {}", errors.join("; "));
+        StatusCode::BAD_REQUEST
+    })?;
 
-    // Parse estimated delivery
-    let estimated_delivery = if let Some(date_str) = payload.estimated_delivery {
-        Some(chrono::DateTime::parse_from_rfc3339(&date_str)
-            .map_err(|_| StatusCode::BAD_REQUEST)?
-            .with_timezone(&Utc))
-    } else {
-        None
-    };
+    // The KYC threshold is a fiat amount
+    let fiat_amount = fiat_value(&payload.currency, payload.value).await;
+    require_kyc_for_amount(&state, sender_id, fiat_amount).await?;
 
-    let shipment_id = Uuid::new_v4();
-    let tracking_number = generate_tracking_number();
     let now = Utc::now();
-
-    // Create shipment
-    sqlx::query(
-        r#"
-        INSERT INTO shipments (
-            id, tracking_number, sender_id, receiver_id, status, priority,
-            weight, dimensions, description, value, currency, pickup_address,
-            delivery_address, estimated_delivery, created_at, updated_at
-        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
-        "#,
-    )
-    .bind(shipment_id)
-    .bind(&tracking_number)
-    .bind(Uuid::parse_str(&payload.sender_id).map_err(|_| StatusCode::BAD_REQUEST)?)
-    .bind(Uuid::parse_str(&payload.receiver_id).map_err(|_| StatusCode::BAD_REQUEST)?)
-    .bind(&ShipmentStatus::Pending)
-    .bind(&priority)
-    .bind(payload.weight)
-    .bind(&payload.dimensions)
-    .bind(&payload.description)
-    .bind(payload.value)
-    .bind(&payload.currency)
-    .bind(&payload.pickup_address)
-    .bind(&payload.delivery_address)
-    .bind(estimated_delivery)
-    .bind(now)
-    .bind(now)
-    .execute(&state.db.pool)
-    .await
-    .map_err(|e| {
-        error!("Database error creating shipment: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let mut tx = state.db.pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (shipment_id, tracking_number) = insert_shipment(&mut tx, &state, &payload, &shipment, &auth_user, now).await?;
+    let (shipping_cost, shipping_cost_currency) = match shipment.quote_id {
+        Some(_) => {
+            let row = sqlx::query(
+                "SELECT shipping_cost::float8 AS shipping_cost, shipping_cost_currency FROM shipments WHERE id = $1",
+            )
+            .bind(shipment_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            (
+                row.get::<Option<f64>, _>("shipping_cost"),
+                row.get::<Option<String>, _>("shipping_cost_currency"),
+            )
+        }
+        None => (None, None),
+    };
+    let shipment_parcels = parcels::load_parcels(&mut *tx, shipment_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     info!("Shipment created successfully: {}", shipment_id);
 
+    let eta = recalculate_eta(&state, shipment_id).await.unwrap_or_else(|e| {
+        warn!("Could not estimate arrival for shipment {}: {}", shipment_id, e);
+        None
+    });
+
+    // Offer it to a driver straight away; dispatchers can retry later
+    if let Err(e) = dispatch_shipment(&state, shipment_id, &[]).await {
+        warn!("Automatic dispatch failed for shipment {}: {}", shipment_id, e);
+    }
+
     // Return shipment response
     Ok(Json(ShipmentResponse {
         id: shipment_id.to_string(),
@@ -194,14 +256,23 @@ pub async fn create_shipment(
         description: payload.description,
         value: payload.value,
         currency: payload.currency,
+        shipping_cost,
+        shipping_cost_currency,
+        quote_id: payload.quote_id,
+        cod_amount: payload.cod_amount,
         pickup_address: payload.pickup_address,
         delivery_address: payload.delivery_address,
         estimated_delivery: payload.estimated_delivery,
         actual_delivery: None,
+        eta,
         nft_token_id: None,
         blockchain_tx_hash: None,
         current_location: None,
         location_history: Vec::new(),
+        parcels: shipment_parcels,
+        delivery_slot_id: shipment.delivery_slot_id.map(|id| id.to_string()),
+        delivery_instructions: shipment.delivery_instructions,
+        leave_authorized: payload.leave_authorized,
         created_at: now.to_rfc3339(),
         updated_at: now.to_rfc3339(),
     }))
@@ -209,6 +280,7 @@ pub async fn create_shipment(
 
 pub async fn get_shipment(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(shipment_id): Path<String>,
 ) -> Result<Json<ShipmentResponse>, StatusCode> {
     info!("Fetching shipment: {}", shipment_id);
@@ -216,7 +288,7 @@ pub async fn get_shipment(
     let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
     // Get shipment
-    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+    let shipment_row = sqlx::query("SELECT *, shipping_cost::float8 AS shipping_cost_amount FROM shipments WHERE id = $1")
         .bind(id)
         .fetch_optional(&state.db.pool)
         .await
@@ -227,6 +299,10 @@ pub async fn get_shipment(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    if !can_access_shipment(&auth_user, &shipment_row) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     // Get current location
     let current_location = sqlx::query(
         "SELECT * FROM location_updates WHERE shipment_id = $1 ORDER BY timestamp DESC LIMIT 1"
@@ -269,29 +345,43 @@ pub async fn get_shipment(
         })
         .collect();
 
+    let eta = load_eta(&state, id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let shipment_parcels = parcels::load_parcels(&state.db.pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
     let response = ShipmentResponse {
         id: shipment_id,
         tracking_number: shipment_row.get::<String, _>("tracking_number"),
         sender_id: shipment_row.get::<Uuid, _>("sender_id").to_string(),
         receiver_id: shipment_row.get::<Uuid, _>("receiver_id").to_string(),
         driver_id: shipment_row.get::<Option<Uuid>, _>("driver_id").map(|id| id.to_string()),
-        status: format!("{:?}", shipment_row.get::<ShipmentStatus, _>("status")).to_lowercase(),
+        status: shipment_row.get::<ShipmentStatus, _>("status").as_str().to_string(),
         priority: format!("{:?}", shipment_row.get::<ShipmentPriority, _>("priority")).to_lowercase(),
         weight: shipment_row.get::<f64, _>("weight"),
         dimensions: shipment_row.get::<serde_json::Value, _>("dimensions"),
         description: shipment_row.get::<String, _>("description"),
         value: shipment_row.get::<f64, _>("value"),
         currency: shipment_row.get::<String, _>("currency"),
+        shipping_cost: shipment_row.get::<Option<f64>, _>("shipping_cost_amount"),
+        shipping_cost_currency: shipment_row.get::<Option<String>, _>("shipping_cost_currency"),
+        quote_id: shipment_row.get::<Option<Uuid>, _>("quote_id").map(|id| id.to_string()),
+        cod_amount: shipment_row.get::<Option<f64>, _>("cod_amount"),
         pickup_address: shipment_row.get::<serde_json::Value, _>("pickup_address"),
         delivery_address: shipment_row.get::<serde_json::Value, _>("delivery_address"),
         estimated_delivery: shipment_row.get::<Option<chrono::DateTime<Utc>>, _>("estimated_delivery")
             .map(|dt| dt.to_rfc3339()),
         actual_delivery: shipment_row.get::<Option<chrono::DateTime<Utc>>, _>("actual_delivery")
             .map(|dt| dt.to_rfc3339()),
+        eta,
         nft_token_id: shipment_row.get::<Option<String>, _>("nft_token_id"),
         blockchain_tx_hash: shipment_row.get::<Option<String>, _>("blockchain_tx_hash"),
         current_location,
         location_history,
+        parcels: shipment_parcels,
+        delivery_slot_id: shipment_row.get::<Option<Uuid>, _>("delivery_slot_id").map(|id| id.to_string()),
+        delivery_instructions: shipment_row.get::<Option<String>, _>("delivery_instructions"),
+        leave_authorized: shipment_row.get::<bool, _>("leave_authorized"),
         created_at: shipment_row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
         updated_at: shipment_row.get::<chrono::DateTime<Utc>, _>("updated_at").to_rfc3339(),
     };
@@ -301,23 +391,28 @@ pub async fn get_shipment(
 
 pub async fn update_location(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(shipment_id): Path<String>,
     Json(payload): Json<UpdateLocationRequest>,
 ) -> Result<Json<LocationResponse>, StatusCode> {
     info!("Updating location for shipment: {}", shipment_id);
 
+    auth_user.require_role(&[UserRole::Driver])?;
+
     let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
 
     // Verify shipment exists
-    let shipment_exists = sqlx::query("SELECT id FROM shipments WHERE id = $1")
+    let driver_id = sqlx::query("SELECT driver_id FROM shipments WHERE id = $1")
         .bind(id)
         .fetch_optional(&state.db.pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .is_some();
+        .ok_or(StatusCode::NOT_FOUND)?
+        .get::<Option<Uuid>, _>("driver_id");
 
-    if !shipment_exists {
-        return Err(StatusCode::NOT_FOUND);
+    // Only the assigned driver may report locations
+    if driver_id != Some(auth_user.user_id) {
+        return Err(StatusCode::FORBIDDEN);
     }
 
     // Create location update
@@ -366,6 +461,24 @@ pub async fn update_location(
         timestamp: now.to_rfc3339(),
     };
 
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(id, "location", serde_json::to_value(&response).unwrap_or_default()))
+        .await;
+
+    if let Err(e) = record_driver_location(&state, auth_user.user_id, payload.latitude, payload.longitude).await {
+        warn!("Could not record position of driver {}: {}", auth_user.user_id, e);
+    }
+
+    // The position is already stored; a geofence failure shouldn't reject it
+    if let Err(e) = process_location(&state, id, payload.latitude, payload.longitude, payload.accuracy, &auth_user).await {
+        error!("Geofence evaluation failed for shipment {}: {}", shipment_id, e);
+    }
+
+    if let Err(e) = recalculate_eta(&state, id).await {
+        error!("ETA recalculation failed for shipment {}: {}", shipment_id, e);
+    }
+
     info!("Location updated successfully for shipment: {}", shipment_id);
 
     Ok(Json(response))
@@ -373,59 +486,150 @@ pub async fn update_location(
 
 pub async fn update_status(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(shipment_id): Path<String>,
     Json(payload): Json<UpdateStatusRequest>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<Json<serde_json::Value>, AppError> {
     info!("Updating status for shipment: {}", shipment_id);
 
-    let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    auth_user
+        .require_role(&[UserRole::Driver, UserRole::ShippingCompany, UserRole::Admin])
+        .map_err(|_| AppError::Authorization("Not allowed to change shipment status".to_string()))?;
 
-    // Parse status
-    let status = match payload.status.as_str() {
-        "pending" => ShipmentStatus::Pending,
-        "picked_up" => ShipmentStatus::PickedUp,
-        "in_transit" => ShipmentStatus::InTransit,
-        "out_for_delivery" => ShipmentStatus::OutForDelivery,
-        "delivered" => ShipmentStatus::Delivered,
-        "returned" => ShipmentStatus::Returned,
-        "cancelled" => ShipmentStatus::Cancelled,
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+    let id = Uuid::parse_str(&shipment_id)
+        .map_err(|_| AppError::InvalidInput("Invalid shipment id".to_string()))?;
 
-    let now = Utc::now();
-    let mut actual_delivery = None;
+    let status = ShipmentStatus::parse(&payload.status)
+        .ok_or_else(|| AppError::InvalidInput(format!("Unknown status '{}'", payload.status)))?;
 
-    // Set actual delivery time if status is delivered
-    if matches!(status, ShipmentStatus::Delivered) {
-        actual_delivery = Some(now);
+    let notes = payload.notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty());
+
+    if payload.parcel_ids.is_some() && status != ShipmentStatus::Delivered {
+        return Err(AppError::InvalidInput("parcel_ids only apply to deliveries".to_string()));
     }
 
-    // Update shipment status
-    sqlx::query(
-        "UPDATE shipments SET status = $1, actual_delivery = $2, updated_at = $3 WHERE id = $4"
+    // Checked before the evidence so other drivers can't spend delivery PIN attempts
+    ensure_assigned_driver(&state.db.pool, id, &auth_user).await?;
+
+    let evidence = if status == ShipmentStatus::Delivered {
+        let request = payload.proof_of_delivery.as_ref().ok_or_else(|| {
+            AppError::Validation("Proof of delivery is required to mark a shipment delivered".to_string())
+        })?;
+        Some(proof_of_delivery::prepare(&state, id, request).await?)
+    } else {
+        None
+    };
+
+    let mut tx = state.db.pool.begin().await?;
+    ensure_assigned_driver(&mut *tx, id, &auth_user).await?;
+    consolidation::ensure_not_consolidated(&mut tx, id).await?;
+    let previous = transition_shipment_status(
+        &mut tx,
+        id,
+        status,
+        Some(&auth_user),
+        notes,
+        serde_json::json!({}),
     )
-    .bind(&status)
-    .bind(actual_delivery)
-    .bind(now)
-    .bind(id)
-    .execute(&state.db.pool)
-    .await
-    .map_err(|e| {
-        error!("Database error updating status: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .await?;
+    consolidation::follow_master(&mut tx, id, status, &auth_user).await?;
+    let proof = match evidence {
+        Some(evidence) => Some(proof_of_delivery::record(&mut tx, &state, &auth_user, evidence).await?),
+        None => None,
+    };
+    let delivered_parcels = match status {
+        ShipmentStatus::Delivered => {
+            Some(parcels::record_delivery(&mut tx, id, payload.parcel_ids.as_deref(), &auth_user).await?)
+        }
+        _ => None,
+    };
+    let return_receipt = match status {
+        ShipmentStatus::Delivered => returns::record_receipt(&mut tx, id, &auth_user).await?,
+        _ => None,
+    };
+    tx.commit().await?;
+
+    state
+        .tracking_hub
+        .publish(ShipmentUpdate::new(id, "status", serde_json::json!({
+            "previous_status": previous.as_str(),
+            "status": status.as_str(),
+            "notes": notes,
+            "evidence_hash": proof.as_ref().map(|proof| proof.evidence_hash.as_str())
+        })))
+        .await;
+
+    // Pending shipments carry handling time and finished ones have no ETA
+    if let Err(e) = recalculate_eta(&state, id).await {
+        warn!("ETA recalculation failed for shipment {}: {}", shipment_id, e);
+    }
+
+    if let Some(receipt) = return_receipt {
+        returns::complete_receipt(&state, receipt).await;
+    }
 
-    info!("Status updated successfully for shipment: {}", shipment_id);
+    info!(
+        "Status updated for shipment {}: {} -> {}",
+        shipment_id,
+        previous.as_str(),
+        status.as_str()
+    );
 
     Ok(Json(serde_json::json!({
-        "status": payload.status,
-        "updated_at": now.to_rfc3339(),
-        "notes": payload.notes
+        "previous_status": previous.as_str(),
+        "status": status.as_str(),
+        "updated_at": Utc::now().to_rfc3339(),
+        "notes": notes,
+        "proof_of_delivery": proof,
+        "parcels": delivered_parcels
     })))
 }
 
+pub async fn get_timeline(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Path(shipment_id): Path<String>,
+) -> Result<Json<Vec<ShipmentEventResponse>>, StatusCode> {
+    let id = Uuid::parse_str(&shipment_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let shipment_row = sqlx::query("SELECT * FROM shipments WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if !can_access_shipment(&auth_user, &shipment_row) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let rows = sqlx::query("SELECT * FROM shipment_events WHERE shipment_id = $1 ORDER BY created_at ASC, sequence ASC")
+        .bind(id)
+        .fetch_all(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let events = rows
+        .iter()
+        .map(|row| ShipmentEventResponse {
+            id: row.get::<Uuid, _>("id").to_string(),
+            event_type: row.get::<String, _>("event_type"),
+            from_status: row.get::<Option<ShipmentStatus>, _>("from_status").map(|s| s.as_str().to_string()),
+            to_status: row.get::<Option<ShipmentStatus>, _>("to_status").map(|s| s.as_str().to_string()),
+            actor_id: row.get::<Option<Uuid>, _>("actor_id").map(|id| id.to_string()),
+            actor_role: row.get::<Option<String>, _>("actor_role"),
+            notes: row.get::<Option<String>, _>("notes"),
+            metadata: row.get::<serde_json::Value, _>("metadata"),
+            created_at: row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(events))
+}
+
 pub async fn convert_to_nft(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Path(shipment_id): Path<String>,
     Json(payload): Json<ConvertToNFTRequest>,
 ) -> Result<Json<NFTConversionResponse>, StatusCode> {
@@ -445,6 +649,10 @@ pub async fn convert_to_nft(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
+    if !can_access_shipment(&auth_user, &shipment_row) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     let status: ShipmentStatus = shipment_row.get("status");
     if !matches!(status, ShipmentStatus::Delivered) {
         return Err(StatusCode::BAD_REQUEST);
@@ -487,17 +695,20 @@ pub async fn convert_to_nft(
 
 pub async fn search_shipments(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Query(params): Query<serde_json::Value>,
 ) -> Result<Json<Vec<ShipmentResponse>>, StatusCode> {
     info!("Searching shipments");
 
+    auth_user.require_role(&[UserRole::ShippingCompany, UserRole::Admin])?;
+
     let tracking_number = params.get("tracking_number").and_then(|v| v.as_str());
     let sender_id = params.get("sender_id").and_then(|v| v.as_str());
     let receiver_id = params.get("receiver_id").and_then(|v| v.as_str());
     let status = params.get("status").and_then(|v| v.as_str());
     let limit = params.get("limit").and_then(|v| v.as_u64()).unwrap_or(50);
 
-    let mut query = "SELECT * FROM shipments WHERE 1=1".to_string();
+    let mut query = "SELECT *, shipping_cost::float8 AS shipping_cost_amount FROM shipments WHERE 1=1".to_string();
     let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
     let mut param_count = 1;
 
@@ -556,29 +767,40 @@ pub async fn search_shipments(
             timestamp: row.get::<chrono::DateTime<Utc>, _>("timestamp").to_rfc3339(),
         });
 
+        let eta = load_eta(&state, shipment_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
         shipments.push(ShipmentResponse {
             id: shipment_id.to_string(),
             tracking_number: row.get::<String, _>("tracking_number"),
             sender_id: row.get::<Uuid, _>("sender_id").to_string(),
             receiver_id: row.get::<Uuid, _>("receiver_id").to_string(),
             driver_id: row.get::<Option<Uuid>, _>("driver_id").map(|id| id.to_string()),
-            status: format!("{:?}", row.get::<ShipmentStatus, _>("status")).to_lowercase(),
+            status: row.get::<ShipmentStatus, _>("status").as_str().to_string(),
             priority: format!("{:?}", row.get::<ShipmentPriority, _>("priority")).to_lowercase(),
             weight: row.get::<f64, _>("weight"),
             dimensions: row.get::<serde_json::Value, _>("dimensions"),
             description: row.get::<String, _>("description"),
             value: row.get::<f64, _>("value"),
             currency: row.get::<String, _>("currency"),
+            shipping_cost: row.get::<Option<f64>, _>("shipping_cost_amount"),
+            shipping_cost_currency: row.get::<Option<String>, _>("shipping_cost_currency"),
+            quote_id: row.get::<Option<Uuid>, _>("quote_id").map(|id| id.to_string()),
+            cod_amount: row.get::<Option<f64>, _>("cod_amount"),
             pickup_address: row.get::<serde_json::Value, _>("pickup_address"),
             delivery_address: row.get::<serde_json::Value, _>("delivery_address"),
             estimated_delivery: row.get::<Option<chrono::DateTime<Utc>>, _>("estimated_delivery")
                 .map(|dt| dt.to_rfc3339()),
             actual_delivery: row.get::<Option<chrono::DateTime<Utc>>, _>("actual_delivery")
                 .map(|dt| dt.to_rfc3339()),
+            eta,
             nft_token_id: row.get::<Option<String>, _>("nft_token_id"),
             blockchain_tx_hash: row.get::<Option<String>, _>("blockchain_tx_hash"),
             current_location,
             location_history: Vec::new(), // Simplified for search results
+            parcels: Vec::new(),
+            delivery_slot_id: row.get::<Option<Uuid>, _>("delivery_slot_id").map(|id| id.to_string()),
+            delivery_instructions: row.get::<Option<String>, _>("delivery_instructions"),
+            leave_authorized: row.get::<bool, _>("leave_authorized"),
             created_at: row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
             updated_at: row.get::<chrono::DateTime<Utc>, _>("updated_at").to_rfc3339(),
         });
@@ -589,9 +811,438 @@ pub async fn search_shipments(
 
 // Helper functions
 
-fn generate_tracking_number() -> String {
-    // Generate a unique tracking number
-    format!("SH{:08}", rand::random::<u32>())
+/// One row for the append-only `shipment_events` log.
+pub(crate) struct ShipmentEventRecord<'a> {
+    pub shipment_id: Uuid,
+    pub event_type: &'a str,
+    pub from_status: Option<ShipmentStatus>,
+    pub to_status: Option<ShipmentStatus>,
+    pub actor: Option<&'a AuthUser>,
+    pub notes: Option<&'a str>,
+    pub metadata: serde_json::Value,
+}
+
+pub(crate) async fn record_shipment_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    event: ShipmentEventRecord<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let event_id = Uuid::new_v4();
+
+    sqlx::query(
+        r#"
+        INSERT INTO shipment_events (
+            id, shipment_id, event_type, from_status, to_status, actor_id, actor_role, notes, metadata, created_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+    )
+    .bind(event_id)
+    .bind(event.shipment_id)
+    .bind(event.event_type)
+    .bind(event.from_status)
+    .bind(event.to_status)
+    .bind(event.actor.map(|actor| actor.user_id))
+    .bind(event.actor.map(|actor| actor.role.as_str()))
+    .bind(event.notes)
+    .bind(&event.metadata)
+    .bind(Utc::now())
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        error!("Failed to record shipment event: {}", e);
+        e
+    })?;
+
+    Ok(event_id)
+}
+
+/// Moves a shipment along the status graph and logs the change. The row is
+/// locked for the rest of the transaction so concurrent updates serialize.
+/// Returns the previous status.
+pub(crate) async fn transition_shipment_status(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shipment_id: Uuid,
+    to: ShipmentStatus,
+    actor: Option<&AuthUser>,
+    notes: Option<&str>,
+    metadata: serde_json::Value,
+) -> Result<ShipmentStatus, AppError> {
+    let from = sqlx::query("SELECT status FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?
+        .get::<ShipmentStatus, _>("status");
+
+    if !from.can_transition_to(to) {
+        let allowed: Vec<&str> = from.allowed_transitions().iter().map(|s| s.as_str()).collect();
+        warn!("Rejected shipment {} transition {} -> {}", shipment_id, from.as_str(), to.as_str());
+        return Err(AppError::Conflict(if allowed.is_empty() {
+            format!("Shipment is {} and can no longer change status", from.as_str())
+        } else {
+            format!(
+                "Cannot move shipment from {} to {}; allowed: {}",
+                from.as_str(),
+                to.as_str(),
+                allowed.join(", ")
+            )
+        }));
+    }
+
+    let now = Utc::now();
+    let actual_delivery = (to == ShipmentStatus::Delivered).then_some(now);
+
+    sqlx::query(
+        "UPDATE shipments SET status = $1, actual_delivery = COALESCE($2, actual_delivery), updated_at = $3 WHERE id = $4"
+    )
+    .bind(to)
+    .bind(actual_delivery)
+    .bind(now)
+    .bind(shipment_id)
+    .execute(&mut **tx)
+    .await?;
+
+    parcels::follow_shipment_status(tx, shipment_id, to).await?;
+    if to == ShipmentStatus::Cancelled {
+        delivery_slots::release_slot(tx, shipment_id).await?;
+    }
+    delivery_exceptions::follow_shipment_status(tx, shipment_id, to, actor).await?;
+
+    record_shipment_event(tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "status_changed",
+        from_status: Some(from),
+        to_status: Some(to),
+        actor,
+        notes,
+        metadata,
+    })
+    .await?;
+
+    Ok(from)
+}
+
+/// The parsed fields of a create request that passed validation.
+pub(crate) struct ValidatedShipment {
+    pub sender_id: Uuid,
+    pub receiver_id: Uuid,
+    pub priority: ShipmentPriority,
+    pub estimated_delivery: Option<chrono::DateTime<Utc>>,
+    pub pickup_window: Option<(chrono::DateTime<Utc>, chrono::DateTime<Utc>)>,
+    pub delivery_window: Option<(chrono::DateTime<Utc>, chrono::DateTime<Utc>)>,
+    pub quote_id: Option<Uuid>,
+    pub delivery_slot_id: Option<Uuid>,
+    pub delivery_instructions: Option<String>,
+}
+
+/// Checks a create request and collects every problem rather than stopping
+/// at the first, so bulk imports can report them per row.
+pub(crate) fn validate_shipment_request(payload: &CreateShipmentRequest) -> Result<ValidatedShipment, Vec<String>> {
+    let mut errors = Vec::new();
+
+    let sender_id = Uuid::parse_str(&payload.sender_id)
+        .map_err(|_| errors.push("sender_id is not a valid id".to_string()))
+        .ok();
+    let receiver_id = Uuid::parse_str(&payload.receiver_id)
+        .map_err(|_| errors.push("receiver_id is not a valid id".to_string()))
+        .ok();
+
+    if !payload.weight.is_finite() || payload.weight <= 0.0 {
+        errors.push("weight must be greater than zero".to_string());
+    }
+    if !payload.value.is_finite() || payload.value < 0.0 {
+        errors.push("value must not be negative".to_string());
+    }
+    if payload.cod_amount.is_some_and(|amount| !amount.is_finite() || amount <= 0.0) {
+        errors.push("cod_amount must be greater than zero".to_string());
+    }
+
+    let priority = ShipmentPriority::parse(&payload.priority);
+    if priority.is_none() {
+        errors.push(format!("priority '{}' is not one of low, medium, high, urgent", payload.priority));
+    }
+
+    let quote_id = match payload.quote_id.as_deref().map(Uuid::parse_str) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            errors.push("quote_id is not a valid id".to_string());
+            None
+        }
+        None => None,
+    };
+
+    let delivery_slot_id = match payload.delivery_slot_id.as_deref().map(Uuid::parse_str) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            errors.push("delivery_slot_id is not a valid id".to_string());
+            None
+        }
+        None => None,
+    };
+    if delivery_slot_id.is_some() && payload.delivery_window.is_some() {
+        errors.push("delivery_window can't be given with delivery_slot_id".to_string());
+    }
+
+    let delivery_instructions = payload
+        .delivery_instructions
+        .as_deref()
+        .map(str::trim)
+        .filter(|instructions| !instructions.is_empty())
+        .map(str::to_string);
+    if delivery_instructions
+        .as_ref()
+        .is_some_and(|instructions| instructions.chars().count() > delivery_slots::MAX_INSTRUCTIONS_LENGTH)
+    {
+        errors.push(format!(
+            "delivery_instructions must be at most {} characters",
+            delivery_slots::MAX_INSTRUCTIONS_LENGTH
+        ));
+    }
+
+    errors.extend(parcels::validate_parcels(payload));
+
+    for (field, address) in [("pickup_address", &payload.pickup_address), ("delivery_address", &payload.delivery_address)] {
+        if let Err(message) = validate_address(address) {
+            errors.push(format!("{} {}", field, message));
+        }
+    }
+
+    let estimated_delivery = match payload.estimated_delivery.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+        Some(Ok(at)) => Some(at.with_timezone(&Utc)),
+        Some(Err(_)) => {
+            errors.push("estimated_delivery must be an RFC 3339 timestamp".to_string());
+            None
+        }
+        None => None,
+    };
+
+    let pickup_window = parse_time_window(payload.pickup_window.as_ref())
+        .map_err(|message| errors.push(format!("pickup_window {}", message)))
+        .ok()
+        .flatten();
+    let delivery_window = parse_time_window(payload.delivery_window.as_ref())
+        .map_err(|message| errors.push(format!("delivery_window {}", message)))
+        .ok()
+        .flatten();
+
+    match (sender_id, receiver_id, priority) {
+        (Some(sender_id), Some(receiver_id), Some(priority)) if errors.is_empty() => Ok(ValidatedShipment {
+            sender_id,
+            receiver_id,
+            priority,
+            estimated_delivery,
+            pickup_window,
+            delivery_window,
+            quote_id,
+            delivery_slot_id,
+            delivery_instructions,
+        }),
+        _ => Err(errors),
+    }
+}
+
+/// Inserts a validated shipment under a fresh tracking number and logs its
+/// creation. Returns the new id and tracking number.
+pub(crate) async fn insert_shipment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    state: &crate::AppState,
+    payload: &CreateShipmentRequest,
+    shipment: &ValidatedShipment,
+    actor: &AuthUser,
+    now: chrono::DateTime<Utc>,
+) -> Result<(Uuid, String), AppError> {
+    let shipment_id = Uuid::new_v4();
+    let tracking_number = generate_tracking_number(state)
+        .await
+        .map_err(|_| AppError::Internal("Could not allocate a tracking number".to_string()))?;
+
+    let delivery_window = match shipment.delivery_slot_id {
+        Some(slot_id) => Some(
+            delivery_slots::reserve_for_new_shipment(tx, slot_id, payload, shipment.priority, now)
+                .await
+                .map_err(|e| {
+                    warn!("Delivery slot {} not booked for shipment {}: {}", slot_id, shipment_id, e);
+                    e
+                })?,
+        ),
+        None => shipment.delivery_window,
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO shipments (
+            id, tracking_number, sender_id, receiver_id, status, priority,
+            weight, dimensions, description, value, currency, pickup_address,
+            delivery_address, estimated_delivery, pickup_window_start, pickup_window_end,
+            delivery_window_start, delivery_window_end, delivery_slot_id, delivery_instructions,
+            leave_authorized, cod_amount, created_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20,
+            $21, $22, $23, $24
+        )
+        "#,
+    )
+    .bind(shipment_id)
+    .bind(&tracking_number)
+    .bind(shipment.sender_id)
+    .bind(shipment.receiver_id)
+    .bind(&ShipmentStatus::Pending)
+    .bind(&shipment.priority)
+    .bind(payload.weight)
+    .bind(&payload.dimensions)
+    .bind(&payload.description)
+    .bind(payload.value)
+    .bind(&payload.currency)
+    .bind(&payload.pickup_address)
+    .bind(&payload.delivery_address)
+    .bind(shipment.estimated_delivery)
+    .bind(shipment.pickup_window.map(|(start, _)| start))
+    .bind(shipment.pickup_window.map(|(_, end)| end))
+    .bind(delivery_window.map(|(start, _)| start))
+    .bind(delivery_window.map(|(_, end)| end))
+    .bind(shipment.delivery_slot_id)
+    .bind(&shipment.delivery_instructions)
+    .bind(payload.leave_authorized)
+    .bind(payload.cod_amount)
+    .bind(now)
+    .bind(now)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        error!("Database error creating shipment: {}", e);
+        AppError::Database(e)
+    })?;
+
+    parcels::insert_parcels(tx, shipment_id, &tracking_number, payload, now)
+        .await
+        .map_err(|e| {
+            error!("Database error creating parcels for shipment {}: {}", shipment_id, e);
+            AppError::Database(e)
+        })?;
+
+    if let Some(quote_id) = shipment.quote_id {
+        apply_quote(tx, quote_id, payload, shipment, actor, shipment_id, now)
+            .await
+            .map_err(|e| {
+                warn!("Quote {} not applied to shipment {}: {}", quote_id, shipment_id, e);
+                e
+            })?;
+    }
+
+    record_shipment_event(tx, ShipmentEventRecord {
+        shipment_id,
+        event_type: "created",
+        from_status: None,
+        to_status: Some(ShipmentStatus::Pending),
+        actor: Some(actor),
+        notes: None,
+        metadata: serde_json::json!({ "tracking_number": tracking_number, "quote_id": shipment.quote_id }),
+    })
+    .await?;
+
+    Ok((shipment_id, tracking_number))
+}
+
+/// Senders, receivers and the assigned driver may see a shipment; shipping
+/// companies and admins may see all of them.
+pub(crate) fn can_access_shipment(auth_user: &AuthUser, shipment_row: &sqlx::postgres::PgRow) -> bool {
+    if matches!(auth_user.role, UserRole::ShippingCompany | UserRole::Admin) {
+        return true;
+    }
+
+    shipment_row.get::<Uuid, _>("sender_id") == auth_user.user_id
+        || shipment_row.get::<Uuid, _>("receiver_id") == auth_user.user_id
+        || shipment_row.get::<Option<Uuid>, _>("driver_id") == Some(auth_user.user_id)
+}
+
+/// Drivers may only move shipments assigned to them; shipping companies and
+/// admins may move any. Locks the shipment when run inside a transaction.
+pub(crate) async fn ensure_assigned_driver<'e, E>(executor: E, shipment_id: Uuid, auth_user: &AuthUser) -> Result<(), AppError>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    if auth_user.role != UserRole::Driver {
+        return Ok(());
+    }
+
+    let driver_id = sqlx::query("SELECT driver_id FROM shipments WHERE id = $1 FOR UPDATE")
+        .bind(shipment_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Shipment not found".to_string()))?
+        .get::<Option<Uuid>, _>("driver_id");
+
+    if driver_id != Some(auth_user.user_id) {
+        return Err(AppError::Authorization("Only the assigned driver can update this shipment".to_string()));
+    }
+
+    Ok(())
+}
+
+fn parse_time_window(
+    window: Option<&TimeWindowRequest>,
+) -> Result<Option<(chrono::DateTime<Utc>, chrono::DateTime<Utc>)>, String> {
+    let Some(window) = window else { return Ok(None) };
+
+    let parse = |value: &str| {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .map(|at| at.with_timezone(&Utc))
+            .map_err(|_| "bounds must be RFC 3339 timestamps".to_string())
+    };
+    let (start, end) = (parse(&window.start)?, parse(&window.end)?);
+
+    if end <= start {
+        return Err("must end after it starts".to_string());
+    }
+
+    Ok(Some((start, end)))
+}
+
+/// Addresses are free-form objects, but coordinates, when given, have to
+/// be usable by geofencing and routing.
+pub(crate) fn validate_address(address: &serde_json::Value) -> Result<(), &'static str> {
+    let Some(fields) = address.as_object() else {
+        return Err("must be an object");
+    };
+    if fields.is_empty() {
+        return Err("must not be empty");
+    }
+
+    let has_coordinates = ["latitude", "lat", "longitude", "lng", "lon"]
+        .iter()
+        .any(|key| fields.contains_key(*key));
+    if has_coordinates && address_coordinates(address).is_none() {
+        return Err("has invalid coordinates");
+    }
+
+    Ok(())
+}
+
+/// Tracking numbers double as the key for public lookups, so they're drawn
+/// from a CSPRNG (~60 bits) rather than being sequential or short.
+async fn generate_tracking_number(state: &crate::AppState) -> Result<String, StatusCode> {
+    use rand::{rngs::OsRng, Rng};
+
+    for _ in 0..TRACKING_NUMBER_ATTEMPTS {
+        let suffix: String = (0..TRACKING_NUMBER_LENGTH)
+            .map(|_| TRACKING_NUMBER_ALPHABET[OsRng.gen_range(0..TRACKING_NUMBER_ALPHABET.len())] as char)
+            .collect();
+        let tracking_number = format!("SH{}", suffix);
+
+        let taken = sqlx::query("SELECT 1 FROM shipments WHERE tracking_number = $1")
+            .bind(&tracking_number)
+            .fetch_optional(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .is_some();
+
+        if !taken {
+            return Ok(tracking_number);
+        }
+        warn!("Tracking number collision, retrying");
+    }
+
+    error!("Could not generate a unique tracking number");
+    Err(StatusCode::INTERNAL_SERVER_ERROR)
 }
 
 #[derive(Debug)]
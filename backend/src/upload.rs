@@ -5,7 +5,9 @@ use axum::{
     routing::{get, post, put},
     Router,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::Utc;
 use anyhow::Result;
@@ -14,6 +16,10 @@ use tracing::{info, warn, error};
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::AuthUser;
+use crate::utils::{decrypt_bytes, encrypt_bytes, AppError};
+
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct UploadService {
@@ -40,9 +46,10 @@ pub struct UploadResponse {
 
 pub async fn upload_document(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     // In a real implementation, this would handle multipart form data
 ) -> Result<Json<UploadResponse>, StatusCode> {
-    info!("Uploading document");
+    info!("Uploading document for user: {}", auth_user.user_id);
 
     // Mock upload response
     let response = UploadResponse {
@@ -59,8 +66,9 @@ pub async fn upload_document(
 
 pub async fn upload_image(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
 ) -> Result<Json<UploadResponse>, StatusCode> {
-    info!("Uploading image");
+    info!("Uploading image for user: {}", auth_user.user_id);
 
     // Mock upload response
     let response = UploadResponse {
@@ -77,8 +85,9 @@ pub async fn upload_image(
 
 pub async fn upload_avatar(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
 ) -> Result<Json<UploadResponse>, StatusCode> {
-    info!("Uploading avatar");
+    info!("Uploading avatar for user: {}", auth_user.user_id);
 
     // Mock upload response
     let response = UploadResponse {
@@ -92,3 +101,102 @@ pub async fn upload_avatar(
 
     Ok(Json(response))
 }
+
+// Helper functions
+
+/// A blob persisted in `uploads`; the digest is of the plaintext.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredUpload {
+    pub id: Uuid,
+    pub content_type: &'static str,
+    pub size_bytes: i64,
+    pub sha256: String,
+}
+
+/// Decodes a base64 image (optionally a data URL) and checks it is a JPEG or
+/// PNG within the size limit.
+pub(crate) fn decode_image(data: &str) -> Result<(&'static str, Vec<u8>), AppError> {
+    let encoded = data.split_once(";base64,").map_or(data, |(_, encoded)| encoded);
+    let bytes = BASE64
+        .decode(encoded.trim())
+        .map_err(|_| AppError::InvalidInput("Image is not valid base64".to_string()))?;
+
+    if bytes.is_empty() {
+        return Err(AppError::InvalidInput("Image is empty".to_string()));
+    }
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(AppError::Validation(format!(
+            "Image exceeds {} MB",
+            MAX_IMAGE_BYTES / (1024 * 1024)
+        )));
+    }
+
+    let content_type = if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else {
+        return Err(AppError::Validation("Only JPEG and PNG images are accepted".to_string()));
+    };
+
+    Ok((content_type, bytes))
+}
+
+/// Encrypts `bytes` at rest and records them under `category`.
+pub(crate) async fn store_upload(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encryption_key: &str,
+    owner_id: Uuid,
+    category: &str,
+    content_type: &'static str,
+    bytes: &[u8],
+) -> Result<StoredUpload, AppError> {
+    use sha2::{Digest, Sha256};
+
+    let upload = StoredUpload {
+        id: Uuid::new_v4(),
+        content_type,
+        size_bytes: bytes.len() as i64,
+        sha256: format!("{:x}", Sha256::digest(bytes)),
+    };
+    let sealed = encrypt_bytes(bytes, encryption_key)
+        .map_err(|e| AppError::Internal(format!("Failed to encrypt upload: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO uploads (id, owner_id, category, content_type, size_bytes, sha256, encrypted_data, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(upload.id)
+    .bind(owner_id)
+    .bind(category)
+    .bind(upload.content_type)
+    .bind(upload.size_bytes)
+    .bind(&upload.sha256)
+    .bind(sealed)
+    .bind(Utc::now())
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(upload)
+}
+
+/// Returns the content type and decrypted bytes of an upload.
+pub(crate) async fn load_upload(
+    state: &crate::AppState,
+    upload_id: Uuid,
+) -> Result<Option<(String, Vec<u8>)>, AppError> {
+    let Some(row) = sqlx::query("SELECT content_type, encrypted_data FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_optional(&state.db.pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let bytes = decrypt_bytes(&row.get::<Vec<u8>, _>("encrypted_data"), &state.config.encryption_key)
+        .map_err(|e| AppError::Internal(format!("Failed to decrypt upload {}: {}", upload_id, e)))?;
+
+    Ok(Some((row.get::<String, _>("content_type"), bytes)))
+}
@@ -1,3 +1,5 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
@@ -245,14 +247,56 @@ pub fn format_file_size(bytes: u64) -> String {
 }
 
 // Encryption/Decryption functions
+// AES-256-GCM keyed by the SHA-256 of `key`; output is base64(nonce || ciphertext)
 pub fn encrypt_data(data: &str, key: &str) -> Result<String> {
-    // In a real implementation, use proper encryption
-    Ok(format!("encrypted_{}", data))
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    Ok(STANDARD.encode(encrypt_bytes(data.as_bytes(), key)?))
 }
 
 pub fn decrypt_data(encrypted_data: &str, key: &str) -> Result<String> {
-    // In a real implementation, use proper decryption
-    Ok(encrypted_data.replace("encrypted_", ""))
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let sealed = STANDARD
+        .decode(encrypted_data)
+        .map_err(|e| anyhow::anyhow!("Invalid ciphertext encoding: {}", e))?;
+    String::from_utf8(decrypt_bytes(&sealed, key)?)
+        .map_err(|e| anyhow::anyhow!("Decrypted data is not UTF-8: {}", e))
+}
+
+pub fn encrypt_bytes(data: &[u8], key: &str) -> Result<Vec<u8>> {
+    use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+
+    let cipher = Aes256Gcm::new_from_slice(&derive_encryption_key(key))
+        .map_err(|e| anyhow::anyhow!("Invalid encryption key: {}", e))?;
+    let nonce: [u8; 12] = rand::random();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn decrypt_bytes(sealed: &[u8], key: &str) -> Result<Vec<u8>> {
+    use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+
+    if sealed.len() < 12 {
+        return Err(anyhow::anyhow!("Ciphertext too short"));
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(&derive_encryption_key(key))
+        .map_err(|e| anyhow::anyhow!("Invalid encryption key: {}", e))?;
+    let (nonce, ciphertext) = sealed.split_at(12);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Decryption failed"))
+}
+
+fn derive_encryption_key(key: &str) -> [u8; 32] {
+    use sha2::{Sha256, Digest};
+    Sha256::digest(key.as_bytes()).into()
 }
 
 // Hash functions
@@ -358,6 +402,9 @@ pub enum AppError {
     
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
 }
 
 impl From<AppError> for axum::http::StatusCode {
@@ -372,7 +419,26 @@ impl From<AppError> for axum::http::StatusCode {
             AppError::ExternalService(_) => StatusCode::BAD_GATEWAY,
             AppError::RateLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
             AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+// Lets handlers return `AppError` directly when the client needs to know why
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Database(e) = &self {
+            error!("Database error: {}", e);
         }
+
+        let message = match &self {
+            // Don't leak driver details to clients
+            AppError::Database(_) => "Internal server error".to_string(),
+            other => other.to_string(),
+        };
+        let status = StatusCode::from(self);
+
+        (status, Json(ApiResponse::<()>::error(message))).into_response()
     }
 }
 
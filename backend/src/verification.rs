@@ -0,0 +1,382 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use uuid::Uuid;
+use chrono::{Duration, Utc};
+use tracing::{info, warn, error};
+
+use crate::auth::AuthUser;
+use crate::services::utils::{send_email, send_sms};
+use crate::utils::{generate_random_number, hash_data};
+
+// Email and phone verification for registered users
+
+const CODE_TTL_MINUTES: i64 = 15;
+const MAX_ATTEMPTS: i32 = 5;
+const RESEND_COOLDOWN_SECONDS: i64 = 60;
+const MAX_SENDS_PER_HOUR: i64 = 5;
+
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+pub const PHONE_VERIFICATION: &str = "phone_verification";
+pub const PASSWORD_RESET: &str = "password_reset";
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationSentResponse {
+    pub channel: String,
+    pub destination: String,
+    pub expires_at: String,
+    pub resend_after: u64,
+}
+
+/// An authenticated caller whose contact details are verified: always the
+/// email address, and the phone number too if the account has one.
+///
+/// Use instead of `AuthUser` on routes that must not be reachable by
+/// accounts with unconfirmed contact details; rejects with `403` otherwise.
+#[derive(Debug, Clone)]
+pub struct VerifiedUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<crate::AppState> for VerifiedUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &crate::AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth_user = AuthUser::from_request_parts(parts, state).await?;
+
+        let row = sqlx::query("SELECT email_verified, phone, phone_verified FROM users WHERE id = $1")
+            .bind(auth_user.user_id)
+            .fetch_optional(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let has_phone = row
+            .get::<Option<String>, _>("phone")
+            .is_some_and(|phone| !phone.trim().is_empty());
+
+        if !row.get::<bool, _>("email_verified") || (has_phone && !row.get::<bool, _>("phone_verified")) {
+            warn!("User {} blocked: contact details not verified", auth_user.user_id);
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        Ok(VerifiedUser(auth_user))
+    }
+}
+
+pub async fn request_email_verification(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<VerificationSentResponse>, StatusCode> {
+    info!("Email verification requested for user: {}", auth_user.user_id);
+
+    let user_row = sqlx::query("SELECT email, email_verified FROM users WHERE id = $1")
+        .bind(auth_user.user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if user_row.get::<bool, _>("email_verified") {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let email = user_row.get::<String, _>("email");
+    let code = generate_numeric_code();
+    let expires_at = issue_code(&state, auth_user.user_id, EMAIL_VERIFICATION, &code, Duration::minutes(CODE_TTL_MINUTES)).await?;
+
+    send_email(
+        &email,
+        "Verify your email address",
+        &format!("Your verification code is {}. It expires in {} minutes.", code, CODE_TTL_MINUTES),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to send verification email: {}", e);
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    Ok(Json(VerificationSentResponse {
+        channel: "email".to_string(),
+        destination: mask_email(&email),
+        expires_at: expires_at.to_rfc3339(),
+        resend_after: RESEND_COOLDOWN_SECONDS as u64,
+    }))
+}
+
+pub async fn confirm_email(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ConfirmCodeRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    info!("Email verification attempt for user: {}", auth_user.user_id);
+
+    consume_code(&state, auth_user.user_id, EMAIL_VERIFICATION, &payload.code).await?;
+
+    sqlx::query("UPDATE users SET email_verified = true, updated_at = $1 WHERE id = $2")
+        .bind(Utc::now())
+        .bind(auth_user.user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    info!("Email verified for user: {}", auth_user.user_id);
+
+    Ok(Json(serde_json::json!({
+        "status": "verified",
+        "message": "Email address verified successfully",
+        "verified_at": Utc::now().to_rfc3339()
+    })))
+}
+
+pub async fn request_phone_verification(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<VerificationSentResponse>, StatusCode> {
+    info!("Phone verification requested for user: {}", auth_user.user_id);
+
+    let user_row = sqlx::query("SELECT phone, phone_verified FROM users WHERE id = $1")
+        .bind(auth_user.user_id)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if user_row.get::<bool, _>("phone_verified") {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    // A phone number has to be on the profile before it can be verified
+    let phone = user_row
+        .get::<Option<String>, _>("phone")
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let code = generate_numeric_code();
+    let expires_at = issue_code(&state, auth_user.user_id, PHONE_VERIFICATION, &code, Duration::minutes(CODE_TTL_MINUTES)).await?;
+
+    send_sms(
+        &phone,
+        &format!("Your verification code is {}. It expires in {} minutes.", code, CODE_TTL_MINUTES),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to send verification SMS: {}", e);
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    Ok(Json(VerificationSentResponse {
+        channel: "sms".to_string(),
+        destination: mask_phone(&phone),
+        expires_at: expires_at.to_rfc3339(),
+        resend_after: RESEND_COOLDOWN_SECONDS as u64,
+    }))
+}
+
+pub async fn confirm_phone(
+    State(state): State<crate::AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ConfirmCodeRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    info!("Phone verification attempt for user: {}", auth_user.user_id);
+
+    consume_code(&state, auth_user.user_id, PHONE_VERIFICATION, &payload.code).await?;
+
+    sqlx::query("UPDATE users SET phone_verified = true, updated_at = $1 WHERE id = $2")
+        .bind(Utc::now())
+        .bind(auth_user.user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    info!("Phone verified for user: {}", auth_user.user_id);
+
+    Ok(Json(serde_json::json!({
+        "status": "verified",
+        "message": "Phone number verified successfully",
+        "verified_at": Utc::now().to_rfc3339()
+    })))
+}
+
+// Helper functions
+
+/// Stores a hashed one-time code for `purpose`, superseding any earlier
+/// unused code. Enforces the resend cooldown and hourly send limit.
+pub(crate) async fn issue_code(
+    state: &crate::AppState,
+    user_id: Uuid,
+    purpose: &str,
+    code: &str,
+    ttl: Duration,
+) -> Result<chrono::DateTime<Utc>, StatusCode> {
+    let now = Utc::now();
+
+    let recent = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS sent, MAX(created_at) AS last_sent
+        FROM verification_codes
+        WHERE user_id = $1 AND purpose = $2 AND created_at > $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .bind(now - Duration::hours(1))
+    .fetch_one(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let sent = recent.get::<i64, _>("sent");
+    let last_sent = recent.get::<Option<chrono::DateTime<Utc>>, _>("last_sent");
+
+    if sent >= MAX_SENDS_PER_HOUR
+        || last_sent.is_some_and(|at| now - at < Duration::seconds(RESEND_COOLDOWN_SECONDS))
+    {
+        warn!("Verification code throttled for user {} ({})", user_id, purpose);
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    sqlx::query(
+        "UPDATE verification_codes SET consumed_at = $1 WHERE user_id = $2 AND purpose = $3 AND consumed_at IS NULL"
+    )
+    .bind(now)
+    .bind(user_id)
+    .bind(purpose)
+    .execute(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let expires_at = now + ttl;
+    sqlx::query(
+        r#"
+        INSERT INTO verification_codes (
+            id, user_id, purpose, code_hash, attempts, expires_at, created_at
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(purpose)
+    .bind(hash_code(user_id, code))
+    .bind(0)
+    .bind(expires_at)
+    .bind(now)
+    .execute(&state.db.pool)
+    .await
+    .map_err(|e| {
+        error!("Database error storing verification code: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(expires_at)
+}
+
+/// Checks `code` against the latest outstanding code for `purpose` and marks
+/// it used. Each wrong guess counts towards `MAX_ATTEMPTS`, after which the
+/// code is burned and a new one has to be requested.
+pub(crate) async fn consume_code(
+    state: &crate::AppState,
+    user_id: Uuid,
+    purpose: &str,
+    code: &str,
+) -> Result<(), StatusCode> {
+    let now = Utc::now();
+
+    let stored = sqlx::query(
+        r#"
+        SELECT id, code_hash, expires_at FROM verification_codes
+        WHERE user_id = $1 AND purpose = $2 AND consumed_at IS NULL
+        ORDER BY created_at DESC LIMIT 1
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let code_id = stored.get::<Uuid, _>("id");
+
+    if stored.get::<chrono::DateTime<Utc>, _>("expires_at") <= now {
+        return Err(StatusCode::GONE);
+    }
+
+    // Spend an attempt before comparing, so concurrent guesses can't exceed the limit
+    let attempts = sqlx::query(
+        r#"
+        UPDATE verification_codes SET attempts = attempts + 1
+        WHERE id = $1 AND attempts < $2 AND consumed_at IS NULL
+        RETURNING attempts
+        "#,
+    )
+    .bind(code_id)
+    .bind(MAX_ATTEMPTS)
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::TOO_MANY_REQUESTS)?
+    .get::<i32, _>("attempts");
+
+    if stored.get::<String, _>("code_hash") != hash_code(user_id, code.trim()) {
+        if attempts >= MAX_ATTEMPTS {
+            sqlx::query("UPDATE verification_codes SET consumed_at = $1 WHERE id = $2 AND consumed_at IS NULL")
+                .bind(now)
+                .bind(code_id)
+                .execute(&state.db.pool)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+
+        warn!("Invalid {} code for user {} (attempt {})", purpose, user_id, attempts);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let consumed = sqlx::query("UPDATE verification_codes SET consumed_at = $1 WHERE id = $2 AND consumed_at IS NULL")
+        .bind(now)
+        .bind(code_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .rows_affected();
+
+    if consumed == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(())
+}
+
+fn generate_numeric_code() -> String {
+    format!("{:06}", generate_random_number(0, 999_999))
+}
+
+fn hash_code(user_id: Uuid, code: &str) -> String {
+    hash_data(&format!("{}:{}", user_id, code))
+}
+
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let visible: String = local.chars().take(2).collect();
+            format!("{}***@{}", visible, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+fn mask_phone(phone: &str) -> String {
+    let digits: Vec<char> = phone.chars().collect();
+    let suffix: String = digits[digits.len().saturating_sub(4)..].iter().collect();
+    format!("***{}", suffix)
+}
@@ -7,13 +7,18 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use anyhow::Result;
+use sqlx::Row;
 use tracing::{info, warn, error};
 
 use crate::models::*;
 use crate::config::Config;
 use crate::database::Database;
+use crate::auth::{begin_login, AuthUser, LoginResponse};
+use crate::siwe::{format_address, verify_signature, SiweMessage, NONCE_TTL_MINUTES};
+use crate::sessions::SessionContext;
+use crate::utils::generate_random_string;
 
 #[derive(Debug, Clone)]
 pub struct Web3Service {
@@ -36,6 +41,14 @@ pub struct WalletConnectRequest {
     pub chain_id: u64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct SiweNonceResponse {
+    pub nonce: String,
+    pub domain: String,
+    pub chain_id: u64,
+    pub expires_at: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TransactionRequest {
     pub to: String,
@@ -94,30 +107,106 @@ pub struct ICPCallRequest {
     pub args: serde_json::Value,
 }
 
+pub async fn get_nonce(
+    State(state): State<crate::AppState>,
+) -> Result<Json<SiweNonceResponse>, StatusCode> {
+    let nonce = generate_random_string(17);
+    let expires_at = Utc::now() + Duration::minutes(NONCE_TTL_MINUTES);
+
+    sqlx::query("INSERT INTO siwe_nonces (nonce, expires_at) VALUES ($1, $2)")
+        .bind(&nonce)
+        .bind(expires_at)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to store SIWE nonce: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(SiweNonceResponse {
+        nonce,
+        domain: state.config.siwe_domain.clone(),
+        chain_id: state.config.ethereum_chain_id,
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
 pub async fn connect_wallet(
     State(state): State<crate::AppState>,
+    auth_user: AuthUser,
     Json(payload): Json<WalletConnectRequest>,
 ) -> Result<Json<WalletResponse>, StatusCode> {
     info!("Wallet connection request for address: {}", payload.wallet_address);
 
-    // Verify signature
-    if !verify_wallet_signature(&payload).await {
-        return Err(StatusCode::UNAUTHORIZED);
+    // Prove ownership of the key before linking it to the account
+    let message = verify_siwe_request(&state, &payload).await?;
+    let address = format_address(&message.address);
+
+    let linked_to = sqlx::query("SELECT id FROM users WHERE wallet_address = $1")
+        .bind(&address)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(|row| row.get::<Uuid, _>("id"));
+
+    if linked_to.is_some_and(|user_id| user_id != auth_user.user_id) {
+        warn!("Wallet {} is already linked to another account", address);
+        return Err(StatusCode::CONFLICT);
     }
 
+    sqlx::query("UPDATE users SET wallet_address = $1, updated_at = $2 WHERE id = $3")
+        .bind(&address)
+        .bind(Utc::now())
+        .bind(auth_user.user_id)
+        .execute(&state.db.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to link wallet: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
     // Get wallet balance
-    let balance = get_wallet_balance(&payload.wallet_address, &payload.chain_id).await
+    let balance = get_wallet_balance(&address, &message.chain_id).await
         .unwrap_or_else(|_| "0".to_string());
 
     let response = WalletResponse {
-        address: payload.wallet_address,
+        address: address.clone(),
         balance,
         currency: "ETH".to_string(), // Default to ETH
-        chain_id: payload.chain_id,
+        chain_id: message.chain_id,
         connected_at: Utc::now().to_rfc3339(),
     };
 
-    info!("Wallet connected successfully: {}", payload.wallet_address);
+    info!("Wallet {} linked to user {}", address, auth_user.user_id);
+
+    Ok(Json(response))
+}
+
+pub async fn wallet_login(
+    State(state): State<crate::AppState>,
+    session_context: SessionContext,
+    Json(payload): Json<WalletConnectRequest>,
+) -> Result<Json<LoginResponse>, StatusCode> {
+    info!("Wallet login attempt for address: {}", payload.wallet_address);
+
+    let message = verify_siwe_request(&state, &payload).await?;
+    let address = format_address(&message.address);
+
+    let user_row = sqlx::query("SELECT * FROM users WHERE wallet_address = $1")
+        .bind(&address)
+        .fetch_optional(&state.db.pool)
+        .await
+        .map_err(|e| {
+            error!("Database error during wallet login: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !matches!(user_row.get::<UserStatus, _>("status"), UserStatus::Active) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let response = begin_login(&state, &user_row, &session_context).await?;
 
     Ok(Json(response))
 }
@@ -359,14 +448,42 @@ struct ICPCallResult {
     tx_id: String,
 }
 
-async fn verify_wallet_signature(request: &WalletConnectRequest) -> bool {
-    // In a real implementation, you would:
-    // 1. Recover the signer from the signature
-    // 2. Verify it matches the wallet address
-    // 3. Verify the message hash
-    
-    // For now, we'll simulate verification
-    !request.signature.is_empty() && !request.wallet_address.is_empty()
+/// Verifies an EIP-4361 message and its signature, then burns the nonce it
+/// was issued with so the same proof cannot be replayed.
+async fn verify_siwe_request(
+    state: &crate::AppState,
+    request: &WalletConnectRequest,
+) -> Result<SiweMessage, StatusCode> {
+    let message = SiweMessage::parse(&request.message).map_err(StatusCode::from)?;
+
+    message
+        .validate(&state.config.siwe_domain, state.config.ethereum_chain_id, Utc::now())
+        .map_err(StatusCode::from)?;
+
+    if message.chain_id != request.chain_id
+        || !format_address(&message.address).eq_ignore_ascii_case(&request.wallet_address)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    verify_signature(&request.message, &request.signature, message.address)
+        .map_err(StatusCode::from)?;
+
+    let consumed = sqlx::query(
+        "UPDATE siwe_nonces SET consumed_at = NOW()
+         WHERE nonce = $1 AND consumed_at IS NULL AND expires_at > NOW()",
+    )
+    .bind(&message.nonce)
+    .execute(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if consumed.rows_affected() != 1 {
+        warn!("Rejected SIWE message with unknown or used nonce for {}", request.wallet_address);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(message)
 }
 
 async fn get_wallet_balance(address: &str, chain_id: &u64) -> Result<String> {